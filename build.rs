@@ -13,11 +13,11 @@ const AUTO_SIZE : u32 = 0x0000_0004; // 16 bit -> OPSIZE , 32-bit -> None   , 64
 const AUTO_NO32 : u32 = 0x0000_0008; // 16 bit -> OPSIZE , 32-bit -> illegal, 64-bit -> None
 const AUTO_REXW : u32 = 0x0000_0010; // 16 bit -> illegal, 32-bit -> None   , 64-bit -> REX.W/VEX.W/XOP.W
 const AUTO_VEXL : u32 = 0x0000_0020; // 128bit -> None   , 256bit -> VEX.L
-const WORD_SIZE : u32 = 0x0000_0040; // implies opsize prefix
+const WORD_SIZE : u32 = 0x0000_0040; // 16-bit operands: implies opsize prefix outside of 16-bit mode
 const WITH_REXW : u32 = 0x0000_0080; // implies REX.W/VEX.W/XOP.W
 const WITH_VEXL : u32 = 0x0000_0100; // implies VEX.L/XOP.L
 
-const PREF_66   : u32 = WORD_SIZE; // mandatory prefix (same as WORD_SIZE)
+const PREF_66   : u32 = 0x0010_0000; // mandatory prefix, regardless of mode
 const PREF_67   : u32 = 0x0000_0200; // mandatory prefix (same as SMALL_ADDRESS)
 const PREF_F0   : u32 = 0x0000_0400; // mandatory prefix (same as LOCK)
 const PREF_F2   : u32 = 0x0000_0800; // mandatory prefix (REPNE)
@@ -30,6 +30,18 @@ const REPE      : u32 = 0x0000_8000;
 const SHORT_ARG : u32 = 0x0001_0000; // a register argument is encoded in the last byte of the opcode
 const ENC_MR    : u32 = 0x0002_0000; //  select alternate arg encoding
 const ENC_VM    : u32 = 0x0004_0000; //  select alternate arg encoding
+const DWORD_SIZE: u32 = 0x0008_0000; // 32-bit operands: implies opsize prefix in 16-bit mode
+
+const WORD_ADDR : u32 = 0x0020_0000; // 16-bit addresses: implies addrsize prefix in 32-bit mode
+const DWORD_ADDR: u32 = 0x0040_0000; // 32-bit addresses: implies addrsize prefix outside of 32-bit mode
+const NO_LONG   : u32 = 0x0080_0000; // not available in 64-bit mode
+const LONG_ONLY : u32 = 0x0100_0000; // only available in 64-bit mode
+
+const EVEX_OP   : u32 = 0x0200_0000; // this instruction requires an EVEX prefix to be encoded
+const WITH_EVEXL: u32 = 0x0400_0000; // implies EVEX.L' (512-bit vectors)
+const BCST      : u32 = 0x0800_0000; // a memory operand can be broadcast from a single element, sized by EVEX.W
+const EVEX_ER   : u32 = 0x1000_0000; // embedded rounding control can be used with register operands
+const EVEX_SAE  : u32 = 0x2000_0000; // exceptions can be suppressed with register operands
 
 #[derive(Debug)]
 pub struct Opdata {
@@ -105,7 +117,8 @@ impl<'a> Iterator for FormatStringIterator<'a> {
 // r : legacy reg
 // f : fp reg
 // x : mmx reg
-// y : xmm/ymm reg
+// y : xmm/ymm/zmm reg
+// j : mask reg
 // s : segment reg
 // c : control reg
 // d : debug reg
@@ -120,7 +133,8 @@ impl<'a> Iterator for FormatStringIterator<'a> {
 // X: matches st0
 
 // b, w, d, q match a byte, word, doubleword and quadword.
-// * matches all possible sizes for this operand (w/d for i/o, w/d/q for r/v, o/h for y/w and everything for m)
+// o, h and z match an oword, hword and zword (xmm, ymm and zmm sized).
+// * matches all possible sizes for this operand (w/d for i/o, w/d/q for r/v, o/h/z for y/w and everything for m)
 // ! matches a lack of size, only useful in combination of m and i
 // ? matches any size and doesn't participate in the operand size calculation
 
@@ -135,6 +149,7 @@ Ops!(make_opmap;
                     b"vbrb",     [0x10            ], X,             LOCK;
                     b"r*v*",     [0x13            ], X, AUTO_SIZE;
                     b"rbvb",     [0x12            ], X;
+] adcx          = [ b"r*v*",     [0x0F, 0x38, 0xF6], X, AUTO_REXW | PREF_66;
 ] add           = [ b"A*i*",     [0x05            ], X, AUTO_SIZE;
                     b"Abib",     [0x04            ], X;
                     b"v*i*",     [0x81            ], 0, AUTO_SIZE | LOCK;
@@ -144,6 +159,7 @@ Ops!(make_opmap;
                     b"vbrb",     [0x00            ], X,             LOCK;
                     b"r*v*",     [0x03            ], X, AUTO_SIZE;
                     b"rbvb",     [0x02            ], X;
+] adox          = [ b"r*v*",     [0x0F, 0x38, 0xF6], X, AUTO_REXW | PREF_F3;
 ] and           = [ b"A*i*",     [0x25            ], X, AUTO_SIZE;
                     b"Abib",     [0x24            ], X;
                     b"v*i*",     [0x81            ], 4, AUTO_SIZE | LOCK;
@@ -181,14 +197,16 @@ Ops!(make_opmap;
 ] call          = [ b"o*",       [0xE8            ], X, AUTO_SIZE;
                     b"r*",       [0xFF            ], 2, AUTO_NO32;
 ] cbw           = [ b"",         [0x98            ], X, WORD_SIZE;
-] cwde          = [ b"",         [0x98            ], X;
+] cwde          = [ b"",         [0x98            ], X, DWORD_SIZE;
 ] cdqe          = [ b"",         [0x98            ], X, WITH_REXW;
 ] cwd           = [ b"",         [0x99            ], X, WORD_SIZE;
-] cdq           = [ b"",         [0x99            ], X;
+] cdq           = [ b"",         [0x99            ], X, DWORD_SIZE;
 ] cqo           = [ b"",         [0x99            ], X, WITH_REXW;
 ] clc           = [ b"",         [0xF8            ], X;
 ] cld           = [ b"",         [0xFC            ], X;
 ] clflush       = [ b"mb",       [0x0F, 0xAE      ], 7;
+] clflushopt    = [ b"mb",       [0x0F, 0xAE      ], 7, PREF_66;
+] clwb          = [ b"mb",       [0x0F, 0xAE      ], 6, PREF_66;
 ] cmc           = [ b"",         [0xF5            ], X;
 ] cmovo         = [ b"r*v*",     [0x0F, 0x40      ], X, AUTO_SIZE;
 ] cmovno        = [ b"r*v*",     [0x0F, 0x41      ], X, AUTO_SIZE;
@@ -231,7 +249,7 @@ Ops!(make_opmap;
                     b"rbvb",     [0x3A            ], X;
 ] cmpsb         = [ b"",         [0xA6            ], X,             REPE;
 ] cmpsw         = [ b"",         [0xA7            ], X, WORD_SIZE | REPE;
-] cmpsd         = [ b"",         [0xA7            ], X,             REPE;
+] cmpsd         = [ b"",         [0xA7            ], X, DWORD_SIZE | REPE;
                     b"yowoib",   [0x0F, 0xC2      ], X, PREF_F2;
 ] cmpsq         = [ b"",         [0xA7            ], X, WITH_REXW | REP;
 ] cmpxchg       = [ b"v*r*",     [0x0F, 0xB1      ], X, AUTO_SIZE | LOCK;
@@ -256,15 +274,15 @@ Ops!(make_opmap;
                     b"r*v*ib",   [0x68            ], X, AUTO_SIZE;
 ] in_           = [ b"Abib",     [0xE4            ], X;
                     b"Awib",     [0xE5            ], X, WORD_SIZE;
-                    b"Adib",     [0xE5            ], X;
+                    b"Adib",     [0xE5            ], X, DWORD_SIZE;
                     b"AbCw",     [0xEC            ], X;
                     b"AwCw",     [0xED            ], X, WORD_SIZE;
-                    b"AdCw",     [0xED            ], X;
+                    b"AdCw",     [0xED            ], X, DWORD_SIZE;
 ] inc           = [ b"v*",       [0xFF            ], 0, AUTO_SIZE | LOCK;
                     b"vb",       [0xFE            ], 0,             LOCK;
 ] insb          = [ b"",         [0x6C            ], X;
 ] insw          = [ b"",         [0x6D            ], X, WORD_SIZE;
-] insd          = [ b"",         [0x6D            ], X;
+] insd          = [ b"",         [0x6D            ], X, DWORD_SIZE;
 ] int           = [ b"ib",       [0xCD            ], X;
 ] jo            = [ b"o*",       [0x0F, 0x80      ], X, AUTO_SIZE;
                     b"ob",       [0x70            ], X;
@@ -312,8 +330,9 @@ Ops!(make_opmap;
 ] jnle          |
   jg            = [ b"o*",       [0x0F, 0x8F      ], X, AUTO_SIZE;
                     b"ob",       [0x7F            ], X;
-] jecxz         = [ b"ob",       [0xE3            ], X, PREF_67;
-] jrcxz         = [ b"ob",       [0xE3            ], X;
+] jcxz          = [ b"ob",       [0xE3            ], X, WORD_ADDR | NO_LONG;
+] jecxz         = [ b"ob",       [0xE3            ], X, DWORD_ADDR;
+] jrcxz         = [ b"ob",       [0xE3            ], X, LONG_ONLY;
 ] jmp           = [ b"o*",       [0xE9            ], X, AUTO_SIZE;
                     b"ob",       [0xEB            ], X;
                     b"v*",       [0xFF            ], 4, AUTO_NO32 ;
@@ -327,7 +346,7 @@ Ops!(make_opmap;
 ] llwpcb        = [ b"r*",       [   9, 0x12      ], 0, AUTO_REXW | XOP_OP;
 ] lodsb         = [ b"",         [0xAC            ], X;
 ] lodsw         = [ b"",         [0xAD            ], X, WORD_SIZE;
-] lodsd         = [ b"",         [0xAD            ], X;
+] lodsd         = [ b"",         [0xAD            ], X, DWORD_SIZE;
 ] lodsq         = [ b"",         [0xAD            ], X, WITH_REXW;
 ] loop_         = [ b"ob",       [0xE2            ], X;
 ] loope         |
@@ -348,7 +367,7 @@ Ops!(make_opmap;
                     b"swrw",     [0x8C            ], X;
                     b"rbib",     [0xB0            ], X,             SHORT_ARG;
                     b"rwiw",     [0xB8            ], X, WORD_SIZE | SHORT_ARG;
-                    b"rdid",     [0xB8            ], X,             SHORT_ARG;
+                    b"rdid",     [0xB8            ], X, DWORD_SIZE | SHORT_ARG;
                     b"v*i*",     [0xC7            ], 0, AUTO_SIZE;
                     b"rqiq",     [0xB8            ], X, WITH_REXW | SHORT_ARG;
                     b"vbib",     [0xC6            ], 0;
@@ -383,7 +402,7 @@ Ops!(make_opmap;
 ] movnti        = [ b"m*r*",     [0x0F, 0xC3      ], X, AUTO_REXW;
 ] movsb         = [ b"",         [0xA4            ], X;
 ] movsw         = [ b"",         [0xA5            ], X, WORD_SIZE;
-] movsd         = [ b"",         [0xA5            ], X;
+] movsd         = [ b"",         [0xA5            ], X, DWORD_SIZE;
                     b"yoyo",     [0x0F, 0x10      ], X, PREF_F2;
                     b"yomq",     [0x0F, 0x10      ], X, PREF_F2;
                     b"mqyo",     [0x0F, 0x11      ], X, PREF_F2;
@@ -412,14 +431,14 @@ Ops!(make_opmap;
                     b"r*v*",     [0x0B            ], X, AUTO_SIZE;
                     b"rbvb",     [0x0A            ], X;
 ] out           = [ b"ibAb",     [0xE6            ], X;
-                    b"ibAw",     [0xE7            ], X;
-                    b"ibAd",     [0xE7            ], X;
+                    b"ibAw",     [0xE7            ], X, WORD_SIZE;
+                    b"ibAd",     [0xE7            ], X, DWORD_SIZE;
                     b"CwAb",     [0xEE            ], X;
                     b"CwAw",     [0xEF            ], X, WORD_SIZE;
-                    b"CwAd",     [0xEF            ], X;
+                    b"CwAd",     [0xEF            ], X, DWORD_SIZE;
 ] outsb         = [ b"",         [0x6E            ], X,             REP;
 ] outsw         = [ b"",         [0x6F            ], X, WORD_SIZE | REP;
-] outsd         = [ b"",         [0x6F            ], X,             REP;
+] outsd         = [ b"",         [0x6F            ], X, DWORD_SIZE | REP;
 ] pause         = [ b"",         [0xF3, 0x90      ], X;
 ] pdep          = [ b"r*r*v*",   [   2, 0xF5      ], X, AUTO_REXW | VEX_OP | PREF_F2;
 ] pext          = [ b"r*r*v*",   [   2, 0xF5      ], X, AUTO_REXW | VEX_OP | PREF_F3;
@@ -428,8 +447,9 @@ Ops!(make_opmap;
                     b"Uw",       [0x0F, 0xA1      ], X;
                     b"Vw",       [0x0F, 0xA9      ], X;
 ] popcnt        = [ b"r*v*",     [0x0F, 0xB8      ], X, AUTO_SIZE | PREF_F3;
-] popf          = [ b"",         [0x9D            ], X, PREF_66;
-] popfq         = [ b"",         [0x9D            ], X;
+] popf          = [ b"",         [0x9D            ], X, WORD_SIZE;
+] popfd         = [ b"",         [0x9D            ], X, DWORD_SIZE | NO_LONG;
+] popfq         = [ b"",         [0x9D            ], X, LONG_ONLY;
 ] prefetch      = [ b"mb",       [0x0F, 0x0D      ], 0;
 ] prefetchw     = [ b"mb",       [0x0F, 0x0D      ], 1;
 ] prefetchnta   = [ b"mb",       [0x0F, 0x18      ], 0;
@@ -438,13 +458,13 @@ Ops!(make_opmap;
 ] prefetcht2    = [ b"mb",       [0x0F, 0x18      ], 3;
 ] push          = [ b"r*",       [0x50            ], X, AUTO_NO32 | SHORT_ARG;
                     b"v*",       [0xFF            ], 6, AUTO_NO32 ;
-                    b"iq",       [0x68            ], X;
-                    b"iw",       [0x68            ], X, WORD_SIZE;
+                    b"i*",       [0x68            ], X, AUTO_NO32 ;
                     b"ib",       [0x6A            ], X;
                     b"Uw",       [0x0F, 0xA0      ], X;
                     b"Vw",       [0x0F, 0xA8      ], X;
-] pushf         = [ b"",         [0x9C            ], X, PREF_66;
-] pushfq        = [ b"",         [0x9C            ], X;
+] pushf         = [ b"",         [0x9C            ], X, WORD_SIZE;
+] pushfd        = [ b"",         [0x9C            ], X, DWORD_SIZE | NO_LONG;
+] pushfq        = [ b"",         [0x9C            ], X, LONG_ONLY;
 ] rcl           = [ b"v*Bb",     [0xD3            ], 2, AUTO_SIZE; // shift by one forms not supported as immediates are only resolved at runtime
                     b"vbBb",     [0xD2            ], 2;
                     b"v*ib",     [0xC1            ], 2, AUTO_SIZE;
@@ -456,6 +476,7 @@ Ops!(make_opmap;
 ] rdfsbase      = [ b"r*",       [0x0F, 0xAE      ], 0, AUTO_REXW | PREF_F3;
 ] rdgsbase      = [ b"r*",       [0x0F, 0xAE      ], 1, AUTO_REXW | PREF_F3;
 ] rdrand        = [ b"r*",       [0x0F, 0xC7      ], 6, AUTO_SIZE;
+] rdseed        = [ b"r*",       [0x0F, 0xC7      ], 7, AUTO_SIZE;
 ] ret           = [ b"",         [0xC3            ], X;
                     b"iw",       [0xC2            ], X;
 ] rol           = [ b"v*Bb",     [0xD3            ], 0, AUTO_SIZE;
@@ -489,7 +510,7 @@ Ops!(make_opmap;
                     b"rbvb",     [0x1A            ], X;
 ] scasb         = [ b"",         [0xAE            ], X,             REPE;
 ] scasw         = [ b"",         [0xAF            ], X, WORD_SIZE | REPE;
-] scasd         = [ b"",         [0xAF            ], X,             REPE;
+] scasd         = [ b"",         [0xAF            ], X, DWORD_SIZE | REPE;
 ] scasq         = [ b"",         [0xAF            ], X, WITH_REXW | REPE;
 ] seto          = [ b"vb",       [0x0F, 0x90      ], 0;
 ] setno         = [ b"vb",       [0x0F, 0x91      ], 0;
@@ -537,7 +558,7 @@ Ops!(make_opmap;
 ] std           = [ b"",         [0xFD            ], X;
 ] stosb         = [ b"",         [0xAA            ], X,             REP;
 ] stosw         = [ b"",         [0xAB            ], X, WORD_SIZE | REP;
-] stosd         = [ b"",         [0xAB            ], X,             REP;
+] stosd         = [ b"",         [0xAB            ], X, DWORD_SIZE | REP;
 ] stosq         = [ b"",         [0xAB            ], X, WITH_REXW | REP;
 ] sub           = [ b"A*i*",     [0x2D            ], X, AUTO_SIZE;
                     b"Abib",     [0x2C            ], X;
@@ -559,14 +580,17 @@ Ops!(make_opmap;
 ] tzmsk         = [ b"r*v*",     [   9, 0x01      ], 4, AUTO_REXW | XOP_OP  | ENC_VM;
 ] wrfsbase      = [ b"r*",       [0x0F, 0xAE      ], 2, AUTO_REXW | PREF_F3;
 ] wrgsbase      = [ b"r*",       [0x0F, 0xAE      ], 3, AUTO_REXW | PREF_F3;
+] xabort        = [ b"ib",       [0xC6, 0xF8      ], X;
 ] xadd          = [ b"v*r*",     [0x0F, 0xC1      ], X, AUTO_SIZE | LOCK;
                     b"vbrb",     [0x0F, 0xC0      ], X,             LOCK;
+] xbegin        = [ b"o*",       [0xC7, 0xF8      ], X, AUTO_SIZE;
 ] xchg          = [ b"A*r*",     [0x90            ], X, AUTO_SIZE | SHORT_ARG;
                     b"r*A*",     [0x90            ], X, AUTO_SIZE | SHORT_ARG;
                     b"v*r*",     [0x87            ], X, AUTO_SIZE | LOCK;
                     b"r*v*",     [0x87            ], X, AUTO_SIZE | LOCK;
                     b"vbrb",     [0x86            ], X,             LOCK;
                     b"rbvb",     [0x86            ], X,             LOCK;
+] xend          = [ b"",         [0x0F, 0x01, 0xD5], X;
 ] xlatb         = [ b"",         [0xD7            ], X;
 ] xor           = [ b"A*i*",     [0x35            ], X, AUTO_SIZE;
                     b"Abib",     [0x34            ], X;
@@ -577,18 +601,24 @@ Ops!(make_opmap;
                     b"vbrb",     [0x30            ], X,             LOCK;
                     b"r*v*",     [0x33            ], X, AUTO_SIZE;
                     b"rbvb",     [0x32            ], X;
+] xtest         = [ b"",         [0x0F, 0x01, 0xD6], X;
 ]
 // System instructions
   clgi          = [ b"",         [0x0F, 0x01, 0xDD], X;
 ] cli           = [ b"",         [0xFA            ], X;
 ] clts          = [ b"",         [0x0F, 0x06      ], X;
+] clrssbsy      = [ b"mq",       [0x0F, 0xAE      ], 6, PREF_F3;
 ] hlt           = [ b"",         [0xF4            ], X;
+] endbr32       = [ b"",         [0x0F, 0x1E, 0xFB], X, PREF_F3;
+] endbr64       = [ b"",         [0x0F, 0x1E, 0xFA], X, PREF_F3;
 ] int3          = [ b"",         [0xCC            ], X;
+] incsspd       = [ b"rd",       [0x0F, 0xAE      ], 5, PREF_F3;
+] incsspq       = [ b"rq",       [0x0F, 0xAE      ], 5, PREF_F3 | WITH_REXW;
 ] invd          = [ b"",         [0x0F, 0x08      ], X;
 ] invlpg        = [ b"mb",       [0x0F, 0x01      ], 7;
 ] invlpga       = [ b"AqBd",     [0x0F, 0x01, 0xDF], X;
 ] iret          = [ b"",         [0xCF            ], X, WORD_SIZE;
-] iretd         = [ b"",         [0xCF            ], X;
+] iretd         = [ b"",         [0xCF            ], X, DWORD_SIZE;
 ] iretq         = [ b"",         [0xCF            ], X, WITH_REXW;
 ] lar           = [ b"r*vw",     [0x0F, 0x02      ], X, AUTO_SIZE;
 ] lgdt          = [ b"m!",       [0x0F, 0x01      ], 2;
@@ -603,9 +633,14 @@ Ops!(make_opmap;
 ] mwaitx        = [ b"",         [0x0F, 0x01, 0xFB], X;
 ] rdmsr         = [ b"",         [0x0F, 0x32      ], X;
 ] rdpmc         = [ b"",         [0x0F, 0x33      ], X;
+] rdsspd        = [ b"rd",       [0x0F, 0x1E      ], 1, PREF_F3;
+] rdsspq        = [ b"rq",       [0x0F, 0x1E      ], 1, PREF_F3 | WITH_REXW;
 ] rdtsc         = [ b"",         [0x0F, 0x31      ], X;
 ] rdtscp        = [ b"",         [0x0F, 0x01, 0xF9], X;
 ] rsm           = [ b"",         [0x0F, 0xAA      ], X;
+] rstorssp      = [ b"mq",       [0x0F, 0x01      ], 5, PREF_F3;
+] saveprevssp   = [ b"",         [0x0F, 0x01, 0xEA], X, PREF_F3;
+] setssbsy      = [ b"",         [0x0F, 0x01, 0xE8], X, PREF_F3;
 ] sgdt          = [ b"m!",       [0x0F, 0x01      ], 0;
 ] sidt          = [ b"m!",       [0x0F, 0x01      ], 1;
 ] skinit        = [ b"Ad",       [0x0F, 0x01, 0xDE], X;
@@ -631,6 +666,10 @@ Ops!(make_opmap;
 ] vmsave        = [ b"Aq",       [0x0F, 0x01, 0xDB], X;
 ] wbinvd        = [ b"",         [0x0F, 0x09      ], X;
 ] wrmsr         = [ b"",         [0x0F, 0x30      ], X;
+] wrssd         = [ b"mdrd",     [0x0F, 0x38, 0xF6], X;
+] wrssq         = [ b"mqrq",     [0x0F, 0x38, 0xF6], X, WITH_REXW;
+] wrussd        = [ b"mdrd",     [0x0F, 0x38, 0xF5], X, PREF_66;
+] wrussq        = [ b"mqrq",     [0x0F, 0x38, 0xF5], X, PREF_66 | WITH_REXW;
 ]
 // x87 FPU instruction set, d   ta taken from amd's programmer manual vol. 5
   f2xm1         = [ b"",         [0xD9, 0xF0      ], X;
@@ -955,28 +994,38 @@ Ops!(make_opmap;
 
   addpd         = [ b"yowo",     [0x0F, 0x58      ], X, PREF_66;
 ] vaddpd        = [ b"y*y*w*",   [   1, 0x58      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   1, 0x58      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST | EVEX_ER;
 ] addps         = [ b"yowo",     [0x0F, 0x58      ], X;
 ] vaddps        = [ b"y*y*w*",   [   1, 0x58      ], X,           AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   1, 0x58      ], X,           AUTO_VEXL | EVEX_OP | BCST | EVEX_ER;
 ] addsd         = [ b"yoyo",     [0x0F, 0x58      ], X, PREF_F2;
                     b"yomq",     [0x0F, 0x58      ], X, PREF_F2;
 ] vaddsd        = [ b"yoyoyo",   [   1, 0x58      ], X, PREF_F2             | VEX_OP;
                     b"yoyomq",   [   1, 0x58      ], X, PREF_F2             | VEX_OP;
+                    b"yoyoyo",   [   1, 0x58      ], X, PREF_F2             | EVEX_OP | WITH_REXW | EVEX_ER;
+                    b"yoyomq",   [   1, 0x58      ], X, PREF_F2             | EVEX_OP | WITH_REXW;
 ] addss         = [ b"yoyo",     [0x0F, 0x58      ], X, PREF_F3;
                     b"yomd",     [0x0F, 0x58      ], X, PREF_F3;
 ] vaddss        = [ b"yoyoyo",   [   1, 0x58      ], X, PREF_F3             | VEX_OP;
                     b"yoyomd",   [   1, 0x58      ], X, PREF_F3             | VEX_OP;
+                    b"yoyoyo",   [   1, 0x58      ], X, PREF_F3             | EVEX_OP | EVEX_ER;
+                    b"yoyomd",   [   1, 0x58      ], X, PREF_F3             | EVEX_OP;
 ] addsubpd      = [ b"yowo",     [0x0F, 0xD0      ], X, PREF_66;
 ] vaddsubpd     = [ b"y*y*w*",   [   1, 0xD0      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
 ] addsubps      = [ b"yowo",     [0x0F, 0xD0      ], X, PREF_F2;
 ] vaddsubps     = [ b"y*y*w*",   [   1, 0xD0      ], X, PREF_F2 | AUTO_VEXL | VEX_OP;
 ] aesdec        = [ b"yowo",     [0x0F, 0x38, 0xDE], X, PREF_66;
-] vaesdec       = [ b"yoyowo",   [   2, 0xDE      ], X, PREF_66             | VEX_OP;
+] vaesdec       = [ b"y*y*w*",   [   2, 0xDE      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   2, 0xDE      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
 ] aesdeclast    = [ b"yowo",     [0x0F, 0x38, 0xDF], X, PREF_66;
-] vaesdeclast   = [ b"yoyowo",   [   2, 0xDF      ], X, PREF_66             | VEX_OP;
+] vaesdeclast   = [ b"y*y*w*",   [   2, 0xDF      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   2, 0xDF      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
 ] aesenc        = [ b"yowo",     [0x0F, 0x38, 0xDC], X, PREF_66;
-] vaesenc       = [ b"yoyowo",   [   2, 0xDC      ], X, PREF_66             | VEX_OP;
+] vaesenc       = [ b"y*y*w*",   [   2, 0xDC      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   2, 0xDC      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
 ] aesenclast    = [ b"yowo",     [0x0F, 0x38, 0xDD], X, PREF_66;
-] vaesenclast   = [ b"yoyowo",   [   2, 0xDD      ], X, PREF_66             | VEX_OP;
+] vaesenclast   = [ b"y*y*w*",   [   2, 0xDD      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   2, 0xDD      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
 ] aesimc        = [ b"yowo",     [0x0F, 0x38, 0xDB], X, PREF_66;
 ] vaesimc       = [ b"yowo",     [   2, 0xDB      ], X, PREF_66             | VEX_OP;
 ] aeskeygenassist  
@@ -985,12 +1034,16 @@ Ops!(make_opmap;
                 = [ b"yowoib",   [   3, 0xDF      ], X, PREF_66             | VEX_OP;
 ] andnpd        = [ b"yowo",     [0x0F, 0x55      ], X, PREF_66;
 ] vandnpd       = [ b"y*y*w*",   [   1, 0x55      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   1, 0x55      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST;
 ] andnps        = [ b"yowo",     [0x0F, 0x55      ], X;
 ] vandnps       = [ b"y*y*w*",   [   1, 0x55      ], X,           AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   1, 0x55      ], X,           AUTO_VEXL | EVEX_OP | BCST;
 ] andpd         = [ b"yowo",     [0x0F, 0x54      ], X, PREF_66;
 ] vandpd        = [ b"y*y*w*",   [   1, 0x54      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   1, 0x54      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST;
 ] andps         = [ b"yowo",     [0x0F, 0x54      ], X;
 ] vandps        = [ b"y*y*w*",   [   1, 0x54      ], X,           AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   1, 0x54      ], X,           AUTO_VEXL | EVEX_OP | BCST;
 ] blendpd       = [ b"yowoib",   [0x0F, 0x3A, 0x0D], X, PREF_66;
 ] vblendpd      = [ b"y*y*w*ib", [   3, 0x0D      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
 ] blendps       = [ b"yowoib",   [0x0F, 0x3A, 0x0C], X, PREF_66;
@@ -1001,8 +1054,10 @@ Ops!(make_opmap;
 ] vblendvps     = [ b"y*y*w*y*", [   3, 0x4A      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
 ] cmppd         = [ b"yowoib",   [0x0F, 0xC2      ], X, PREF_66;
 ] vcmppd        = [ b"y*y*w*ib", [   1, 0xC2      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"j?y*w*ib", [   1, 0xC2      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST | EVEX_SAE;
 ] cmpps         = [ b"yowoib",   [0x0F, 0xC2      ], X;
 ] vcmpps        = [ b"y*y*w*ib", [   1, 0xC2      ], X,           AUTO_VEXL | VEX_OP;
+                    b"j?y*w*ib", [   1, 0xC2      ], X,           AUTO_VEXL | EVEX_OP | BCST | EVEX_SAE;
 ] // cmpsd is found in generic instructions
   vcmpsd        = [ b"y*y*w*ib", [   1, 0xC2      ], X, PREF_F2 | AUTO_VEXL | VEX_OP;
 ] cmpss         = [ b"yowoib",   [0x0F, 0xC2      ], X, PREF_F3;
@@ -1024,12 +1079,14 @@ Ops!(make_opmap;
                     b"yhmo",     [   1, 0xE6      ], X, PREF_F3 | WITH_VEXL | VEX_OP; // intel/amd disagree over this memory ops size
 ] cvtdq2ps      = [ b"yowo",     [0x0F, 0x5B      ], X;
 ] vcvtdq2ps     = [ b"y*w*",     [   1, 0x5B      ], X,           AUTO_VEXL | VEX_OP;
+                    b"y*w*",     [   1, 0x5B      ], X,           AUTO_VEXL | EVEX_OP | BCST | EVEX_ER;
 ] cvtpd2dq      = [ b"yowo",     [0x0F, 0xE6      ], X, PREF_F2;
 ] vcvtpd2dq     = [ b"y*w*",     [   1, 0xE6      ], X, PREF_F2 | AUTO_VEXL | VEX_OP;
 ] cvtpd2dS      = [ b"yowo",     [0x0F, 0x5A      ], X, PREF_66;
 ] vcvtpd2dS     = [ b"y*w*",     [   1, 0x5A      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
 ] cvtps2dq      = [ b"yowo",     [0x0F, 0x5B      ], X, PREF_66;
 ] vcvtps2dq     = [ b"y*w*",     [   1, 0x5B      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*w*",     [   1, 0x5B      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | BCST | EVEX_ER;
 ] cvtps2pd      = [ b"yoyo",     [0x0F, 0x5A      ], X;
                     b"yomq",     [0x0F, 0x5A      ], X;
 ] vcvtps2pd     = [ b"y*y*",     [   1, 0x5A      ], X,           AUTO_VEXL | VEX_OP;
@@ -1059,6 +1116,7 @@ Ops!(make_opmap;
 ] vcvttpd2dq    = [ b"y*w*",     [   1, 0xE6      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
 ] cvttps2dq     = [ b"yowo",     [0x0F, 0x5B      ], X, PREF_F3;
 ] vcvttps2dq    = [ b"y*w*",     [   1, 0x5B      ], X, PREF_F3 | AUTO_VEXL | VEX_OP;
+                    b"y*w*",     [   1, 0x5B      ], X, PREF_F3 | AUTO_VEXL | EVEX_OP | BCST | EVEX_SAE;
 ] cvttsd2si     = [ b"r*yo",     [0x0F, 0x2C      ], X, PREF_F2 | AUTO_REXW;
                     b"r*mq",     [0x0F, 0x2C      ], X, PREF_F2 | AUTO_REXW;
 ] vcvttsd2si    = [ b"r*yo",     [   1, 0x2C      ], X, PREF_F2 | AUTO_REXW | VEX_OP;
@@ -1071,22 +1129,40 @@ Ops!(make_opmap;
 
   divpd         = [ b"yowo",     [0x0F, 0x5E      ], X, PREF_66;
 ] vdivpd        = [ b"y*y*w*",   [   1, 0x5E      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   1, 0x5E      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST | EVEX_ER;
 ] divps         = [ b"yowo",     [0x0F, 0x5E      ], X;
 ] vdivps        = [ b"y*y*w*",   [   1, 0x5E      ], X,           AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   1, 0x5E      ], X,           AUTO_VEXL | EVEX_OP | BCST | EVEX_ER;
 ] divsd         = [ b"yoyo",     [0x0F, 0x5E      ], X, PREF_F2;
                     b"yomq",     [0x0F, 0x5E      ], X, PREF_F2;
 ] vdivsd        = [ b"yoyoyo",   [   1, 0x5E      ], X, PREF_F2             | VEX_OP;
                     b"yoyomq",   [   1, 0x5E      ], X, PREF_F2             | VEX_OP;
+                    b"yoyoyo",   [   1, 0x5E      ], X, PREF_F2             | EVEX_OP | WITH_REXW | EVEX_ER;
+                    b"yoyomq",   [   1, 0x5E      ], X, PREF_F2             | EVEX_OP | WITH_REXW;
 ] divss         = [ b"yoyo",     [0x0F, 0x5E      ], X, PREF_F3;
                     b"yomd",     [0x0F, 0x5E      ], X, PREF_F3;
 ] vdivss        = [ b"yoyoyo",   [   1, 0x5E      ], X, PREF_F3             | VEX_OP;
                     b"yoyomd",   [   1, 0x5E      ], X, PREF_F3             | VEX_OP;
+                    b"yoyoyo",   [   1, 0x5E      ], X, PREF_F3             | EVEX_OP | EVEX_ER;
+                    b"yoyomd",   [   1, 0x5E      ], X, PREF_F3             | EVEX_OP;
 ] dppd          = [ b"yowoib",   [0x0F, 0x3A, 0x41], X, PREF_66;
 ] vdppd         = [ b"yoyowoib", [   3, 0x41      ], X, PREF_66             | VEX_OP;
 ] dpps          = [ b"yowoib",   [0x0F, 0x3A, 0x40], X, PREF_66;
 ] vdpps         = [ b"y*y*w*ib", [   3, 0x40      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
 ] extractps     = [ b"vwyoib",   [0x0F, 0x3A, 0x17], X, PREF_66;
 ] vextractps    = [ b"vwyoib",   [   3, 0x17      ], X, PREF_66             | VEX_OP;
+] gf2p8affineinvqb
+                = [ b"yowoib",   [0x0F, 0x3A, 0xCF], X, PREF_66;
+] vgf2p8affineinvqb
+                = [ b"y*y*w*ib", [   3, 0xCF      ], X, PREF_66 | AUTO_VEXL | VEX_OP | WITH_REXW;
+                    b"y*y*w*ib", [   3, 0xCF      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST;
+] gf2p8affineqb = [ b"yowoib",   [0x0F, 0x3A, 0xCE], X, PREF_66;
+] vgf2p8affineqb
+                = [ b"y*y*w*ib", [   3, 0xCE      ], X, PREF_66 | AUTO_VEXL | VEX_OP | WITH_REXW;
+                    b"y*y*w*ib", [   3, 0xCE      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST;
+] gf2p8mulb     = [ b"yowo",     [0x0F, 0x38, 0xCF], X, PREF_66;
+] vgf2p8mulb    = [ b"y*y*w*",   [   2, 0xCF      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   2, 0xCF      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
 ] haddpd        = [ b"yowo",     [0x0F, 0x7C      ], X, PREF_66;
 ] vhaddpd       = [ b"y*y*w*",   [   1, 0x7C      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
 ] haddps        = [ b"yowo",     [0x0F, 0x7C      ], X, PREF_F2;
@@ -1105,41 +1181,59 @@ Ops!(make_opmap;
 ] vmaskmovdqu   = [ b"yoyo",     [   1, 0xF7      ], X, PREF_66             | VEX_OP;
 ] maxpd         = [ b"yowo",     [0x0F, 0x5F      ], X, PREF_66;
 ] vmaxpd        = [ b"y*y*w*",   [   1, 0x5F      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   1, 0x5F      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST | EVEX_SAE;
 ] maxps         = [ b"yowo",     [0x0F, 0x5F      ], X;
 ] vmaxps        = [ b"y*y*w*",   [   1, 0x5F      ], X,           AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   1, 0x5F      ], X,           AUTO_VEXL | EVEX_OP | BCST | EVEX_SAE;
 ] maxsd         = [ b"yoyo",     [0x0F, 0x5F      ], X, PREF_F2;
                     b"yomq",     [0x0F, 0x5F      ], X, PREF_F2;
 ] vmaxsd        = [ b"yoyoyo",   [   1, 0x5F      ], X, PREF_F2             | VEX_OP;
                     b"yoyomq",   [   1, 0x5F      ], X, PREF_F2             | VEX_OP;
+                    b"yoyoyo",   [   1, 0x5F      ], X, PREF_F2             | EVEX_OP | WITH_REXW | EVEX_SAE;
+                    b"yoyomq",   [   1, 0x5F      ], X, PREF_F2             | EVEX_OP | WITH_REXW;
 ] maxss         = [ b"yoyo",     [0x0F, 0x5F      ], X, PREF_F3;
                     b"yomd",     [0x0F, 0x5F      ], X, PREF_F3;
 ] vmaxss        = [ b"yoyoyo",   [   1, 0x5F      ], X, PREF_F3             | VEX_OP;
                     b"yoyomd",   [   1, 0x5F      ], X, PREF_F3             | VEX_OP;
+                    b"yoyoyo",   [   1, 0x5F      ], X, PREF_F3             | EVEX_OP | EVEX_SAE;
+                    b"yoyomd",   [   1, 0x5F      ], X, PREF_F3             | EVEX_OP;
 ] minpd         = [ b"yowo",     [0x0F, 0x5D      ], X, PREF_66;
 ] vminpd        = [ b"y*y*w*",   [   1, 0x5D      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   1, 0x5D      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST | EVEX_SAE;
 ] minps         = [ b"yowo",     [0x0F, 0x5D      ], X;
 ] vminps        = [ b"y*y*w*",   [   1, 0x5D      ], X,           AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   1, 0x5D      ], X,           AUTO_VEXL | EVEX_OP | BCST | EVEX_SAE;
 ] minsd         = [ b"yoyo",     [0x0F, 0x5D      ], X, PREF_F2;
                     b"yomq",     [0x0F, 0x5D      ], X, PREF_F2;
 ] vminsd        = [ b"yoyoyo",   [   1, 0x5D      ], X, PREF_F2             | VEX_OP;
                     b"yoyomq",   [   1, 0x5D      ], X, PREF_F2             | VEX_OP;
+                    b"yoyoyo",   [   1, 0x5D      ], X, PREF_F2             | EVEX_OP | WITH_REXW | EVEX_SAE;
+                    b"yoyomq",   [   1, 0x5D      ], X, PREF_F2             | EVEX_OP | WITH_REXW;
 ] minss         = [ b"yoyo",     [0x0F, 0x5D      ], X, PREF_F3;
                     b"yomd",     [0x0F, 0x5D      ], X, PREF_F3;
 ] vminss        = [ b"yoyoyo",   [   1, 0x5D      ], X, PREF_F3             | VEX_OP;
                     b"yoyomd",   [   1, 0x5D      ], X, PREF_F3             | VEX_OP;
+                    b"yoyoyo",   [   1, 0x5D      ], X, PREF_F3             | EVEX_OP | EVEX_SAE;
+                    b"yoyomd",   [   1, 0x5D      ], X, PREF_F3             | EVEX_OP;
 ]
 
   movapd        = [ b"yowo",     [0x0F, 0x28      ], X, PREF_66;
                     b"woyo",     [0x0F, 0x29      ], X, PREF_66;
 ] vmovapd       = [ b"y*w*",     [   1, 0x28      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
                     b"w*y*",     [   1, 0x29      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*w*",     [   1, 0x28      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW;
+                    b"w*y*",     [   1, 0x29      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW;
 ] movaps        = [ b"yowo",     [0x0F, 0x28      ], X;
                     b"woyo",     [0x0F, 0x29      ], X;
 ] vmovaps       = [ b"y*w*",     [   1, 0x28      ], X,           AUTO_VEXL | VEX_OP;
                     b"w*y*",     [   1, 0x29      ], X,           AUTO_VEXL | VEX_OP;
+                    b"y*w*",     [   1, 0x28      ], X,           AUTO_VEXL | EVEX_OP;
+                    b"w*y*",     [   1, 0x29      ], X,           AUTO_VEXL | EVEX_OP;
 ] // movd is found under the general purpose instructions
   vmovd         = [ b"yov*",     [   1, 0x6E      ], X, PREF_66 | AUTO_REXW | VEX_OP;
                     b"v*yo",     [   1, 0x7E      ], X, PREF_66 | AUTO_REXW | VEX_OP;
+                    b"yov*",     [   1, 0x6E      ], X, PREF_66 | AUTO_REXW | EVEX_OP;
+                    b"v*yo",     [   1, 0x7E      ], X, PREF_66 | AUTO_REXW | EVEX_OP;
 ] movddup       = [ b"yoyo",     [0x0F, 0x12      ], X, PREF_F2;
                     b"yomq",     [0x0F, 0x12      ], X, PREF_F2;
 ] vmovddup      = [ b"y*y*",     [   1, 0x12      ], X, PREF_F2 | AUTO_VEXL | VEX_OP;
@@ -1191,6 +1285,9 @@ Ops!(make_opmap;
 ] vmovq         = [ b"yoyo",     [   1, 0x7E      ], X, PREF_F3             | VEX_OP;
                     b"yomq",     [   1, 0x7E      ], X, PREF_F3             | VEX_OP;
                     b"mqyo",     [   1, 0xD6      ], X, PREF_66             | VEX_OP;
+                    b"yoyo",     [   1, 0x7E      ], X, PREF_F3             | EVEX_OP | WITH_REXW;
+                    b"yomq",     [   1, 0x7E      ], X, PREF_F3             | EVEX_OP | WITH_REXW;
+                    b"mqyo",     [   1, 0xD6      ], X, PREF_66             | EVEX_OP | WITH_REXW;
   // movsd variants can be found in the general purpose section
 ] vmovsd        = [ b"yoyoyo",   [   1, 0x10      ], X, PREF_F2             | VEX_OP; // distinguished from the others by addressing bits
                     b"yomq",     [   1, 0x10      ], X, PREF_F2             | VEX_OP;
@@ -1209,36 +1306,51 @@ Ops!(make_opmap;
                     b"woyo",     [0x0F, 0x11      ], X, PREF_66;
 ] vmovupd       = [ b"y*w*",     [   1, 0x10      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
                     b"w*y*",     [   1, 0x11      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*w*",     [   1, 0x10      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW;
+                    b"w*y*",     [   1, 0x11      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW;
 ] movups        = [ b"yowo",     [0x0F, 0x10      ], X;
                     b"woyo",     [0x0F, 0x11      ], X;
 ] vmovups       = [ b"y*w*",     [   1, 0x10      ], X,           AUTO_VEXL | VEX_OP;
                     b"w*y*",     [   1, 0x11      ], X,           AUTO_VEXL | VEX_OP;
+                    b"y*w*",     [   1, 0x10      ], X,           AUTO_VEXL | EVEX_OP;
+                    b"w*y*",     [   1, 0x11      ], X,           AUTO_VEXL | EVEX_OP;
 ]
 // and we're done with mov ins.
   mpsadbw       = [ b"yowoib",   [0x0F, 0x3A, 0x42], X, PREF_66;
 ] vmpsadbw      = [ b"y*y*w*ib", [   3, 0x42      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
 ] mulpd         = [ b"yowo",     [0x0F, 0x59      ], X, PREF_66;
 ] vmulpd        = [ b"y*y*w*",   [   1, 0x59      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   1, 0x59      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST | EVEX_ER;
 ] mulps         = [ b"yowo",     [0x0F, 0x59      ], X;
 ] vmulps        = [ b"y*y*w*",   [   1, 0x59      ], X,           AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   1, 0x59      ], X,           AUTO_VEXL | EVEX_OP | BCST | EVEX_ER;
 ] mulsd         = [ b"yoyo",     [0x0F, 0x59      ], X, PREF_F2;
                     b"yomq",     [0x0F, 0x59      ], X, PREF_F2;
 ] vmulsd        = [ b"yoyoyo",   [   1, 0x59      ], X, PREF_F2             | VEX_OP;
                     b"yoyomq",   [   1, 0x59      ], X, PREF_F2             | VEX_OP;
+                    b"yoyoyo",   [   1, 0x59      ], X, PREF_F2             | EVEX_OP | WITH_REXW | EVEX_ER;
+                    b"yoyomq",   [   1, 0x59      ], X, PREF_F2             | EVEX_OP | WITH_REXW;
 ] mulss         = [ b"yoyo",     [0x0F, 0x59      ], X, PREF_F3;
                     b"yomd",     [0x0F, 0x59      ], X, PREF_F3;
 ] vmulss        = [ b"yoyoyo",   [   1, 0x59      ], X, PREF_F3             | VEX_OP;
                     b"yoyomd",   [   1, 0x59      ], X, PREF_F3             | VEX_OP;
+                    b"yoyoyo",   [   1, 0x59      ], X, PREF_F3             | EVEX_OP | EVEX_ER;
+                    b"yoyomd",   [   1, 0x59      ], X, PREF_F3             | EVEX_OP;
 ] orpd          = [ b"yowo",     [0x0F, 0x56      ], X, PREF_66;
 ] vorpd         = [ b"y*y*w*",   [   1, 0x56      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   1, 0x56      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST;
 ] orps          = [ b"yowo",     [0x0F, 0x56      ], X;
 ] vorps         = [ b"y*y*w*",   [   1, 0x56      ], X,           AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   1, 0x56      ], X,           AUTO_VEXL | EVEX_OP | BCST;
 ] pabsb         = [ b"yowo",     [0x0F, 0x38, 0x1C], X;
 ] vpabsb        = [ b"y*w*",     [   2, 0x1C      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*w*",     [   2, 0x1C      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
 ] pabsd         = [ b"yowo",     [0x0F, 0x38, 0x1E], X;
 ] vpabsd        = [ b"y*w*",     [   2, 0x1E      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*w*",     [   2, 0x1E      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | BCST;
 ] pabsw         = [ b"yowo",     [0x0F, 0x38, 0x1D], X;
 ] vpabsw        = [ b"y*w*",     [   2, 0x1D      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*w*",     [   2, 0x1D      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
 ] // packssdw is found in the MMX section
   vpackssdw     = [ b"y*y*w*",   [   1, 0x6B      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
 ] // same for packsswb
@@ -1249,46 +1361,61 @@ Ops!(make_opmap;
   vpackuswb     = [ b"y*y*w*",   [   1, 0x67      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
 ] // and all legacy padd forms
   vpaddb        = [ b"y*y*w*",   [   1, 0xFC      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   1, 0xFC      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
 ] vpaddd        = [ b"y*y*w*",   [   1, 0xFE      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   1, 0xFE      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | BCST;
 ] vpaddq        = [ b"y*y*w*",   [   1, 0xD4      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   1, 0xD4      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST;
 ] vpaddsb       = [ b"y*y*w*",   [   1, 0xEC      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
 ] vpaddsw       = [ b"y*y*w*",   [   1, 0xED      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
 ] vpaddusb      = [ b"y*y*w*",   [   1, 0xDC      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
 ] vpaddusw      = [ b"y*y*w*",   [   1, 0xDD      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
 ] vpaddw        = [ b"y*y*w*",   [   1, 0xFD      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   1, 0xFD      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
 ] palign        = [ b"y*w*ib",   [0x0F, 0x3A, 0x0F], X, PREF_66;
 ] vpalign       = [ b"y*y*w*ib", [   3, 0x0F      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
 ] // pand/pandn/pavg are also in the MMX section
   vpand         = [ b"y*y*w*",   [   1, 0xDB      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
 ] vpandn        = [ b"y*y*w*",   [   1, 0xDF      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
 ] vpavgb        = [ b"y*y*w*",   [   1, 0xE0      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   1, 0xE0      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
 ] vpavgw        = [ b"y*y*w*",   [   1, 0xE3      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   1, 0xE3      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
 ] pblendvb      = [ b"yowo",     [0x0F, 0x38, 0x10], X, PREF_66;
 ] vpblendvb     = [ b"y*y*w*y*", [   3, 0x4C      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
 ] pblenddw      = [ b"yowoib",   [0x0F, 0x3A, 0x0E], X, PREF_66;
 ] vpblenddw     = [ b"y*y*w*ib", [   3, 0x0E      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
 ] pclmulqdq     = [ b"yowoib",   [0x0F, 0x3A, 0x44], X, PREF_66;
-] vpclmulqdq    = [ b"yoyowoib", [   3, 0x44      ], X, PREF_66             | VEX_OP;
+] vpclmulqdq    = [ b"y*y*w*ib", [   3, 0x44      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*ib", [   3, 0x44      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
 ] // pcmpeqb is in the MMX section
   vpcmpeqb      = [ b"y*y*w*",   [   1, 0x74      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"j?y*w*",   [   1, 0x74      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
 ] // pcmpeqd is in the MMX section
   vpcmpeqd      = [ b"y*y*w*",   [   1, 0x76      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"j?y*w*",   [   1, 0x76      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | BCST;
 ] pcmpeqq       = [ b"yowo",     [0x0F, 0x38, 0x29], X, PREF_66;
 ] vpcmpeqq      = [ b"y*y*w*",   [   2, 0x29      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"j?y*w*",   [   2, 0x29      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST;
 ] // pcmpeqw is in the MMX section
   vpcmpeqw      = [ b"y*y*w*",   [   1, 0x75      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"j?y*w*",   [   1, 0x75      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
 ] pcmpestri     = [ b"yowoib",   [0x0F, 0x3A, 0x61], X, PREF_66;
 ] vpcmpestri    = [ b"yowoib",   [   3, 0x61      ], X, PREF_66             | VEX_OP;
 ] pcmpestrm     = [ b"yowoib",   [0x0F, 0x3A, 0x60], X, PREF_66;
 ] vpcmpestrm    = [ b"yowoib",   [   3, 0x60      ], X, PREF_66             | VEX_OP;
 ] // pcmpgtb is in the MMX section
   vpcmpgtb      = [ b"y*y*w*",   [   1, 0x64      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"j?y*w*",   [   1, 0x64      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
 ] // pcmpgtd is in the MMX section
   vpcmpgtd      = [ b"y*y*w*",   [   1, 0x66      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"j?y*w*",   [   1, 0x66      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | BCST;
 ] pcmpgtq       = [ b"yowo",     [0x0F, 0x38, 0x37], X, PREF_66;
 ] vpcmpgtq      = [ b"y*y*w*",   [   2, 0x37      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"j?y*w*",   [   2, 0x37      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST;
 ] // pcmpgtw is in the MMX section
   vpcmpgtw      = [ b"y*y*w*",   [   1, 0x65      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"j?y*w*",   [   1, 0x65      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
 ] pcmpistri     = [ b"yowoib",   [0x0F, 0x3A, 0x63], X, PREF_66;
 ] vpcmpistri    = [ b"yowoib",   [   3, 0x63      ], X, PREF_66             | VEX_OP;
 ] pcmpistrm     = [ b"yowoib",   [0x0F, 0x3A, 0x62], X, PREF_66;
@@ -1331,18 +1458,23 @@ Ops!(make_opmap;
                     b"yomwyoib", [   1, 0xC4      ], X, PREF_66             | VEX_OP;
 ] pmaddubsw     = [ b"yowo",     [0x0F, 0x38, 0x04], X, PREF_66;
 ] vpmaddubsw    = [ b"y*y*w*",   [   2, 0x04      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   2, 0x04      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
 ] // pmaddwd is in the MMX section
   vpmaddwd      = [ b"y*y*w*",   [   1, 0xF5      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   1, 0xF5      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
 ] pmaxsb        = [ b"yowo",     [0x0F, 0x38, 0x3C], X, PREF_66;
 ] vpmaxsb       = [ b"y*y*w*",   [   2, 0x3C      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
 ] pmaxsd        = [ b"yowo",     [0x0F, 0x38, 0x3D], X, PREF_66;
 ] vpmaxsd       = [ b"y*y*w*",   [   2, 0x3D      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   2, 0x3D      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | BCST;
 ] // pmaxsw is in the MMX section
   vpmaxsw       = [ b"y*y*w*",   [   1, 0xEE      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
 ] // pmaxub is in the MMX section
   vpmaxub       = [ b"y*y*w*",   [   1, 0xDE      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   1, 0xDE      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
 ] pmaxud        = [ b"yowo",     [0x0F, 0x38, 0x3F], X, PREF_66;
 ] vpmaxud       = [ b"y*y*w*",   [   2, 0x3F      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   2, 0x3F      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | BCST;
 ] pmaxuw        = [ b"yowo",     [0x0F, 0x38, 0x3E], X, PREF_66;
 ] vpmaxuw       = [ b"y*y*w*",   [   2, 0x3E      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
 ]
@@ -1350,12 +1482,15 @@ Ops!(make_opmap;
 ] vpminsb       = [ b"y*y*w*",   [   2, 0x38      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
 ] pminsd        = [ b"yowo",     [0x0F, 0x38, 0x39], X, PREF_66;
 ] vpminsd       = [ b"y*y*w*",   [   2, 0x39      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   2, 0x39      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | BCST;
 ] // pminsw is in the MMX section
   vpminsw       = [ b"y*y*w*",   [   1, 0xEA      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
 ] // pminub is in the MMX section
   vpminub       = [ b"y*y*w*",   [   1, 0xDA      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   1, 0xDA      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
 ] pminud        = [ b"yowo",     [0x0F, 0x38, 0x3B], X, PREF_66;
 ] vpminud       = [ b"y*y*w*",   [   2, 0x3B      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   2, 0x3B      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | BCST;
 ] pminuw        = [ b"yowo",     [0x0F, 0x38, 0x3A], X, PREF_66;
 ] vpminuw       = [ b"y*y*w*",   [   2, 0x3A      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
 ]
@@ -1425,6 +1560,7 @@ Ops!(make_opmap;
 ] // and back to arithmetric
   pmuldq        = [ b"yowo",     [0x0F, 0x38, 0x28], X, PREF_66;
 ] vpmuldq       = [ b"y*y*w*",   [   2, 0x28      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   2, 0x28      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST;
 ] pmulhrsw      = [ b"yowo",     [0x0F, 0x38, 0x0B], X, PREF_66;
 ] vpmulhrsw     = [ b"y*y*w*",   [   2, 0x0B      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
 ] // legacy form is in the MMX section
@@ -1433,18 +1569,23 @@ Ops!(make_opmap;
   vpmulhw       = [ b"y*y*w*",   [   1, 0xE5      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
 ] pmulld        = [ b"yowo",     [0x0F, 0x38, 0x40], X, PREF_66;
 ] vpmulld       = [ b"y*y*w*",   [   2, 0x40      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   2, 0x40      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | BCST;
 ] // legacy form is in the MMX section
   vpmullw       = [ b"y*y*w*",   [   1, 0xD5      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   1, 0xD5      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
 ] // legacy form is in the MMX section
   vpmuludq      = [ b"y*y*w*",   [   1, 0xF4      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   1, 0xF4      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST;
 ] // legacy form is in the MMX section
   vpor          = [ b"y*y*w*",   [   1, 0xEB      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
 ] // legacy form is in the MMX section
   vpsadbw       = [ b"y*y*w*",   [   1, 0xF6      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
 ] pshufb        = [ b"yowo",     [0x0F, 0x38, 0x00], X, PREF_66;
 ] vpshufb       = [ b"y*y*w*",   [   2, 0x00      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   2, 0x00      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
 ] pshufd        = [ b"yowoib",   [0x0F, 0x70      ], X, PREF_66;
 ] vpshufd       = [ b"y*w*ib",   [   1, 0x70      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*w*ib",   [   1, 0x70      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | BCST;
 ] // legacy form is in the MMX section
   vpshufw       = [ b"y*w*ib",   [   1, 0x70      ], X, PREF_F3 | AUTO_VEXL | VEX_OP;
 ] pshuflw       = [ b"yowoib",   [0x0F, 0x70      ], X, PREF_F2;
@@ -1458,46 +1599,74 @@ Ops!(make_opmap;
 ] // Legacy forms of the shift instructions are in the MMX section
   vpslld        = [ b"y*y*wo",   [   1, 0xF2      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
                     b"y*y*ib",   [   1, 0x72      ], 6, PREF_66 | AUTO_VEXL | VEX_OP | ENC_VM;
+                    b"y*y*wo",   [   1, 0xF2      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
+                    b"y*w*ib",   [   1, 0x72      ], 6, PREF_66 | AUTO_VEXL | EVEX_OP | BCST | ENC_VM;
 ] pslldq        = [ b"yoib",     [0x0F, 0x73      ], 7, PREF_66;
 ] vpslldq       = [ b"y*y*ib",   [   1, 0x73      ], 7, PREF_66 | AUTO_VEXL | VEX_OP | ENC_VM;
 ] vpsllq        = [ b"y*y*wo",   [   1, 0xF3      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
                     b"y*y*ib",   [   1, 0x73      ], 6, PREF_66 | AUTO_VEXL | VEX_OP | ENC_VM;
+                    b"y*y*wo",   [   1, 0xF3      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW;
+                    b"y*w*ib",   [   1, 0x73      ], 6, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST | ENC_VM;
 ] vpsllw        = [ b"y*y*wo",   [   1, 0xF1      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
                     b"y*y*ib",   [   1, 0x71      ], 6, PREF_66 | AUTO_VEXL | VEX_OP | ENC_VM;
+                    b"y*y*wo",   [   1, 0xF1      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
+                    b"y*w*ib",   [   1, 0x71      ], 6, PREF_66 | AUTO_VEXL | EVEX_OP | ENC_VM;
 ] vpsrad        = [ b"y*y*wo",   [   1, 0xE2      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
                     b"y*y*ib",   [   1, 0x72      ], 4, PREF_66 | AUTO_VEXL | VEX_OP | ENC_VM;
+                    b"y*y*wo",   [   1, 0xE2      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
+                    b"y*w*ib",   [   1, 0x72      ], 4, PREF_66 | AUTO_VEXL | EVEX_OP | BCST | ENC_VM;
 ] vpsraw        = [ b"y*y*wo",   [   1, 0xE1      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
                     b"y*y*ib",   [   1, 0x71      ], 4, PREF_66 | AUTO_VEXL | VEX_OP | ENC_VM;
+                    b"y*y*wo",   [   1, 0xE1      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
+                    b"y*w*ib",   [   1, 0x71      ], 4, PREF_66 | AUTO_VEXL | EVEX_OP | ENC_VM;
 ] vpsrld        = [ b"y*y*wo",   [   1, 0xD2      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
                     b"y*y*ib",   [   1, 0x72      ], 2, PREF_66 | AUTO_VEXL | VEX_OP | ENC_VM;
+                    b"y*y*wo",   [   1, 0xD2      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
+                    b"y*w*ib",   [   1, 0x72      ], 2, PREF_66 | AUTO_VEXL | EVEX_OP | BCST | ENC_VM;
 ] psrldq        = [ b"yoib",     [0x0F, 0x73      ], 3, PREF_66;
 ] vpsrldq       = [ b"y*y*ib",   [   1, 0x73      ], 3, PREF_66 | AUTO_VEXL | VEX_OP | ENC_VM;
 ] vpsrlq        = [ b"y*y*wo",   [   1, 0xD3      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
                     b"y*y*ib",   [   1, 0x73      ], 2, PREF_66 | AUTO_VEXL | VEX_OP | ENC_VM;
+                    b"y*y*wo",   [   1, 0xD3      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW;
+                    b"y*w*ib",   [   1, 0x73      ], 2, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST | ENC_VM;
 ] vpsrlw        = [ b"y*y*wo",   [   1, 0xD1      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
                     b"y*y*ib",   [   1, 0x71      ], 2, PREF_66 | AUTO_VEXL | VEX_OP | ENC_VM;
+                    b"y*y*wo",   [   1, 0xD1      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
+                    b"y*w*ib",   [   1, 0x71      ], 2, PREF_66 | AUTO_VEXL | EVEX_OP | ENC_VM;
 ] // legacy padd forms are in the MMX section
   vpsubb        = [ b"y*y*w*",   [   1, 0xF8      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   1, 0xF8      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
 ] vpsubd        = [ b"y*y*w*",   [   1, 0xFA      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   1, 0xFA      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | BCST;
 ] vpsubq        = [ b"y*y*w*",   [   1, 0xFB      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   1, 0xFB      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST;
 ] vpsubsb       = [ b"y*y*w*",   [   1, 0xE8      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
 ] vpsubsw       = [ b"y*y*w*",   [   1, 0xE9      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
 ] vpsubusb      = [ b"y*y*w*",   [   1, 0xD8      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
 ] vpsubusw      = [ b"y*y*w*",   [   1, 0xD9      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
 ] vpsubw        = [ b"y*y*w*",   [   1, 0xF9      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   1, 0xF9      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
 ] ptest         = [ b"yowo",     [0x0F, 0x38, 0x17], X, PREF_66;
 ] vptest        = [ b"y*w*",     [   2, 0x17      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
 ] // legacy punpck forms too
   vpunpckhbw    = [ b"y*y*w*",   [   1, 0x68      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   1, 0x68      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
 ] vpunpckhdq    = [ b"y*y*w*",   [   1, 0x6A      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   1, 0x6A      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | BCST;
 ] punpckhqdq    = [ b"yowo",     [0x0F, 0x6D      ], X, PREF_66;
 ] vpunpckhqdq   = [ b"y*y*w*",   [   1, 0x6D      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   1, 0x6D      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST;
 ] vpunpckhwd    = [ b"y*y*w*",   [   1, 0x69      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   1, 0x69      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
 ] vpunpcklbw    = [ b"y*y*w*",   [   1, 0x60      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   1, 0x60      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
 ] vpunpckldq    = [ b"y*y*w*",   [   1, 0x62      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   1, 0x62      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | BCST;
 ] punpcklqdq    = [ b"yowo",     [0x0F, 0x6C      ], X, PREF_66;
 ] vpunpcklqdq   = [ b"y*y*w*",   [   1, 0x6C      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   1, 0x6C      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST;
 ] vpunpcklwd    = [ b"y*y*w*",   [   1, 0x61      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   1, 0x61      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
 ] // pxor is in the MMX section too
   vpxor         = [ b"y*y*w*",   [   1, 0xEF      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
 ] rcpps         = [ b"yowo",     [0x0F, 0x53      ], X;
@@ -1526,32 +1695,51 @@ Ops!(make_opmap;
 ] vshufpd       = [ b"y*y*w*ib", [   1, 0xC6      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
 ] shufps        = [ b"yowoib",   [0x0F, 0xC6      ], X;
 ] vshufps       = [ b"y*y*w*ib", [   1, 0xC6      ], X,           AUTO_VEXL | VEX_OP;
+] sha1msg1      = [ b"yowo",     [0x0F, 0x38, 0xC9], X;
+] sha1msg2      = [ b"yowo",     [0x0F, 0x38, 0xCA], X;
+] sha1nexte     = [ b"yowo",     [0x0F, 0x38, 0xC8], X;
+] sha1rnds4     = [ b"yowoib",   [0x0F, 0x3A, 0xCC], X;
+] sha256msg1    = [ b"yowo",     [0x0F, 0x38, 0xCC], X;
+] sha256msg2    = [ b"yowo",     [0x0F, 0x38, 0xCD], X;
+] sha256rnds2   = [ b"yowo",     [0x0F, 0x38, 0xCB], X; // xmm0 is an implicit operand
 ] sqrtpd        = [ b"yowo",     [0x0F, 0x51      ], X, PREF_66;
 ] vsqrtpd       = [ b"y*w*",     [   1, 0x51      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*w*",     [   1, 0x51      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST | EVEX_ER;
 ] sqrtps        = [ b"yowo",     [0x0F, 0x51      ], X;
 ] vsqrtps       = [ b"y*w*",     [   1, 0x51      ], X,           AUTO_VEXL | VEX_OP;
+                    b"y*w*",     [   1, 0x51      ], X,           AUTO_VEXL | EVEX_OP | BCST | EVEX_ER;
 ] sqrtsd        = [ b"yoyo",     [0x0F, 0x51      ], X, PREF_F2;
                     b"yomq",     [0x0F, 0x51      ], X, PREF_F2;
 ] vsqrtsd       = [ b"yoyoyo",   [   1, 0x51      ], X, PREF_F2             | VEX_OP;
                     b"yoyomq",   [   1, 0x51      ], X, PREF_F2             | VEX_OP;
+                    b"yoyoyo",   [   1, 0x51      ], X, PREF_F2             | EVEX_OP | WITH_REXW | EVEX_ER;
+                    b"yoyomq",   [   1, 0x51      ], X, PREF_F2             | EVEX_OP | WITH_REXW;
 ] sqrtss        = [ b"yoyo",     [0x0F, 0x51      ], X, PREF_F3;
                     b"yomd",     [0x0F, 0x51      ], X, PREF_F3;
 ] vsqrtss       = [ b"yoyoyo",   [   1, 0x51      ], X, PREF_F3             | VEX_OP;
                     b"yoyomd",   [   1, 0x51      ], X, PREF_F3             | VEX_OP;
+                    b"yoyoyo",   [   1, 0x51      ], X, PREF_F3             | EVEX_OP | EVEX_ER;
+                    b"yoyomd",   [   1, 0x51      ], X, PREF_F3             | EVEX_OP;
 ] stmxcsr       = [ b"md",       [0x0F, 0xAE      ], 3;
 ] vstmxcsr      = [ b"md",       [   1, 0xAE      ], 3;
 ] subpd         = [ b"yowo",     [0x0F, 0x5C      ], X, PREF_66;
 ] vsubpd        = [ b"y*y*w*",   [   1, 0x5C      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   1, 0x5C      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST | EVEX_ER;
 ] subps         = [ b"yowo",     [0x0F, 0x5C      ], X;
 ] vsubps        = [ b"y*y*w*",   [   1, 0x5C      ], X,           AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   1, 0x5C      ], X,           AUTO_VEXL | EVEX_OP | BCST | EVEX_ER;
 ] subsd         = [ b"yoyo",     [0x0F, 0x5C      ], X, PREF_F2;
                     b"yomq",     [0x0F, 0x5C      ], X, PREF_F2;
 ] vsubsd        = [ b"yoyoyo",   [   1, 0x5C      ], X, PREF_F2             | VEX_OP;
                     b"yoyomq",   [   1, 0x5C      ], X, PREF_F2             | VEX_OP;
+                    b"yoyoyo",   [   1, 0x5C      ], X, PREF_F2             | EVEX_OP | WITH_REXW | EVEX_ER;
+                    b"yoyomq",   [   1, 0x5C      ], X, PREF_F2             | EVEX_OP | WITH_REXW;
 ] subss         = [ b"yoyo",     [0x0F, 0x5C      ], X, PREF_F3;
                     b"yomd",     [0x0F, 0x5C      ], X, PREF_F3;
 ] vsubss        = [ b"yoyoyo",   [   1, 0x5C      ], X, PREF_F3             | VEX_OP;
                     b"yoyomd",   [   1, 0x5C      ], X, PREF_F3             | VEX_OP;
+                    b"yoyoyo",   [   1, 0x5C      ], X, PREF_F3             | EVEX_OP | EVEX_ER;
+                    b"yoyomd",   [   1, 0x5C      ], X, PREF_F3             | EVEX_OP;
 ] ucomisd       = [ b"yoyo",     [0x0F, 0x2E      ], X, PREF_66;
                     b"yomq",     [0x0F, 0x2E      ], X, PREF_66;
 ] vucomisd      = [ b"yoyoyo",   [   1, 0x2E      ], X, PREF_66             | VEX_OP;
@@ -1575,8 +1763,12 @@ Ops!(make_opmap;
                 = [ b"yhmo",     [   2, 0x5A      ], X, PREF_66 | WITH_VEXL | VEX_OP;
 ] vbroadcastsd  = [ b"yhyo",     [   2, 0x19      ], X, PREF_66 | WITH_VEXL | VEX_OP;
                     b"yhmq",     [   2, 0x19      ], X, PREF_66 | WITH_VEXL | VEX_OP;
+                    b"y*yo",     [   2, 0x19      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW;
+                    b"y*mq",     [   2, 0x19      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW;
 ] vbroadcastss  = [ b"y*yo",     [   2, 0x18      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
                     b"y*md",     [   2, 0x18      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*yo",     [   2, 0x18      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
+                    b"y*md",     [   2, 0x18      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
 ] vcvtph2ps     = [ b"y*yo",     [   2, 0x13      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
                     b"yomq",     [   2, 0x13      ], X, PREF_66             | VEX_OP;
                     b"yhmo",     [   2, 0x13      ], X, PREF_66 | WITH_VEXL | VEX_OP;
@@ -1588,31 +1780,49 @@ Ops!(make_opmap;
 ] vfmaddpd      = [ b"y*y*w*y*", [   3, 0x69      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
                     b"y*y*y*w*", [   3, 0x69      ], X, PREF_66 | AUTO_VEXL | VEX_OP | WITH_REXW;
 ] vfmadd132pd   = [ b"y*y*w*",   [   2, 0x98      ], X, PREF_66 | AUTO_VEXL | VEX_OP | WITH_REXW;
+                    b"y*y*w*",   [   2, 0x98      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST | EVEX_ER;
 ] vfmadd213pd   = [ b"y*y*w*",   [   2, 0xA8      ], X, PREF_66 | AUTO_VEXL | VEX_OP | WITH_REXW;
+                    b"y*y*w*",   [   2, 0xA8      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST | EVEX_ER;
 ] vfmadd231pd   = [ b"y*y*w*",   [   2, 0xB8      ], X, PREF_66 | AUTO_VEXL | VEX_OP | WITH_REXW;
+                    b"y*y*w*",   [   2, 0xB8      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST | EVEX_ER;
 ] vfmaddps      = [ b"y*y*w*y*", [   3, 0x68      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
                     b"y*y*y*w*", [   3, 0x68      ], X, PREF_66 | AUTO_VEXL | VEX_OP | WITH_REXW;
 ] vfmadd132ps   = [ b"y*y*w*",   [   2, 0x98      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   2, 0x98      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | BCST | EVEX_ER;
 ] vfmadd213ps   = [ b"y*y*w*",   [   2, 0xA8      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   2, 0xA8      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | BCST | EVEX_ER;
 ] vfmadd231ps   = [ b"y*y*w*",   [   2, 0xB8      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   2, 0xB8      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | BCST | EVEX_ER;
 ] vfmaddsd      = [ b"yoyoyoyo", [   3, 0x6B      ], X, PREF_66             | VEX_OP | WITH_REXW;
                     b"yoyoyomq", [   3, 0x6B      ], X, PREF_66             | VEX_OP | WITH_REXW;
                     b"yoyomqyo", [   3, 0x6B      ], X, PREF_66             | VEX_OP;
 ] vfmadd132sd   = [ b"yoyoyo",   [   2, 0x99      ], X, PREF_66             | VEX_OP | WITH_REXW;
                     b"yoyomq",   [   2, 0x99      ], X, PREF_66             | VEX_OP | WITH_REXW;
+                    b"yoyoyo",   [   2, 0x99      ], X, PREF_66             | EVEX_OP | WITH_REXW | EVEX_ER;
+                    b"yoyomq",   [   2, 0x99      ], X, PREF_66             | EVEX_OP | WITH_REXW;
 ] vfmadd213sd   = [ b"yoyoyo",   [   2, 0xA9      ], X, PREF_66             | VEX_OP | WITH_REXW;
                     b"yoyomq",   [   2, 0xA9      ], X, PREF_66             | VEX_OP | WITH_REXW;
+                    b"yoyoyo",   [   2, 0xA9      ], X, PREF_66             | EVEX_OP | WITH_REXW | EVEX_ER;
+                    b"yoyomq",   [   2, 0xA9      ], X, PREF_66             | EVEX_OP | WITH_REXW;
 ] vfmadd231sd   = [ b"yoyoyo",   [   2, 0xB9      ], X, PREF_66             | VEX_OP | WITH_REXW;
                     b"yoyomq",   [   2, 0xB9      ], X, PREF_66             | VEX_OP | WITH_REXW;
+                    b"yoyoyo",   [   2, 0xB9      ], X, PREF_66             | EVEX_OP | WITH_REXW | EVEX_ER;
+                    b"yoyomq",   [   2, 0xB9      ], X, PREF_66             | EVEX_OP | WITH_REXW;
 ] vfmaddss      = [ b"yoyoyoyo", [   3, 0x6A      ], X, PREF_66             | VEX_OP | WITH_REXW;
                     b"yoyoyomq", [   3, 0x6A      ], X, PREF_66             | VEX_OP | WITH_REXW;
                     b"yoyomqyo", [   3, 0x6A      ], X, PREF_66             | VEX_OP;
 ] vfmadd132ss   = [ b"yoyoyo",   [   2, 0x99      ], X, PREF_66             | VEX_OP;
                     b"yoyomq",   [   2, 0x99      ], X, PREF_66             | VEX_OP;
+                    b"yoyoyo",   [   2, 0x99      ], X, PREF_66             | EVEX_OP | EVEX_ER;
+                    b"yoyomd",   [   2, 0x99      ], X, PREF_66             | EVEX_OP;
 ] vfmadd213ss   = [ b"yoyoyo",   [   2, 0xA9      ], X, PREF_66             | VEX_OP;
                     b"yoyomq",   [   2, 0xA9      ], X, PREF_66             | VEX_OP;
+                    b"yoyoyo",   [   2, 0xA9      ], X, PREF_66             | EVEX_OP | EVEX_ER;
+                    b"yoyomd",   [   2, 0xA9      ], X, PREF_66             | EVEX_OP;
 ] vfmadd231ss   = [ b"yoyoyo",   [   2, 0xB9      ], X, PREF_66             | VEX_OP;
                     b"yoyomq",   [   2, 0xB9      ], X, PREF_66             | VEX_OP;
+                    b"yoyoyo",   [   2, 0xB9      ], X, PREF_66             | EVEX_OP | EVEX_ER;
+                    b"yoyomd",   [   2, 0xB9      ], X, PREF_66             | EVEX_OP;
 ] vfmaddsuppd     =[b"y*y*w*y*", [   3, 0x5D      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
                     b"y*y*y*w*", [   3, 0x5D      ], X, PREF_66 | AUTO_VEXL | VEX_OP | WITH_REXW;
 ] vfmaddsub132pd  =[b"y*y*w*",   [   2, 0x96      ], X, PREF_66 | AUTO_VEXL | VEX_OP | WITH_REXW;
@@ -1638,11 +1848,13 @@ Ops!(make_opmap;
 ] vfmsub132pd   = [ b"y*y*w*",   [   2, 0x9A      ], X, PREF_66 | AUTO_VEXL | VEX_OP | WITH_REXW;
 ] vfmsub213pd   = [ b"y*y*w*",   [   2, 0xAA      ], X, PREF_66 | AUTO_VEXL | VEX_OP | WITH_REXW;
 ] vfmsub231pd   = [ b"y*y*w*",   [   2, 0xBA      ], X, PREF_66 | AUTO_VEXL | VEX_OP | WITH_REXW;
+                    b"y*y*w*",   [   2, 0xBA      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST | EVEX_ER;
 ] vfmsubps      = [ b"y*y*w*y*", [   3, 0x6C      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
                     b"y*y*y*w*", [   3, 0x6C      ], X, PREF_66 | AUTO_VEXL | VEX_OP | WITH_REXW;
 ] vfmsub132ps   = [ b"y*y*w*",   [   2, 0x9A      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
 ] vfmsub213ps   = [ b"y*y*w*",   [   2, 0xAA      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
 ] vfmsub231ps   = [ b"y*y*w*",   [   2, 0xBA      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   2, 0xBA      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | BCST | EVEX_ER;
 ] vfmsubsd      = [ b"yoyoyoyo", [   3, 0x6F      ], X, PREF_66             | VEX_OP | WITH_REXW;
                     b"yoyoyomq", [   3, 0x6F      ], X, PREF_66             | VEX_OP | WITH_REXW;
                     b"yoyomqyo", [   3, 0x6F      ], X, PREF_66             | VEX_OP;
@@ -1666,11 +1878,13 @@ Ops!(make_opmap;
 ] vfnmadd132pd  = [ b"y*y*w*",   [   2, 0x9C      ], X, PREF_66 | AUTO_VEXL | VEX_OP | WITH_REXW;
 ] vfnmadd213pd  = [ b"y*y*w*",   [   2, 0xAC      ], X, PREF_66 | AUTO_VEXL | VEX_OP | WITH_REXW;
 ] vfnmadd231pd  = [ b"y*y*w*",   [   2, 0xBC      ], X, PREF_66 | AUTO_VEXL | VEX_OP | WITH_REXW;
+                    b"y*y*w*",   [   2, 0xBC      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST | EVEX_ER;
 ] vfnmaddps     = [ b"y*y*w*y*", [   3, 0x78      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
                     b"y*y*y*w*", [   3, 0x78      ], X, PREF_66 | AUTO_VEXL | VEX_OP | WITH_REXW;
 ] vfnmadd132ps  = [ b"y*y*w*",   [   2, 0x9C      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
 ] vfnmadd213ps  = [ b"y*y*w*",   [   2, 0xAC      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
 ] vfnmadd231ps  = [ b"y*y*w*",   [   2, 0xBC      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   2, 0xBC      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | BCST | EVEX_ER;
 ] vfnmaddsd     = [ b"yoyoyoyo", [   3, 0x7B      ], X, PREF_66             | VEX_OP | WITH_REXW;
                     b"yoyoyomq", [   3, 0x7B      ], X, PREF_66             | VEX_OP | WITH_REXW;
                     b"yoyomqyo", [   3, 0x7B      ], X, PREF_66             | VEX_OP;
@@ -1724,9 +1938,17 @@ Ops!(make_opmap;
 ] vfrczss       = [ b"yoyo",     [   9, 0x82      ], X,                       XOP_OP;
                     b"yomd",     [   9, 0x82      ], X,                       XOP_OP;
 ] vgatherdpd    = [ b"y*koy*",   [   2, 0x92      ], X, PREF_66 | AUTO_VEXL | VEX_OP | WITH_REXW;
+                    b"yzlh",     [   2, 0x92      ], X, PREF_66 | WITH_EVEXL | EVEX_OP | WITH_REXW;
+                    b"yhlo",     [   2, 0x92      ], X, PREF_66 | WITH_VEXL | EVEX_OP | WITH_REXW;
+                    b"yolo",     [   2, 0x92      ], X, PREF_66             | EVEX_OP | WITH_REXW;
 ] vgatherdps    = [ b"y*k*y*",   [   2, 0x92      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*k*",     [   2, 0x92      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
 ] vgatherqpd    = [ b"y*l*y*",   [   2, 0x93      ], X, PREF_66 | AUTO_VEXL | VEX_OP | WITH_REXW;
+                    b"y*l*",     [   2, 0x93      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW;
 ] vgatherqps    = [ b"yol*yo",   [   2, 0x93      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"yhkz",     [   2, 0x93      ], X, PREF_66 | WITH_EVEXL | EVEX_OP;
+                    b"yokh",     [   2, 0x93      ], X, PREF_66 | WITH_VEXL | EVEX_OP;
+                    b"yoko",     [   2, 0x93      ], X, PREF_66             | EVEX_OP;
 ] vinsertf128   = [ b"yhyhwoib", [   3, 0x18      ], X, PREF_66 | WITH_VEXL | VEX_OP;
 ] vinserti128   = [ b"yhyhwoib", [   3, 0x38      ], X, PREF_66 | WITH_VEXL | VEX_OP;
 ] vmaskmovpd    = [ b"y*y*m*",   [   2, 0x2D      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
@@ -1736,12 +1958,24 @@ Ops!(make_opmap;
 ] vpblendd      = [ b"y*y*w*ib", [   3, 0x02      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
 ] vpbroadcastb  = [ b"y*yo",     [   2, 0x78      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
                     b"y*mb",     [   2, 0x78      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*yo",     [   2, 0x78      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
+                    b"y*mb",     [   2, 0x78      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
+                    b"y*rd",     [   2, 0x7A      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
 ] vpbroadcastd  = [ b"y*yo",     [   2, 0x58      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
                     b"y*md",     [   2, 0x58      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*yo",     [   2, 0x58      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
+                    b"y*md",     [   2, 0x58      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
+                    b"y*rd",     [   2, 0x7C      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
 ] vpbroadcastq  = [ b"y*yo",     [   2, 0x59      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
                     b"y*mq",     [   2, 0x59      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*yo",     [   2, 0x59      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW;
+                    b"y*mq",     [   2, 0x59      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW;
+                    b"y*rq",     [   2, 0x7C      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW;
 ] vpbroadcastw  = [ b"y*yo",     [   2, 0x79      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
                     b"y*mw",     [   2, 0x79      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*yo",     [   2, 0x79      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
+                    b"y*mw",     [   2, 0x79      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
+                    b"y*rd",     [   2, 0x7B      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
 ] vpcmov        = [ b"y*y*w*y*", [   8, 0xA2      ], X,           AUTO_VEXL | XOP_OP;
                     b"y*y*y*w*", [   8, 0xA2      ], X,           AUTO_VEXL | XOP_OP | WITH_REXW;
 ] vpcomb        = [ b"yoyowoib", [   8, 0xCC      ], X,                       XOP_OP;
@@ -1755,6 +1989,7 @@ Ops!(make_opmap;
 ] vperm2f128    = [ b"yhyhwhib", [   3, 0x06      ], X, PREF_66 | WITH_VEXL | VEX_OP;
 ] vperm2i128    = [ b"yhyhwhib", [   3, 0x46      ], X, PREF_66 | WITH_VEXL | VEX_OP;
 ] vpermd        = [ b"yhyhwh",   [   3, 0x36      ], X, PREF_66 | WITH_VEXL | VEX_OP;
+                    b"y*y*w*",   [   2, 0x36      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | BCST;
 ] vpermil2pd    = [ b"y*y*w*y*ib",[  3, 0x49      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
                     b"y*y*y*w*ib",[  3, 0x49      ], X, PREF_66 | AUTO_VEXL | VEX_OP | WITH_REXW;
 ] vpermil2ps    = [ b"y*y*w*y*ib",[  3, 0x48      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
@@ -1764,12 +1999,25 @@ Ops!(make_opmap;
 ] vpermilps     = [ b"y*y*w*",   [   2, 0x0C      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
                     b"y*w*ib",   [   3, 0x04      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
 ] vpermpd       = [ b"yhwhib",   [   3, 0x01      ], X, PREF_66 | WITH_VEXL | VEX_OP | WITH_REXW;
+                    b"y*y*w*",   [   2, 0x16      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST;
+                    b"y*w*ib",   [   3, 0x01      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST;
 ] vpermps       = [ b"yhyhwh",   [   2, 0x01      ], X, PREF_66 | WITH_VEXL | VEX_OP;
+                    b"y*y*w*",   [   2, 0x16      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | BCST;
 ] vpermq        = [ b"yhwhib",   [   3, 0x00      ], X, PREF_66 | WITH_VEXL | VEX_OP | WITH_REXW;
+                    b"y*y*w*",   [   2, 0x36      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST;
+                    b"y*w*ib",   [   3, 0x00      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST;
 ] vpgatherdd    = [ b"y*k*y*",   [   2, 0x90      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*k*",     [   2, 0x90      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
 ] vpgatherdq    = [ b"y*koy*",   [   2, 0x90      ], X, PREF_66 | AUTO_VEXL | VEX_OP | WITH_REXW;
+                    b"yzlh",     [   2, 0x90      ], X, PREF_66 | WITH_EVEXL | EVEX_OP | WITH_REXW;
+                    b"yhlo",     [   2, 0x90      ], X, PREF_66 | WITH_VEXL | EVEX_OP | WITH_REXW;
+                    b"yolo",     [   2, 0x90      ], X, PREF_66             | EVEX_OP | WITH_REXW;
 ] vpgatherqd    = [ b"yok*yo",   [   2, 0x91      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"yhkz",     [   2, 0x91      ], X, PREF_66 | WITH_EVEXL | EVEX_OP;
+                    b"yokh",     [   2, 0x91      ], X, PREF_66 | WITH_VEXL | EVEX_OP;
+                    b"yoko",     [   2, 0x91      ], X, PREF_66             | EVEX_OP;
 ] vpgatherqq    = [ b"y*k*y*",   [   2, 0x91      ], X, PREF_66 | AUTO_VEXL | VEX_OP | WITH_REXW;
+                    b"y*l*",     [   2, 0x91      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW;
 ] vphaddbd      = [ b"yowo",     [   9, 0xC2      ], X,                       XOP_OP;
 ] vphaddbq      = [ b"yowo",     [   9, 0xC3      ], X,                       XOP_OP;
 ] vphaddbw      = [ b"yowo",     [   9, 0xC1      ], X,                       XOP_OP;
@@ -1832,10 +2080,15 @@ Ops!(make_opmap;
 ] vpshlw        = [ b"yowoyo",   [   9, 0x95      ], X,                       XOP_OP;
                     b"yoyowo",   [   9, 0x95      ], X,                       XOP_OP | WITH_REXW;
 ] vpsllvd       = [ b"y*y*w*",   [   2, 0x47      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   2, 0x47      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | BCST;
 ] vpsllvq       = [ b"y*y*w*",   [   2, 0x47      ], X, PREF_66 | AUTO_VEXL | VEX_OP | WITH_REXW;
+                    b"y*y*w*",   [   2, 0x47      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST;
 ] vpsravd       = [ b"y*y*w*",   [   2, 0x46      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   2, 0x46      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | BCST;
 ] vpsrlvd       = [ b"y*y*w*",   [   2, 0x45      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   2, 0x45      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | BCST;
 ] vpsrlvq       = [ b"y*y*w*",   [   2, 0x45      ], X, PREF_66 | AUTO_VEXL | VEX_OP | WITH_REXW;
+                    b"y*y*w*",   [   2, 0x45      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST;
 ] vtestpd       = [ b"y*w*",     [   2, 0x0F      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
 ] vtestps       = [ b"y*w*",     [   2, 0x0E      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
 ] vzeroall      = [ b"",         [   1, 0x77      ], X,           WITH_VEXL | VEX_OP;
@@ -1846,15 +2099,355 @@ Ops!(make_opmap;
   // to begin with.
   xorpd         = [ b"yowo",     [0x0F, 0x57      ], X, PREF_66;
 ] vxorpd        = [ b"y*y*w*",   [   1, 0x57      ], X, PREF_66 | AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   1, 0x57      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST;
 ] xorps         = [ b"yowo",     [0x0F, 0x57      ], X;
 ] vxorps        = [ b"y*y*w*",   [   1, 0x57      ], X,           AUTO_VEXL | VEX_OP;
+                    b"y*y*w*",   [   1, 0x57      ], X,           AUTO_VEXL | EVEX_OP | BCST;
 ] xrstor        = [ b"m!",       [0x0F, 0xAE      ], 5;
 ] xsave         = [ b"m!",       [0x0F, 0xAE      ], 4;
 ] xsaveopt      = [ b"m!",       [0x0F, 0xAE      ], 6;
 ] xsetbv        = [ b"",         [0x0F, 0x01, 0xD1], X;
+] // AVX-512 instructions that only have EVEX encoded forms
+  valignd       = [ b"y*y*w*ib", [   3, 0x03      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | BCST;
+] valignq       = [ b"y*y*w*ib", [   3, 0x03      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST;
+] vbroadcastf32x4
+                = [ b"y*mo",     [   2, 0x1A      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
+] vbroadcastf64x4
+                = [ b"yzmh",     [   2, 0x1B      ], X, PREF_66 | WITH_EVEXL | EVEX_OP | WITH_REXW;
+] vbroadcasti32x4
+                = [ b"y*mo",     [   2, 0x5A      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
+] vbroadcasti64x4
+                = [ b"yzmh",     [   2, 0x5B      ], X, PREF_66 | WITH_EVEXL | EVEX_OP | WITH_REXW;
+] vcvtpd2qq     = [ b"y*w*",     [   1, 0x7B      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST | EVEX_ER;
+] vcvtqq2pd     = [ b"y*w*",     [   1, 0xE6      ], X, PREF_F3 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST | EVEX_ER;
+] vcvttpd2qq    = [ b"y*w*",     [   1, 0x7A      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST | EVEX_SAE;
+] vextractf32x4 = [ b"woy*ib",   [   3, 0x19      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | ENC_MR;
+] vextractf64x4 = [ b"whyzib",   [   3, 0x1B      ], X, PREF_66 | WITH_EVEXL | EVEX_OP | WITH_REXW | ENC_MR;
+] vextracti32x4 = [ b"woy*ib",   [   3, 0x39      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | ENC_MR;
+] vextracti64x4 = [ b"whyzib",   [   3, 0x3B      ], X, PREF_66 | WITH_EVEXL | EVEX_OP | WITH_REXW | ENC_MR;
+] vinsertf32x4  = [ b"y*y*woib", [   3, 0x18      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
+] vinsertf64x4  = [ b"yzyzwhib", [   3, 0x1A      ], X, PREF_66 | WITH_EVEXL | EVEX_OP | WITH_REXW;
+] vinserti32x4  = [ b"y*y*woib", [   3, 0x38      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
+] vinserti64x4  = [ b"yzyzwhib", [   3, 0x3A      ], X, PREF_66 | WITH_EVEXL | EVEX_OP | WITH_REXW;
+] vmovdqa32     = [ b"y*w*",     [   1, 0x6F      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
+                    b"w*y*",     [   1, 0x7F      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
+] vmovdqa64     = [ b"y*w*",     [   1, 0x6F      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW;
+                    b"w*y*",     [   1, 0x7F      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW;
+] vmovdqu16     = [ b"y*w*",     [   1, 0x6F      ], X, PREF_F2 | AUTO_VEXL | EVEX_OP | WITH_REXW;
+                    b"w*y*",     [   1, 0x7F      ], X, PREF_F2 | AUTO_VEXL | EVEX_OP | WITH_REXW;
+] vmovdqu32     = [ b"y*w*",     [   1, 0x6F      ], X, PREF_F3 | AUTO_VEXL | EVEX_OP;
+                    b"w*y*",     [   1, 0x7F      ], X, PREF_F3 | AUTO_VEXL | EVEX_OP;
+] vmovdqu64     = [ b"y*w*",     [   1, 0x6F      ], X, PREF_F3 | AUTO_VEXL | EVEX_OP | WITH_REXW;
+                    b"w*y*",     [   1, 0x7F      ], X, PREF_F3 | AUTO_VEXL | EVEX_OP | WITH_REXW;
+] vmovdqu8      = [ b"y*w*",     [   1, 0x6F      ], X, PREF_F2 | AUTO_VEXL | EVEX_OP;
+                    b"w*y*",     [   1, 0x7F      ], X, PREF_F2 | AUTO_VEXL | EVEX_OP;
+] vpabsq        = [ b"y*w*",     [   2, 0x1F      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST;
+] vpandd        = [ b"y*y*w*",   [   1, 0xDB      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | BCST;
+] vpandnd       = [ b"y*y*w*",   [   1, 0xDF      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | BCST;
+] vpandnq       = [ b"y*y*w*",   [   1, 0xDF      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST;
+] vpandq        = [ b"y*y*w*",   [   1, 0xDB      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST;
+] vpcmpb        = [ b"j?y*w*ib", [   3, 0x3F      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
+] vpcmpd        = [ b"j?y*w*ib", [   3, 0x1F      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | BCST;
+] vpcmpq        = [ b"j?y*w*ib", [   3, 0x1F      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST;
+] vpcmpub       = [ b"j?y*w*ib", [   3, 0x3E      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
+] vpcmpud       = [ b"j?y*w*ib", [   3, 0x1E      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | BCST;
+] vpcmpuq       = [ b"j?y*w*ib", [   3, 0x1E      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST;
+] vpcmpuw       = [ b"j?y*w*ib", [   3, 0x3E      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW;
+] vpcmpw        = [ b"j?y*w*ib", [   3, 0x3F      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW;
+] vpmaxsq       = [ b"y*y*w*",   [   2, 0x3D      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST;
+] vpmaxuq       = [ b"y*y*w*",   [   2, 0x3F      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST;
+] vpminsq       = [ b"y*y*w*",   [   2, 0x39      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST;
+] vpminuq       = [ b"y*y*w*",   [   2, 0x3B      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST;
+] vpmovb2m      = [ b"j?y*",     [   2, 0x29      ], X, PREF_F3 | AUTO_VEXL | EVEX_OP;
+] vpmovd2m      = [ b"j?y*",     [   2, 0x39      ], X, PREF_F3 | AUTO_VEXL | EVEX_OP;
+] vpmovm2b      = [ b"y*j?",     [   2, 0x28      ], X, PREF_F3 | AUTO_VEXL | EVEX_OP;
+] vpmovm2d      = [ b"y*j?",     [   2, 0x38      ], X, PREF_F3 | AUTO_VEXL | EVEX_OP;
+] vpmovm2q      = [ b"y*j?",     [   2, 0x38      ], X, PREF_F3 | AUTO_VEXL | EVEX_OP | WITH_REXW;
+] vpmovm2w      = [ b"y*j?",     [   2, 0x28      ], X, PREF_F3 | AUTO_VEXL | EVEX_OP | WITH_REXW;
+] vpmovq2m      = [ b"j?y*",     [   2, 0x39      ], X, PREF_F3 | AUTO_VEXL | EVEX_OP | WITH_REXW;
+] vpmovw2m      = [ b"j?y*",     [   2, 0x29      ], X, PREF_F3 | AUTO_VEXL | EVEX_OP | WITH_REXW;
+] vpmullq       = [ b"y*y*w*",   [   2, 0x40      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST;
+] vpord         = [ b"y*y*w*",   [   1, 0xEB      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | BCST;
+] vporq         = [ b"y*y*w*",   [   1, 0xEB      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST;
+] vprold        = [ b"y*w*ib",   [   1, 0x72      ], 1, PREF_66 | AUTO_VEXL | EVEX_OP | BCST | ENC_VM;
+] vprolq        = [ b"y*w*ib",   [   1, 0x72      ], 1, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST | ENC_VM;
+] vprord        = [ b"y*w*ib",   [   1, 0x72      ], 0, PREF_66 | AUTO_VEXL | EVEX_OP | BCST | ENC_VM;
+] vprorq        = [ b"y*w*ib",   [   1, 0x72      ], 0, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST | ENC_VM;
+] vpscatterdd   = [ b"k*y*",     [   2, 0xA0      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
+] vpscatterdq   = [ b"lhyz",     [   2, 0xA0      ], X, PREF_66 | WITH_EVEXL | EVEX_OP | WITH_REXW;
+                    b"loyh",     [   2, 0xA0      ], X, PREF_66 | WITH_VEXL | EVEX_OP | WITH_REXW;
+                    b"loyo",     [   2, 0xA0      ], X, PREF_66             | EVEX_OP | WITH_REXW;
+] vpscatterqd   = [ b"kzyh",     [   2, 0xA1      ], X, PREF_66 | WITH_EVEXL | EVEX_OP;
+                    b"khyo",     [   2, 0xA1      ], X, PREF_66 | WITH_VEXL | EVEX_OP;
+                    b"koyo",     [   2, 0xA1      ], X, PREF_66             | EVEX_OP;
+] vpscatterqq   = [ b"l*y*",     [   2, 0xA1      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW;
+] vpsraq        = [ b"y*y*wo",   [   1, 0xE2      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW;
+                    b"y*w*ib",   [   1, 0x72      ], 4, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST | ENC_VM;
+] vpsravq       = [ b"y*y*w*",   [   2, 0x46      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST;
+] vpternlogd    = [ b"y*y*w*ib", [   3, 0x25      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | BCST;
+] vpternlogq    = [ b"y*y*w*ib", [   3, 0x25      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST;
+] vptestmb      = [ b"j?y*w*",   [   2, 0x26      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
+] vptestmd      = [ b"j?y*w*",   [   2, 0x27      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | BCST;
+] vptestmq      = [ b"j?y*w*",   [   2, 0x27      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST;
+] vptestmw      = [ b"j?y*w*",   [   2, 0x26      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW;
+] vptestnmb     = [ b"j?y*w*",   [   2, 0x26      ], X, PREF_F3 | AUTO_VEXL | EVEX_OP;
+] vptestnmd     = [ b"j?y*w*",   [   2, 0x27      ], X, PREF_F3 | AUTO_VEXL | EVEX_OP | BCST;
+] vptestnmq     = [ b"j?y*w*",   [   2, 0x27      ], X, PREF_F3 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST;
+] vptestnmw     = [ b"j?y*w*",   [   2, 0x26      ], X, PREF_F3 | AUTO_VEXL | EVEX_OP | WITH_REXW;
+] vpxord        = [ b"y*y*w*",   [   1, 0xEF      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | BCST;
+] vpxorq        = [ b"y*y*w*",   [   1, 0xEF      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW | BCST;
+] vscatterdpd   = [ b"lhyz",     [   2, 0xA2      ], X, PREF_66 | WITH_EVEXL | EVEX_OP | WITH_REXW;
+                    b"loyh",     [   2, 0xA2      ], X, PREF_66 | WITH_VEXL | EVEX_OP | WITH_REXW;
+                    b"loyo",     [   2, 0xA2      ], X, PREF_66             | EVEX_OP | WITH_REXW;
+] vscatterdps   = [ b"k*y*",     [   2, 0xA2      ], X, PREF_66 | AUTO_VEXL | EVEX_OP;
+] vscatterqpd   = [ b"l*y*",     [   2, 0xA3      ], X, PREF_66 | AUTO_VEXL | EVEX_OP | WITH_REXW;
+] vscatterqps   = [ b"kzyh",     [   2, 0xA3      ], X, PREF_66 | WITH_EVEXL | EVEX_OP;
+                    b"khyo",     [   2, 0xA3      ], X, PREF_66 | WITH_VEXL | EVEX_OP;
+                    b"koyo",     [   2, 0xA3      ], X, PREF_66             | EVEX_OP;
+] // AVX-512 opmask instructions
+  kmovb         = [ b"j?j?",     [   1, 0x90      ], X, PREF_66             | VEX_OP;
+                    b"j?mb",     [   1, 0x90      ], X, PREF_66             | VEX_OP;
+                    b"mbj?",     [   1, 0x91      ], X, PREF_66             | VEX_OP;
+                    b"j?rd",     [   1, 0x92      ], X, PREF_66             | VEX_OP;
+                    b"rdj?",     [   1, 0x93      ], X, PREF_66             | VEX_OP;
+] kmovw         = [ b"j?j?",     [   1, 0x90      ], X,                       VEX_OP;
+                    b"j?mw",     [   1, 0x90      ], X,                       VEX_OP;
+                    b"mwj?",     [   1, 0x91      ], X,                       VEX_OP;
+                    b"j?rd",     [   1, 0x92      ], X,                       VEX_OP;
+                    b"rdj?",     [   1, 0x93      ], X,                       VEX_OP;
+] kmovd         = [ b"j?j?",     [   1, 0x90      ], X, PREF_66             | VEX_OP | WITH_REXW;
+                    b"j?md",     [   1, 0x90      ], X, PREF_66             | VEX_OP | WITH_REXW;
+                    b"mdj?",     [   1, 0x91      ], X, PREF_66             | VEX_OP | WITH_REXW;
+                    b"j?rd",     [   1, 0x92      ], X, PREF_F2             | VEX_OP;
+                    b"rdj?",     [   1, 0x93      ], X, PREF_F2             | VEX_OP;
+] kmovq         = [ b"j?j?",     [   1, 0x90      ], X,                       VEX_OP | WITH_REXW;
+                    b"j?mq",     [   1, 0x90      ], X,                       VEX_OP | WITH_REXW;
+                    b"mqj?",     [   1, 0x91      ], X,                       VEX_OP | WITH_REXW;
+                    b"j?rq",     [   1, 0x92      ], X, PREF_F2             | VEX_OP | WITH_REXW;
+                    b"rqj?",     [   1, 0x93      ], X, PREF_F2             | VEX_OP | WITH_REXW;
+] kaddb         = [ b"j?j?j?",   [   1, 0x4A      ], X, PREF_66 | WITH_VEXL | VEX_OP;
+] kaddw         = [ b"j?j?j?",   [   1, 0x4A      ], X,           WITH_VEXL | VEX_OP;
+] kaddd         = [ b"j?j?j?",   [   1, 0x4A      ], X, PREF_66 | WITH_VEXL | VEX_OP | WITH_REXW;
+] kaddq         = [ b"j?j?j?",   [   1, 0x4A      ], X,           WITH_VEXL | VEX_OP | WITH_REXW;
+] kandb         = [ b"j?j?j?",   [   1, 0x41      ], X, PREF_66 | WITH_VEXL | VEX_OP;
+] kandw         = [ b"j?j?j?",   [   1, 0x41      ], X,           WITH_VEXL | VEX_OP;
+] kandd         = [ b"j?j?j?",   [   1, 0x41      ], X, PREF_66 | WITH_VEXL | VEX_OP | WITH_REXW;
+] kandq         = [ b"j?j?j?",   [   1, 0x41      ], X,           WITH_VEXL | VEX_OP | WITH_REXW;
+] kandnb        = [ b"j?j?j?",   [   1, 0x42      ], X, PREF_66 | WITH_VEXL | VEX_OP;
+] kandnw        = [ b"j?j?j?",   [   1, 0x42      ], X,           WITH_VEXL | VEX_OP;
+] kandnd        = [ b"j?j?j?",   [   1, 0x42      ], X, PREF_66 | WITH_VEXL | VEX_OP | WITH_REXW;
+] kandnq        = [ b"j?j?j?",   [   1, 0x42      ], X,           WITH_VEXL | VEX_OP | WITH_REXW;
+] korb          = [ b"j?j?j?",   [   1, 0x45      ], X, PREF_66 | WITH_VEXL | VEX_OP;
+] korw          = [ b"j?j?j?",   [   1, 0x45      ], X,           WITH_VEXL | VEX_OP;
+] kord          = [ b"j?j?j?",   [   1, 0x45      ], X, PREF_66 | WITH_VEXL | VEX_OP | WITH_REXW;
+] korq          = [ b"j?j?j?",   [   1, 0x45      ], X,           WITH_VEXL | VEX_OP | WITH_REXW;
+] kxnorb        = [ b"j?j?j?",   [   1, 0x46      ], X, PREF_66 | WITH_VEXL | VEX_OP;
+] kxnorw        = [ b"j?j?j?",   [   1, 0x46      ], X,           WITH_VEXL | VEX_OP;
+] kxnord        = [ b"j?j?j?",   [   1, 0x46      ], X, PREF_66 | WITH_VEXL | VEX_OP | WITH_REXW;
+] kxnorq        = [ b"j?j?j?",   [   1, 0x46      ], X,           WITH_VEXL | VEX_OP | WITH_REXW;
+] kxorb         = [ b"j?j?j?",   [   1, 0x47      ], X, PREF_66 | WITH_VEXL | VEX_OP;
+] kxorw         = [ b"j?j?j?",   [   1, 0x47      ], X,           WITH_VEXL | VEX_OP;
+] kxord         = [ b"j?j?j?",   [   1, 0x47      ], X, PREF_66 | WITH_VEXL | VEX_OP | WITH_REXW;
+] kxorq         = [ b"j?j?j?",   [   1, 0x47      ], X,           WITH_VEXL | VEX_OP | WITH_REXW;
+] knotb         = [ b"j?j?",     [   1, 0x44      ], X, PREF_66             | VEX_OP;
+] knotw         = [ b"j?j?",     [   1, 0x44      ], X,                       VEX_OP;
+] knotd         = [ b"j?j?",     [   1, 0x44      ], X, PREF_66             | VEX_OP | WITH_REXW;
+] knotq         = [ b"j?j?",     [   1, 0x44      ], X,                       VEX_OP | WITH_REXW;
+] kortestb      = [ b"j?j?",     [   1, 0x98      ], X, PREF_66             | VEX_OP;
+] kortestw      = [ b"j?j?",     [   1, 0x98      ], X,                       VEX_OP;
+] kortestd      = [ b"j?j?",     [   1, 0x98      ], X, PREF_66             | VEX_OP | WITH_REXW;
+] kortestq      = [ b"j?j?",     [   1, 0x98      ], X,                       VEX_OP | WITH_REXW;
+] ktestb        = [ b"j?j?",     [   1, 0x99      ], X, PREF_66             | VEX_OP;
+] ktestw        = [ b"j?j?",     [   1, 0x99      ], X,                       VEX_OP;
+] ktestd        = [ b"j?j?",     [   1, 0x99      ], X, PREF_66             | VEX_OP | WITH_REXW;
+] ktestq        = [ b"j?j?",     [   1, 0x99      ], X,                       VEX_OP | WITH_REXW;
+] kshiftlb      = [ b"j?j?ib",   [   3, 0x32      ], X, PREF_66             | VEX_OP;
+] kshiftlw      = [ b"j?j?ib",   [   3, 0x32      ], X, PREF_66             | VEX_OP | WITH_REXW;
+] kshiftld      = [ b"j?j?ib",   [   3, 0x33      ], X, PREF_66             | VEX_OP;
+] kshiftlq      = [ b"j?j?ib",   [   3, 0x33      ], X, PREF_66             | VEX_OP | WITH_REXW;
+] kshiftrb      = [ b"j?j?ib",   [   3, 0x30      ], X, PREF_66             | VEX_OP;
+] kshiftrw      = [ b"j?j?ib",   [   3, 0x30      ], X, PREF_66             | VEX_OP | WITH_REXW;
+] kshiftrd      = [ b"j?j?ib",   [   3, 0x31      ], X, PREF_66             | VEX_OP;
+] kshiftrq      = [ b"j?j?ib",   [   3, 0x31      ], X, PREF_66             | VEX_OP | WITH_REXW;
+] kunpckbw      = [ b"j?j?j?",   [   1, 0x4B      ], X, PREF_66 | WITH_VEXL | VEX_OP;
+] kunpckwd      = [ b"j?j?j?",   [   1, 0x4B      ], X,           WITH_VEXL | VEX_OP;
+] kunpckdq      = [ b"j?j?j?",   [   1, 0x4B      ], X,           WITH_VEXL | VEX_OP | WITH_REXW;
 ] // and we're done. well, until intel's new extensions get more use
 );
 
+// The CPUID features needed by each mnemonic, named after the constants of x64::Features. Mnemonics
+// that aren't listed belong to the baseline. For MMX (and MMXEXT, the integer additions of SSE)
+// only the forms with mmx registers belong to that feature, the xmm register forms arrived with
+// SSE2. VEX encoded forms of SSE instructions aren't listed, they are derived in form_features.
+const FEATURES: &[(&str, &str)] = &[
+    ("MMX",        "emms movd movq packssdw packsswb packuswb paddb paddd paddsb paddsw paddusb paddusw paddw pand
+                    pandn pcmpeqb pcmpeqd pcmpeqw pcmpgtb pcmpgtd pcmpgtw pmaddwd pmulhw pmullw por pslld psllq psllw
+                    psrad psraw psrld psrlq psrlw psubb psubd psubsb psubsw psubusb psubusw psubw punpckhbw punpckhdq
+                    punpckhwd punpcklbw punpckldq punpcklwd pxor cmpsd movsd"),
+    ("MMXEXT",     "maskmovq movntq pavgb pavgw pextrw pinsrw pmaxsw pmaxub pminsw pminub pmovmskb pmulhuw psadbw
+                    pshufw"),
+    ("SSE",        "addps addss andnps andps cmpps cmpss comiss cvtpi2ps cvtps2pi cvtsi2ss cvtss2si cvttps2pi cvttss2si
+                    divps divss ldmxcsr maxps maxss minps minss movaps movhlps movhps movlhps movlps movmskps movntps
+                    movss movups mulps mulss orps rcpps rcpss rsqrtps rsqrtss shufps sqrtps sqrtss stmxcsr subps subss
+                    ucomiss unpckhps unpcklps xorps sfence prefetchnta prefetcht0 prefetcht1 prefetcht2"),
+    ("SSE2",       "addpd addsd andnpd andpd clflush cmppd comisd cvtdq2pd cvtdq2ps cvtpd2dq cvtpd2pi cvtpi2pd
+                    cvtps2dq cvtps2pd cvtsd2si cvtsd2ss cvtsi2sd cvtss2sd cvttpd2dq cvttpd2pi cvttps2dq cvttsd2si
+                    divpd divsd lfence maskmovdqu maxpd maxsd mfence minpd minsd movapd movdq2q movdqa movdqu movhpd
+                    movlpd movmskpd movnti movntdq movntpd movq2dq movupd mulpd mulsd orpd paddq pmuludq psubq pshufd
+                    pshuflw pslldq psrldq punpckhqdq punpcklqdq shufpd sqrtpd sqrtsd subpd subsd ucomisd unpckhpd
+                    unpcklpd xorpd"),
+    ("SSE3",       "addsubpd addsubps haddpd haddps hsubpd hsubps lddqu movddup movshdup movsldup fisttp monitor mwait"),
+    ("SSSE3",      "pabsb pabsd pabsw palign phaddd phaddsw phaddw phsubd phsubsw phsubw pmaddubsw pmulhrsw pshufb
+                    psignb psignd psignw"),
+    ("SSE4_1",     "blendpd blendps blendvpd blendvps dppd dpps extractps insertps movntdqa mpsadbw packusdw pblendvb
+                    pblenddw pcmpeqq pextrb pextrd pextrq phminposuw pinsrb pinsrd pinsrq pmaxsb pmaxsd pmaxud pmaxuw
+                    pminsb pminsd pminud pminuw pmovsxbd pmovsxbq pmovsxbw pmovsxdq pmovsxwd pmovsxwq pmovzxbd pmovzxbq
+                    pmovzxbw pmovzxdq pmovzxwd pmovzxwq pmuldq pmulld ptest roundpd roundps roundsd roundss"),
+    ("SSE4_2",     "crc32 pcmpestri pcmpestrm pcmpgtq pcmpistri pcmpistrm"),
+    ("SSE4A",      "movntsd movntss"),
+    ("POPCNT",     "popcnt"),
+    ("LZCNT",      "lzcnt"),
+    ("MOVBE",      "movbe"),
+    ("CX16",       "cmpxchg16b"),
+    ("AES",        "aesdec aesdeclast aesenc aesenclast aesimc aeskeygenassist"),
+    ("PCLMULQDQ",  "pclmulqdq"),
+    ("GFNI",       "gf2p8affineinvqb gf2p8affineqb gf2p8mulb"),
+    ("SHA",        "sha1msg1 sha1msg2 sha1nexte sha1rnds4 sha256msg1 sha256msg2 sha256rnds2"),
+    ("ADX",        "adcx adox"),
+    ("RDRAND",     "rdrand"),
+    ("RDSEED",     "rdseed"),
+    ("BMI1",       "andn bextr blsi blsmsk blsr tzcnt"),
+    ("BMI2",       "bzhi mulx pdep pext rorx sarx shlx shrx"),
+    ("TBM",        "bextr blcfill blci blcic blcmsk blcs blsfill blsic t1mskc tzmsk"),
+    ("LWP",        "llwpcb lwpins lwpval slwpcb"),
+    ("AVX2",       "vbroadcasti128 vextracti128 vinserti128 vperm2i128 vpblendd vpbroadcastb vpbroadcastd vpbroadcastq
+                    vpbroadcastw vpermd vpermpd vpermps vpermq vpmaskmovd vpmaskmovq vpsllvd vpsllvq vpsravd vpsrlvd
+                    vpsrlvq vgatherdpd vgatherdps vgatherqpd vgatherqps vpgatherdd vpgatherdq vpgatherqd vpgatherqq"),
+    ("F16C",       "vcvtph2ps vcvtps2ph"),
+    ("FMA4",       "vfmaddpd vfmaddps vfmaddsd vfmaddss vfmaddsuppd vfmaddsubps vfmsubaddpd vfmsubaddps vfmsubpd
+                    vfmsubps vfmsubsd vfmsubss vfnmaddpd vfnmaddps vfnmaddsd vfnmaddss vfnmsubpd vfnmsubps vfnmsubsd
+                    vfnmsubss"),
+    ("XOP",        "vpermil2pd vpermil2ps"),
+    ("AVX512F",    "kmovw kandw kandnw korw kxnorw kxorw knotw kortestw kshiftlw kshiftrw kunpckbw"),
+    ("AVX512DQ",   "kmovb kandb kandnb korb kxnorb kxorb knotb kortestb ktestb ktestw kaddb kaddw kshiftlb kshiftrb"),
+    ("AVX512BW",   "kmovd kmovq kandd kandq kandnd kandnq kord korq kxnord kxnorq kxord kxorq knotd knotq kortestd
+                    kortestq ktestd ktestq kaddd kaddq kshiftld kshiftlq kshiftrd kshiftrq kunpckwd kunpckdq"),
+    ("CLFLUSHOPT", "clflushopt"),
+    ("CLWB",       "clwb"),
+    ("RTM",        "xabort xbegin xend xtest"),
+    ("SHSTK",      "clrssbsy incsspd incsspq rdsspd rdsspq rstorssp saveprevssp setssbsy wrssd wrssq wrussd wrussq"),
+    ("FSGSBASE",   "rdfsbase rdgsbase wrfsbase wrgsbase"),
+    ("XSAVE",      "xgetbv xrstor xsave xsetbv"),
+    ("XSAVEOPT",   "xsaveopt"),
+    ("MONITORX",   "monitorx mwaitx"),
+    ("RDTSCP",     "rdtscp"),
+    ("SVM",        "clgi invlpga skinit stgi vmload vmmcall vmrun vmsave"),
+    ("PRFCHW",     "prefetch prefetchw"),
+];
+
+// EVEX encoded forms need AVX512F unless they're listed here
+const EVEX_FEATURES: &[(&str, &str)] = &[
+    ("AVX512BW",   "vpaddb vpaddw vpsubb vpsubw vpmullw vpshufb vpavgb vpavgw vpmaxub vpminub vpmaddwd vpmaddubsw
+                    vpunpcklbw vpunpckhbw vpunpcklwd vpunpckhwd vpabsb vpabsw vpcmpeqb vpcmpeqw vpcmpgtb vpcmpgtw
+                    vpsllw vpsrlw vpsraw vmovdqu8 vmovdqu16 vpcmpb vpcmpub vpcmpw vpcmpuw vptestmb vptestmw vptestnmb
+                    vptestnmw vpmovm2b vpmovm2w vpmovb2m vpmovw2m"),
+    ("AVX512DQ",   "vandps vandpd vandnps vandnpd vorps vorpd vxorps vxorpd vpmullq vcvtqq2pd vcvtpd2qq vcvttpd2qq
+                    vpmovm2d vpmovm2q vpmovd2m vpmovq2m"),
+];
+
+// VEX encoded integer instructions were restricted to xmm registers until AVX2
+const AVX_NOT_WIDENED_BY_AVX2: &[&str] = &["vpermilpd", "vpermilps", "vperm2f128", "vptest"];
+const AVX_WIDENED_BY_AVX2: &[&str] = &["vmpsadbw", "vmovntdqa"];
+
+fn feature_map(table: &'static [(&'static str, &'static str)]) -> HashMap<&'static str, Vec<&'static str>> {
+    let mut map: HashMap<&'static str, Vec<&'static str>> = HashMap::new();
+    for &(feature, mnemonics) in table {
+        for mnemonic in mnemonics.split_whitespace() {
+            map.entry(mnemonic).or_default().push(feature);
+        }
+    }
+    map
+}
+
+// Determines the features an instruction form needs, both for its default vector length and for
+// 256-bit vectors (VEX.L). EVEX forms additionally need AVX512VL for xmm and ymm vectors, which
+// the compiler checks as it depends on the operands.
+fn form_features(name: &str, opdata: &Opdata, features: &HashMap<&'static str, Vec<&'static str>>,
+                 evex_features: &HashMap<&'static str, Vec<&'static str>>) -> (Vec<&'static str>, Vec<&'static str>) {
+    let listed = features.get(name).cloned().unwrap_or_else(Vec::new);
+    let legacy = name.strip_prefix('v').and_then(|name| features.get(name)).cloned().unwrap_or_else(Vec::new);
+    let uses = |codes: &[u8]| opdata.args.chunks(2).any(|arg| codes.contains(&arg[0]));
+
+    if opdata.flags & EVEX_OP != 0 {
+        let mut result = evex_features.get(name).cloned().unwrap_or_else(|| vec!["AVX512F"]);
+        for feature in legacy {
+            match feature {
+                "AES"       => result.push("VAES"),
+                "PCLMULQDQ" => result.push("VPCLMULQDQ"),
+                "GFNI"      => result.push("GFNI"),
+                _ => ()
+            }
+        }
+        (result.clone(), result)
+    } else if opdata.flags & XOP_OP != 0 {
+        let result = if listed.contains(&"TBM") { vec!["TBM"] } else { vec!["XOP"] };
+        (result.clone(), result)
+    } else if opdata.flags & VEX_OP != 0 {
+        let listed: Vec<_> = listed.into_iter().filter(|&f| f != "TBM").collect();
+        if !listed.is_empty() {
+            return (listed.clone(), listed);
+        }
+        if name.starts_with("vf") && (name.contains("132") || name.contains("213") || name.contains("231")) {
+            return (vec!["FMA"], vec!["FMA"]);
+        }
+        // the register source forms of vbroadcastss/sd were added by AVX2
+        if (name == "vbroadcastss" || name == "vbroadcastsd") && opdata.args[2] == b'y' {
+            return (vec!["AVX2"], vec!["AVX2"]);
+        }
+
+        let mut base = vec!["AVX"];
+        let mut wide = if (name.starts_with("vp") && !AVX_NOT_WIDENED_BY_AVX2.contains(&name)) || AVX_WIDENED_BY_AVX2.contains(&name) {
+            vec!["AVX2"]
+        } else {
+            vec!["AVX"]
+        };
+        for feature in legacy {
+            match feature {
+                "AES"       => { base.push("AES"); wide = vec!["AVX", "VAES"]; }
+                "PCLMULQDQ" => { base.push("PCLMULQDQ"); wide = vec!["AVX", "VPCLMULQDQ"]; }
+                "GFNI"      => { base.push("GFNI"); wide.push("GFNI"); }
+                _ => ()
+            }
+        }
+        (base, wide)
+    } else {
+        let mut result = Vec::new();
+        for feature in listed {
+            match feature {
+                "MMX" | "MMXEXT" => {
+                    if uses(b"xu") {
+                        result.push(if feature == "MMX" { "MMX" } else { "SSE" });
+                    }
+                    if uses(b"yw") {
+                        result.push("SSE2");
+                    }
+                },
+                feature => result.push(feature)
+            }
+        }
+        (result.clone(), result)
+    }
+}
+
+fn features_expr(features: &[&str]) -> String {
+    if features.is_empty() {
+        "Features(0)".to_string()
+    } else {
+        let parts: Vec<String> = features.iter().map(|f| format!("Features::{}.0", f)).collect();
+        format!("Features({})", parts.join(" | "))
+    }
+}
+
 fn chr(num: usize) -> char {
     assert!(num < 26);
     (('a' as u8) + (num as u8)) as char
@@ -1928,6 +2521,11 @@ fn main() {
     lines.push("impl FlaggedAssembler {".to_string());
     
     let ops = make_opmap();
+    let features = feature_map(FEATURES);
+    let evex_features = feature_map(EVEX_FEATURES);
+    for mnemonic in features.keys().chain(evex_features.keys()) {
+        assert!(ops.iter().any(|&(name, _)| name == *mnemonic), "feature table lists unknown mnemonic {}", mnemonic);
+    }
     for &(name, opdatas) in &ops {
         assert!(opdatas.len()>0);
         
@@ -2004,7 +2602,9 @@ fn main() {
         
         lines.push(format!("        static FORMS: [Opdata; {}] = [", opdatas.len()));
         for opdata in opdatas {
-            lines.push(format!("            Opdata{{args: b{:?}, ops: b\"{}\", reg: {}, flags: {}}},", str::from_utf8(opdata.args).expect("bad args"), hexify(opdata.ops), opdata.reg, opdata.flags));
+            let (base_features, wide_features) = form_features(name, opdata, &features, &evex_features);
+            lines.push(format!("            Opdata{{args: b{:?}, ops: b\"{}\", reg: {}, flags: {}, features: {}, features_256: {}}},", str::from_utf8(opdata.args).expect("bad args"), hexify(opdata.ops), opdata.reg, opdata.flags,
+                               features_expr(&base_features), features_expr(&wide_features)));
         }
         lines.push(format!("        ];"));
         lines.push(format!("        self.encode({:?}, &FORMS[..],  {});", name, 
@@ -0,0 +1,314 @@
+// PE32+ images: executables and DLLs, as opposed to the COFF object files written by Coff.
+
+use std::cmp;
+use std::io;
+use std::io::Write;
+use byteorder::{LittleEndian, ByteOrder, WriteBytesExt};
+
+pub const IMAGE_SUBSYSTEM_WINDOWS_GUI: u16 = 2;
+pub const IMAGE_SUBSYSTEM_WINDOWS_CUI: u16 = 3;
+
+const IMAGE_FILE_EXECUTABLE_IMAGE: u16 = 0x0002;
+const IMAGE_FILE_LARGE_ADDRESS_AWARE: u16 = 0x0020;
+const IMAGE_FILE_DLL: u16 = 0x2000;
+const IMAGE_DLLCHARACTERISTICS_NX_COMPAT: u16 = 0x0100;
+const IMAGE_DLLCHARACTERISTICS_TERMINAL_SERVER_AWARE: u16 = 0x8000;
+
+const IMAGE_SCN_CNT_CODE: u32 = 0x00000020;
+const IMAGE_SCN_CNT_INITIALIZED_DATA: u32 = 0x00000040;
+const IMAGE_SCN_MEM_EXECUTE: u32 = 0x20000000;
+const IMAGE_SCN_MEM_READ: u32 = 0x40000000;
+
+const IMAGE_DIRECTORY_ENTRY_EXPORT: usize = 0;
+const IMAGE_DIRECTORY_ENTRY_IMPORT: usize = 1;
+const IMAGE_DIRECTORY_ENTRY_IAT: usize = 12;
+
+// The code always starts at this RVA, at the beginning of the first section
+pub const PE_CODE_RVA: u32 = 0x1000;
+
+const SECTION_ALIGNMENT: u32 = 0x1000;
+const FILE_ALIGNMENT: u32 = 0x200;
+const OPTIONAL_HEADER_SIZE: u32 = 240;
+const PE_HEADER_OFFSET: u32 = 0x80;
+
+// The traditional real mode stub, printing "This program cannot be run in DOS mode."
+const DOS_STUB: [u8; 64] = [
+    0x0e, 0x1f, 0xba, 0x0e, 0x00, 0xb4, 0x09, 0xcd, 0x21, 0xb8, 0x01, 0x4c, 0xcd, 0x21, 0x54, 0x68,
+    0x69, 0x73, 0x20, 0x70, 0x72, 0x6f, 0x67, 0x72, 0x61, 0x6d, 0x20, 0x63, 0x61, 0x6e, 0x6e, 0x6f,
+    0x74, 0x20, 0x62, 0x65, 0x20, 0x72, 0x75, 0x6e, 0x20, 0x69, 0x6e, 0x20, 0x44, 0x4f, 0x53, 0x20,
+    0x6d, 0x6f, 0x64, 0x65, 0x2e, 0x0d, 0x0d, 0x0a, 0x24, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+// Functions imported by name from a single DLL
+pub struct Import {
+    pub dll: String,
+    pub functions: Vec<String>,
+}
+
+// A 32-bit field in the code, relative to the end of the field, that should refer to the import address
+// table slot of a function, as in `call [rip + slot]`.
+pub struct ImportReference {
+    pub offset: u32,
+    pub dll: usize, // index into PeImage::imports
+    pub function: usize, // index into Import::functions
+}
+
+pub struct Export {
+    pub name: String,
+    pub offset: u32, // in the code
+}
+
+pub struct PeImage {
+    pub machine: u16,
+    pub image_base: u64,
+    pub dll: bool,
+    pub subsystem: u16,
+    pub name: String, // of the image itself, recorded in the export directory
+    pub entry: Option<u32>, // offset in the code; DLLs may go without
+    pub code: Vec<u8>,
+    pub imports: Vec<Import>,
+    pub import_references: Vec<ImportReference>,
+    pub exports: Vec<Export>,
+}
+
+fn align_to(value: u32, alignment: u32) -> u32 {
+    (value + alignment - 1) & !(alignment - 1)
+}
+
+fn invalid_input(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message.to_string())
+}
+
+fn push_string(data: &mut Vec<u8>, s: &str) -> u32 {
+    let offset = data.len() as u32;
+    data.extend_from_slice(s.as_bytes());
+    data.push(0);
+    offset
+}
+
+struct ImageSection {
+    name: &'static [u8],
+    characteristics: u32,
+    rva: u32,
+    data: Vec<u8>,
+}
+
+impl PeImage {
+    // Builds the read-only data section holding the import and export directories, to be placed at rva.
+    // Returns it along with the data directories it fills and the RVA of each import address table slot.
+    fn read_only_data(&self, rva: u32, code_rva: u32) -> io::Result<(Vec<u8>, Vec<(usize, u32, u32)>, Vec<Vec<u32>>)> {
+        let mut data = Vec::new();
+        let mut directories = Vec::new();
+        let mut slots = Vec::new();
+
+        if !self.imports.is_empty() {
+            // import address tables first, so they form one contiguous directory
+            let slot_count: usize = self.imports.iter().map(|import| import.functions.len() + 1).sum();
+            let iat_size = 8 * slot_count as u32;
+            let mut lookup_tables = Vec::new();
+            let mut slot = 0;
+            for import in self.imports.iter() {
+                slots.push((0..import.functions.len()).map(|i| rva + 8 * (slot + i) as u32).collect());
+                lookup_tables.push(slot as u32 * 8);
+                slot += import.functions.len() + 1;
+            }
+            data.resize(iat_size as usize, 0);
+
+            let descriptors_start = data.len();
+            let descriptors_size = 20 * (self.imports.len() + 1);
+            data.resize(descriptors_start + descriptors_size, 0);
+            // the lookup tables mirror the address tables, which the loader overwrites
+            let lookup_start = data.len() as u32;
+            data.resize((lookup_start + iat_size) as usize, 0);
+
+            for (i, import) in self.imports.iter().enumerate() {
+                for (j, function) in import.functions.iter().enumerate() {
+                    if data.len() % 2 != 0 {
+                        data.push(0);
+                    }
+                    let hint_name = rva + data.len() as u32;
+                    try!(data.write_u16::<LittleEndian>(0)); // hint
+                    push_string(&mut data, function);
+                    let entry = (lookup_tables[i] + 8 * j as u32) as usize;
+                    LittleEndian::write_u64(&mut data[entry..], hint_name as u64);
+                    LittleEndian::write_u64(&mut data[lookup_start as usize + entry..], hint_name as u64);
+                }
+                let name = rva + push_string(&mut data, &import.dll);
+                let descriptor = &mut data[descriptors_start + 20 * i..];
+                LittleEndian::write_u32(&mut descriptor[0..], rva + lookup_start + lookup_tables[i]);
+                LittleEndian::write_u32(&mut descriptor[12..], name);
+                LittleEndian::write_u32(&mut descriptor[16..], rva + lookup_tables[i]);
+            }
+
+            directories.push((IMAGE_DIRECTORY_ENTRY_IAT, rva, iat_size));
+            directories.push((IMAGE_DIRECTORY_ENTRY_IMPORT, rva + descriptors_start as u32, descriptors_size as u32));
+        }
+
+        if !self.exports.is_empty() {
+            while data.len() % 4 != 0 {
+                data.push(0);
+            }
+            let start = data.len();
+            let count = self.exports.len() as u32;
+            let directory = rva + start as u32;
+            let functions = directory + 40;
+            let names = functions + 4 * count;
+            let ordinals = names + 4 * count;
+            data.resize(start + 40 + 10 * count as usize, 0);
+
+            // the name table has to be sorted for the loader's binary search
+            let mut order: Vec<usize> = (0..self.exports.len()).collect();
+            order.sort_by(|&a, &b| self.exports[a].name.cmp(&self.exports[b].name));
+            let image_name = rva + push_string(&mut data, &self.name);
+            for (i, export) in self.exports.iter().enumerate() {
+                LittleEndian::write_u32(&mut data[(functions - rva) as usize + 4 * i..], code_rva + export.offset);
+            }
+            for (position, &i) in order.iter().enumerate() {
+                let name = rva + push_string(&mut data, &self.exports[i].name);
+                LittleEndian::write_u32(&mut data[(names - rva) as usize + 4 * position..], name);
+                LittleEndian::write_u16(&mut data[(ordinals - rva) as usize + 2 * position..], i as u16);
+            }
+
+            let header = &mut data[start..];
+            LittleEndian::write_u32(&mut header[12..], image_name);
+            LittleEndian::write_u32(&mut header[16..], 1); // ordinal base
+            LittleEndian::write_u32(&mut header[20..], count);
+            LittleEndian::write_u32(&mut header[24..], count);
+            LittleEndian::write_u32(&mut header[28..], functions);
+            LittleEndian::write_u32(&mut header[32..], names);
+            LittleEndian::write_u32(&mut header[36..], ordinals);
+            directories.push((IMAGE_DIRECTORY_ENTRY_EXPORT, directory, data.len() as u32 - start as u32));
+        }
+
+        Ok((data, directories, slots))
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        if self.entry.is_none() && !self.dll {
+            return Err(invalid_input("executables need an entry point"));
+        }
+
+        let code_rva = PE_CODE_RVA;
+        let data_rva = code_rva + align_to(cmp::max(self.code.len() as u32, 1), SECTION_ALIGNMENT);
+        let (read_only_data, directories, slots) = try!(self.read_only_data(data_rva, code_rva));
+
+        let mut code = self.code.clone();
+        for reference in self.import_references.iter() {
+            let slot = try!(slots.get(reference.dll).and_then(|slots| slots.get(reference.function)).cloned()
+                .ok_or_else(|| invalid_input("reference to a function that is not imported")));
+            let end_of_field = code_rva + reference.offset + 4;
+            LittleEndian::write_i32(&mut code[reference.offset as usize..], slot as i32 - end_of_field as i32);
+        }
+
+        let mut sections = vec![
+            ImageSection{
+                name: b".text",
+                characteristics: IMAGE_SCN_CNT_CODE | IMAGE_SCN_MEM_EXECUTE | IMAGE_SCN_MEM_READ,
+                rva: code_rva,
+                data: code,
+            },
+        ];
+        if !read_only_data.is_empty() {
+            sections.push(ImageSection{
+                name: b".rdata",
+                characteristics: IMAGE_SCN_CNT_INITIALIZED_DATA | IMAGE_SCN_MEM_READ,
+                rva: data_rva,
+                data: read_only_data,
+            });
+        }
+
+        let headers_size = align_to(PE_HEADER_OFFSET + 4 + 20 + OPTIONAL_HEADER_SIZE + 40 * sections.len() as u32, FILE_ALIGNMENT);
+        let image_size = {
+            let last = sections.last().unwrap();
+            last.rva + align_to(last.data.len() as u32, SECTION_ALIGNMENT)
+        };
+        let code_size = align_to(sections[0].data.len() as u32, FILE_ALIGNMENT);
+        let initialized_data_size = sections[1..].iter().fold(0, |sum, section| sum + align_to(section.data.len() as u32, FILE_ALIGNMENT));
+
+        // DOS header, of which only the magic and the offset of the PE header matter
+        let mut dos_header = [0u8; 64];
+        dos_header[0] = b'M';
+        dos_header[1] = b'Z';
+        LittleEndian::write_u16(&mut dos_header[2..], 0x90); // bytes on the last page
+        LittleEndian::write_u16(&mut dos_header[4..], 3); // pages
+        LittleEndian::write_u16(&mut dos_header[8..], 4); // header size in paragraphs
+        LittleEndian::write_u16(&mut dos_header[12..], 0xffff); // maximum extra paragraphs
+        LittleEndian::write_u16(&mut dos_header[16..], 0xb8); // initial sp
+        LittleEndian::write_u16(&mut dos_header[24..], 0x40); // relocation table offset
+        LittleEndian::write_u32(&mut dos_header[60..], PE_HEADER_OFFSET);
+        try!(w.write_all(&dos_header[..]));
+        try!(w.write_all(&DOS_STUB[..]));
+
+        try!(w.write_all(b"PE\0\0"));
+        try!(w.write_u16::<LittleEndian>(self.machine));
+        try!(w.write_u16::<LittleEndian>(sections.len() as u16));
+        try!(w.write_u32::<LittleEndian>(0)); // timestamp
+        try!(w.write_u32::<LittleEndian>(0)); // no symbol table
+        try!(w.write_u32::<LittleEndian>(0));
+        try!(w.write_u16::<LittleEndian>(OPTIONAL_HEADER_SIZE as u16));
+        try!(w.write_u16::<LittleEndian>(IMAGE_FILE_EXECUTABLE_IMAGE | IMAGE_FILE_LARGE_ADDRESS_AWARE | if self.dll { IMAGE_FILE_DLL } else { 0 }));
+
+        try!(w.write_u16::<LittleEndian>(0x20b)); // PE32+
+        try!(w.write_u8(1)); // linker version
+        try!(w.write_u8(0));
+        try!(w.write_u32::<LittleEndian>(code_size));
+        try!(w.write_u32::<LittleEndian>(initialized_data_size));
+        try!(w.write_u32::<LittleEndian>(0)); // uninitialized data size
+        try!(w.write_u32::<LittleEndian>(self.entry.map_or(0, |entry| code_rva + entry)));
+        try!(w.write_u32::<LittleEndian>(code_rva));
+        try!(w.write_u64::<LittleEndian>(self.image_base));
+        try!(w.write_u32::<LittleEndian>(SECTION_ALIGNMENT));
+        try!(w.write_u32::<LittleEndian>(FILE_ALIGNMENT));
+        try!(w.write_u16::<LittleEndian>(6)); // operating system version
+        try!(w.write_u16::<LittleEndian>(0));
+        try!(w.write_u16::<LittleEndian>(0)); // image version
+        try!(w.write_u16::<LittleEndian>(0));
+        try!(w.write_u16::<LittleEndian>(6)); // subsystem version
+        try!(w.write_u16::<LittleEndian>(0));
+        try!(w.write_u32::<LittleEndian>(0)); // Win32 version, reserved
+        try!(w.write_u32::<LittleEndian>(image_size));
+        try!(w.write_u32::<LittleEndian>(headers_size));
+        try!(w.write_u32::<LittleEndian>(0)); // checksum
+        try!(w.write_u16::<LittleEndian>(self.subsystem));
+        // without base relocations the image cannot be moved, so it does not claim DYNAMIC_BASE
+        try!(w.write_u16::<LittleEndian>(IMAGE_DLLCHARACTERISTICS_NX_COMPAT | IMAGE_DLLCHARACTERISTICS_TERMINAL_SERVER_AWARE));
+        try!(w.write_u64::<LittleEndian>(0x100000)); // stack reserve
+        try!(w.write_u64::<LittleEndian>(0x1000)); // stack commit
+        try!(w.write_u64::<LittleEndian>(0x100000)); // heap reserve
+        try!(w.write_u64::<LittleEndian>(0x1000)); // heap commit
+        try!(w.write_u32::<LittleEndian>(0)); // loader flags
+        try!(w.write_u32::<LittleEndian>(16)); // number of data directories
+        for index in 0..16 {
+            let (rva, size) = directories.iter().find(|directory| directory.0 == index).map_or((0, 0), |directory| (directory.1, directory.2));
+            try!(w.write_u32::<LittleEndian>(rva));
+            try!(w.write_u32::<LittleEndian>(size));
+        }
+
+        let mut file_offset = headers_size;
+        for section in sections.iter() {
+            let mut name = [0u8; 8];
+            name[..section.name.len()].copy_from_slice(section.name);
+            try!(w.write_all(&name[..]));
+            try!(w.write_u32::<LittleEndian>(section.data.len() as u32)); // virtual size
+            try!(w.write_u32::<LittleEndian>(section.rva));
+            try!(w.write_u32::<LittleEndian>(align_to(section.data.len() as u32, FILE_ALIGNMENT)));
+            try!(w.write_u32::<LittleEndian>(file_offset));
+            try!(w.write_u32::<LittleEndian>(0)); // relocations
+            try!(w.write_u32::<LittleEndian>(0)); // line numbers
+            try!(w.write_u16::<LittleEndian>(0));
+            try!(w.write_u16::<LittleEndian>(0));
+            try!(w.write_u32::<LittleEndian>(section.characteristics));
+            file_offset += align_to(section.data.len() as u32, FILE_ALIGNMENT);
+        }
+
+        let headers_written = PE_HEADER_OFFSET + 4 + 20 + OPTIONAL_HEADER_SIZE + 40 * sections.len() as u32;
+        try!(w.write_all(&vec![0u8; (headers_size - headers_written) as usize][..]));
+        for section in sections.iter() {
+            try!(w.write_all(&section.data[..]));
+            let padding = align_to(section.data.len() as u32, FILE_ALIGNMENT) - section.data.len() as u32;
+            try!(w.write_all(&vec![0u8; padding as usize][..]));
+        }
+
+        Ok( () )
+    }
+}
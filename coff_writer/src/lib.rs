@@ -6,10 +6,39 @@ use std::io::Write;
 use byteorder::{LittleEndian, WriteBytesExt};
 use checked_int_cast::CheckedIntCast;
 
+mod image;
+mod reader;
+pub use image::{PeImage, Import, ImportReference, Export, PE_CODE_RVA, IMAGE_SUBSYSTEM_WINDOWS_GUI, IMAGE_SUBSYSTEM_WINDOWS_CUI};
+
 pub const MACHINE_I386: u16 = 0x014c;
 pub const MACHINE_IA64: u16 = 0x0200;
 pub const MACHINE_AMD64: u16 = 0x8664;
 
+pub const IMAGE_REL_AMD64_ADDR64: u16 = 0x0001;
+pub const IMAGE_REL_AMD64_ADDR32: u16 = 0x0002;
+pub const IMAGE_REL_AMD64_ADDR32NB: u16 = 0x0003;
+pub const IMAGE_REL_AMD64_REL32: u16 = 0x0004;
+pub const IMAGE_REL_AMD64_REL32_5: u16 = 0x0009; // REL32_1 to REL32_5 are relative to 1 to 5 bytes further on
+pub const IMAGE_REL_AMD64_SECTION: u16 = 0x000A;
+pub const IMAGE_REL_AMD64_SECREL: u16 = 0x000B;
+
+pub const IMAGE_REL_I386_DIR32: u16 = 0x0006;
+pub const IMAGE_REL_I386_DIR32NB: u16 = 0x0007;
+pub const IMAGE_REL_I386_SECTION: u16 = 0x000A;
+pub const IMAGE_REL_I386_SECREL: u16 = 0x000B;
+pub const IMAGE_REL_I386_REL32: u16 = 0x0014;
+
+pub const IMAGE_FILE_32BIT_MACHINE: u16 = 0x0100;
+
+pub const IMAGE_SYM_CLASS_EXTERNAL: u8 = 2;
+pub const IMAGE_SYM_CLASS_STATIC: u8 = 3;
+
+const CV_SIGNATURE_C13: u32 = 4;
+const DEBUG_S_LINES: u32 = 0xF2;
+const DEBUG_S_STRINGTABLE: u32 = 0xF3;
+const DEBUG_S_FILECHKSMS: u32 = 0xF4;
+const CV_LINES_HAVE_COLUMNS: u16 = 0x1;
+
 
 pub struct Section {
     pub name: String,
@@ -40,6 +69,192 @@ pub struct Coff {
     pub characteristics: u16,
 }
 
+// One operation of a function prologue, as recorded in the unwind codes of an UNWIND_INFO.
+// Registers are numbered as in the x64 instruction encoding.
+#[derive(Clone, Debug, PartialEq)]
+pub enum UnwindCode {
+    PushNonvol(u8),
+    AllocStack(u32),
+    SetFrame,
+    SaveNonvol(u8, u32), // offset from the stack pointer (or the frame register, if one is set)
+    SaveXmm128(u8, u32),
+}
+
+// Unwind data for a single function, written into .xdata with a matching .pdata entry.
+#[derive(Clone, Debug)]
+pub struct UnwindInfo {
+    pub begin: u32, // offset of the function in .text
+    pub end: u32,
+    pub prolog_size: u8,
+    pub frame_register: u8,
+    pub frame_offset: u8, // must be a multiple of 16
+    pub codes: Vec<(u8, UnwindCode)>, // (offset of the end of the instruction in the prolog, code)
+}
+
+impl UnwindInfo {
+    pub fn encode(&self) -> io::Result<Vec<u8>> {
+        if self.frame_offset % 16 != 0 || self.frame_offset > 240 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "frame offset must be a multiple of 16 no larger than 240"));
+        }
+
+        // slots are listed in reverse order of the prolog
+        let mut slots: Vec<u16> = Vec::new();
+        for &(offset, ref code) in self.codes.iter().rev() {
+            let slot = |operation: u8, info: u8| (offset as u16) | ((operation as u16 | (info as u16) << 4) << 8);
+            match *code {
+                UnwindCode::PushNonvol(reg) => slots.push(slot(0, reg)),
+                UnwindCode::AllocStack(size) => {
+                    if size == 0 || size % 8 != 0 {
+                        return Err(io::Error::new(io::ErrorKind::InvalidInput, "stack allocations must be a nonzero multiple of 8"));
+                    } else if size <= 128 {
+                        slots.push(slot(2, ((size - 8) / 8) as u8));
+                    } else if size <= 512*1024 - 8 {
+                        slots.push(slot(1, 0));
+                        slots.push((size / 8) as u16);
+                    } else {
+                        slots.push(slot(1, 1));
+                        slots.push(size as u16);
+                        slots.push((size >> 16) as u16);
+                    }
+                }
+                UnwindCode::SetFrame => slots.push(slot(3, 0)),
+                UnwindCode::SaveNonvol(reg, at) => {
+                    if at % 8 != 0 {
+                        return Err(io::Error::new(io::ErrorKind::InvalidInput, "register save offset must be a multiple of 8"));
+                    } else if at / 8 <= 0xffff {
+                        slots.push(slot(4, reg));
+                        slots.push((at / 8) as u16);
+                    } else {
+                        slots.push(slot(5, reg));
+                        slots.push(at as u16);
+                        slots.push((at >> 16) as u16);
+                    }
+                }
+                UnwindCode::SaveXmm128(reg, at) => {
+                    if at % 16 != 0 {
+                        return Err(io::Error::new(io::ErrorKind::InvalidInput, "xmm save offset must be a multiple of 16"));
+                    } else if at / 16 <= 0xffff {
+                        slots.push(slot(8, reg));
+                        slots.push((at / 16) as u16);
+                    } else {
+                        slots.push(slot(9, reg));
+                        slots.push(at as u16);
+                        slots.push((at >> 16) as u16);
+                    }
+                }
+            }
+        }
+        let slot_count = try!(slots.len().as_u8_checked().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "too many unwind codes")));
+
+        let mut result = Vec::new();
+        try!(result.write_u8(1)); // version 1, no flags
+        try!(result.write_u8(self.prolog_size));
+        try!(result.write_u8(slot_count));
+        try!(result.write_u8((self.frame_register & 0xf) | (self.frame_offset / 16) << 4));
+        for slot in slots.iter() {
+            try!(result.write_u16::<LittleEndian>(*slot));
+        }
+        if slots.len() % 2 == 1 {
+            try!(result.write_u16::<LittleEndian>(0));
+        }
+        Ok(result)
+    }
+}
+
+// Maps an offset in .text to a position in one of the source files of a LineInfo
+#[derive(Clone, Debug, PartialEq)]
+pub struct LineRow {
+    pub offset: u32,
+    pub file: usize, // index into LineInfo::files
+    pub line: u32,
+    pub column: u16,
+}
+
+// CodeView line information for .text, written into .debug$S
+#[derive(Clone, Debug)]
+pub struct LineInfo {
+    pub machine: u16, // picks the relocation types
+    pub files: Vec<String>,
+    pub code_size: u32,
+    pub rows: Vec<LineRow>, // in order of offset
+}
+
+fn write_subsection(w: &mut Vec<u8>, kind: u32, contents: &[u8]) -> io::Result<()> {
+    try!(w.write_u32::<LittleEndian>(kind));
+    try!(w.write_u32::<LittleEndian>(contents.len() as u32));
+    try!(w.write_all(contents));
+    while w.len() % 4 != 0 {
+        try!(w.write_u8(0));
+    }
+    Ok( () )
+}
+
+impl LineInfo {
+    // Returns the .debug$S contents along with their relocations against the given .text symbol.
+    pub fn encode(&self, text_symbol_index: u32) -> io::Result<(Vec<u8>, Vec<Relocation>)> {
+        let mut strings = vec![0u8];
+        let mut checksums = Vec::new();
+        let mut file_ids = Vec::new();
+        for file in self.files.iter() {
+            file_ids.push(checksums.len() as u32);
+            try!(checksums.write_u32::<LittleEndian>(strings.len() as u32));
+            try!(checksums.write_u8(0)); // no checksum
+            try!(checksums.write_u8(0)); // checksum kind: none
+            try!(checksums.write_u16::<LittleEndian>(0)); // padding
+            strings.extend_from_slice(file.as_bytes());
+            strings.push(0);
+        }
+
+        let mut lines = Vec::new();
+        try!(lines.write_u32::<LittleEndian>(0)); // offset in .text, filled by a SECREL relocation
+        try!(lines.write_u16::<LittleEndian>(0)); // section index, filled by a SECTION relocation
+        try!(lines.write_u16::<LittleEndian>(CV_LINES_HAVE_COLUMNS));
+        try!(lines.write_u32::<LittleEndian>(self.code_size));
+
+        // Rows are grouped into blocks of consecutive rows from the same file
+        let mut block_start = 0;
+        while block_start < self.rows.len() {
+            let file = self.rows[block_start].file;
+            let file_id = try!(file_ids.get(file).cloned().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "line row refers to an unknown file")));
+            let block_end = self.rows[block_start..].iter().position(|row| row.file != file).map(|n| block_start + n).unwrap_or(self.rows.len());
+            let block = &self.rows[block_start..block_end];
+
+            try!(lines.write_u32::<LittleEndian>(file_id));
+            try!(lines.write_u32::<LittleEndian>(block.len() as u32));
+            try!(lines.write_u32::<LittleEndian>(12 + 12 * block.len() as u32));
+            for row in block {
+                if row.line > 0xffffff {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, "line numbers must fit in 24 bits"));
+                }
+                try!(lines.write_u32::<LittleEndian>(row.offset));
+                try!(lines.write_u32::<LittleEndian>(0x80000000 | row.line)); // is a statement
+            }
+            for row in block {
+                try!(lines.write_u16::<LittleEndian>(row.column));
+                try!(lines.write_u16::<LittleEndian>(0)); // end column unknown
+            }
+            block_start = block_end;
+        }
+
+        let mut result = Vec::new();
+        try!(result.write_u32::<LittleEndian>(CV_SIGNATURE_C13));
+        try!(write_subsection(&mut result, DEBUG_S_STRINGTABLE, &strings[..]));
+        try!(write_subsection(&mut result, DEBUG_S_FILECHKSMS, &checksums[..]));
+        let lines_start = result.len() as u32 + 8;
+        try!(write_subsection(&mut result, DEBUG_S_LINES, &lines[..]));
+
+        let (secrel, section) = match self.machine {
+            MACHINE_I386 => (IMAGE_REL_I386_SECREL, IMAGE_REL_I386_SECTION),
+            _ => (IMAGE_REL_AMD64_SECREL, IMAGE_REL_AMD64_SECTION),
+        };
+        let relocations = vec![
+            Relocation{ virtual_address: lines_start, symbol_index: text_symbol_index, type_flags: secrel },
+            Relocation{ virtual_address: lines_start + 4, symbol_index: text_symbol_index, type_flags: section },
+        ];
+        Ok((result, relocations))
+    }
+}
+
 impl Coff {
     pub fn write<W: Write>(&self, wtr: &mut W) -> io::Result<()> {
         let header_length = 20; assert!(self.optional_header.len() == 0);
@@ -151,7 +366,10 @@ impl Coff {
 
 #[cfg(test)]
 mod tests {
-    use super::{Coff, Section, Relocation, Symbol, MACHINE_AMD64};
+    use super::{Coff, Section, Relocation, Symbol, MACHINE_AMD64, UnwindInfo, UnwindCode, LineInfo, LineRow};
+    use super::{IMAGE_REL_AMD64_SECREL, IMAGE_REL_AMD64_SECTION};
+    use super::{PeImage, Import, ImportReference, Export, IMAGE_SUBSYSTEM_WINDOWS_CUI};
+    use byteorder::{LittleEndian, ByteOrder};
     use std::fs::File;
     use std::io::Read;
     
@@ -321,14 +539,165 @@ mod tests {
             let mut xs = Vec::new();
             let mut ys = Vec::new();
             File::open("out.o").unwrap().read_to_end(&mut xs).unwrap();
-            File::open("..\\buildwithgcc\\foo.o").unwrap().read_to_end(&mut xs).unwrap();
+            Coff::read(&xs[..]).unwrap().write(&mut ys).unwrap();
             
             for (idx, (x, y)) in xs.iter().zip(ys.iter()).enumerate() {
                 if *x != *y {
                     panic!("differ at {}", idx);
                 }
             }
+            assert_eq!(xs.len(), ys.len());
         }
     
     }
+
+    #[test]
+    fn unwind_info() {
+        let info = UnwindInfo {
+            begin: 0,
+            end: 0x40,
+            prolog_size: 12,
+            frame_register: 5,
+            frame_offset: 32,
+            codes: vec![
+                (1, UnwindCode::PushNonvol(5)),
+                (3, UnwindCode::PushNonvol(12)),
+                (7, UnwindCode::AllocStack(0x30)),
+                (12, UnwindCode::SetFrame),
+            ],
+        };
+        assert_eq!(info.encode().unwrap(), vec![
+            0x01, 12, 4, 0x25,
+            12, 0x03,
+            7, 0x52,
+            3, 0xC0,
+            1, 0x50,
+        ]);
+
+        let info = UnwindInfo {
+            begin: 0,
+            end: 0x40,
+            prolog_size: 15,
+            frame_register: 0,
+            frame_offset: 0,
+            codes: vec![
+                (7, UnwindCode::AllocStack(0x1000)),
+                (15, UnwindCode::SaveXmm128(6, 0x20)),
+            ],
+        };
+        assert_eq!(info.encode().unwrap(), vec![
+            0x01, 15, 4, 0x00,
+            15, 0x68, 2, 0,
+            7, 0x01, 0x00, 0x02,
+        ]);
+    }
+
+    #[test]
+    fn line_info() {
+        let info = LineInfo {
+            machine: MACHINE_AMD64,
+            files: vec!["a.rs".to_string(), "b.rs".to_string()],
+            code_size: 8,
+            rows: vec![
+                LineRow{ offset: 0, file: 0, line: 10, column: 5 },
+                LineRow{ offset: 4, file: 1, line: 2, column: 1 },
+            ],
+        };
+        let (data, relocations) = info.encode(7).unwrap();
+        assert_eq!(data, vec![
+            4, 0, 0, 0,
+            0xF3, 0, 0, 0, 11, 0, 0, 0, 0, b'a', b'.', b'r', b's', 0, b'b', b'.', b'r', b's', 0, 0,
+            0xF4, 0, 0, 0, 16, 0, 0, 0,
+                1, 0, 0, 0, 0, 0, 0, 0,
+                6, 0, 0, 0, 0, 0, 0, 0,
+            0xF2, 0, 0, 0, 60, 0, 0, 0,
+                0, 0, 0, 0, 0, 0, 1, 0, 8, 0, 0, 0,
+                0, 0, 0, 0, 1, 0, 0, 0, 24, 0, 0, 0,
+                    0, 0, 0, 0, 10, 0, 0, 0x80,
+                    5, 0, 0, 0,
+                8, 0, 0, 0, 1, 0, 0, 0, 24, 0, 0, 0,
+                    4, 0, 0, 0, 2, 0, 0, 0x80,
+                    1, 0, 0, 0,
+        ]);
+        assert_eq!(relocations.len(), 2);
+        assert_eq!((relocations[0].virtual_address, relocations[0].symbol_index, relocations[0].type_flags), (56, 7, IMAGE_REL_AMD64_SECREL));
+        assert_eq!((relocations[1].virtual_address, relocations[1].type_flags), (60, IMAGE_REL_AMD64_SECTION));
+    }
+
+    #[test]
+    fn pe_image() {
+        let image = PeImage {
+            machine: MACHINE_AMD64,
+            image_base: 0x180000000,
+            dll: true,
+            subsystem: IMAGE_SUBSYSTEM_WINDOWS_CUI,
+            name: "test.dll".to_string(),
+            entry: Some(6),
+            code: vec![0xff, 0x15, 0, 0, 0, 0, 0xc3],
+            imports: vec![
+                Import { dll: "kernel32.dll".to_string(), functions: vec!["ExitProcess".to_string(), "GetStdHandle".to_string()] },
+            ],
+            import_references: vec![
+                ImportReference { offset: 2, dll: 0, function: 1 },
+            ],
+            exports: vec![
+                Export { name: "second".to_string(), offset: 6 },
+                Export { name: "first".to_string(), offset: 0 },
+            ],
+        };
+        let mut xs = Vec::new();
+        image.write(&mut xs).unwrap();
+
+        assert_eq!(&xs[0..2], b"MZ");
+        let pe = LittleEndian::read_u32(&xs[0x3c..]) as usize;
+        assert_eq!(&xs[pe..pe + 4], b"PE\0\0");
+        assert_eq!(LittleEndian::read_u16(&xs[pe + 4..]), MACHINE_AMD64);
+        assert_eq!(LittleEndian::read_u16(&xs[pe + 22..]) & 0x2000, 0x2000); // DLL
+        let optional = pe + 24;
+        assert_eq!(LittleEndian::read_u16(&xs[optional..]), 0x20b);
+        assert_eq!(LittleEndian::read_u32(&xs[optional + 16..]), 0x1006); // entry point
+        assert_eq!(LittleEndian::read_u64(&xs[optional + 24..]), 0x180000000);
+
+        // maps an RVA to the file through the section headers
+        let section_count = LittleEndian::read_u16(&xs[pe + 6..]) as usize;
+        let sections = optional + 240;
+        let at = |rva: u32| -> usize {
+            for i in 0..section_count {
+                let header = &xs[sections + 40 * i..];
+                let (size, address, offset) = (LittleEndian::read_u32(&header[8..]), LittleEndian::read_u32(&header[12..]), LittleEndian::read_u32(&header[20..]));
+                if rva >= address && rva < address + size {
+                    return (rva - address + offset) as usize;
+                }
+            }
+            panic!("rva {:x} is not in any section", rva);
+        };
+        let string = |rva: u32| -> String {
+            let start = at(rva);
+            let len = xs[start..].iter().position(|b| *b == 0).unwrap();
+            String::from_utf8(xs[start..start + len].to_vec()).unwrap()
+        };
+        let directory = |index: usize| LittleEndian::read_u32(&xs[optional + 112 + 8 * index..]);
+
+        let imports = directory(1);
+        assert_eq!(string(LittleEndian::read_u32(&xs[at(imports) + 12..])), "kernel32.dll");
+        let lookup = LittleEndian::read_u32(&xs[at(imports)..]);
+        let address_table = LittleEndian::read_u32(&xs[at(imports) + 16..]);
+        let names: Vec<String> = (0..2).map(|i| string(LittleEndian::read_u64(&xs[at(lookup) + 8 * i..]) as u32 + 2)).collect();
+        assert_eq!(names, vec!["ExitProcess".to_string(), "GetStdHandle".to_string()]);
+        assert_eq!(LittleEndian::read_u64(&xs[at(lookup) + 16..]), 0);
+        assert_eq!(directory(12), address_table);
+
+        // the call goes through the GetStdHandle slot
+        let displacement = LittleEndian::read_i32(&xs[at(0x1002)..]);
+        assert_eq!((0x1006 + displacement) as u32, address_table + 8);
+
+        let exports = at(directory(0));
+        assert_eq!(string(LittleEndian::read_u32(&xs[exports + 12..])), "test.dll");
+        let (functions, names, ordinals) = (LittleEndian::read_u32(&xs[exports + 28..]), LittleEndian::read_u32(&xs[exports + 32..]), LittleEndian::read_u32(&xs[exports + 36..]));
+        let exported: Vec<(String, u32)> = (0..2).map(|i| {
+            let ordinal = LittleEndian::read_u16(&xs[at(ordinals) + 2 * i..]) as usize;
+            (string(LittleEndian::read_u32(&xs[at(names) + 4 * i..])), LittleEndian::read_u32(&xs[at(functions) + 4 * ordinal..]))
+        }).collect();
+        assert_eq!(exported, vec![("first".to_string(), 0x1000), ("second".to_string(), 0x1006)]);
+    }
 }
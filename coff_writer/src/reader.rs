@@ -0,0 +1,127 @@
+// Parses COFF object files, such as the ones Coff::write produces, back into a Coff.
+
+use std::io;
+use std::str;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use {Coff, Section, Relocation, Symbol};
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn range(data: &[u8], offset: usize, size: usize) -> io::Result<&[u8]> {
+    match offset.checked_add(size) {
+        Some(end) if end <= data.len() => Ok(&data[offset..end]),
+        _ => Err(invalid("COFF table extends past the end of the file")),
+    }
+}
+
+// A name stored inline, padded with NULs
+fn short_name(bytes: &[u8]) -> io::Result<String> {
+    let length = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    str::from_utf8(&bytes[..length]).map(|name| name.to_string()).map_err(|_| invalid("name is not valid UTF-8"))
+}
+
+fn long_name(string_table: &[u8], offset: usize) -> io::Result<String> {
+    if offset < 4 || offset >= string_table.len() {
+        return Err(invalid("string table offset out of range"));
+    }
+    short_name(&string_table[offset..])
+}
+
+impl Coff {
+    pub fn read(data: &[u8]) -> io::Result<Coff> {
+        if data.len() < 20 {
+            return Err(invalid("truncated COFF header"));
+        }
+        let section_count = LittleEndian::read_u16(&data[2..]) as usize;
+        let symbol_table_start = LittleEndian::read_u32(&data[8..]) as usize;
+        let symbol_table_count = LittleEndian::read_u32(&data[12..]) as usize;
+        let optional_header_length = LittleEndian::read_u16(&data[16..]) as usize;
+
+        let symbol_table = try!(range(data, symbol_table_start, symbol_table_count * 18));
+        let string_table = if symbol_table_start == 0 {
+            &data[0..0]
+        } else {
+            let start = symbol_table_start + symbol_table_count * 18;
+            let length = LittleEndian::read_u32(try!(range(data, start, 4))) as usize;
+            try!(range(data, start, length))
+        };
+
+        let optional_header = try!(range(data, 20, optional_header_length)).to_vec();
+        let section_headers = try!(range(data, 20 + optional_header_length, section_count * 40));
+        let mut sections = Vec::new();
+        for header in section_headers.chunks(40) {
+            let name = if header[0] == b'/' {
+                let offset = try!(short_name(&header[1..8]));
+                match offset.parse() {
+                    Ok(offset) => try!(long_name(string_table, offset)),
+                    Err(_) => return Err(invalid("malformed long section name")),
+                }
+            } else {
+                try!(short_name(&header[0..8]))
+            };
+            let size = LittleEndian::read_u32(&header[16..]) as usize;
+            let data_start = LittleEndian::read_u32(&header[20..]) as usize;
+            let relocations_start = LittleEndian::read_u32(&header[24..]) as usize;
+            let relocation_count = LittleEndian::read_u16(&header[32..]) as usize;
+            let characteristics = LittleEndian::read_u32(&header[36..]);
+
+            let mut relocations = Vec::new();
+            for entry in try!(range(data, relocations_start, relocation_count * 10)).chunks(10) {
+                relocations.push(Relocation{
+                    virtual_address: LittleEndian::read_u32(entry),
+                    symbol_index: LittleEndian::read_u32(&entry[4..]),
+                    type_flags: LittleEndian::read_u16(&entry[8..]),
+                });
+            }
+            sections.push(Section{
+                name: name,
+                // uninitialized data has a size but nothing in the file
+                data: if data_start == 0 { vec![0; size] } else { try!(range(data, data_start, size)).to_vec() },
+                characteristics: characteristics,
+                relocations: relocations,
+            });
+        }
+
+        let mut symbols = Vec::new();
+        let mut index = 0;
+        while index < symbol_table_count {
+            let entry = &symbol_table[index * 18..(index + 1) * 18];
+            let aux_count = entry[17] as usize;
+            if index + 1 + aux_count > symbol_table_count {
+                return Err(invalid("auxiliary symbol records extend past the symbol table"));
+            }
+            let mut aux_symbols = Vec::new();
+            for aux in 0..aux_count {
+                let mut record = [0u8; 18];
+                record.copy_from_slice(&symbol_table[(index + 1 + aux) * 18..(index + 2 + aux) * 18]);
+                aux_symbols.push(record);
+            }
+            symbols.push(Symbol{
+                name: if LittleEndian::read_u32(entry) == 0 {
+                    try!(long_name(string_table, LittleEndian::read_u32(&entry[4..]) as usize))
+                } else {
+                    try!(short_name(&entry[0..8]))
+                },
+                value: LittleEndian::read_u32(&entry[8..]),
+                section_number: LittleEndian::read_i16(&entry[12..]),
+                type_flags: LittleEndian::read_u16(&entry[14..]),
+                storage_class: entry[16],
+                aux_symbols: aux_symbols,
+            });
+            index += 1 + aux_count;
+        }
+
+        Ok(Coff{
+            machine: LittleEndian::read_u16(data),
+            timestamp: LittleEndian::read_u32(&data[4..]),
+            sections: sections,
+            symbols: symbols,
+            optional_header: optional_header,
+            characteristics: LittleEndian::read_u16(&data[18..]),
+        })
+    }
+}
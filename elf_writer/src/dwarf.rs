@@ -0,0 +1,529 @@
+// Encoders for the DWARF based sections: .eh_frame and the .debug_* sections, and a decoder for .eh_frame.
+
+use std::io;
+use std::io::Write;
+
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+
+use {Elf, WordSize, CallFrameInstruction, FrameDescription, DebugInfo, Relocation};
+
+const DW_TAG_COMPILE_UNIT: u64 = 0x11;
+const DW_TAG_SUBPROGRAM: u64 = 0x2e;
+
+const DW_AT_NAME: u64 = 0x03;
+const DW_AT_STMT_LIST: u64 = 0x10;
+const DW_AT_LOW_PC: u64 = 0x11;
+const DW_AT_HIGH_PC: u64 = 0x12;
+const DW_AT_LANGUAGE: u64 = 0x13;
+const DW_AT_PRODUCER: u64 = 0x25;
+const DW_AT_EXTERNAL: u64 = 0x3f;
+
+const DW_FORM_ADDR: u64 = 0x01;
+const DW_FORM_DATA2: u64 = 0x05;
+const DW_FORM_DATA4: u64 = 0x06;
+const DW_FORM_STRING: u64 = 0x08;
+const DW_FORM_SEC_OFFSET: u64 = 0x17;
+const DW_FORM_FLAG_PRESENT: u64 = 0x19;
+
+const DW_LANG_MIPS_ASSEMBLER: u16 = 0x8001;
+
+const DW_LNS_COPY: u8 = 1;
+const DW_LNS_ADVANCE_PC: u8 = 2;
+const DW_LNS_ADVANCE_LINE: u8 = 3;
+const DW_LNS_SET_FILE: u8 = 4;
+const DW_LNS_SET_COLUMN: u8 = 5;
+const DW_LNE_END_SEQUENCE: u8 = 1;
+const DW_LNE_SET_ADDRESS: u8 = 2;
+
+const ABBREV_COMPILE_UNIT: u64 = 1;
+const ABBREV_SUBPROGRAM: u64 = 2;
+
+pub fn write_uleb128(w: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            w.push(byte);
+            return;
+        }
+        w.push(byte | 0x80);
+    }
+}
+
+pub fn write_sleb128(w: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+            w.push(byte);
+            return;
+        }
+        w.push(byte | 0x80);
+    }
+}
+
+// Prefixes a CIE or FDE body with its length, padding it with DW_CFA_nop to keep records aligned.
+fn write_call_frame_record(contents: &mut Vec<u8>, body: &mut Vec<u8>, alignment: usize) -> io::Result<()> {
+    while (body.len() + 4) % alignment != 0 {
+        body.push(0);
+    }
+    try!(contents.write_u32::<LittleEndian>(body.len() as u32));
+    contents.extend_from_slice(&body[..]);
+    Ok( () )
+}
+
+fn encode_advance_loc(w: &mut Vec<u8>, delta: u32) -> io::Result<()> {
+    if delta == 0 {
+        Ok( () )
+    } else if delta < 0x40 {
+        w.write_u8(0x40 | delta as u8)
+    } else if delta <= 0xff {
+        try!(w.write_u8(0x02));
+        w.write_u8(delta as u8)
+    } else if delta <= 0xffff {
+        try!(w.write_u8(0x03));
+        w.write_u16::<LittleEndian>(delta as u16)
+    } else {
+        try!(w.write_u8(0x04));
+        w.write_u32::<LittleEndian>(delta)
+    }
+}
+
+fn encode_call_frame_instruction(w: &mut Vec<u8>, instruction: &CallFrameInstruction, data_alignment: i64) -> io::Result<()> {
+    match *instruction {
+        CallFrameInstruction::DefCfa(register, offset) => {
+            try!(w.write_u8(0x0c));
+            write_uleb128(w, register as u64);
+            write_uleb128(w, offset);
+        }
+        CallFrameInstruction::DefCfaRegister(register) => {
+            try!(w.write_u8(0x0d));
+            write_uleb128(w, register as u64);
+        }
+        CallFrameInstruction::DefCfaOffset(offset) => {
+            try!(w.write_u8(0x0e));
+            write_uleb128(w, offset);
+        }
+        CallFrameInstruction::Offset(register, offset) => {
+            if offset % data_alignment != 0 {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "register save offset is not a multiple of the data alignment"));
+            }
+            let factored = offset / data_alignment;
+            if register < 0x40 && factored >= 0 {
+                try!(w.write_u8(0x80 | register));
+                write_uleb128(w, factored as u64);
+            } else {
+                try!(w.write_u8(0x11)); // DW_CFA_offset_extended_sf
+                write_uleb128(w, register as u64);
+                write_sleb128(w, factored);
+            }
+        }
+        CallFrameInstruction::Restore(register) => {
+            if register < 0x40 {
+                try!(w.write_u8(0xc0 | register));
+            } else {
+                try!(w.write_u8(0x06)); // DW_CFA_restore_extended
+                write_uleb128(w, register as u64);
+            }
+        }
+        CallFrameInstruction::RememberState => try!(w.write_u8(0x0a)),
+        CallFrameInstruction::RestoreState => try!(w.write_u8(0x0b)),
+    }
+    Ok( () )
+}
+
+// Builds the .eh_frame contents: a single CIE followed by an FDE for each frame description.
+pub fn eh_frame(elf: &Elf, text_symbol: u32) -> io::Result<(Vec<u8>, Vec<Relocation>)> {
+    let mut contents = Vec::new();
+    let mut relocations = Vec::new();
+    if elf.frames.is_empty() {
+        return Ok((contents, relocations));
+    }
+
+    let (data_alignment, return_address) = match elf.word_size {
+        WordSize::Bits32 => (-4, 8),
+        WordSize::Bits64 => (-8, 16),
+    };
+    let stack_pointer = match elf.word_size {
+        WordSize::Bits32 => 4,
+        WordSize::Bits64 => 7,
+    };
+
+    let mut cie = Vec::new();
+    try!(cie.write_u32::<LittleEndian>(0)); // CIE id
+    try!(cie.write_u8(1)); // version
+    try!(cie.write_all(b"zR\0")); // augmentation
+    write_uleb128(&mut cie, 1); // code alignment factor
+    write_sleb128(&mut cie, data_alignment);
+    write_uleb128(&mut cie, return_address);
+    write_uleb128(&mut cie, 1); // augmentation data length
+    try!(cie.write_u8(0x1b)); // FDE pointers are pc-relative signed 4-byte values
+    // on entry, the CFA is just above the return address
+    try!(encode_call_frame_instruction(&mut cie, &CallFrameInstruction::DefCfa(stack_pointer, -data_alignment as u64), data_alignment));
+    try!(encode_call_frame_instruction(&mut cie, &CallFrameInstruction::Offset(return_address as u8, data_alignment), data_alignment));
+    try!(write_call_frame_record(&mut contents, &mut cie, elf.word_bytes() as usize));
+
+    for frame in elf.frames.iter() {
+        let record_start = contents.len();
+        let mut fde = Vec::new();
+        try!(fde.write_u32::<LittleEndian>(record_start as u32 + 4)); // distance back to the CIE
+        relocations.push(Relocation{
+            offset: (record_start + 4 + fde.len()) as u64,
+            symbol: text_symbol,
+            relocation_type: elf.relocation_types().2,
+            addend: frame.offset as i64,
+        });
+        try!(fde.write_u32::<LittleEndian>(0)); // function start, filled by the relocation
+        try!(fde.write_u32::<LittleEndian>(frame.length as u32));
+        write_uleb128(&mut fde, 0); // augmentation data length
+
+        let mut location = 0;
+        for &(offset, ref instruction) in frame.instructions.iter() {
+            if offset < location || offset > frame.length {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "call frame instruction outside of its function"));
+            }
+            try!(encode_advance_loc(&mut fde, (offset - location) as u32));
+            location = offset;
+            try!(encode_call_frame_instruction(&mut fde, instruction, data_alignment));
+        }
+        try!(write_call_frame_record(&mut contents, &mut fde, elf.word_bytes() as usize));
+    }
+
+    Ok((contents, relocations))
+}
+
+fn write_string(w: &mut Vec<u8>, s: &str) {
+    w.extend_from_slice(s.as_bytes());
+    w.push(0);
+}
+
+fn write_abbreviation(w: &mut Vec<u8>, code: u64, tag: u64, has_children: bool, attributes: &[(u64, u64)]) {
+    write_uleb128(w, code);
+    write_uleb128(w, tag);
+    w.push(has_children as u8);
+    for &(attribute, form) in attributes {
+        write_uleb128(w, attribute);
+        write_uleb128(w, form);
+    }
+    w.push(0);
+    w.push(0);
+}
+
+// (name, contents, relocations) of a section
+type DebugSection = (&'static str, Vec<u8>, Vec<Relocation>);
+
+// Builds .debug_abbrev, .debug_info and .debug_line, in that order.
+// The compilation unit covers all of .text and has a subprogram for each function.
+pub fn debug_sections(elf: &Elf, debug_info: &DebugInfo, text_symbol: u32, abbrev_symbol: u32, line_symbol: u32)
+        -> io::Result<Vec<DebugSection>> {
+    let (address_relocation, offset_relocation, _) = elf.relocation_types();
+    let text_length = elf.text_content.len();
+
+    let mut abbrev = Vec::new();
+    write_abbreviation(&mut abbrev, ABBREV_COMPILE_UNIT, DW_TAG_COMPILE_UNIT, true, &[
+        (DW_AT_PRODUCER, DW_FORM_STRING),
+        (DW_AT_LANGUAGE, DW_FORM_DATA2),
+        (DW_AT_NAME, DW_FORM_STRING),
+        (DW_AT_STMT_LIST, DW_FORM_SEC_OFFSET),
+        (DW_AT_LOW_PC, DW_FORM_ADDR),
+        (DW_AT_HIGH_PC, DW_FORM_DATA4),
+    ]);
+    write_abbreviation(&mut abbrev, ABBREV_SUBPROGRAM, DW_TAG_SUBPROGRAM, false, &[
+        (DW_AT_NAME, DW_FORM_STRING),
+        (DW_AT_EXTERNAL, DW_FORM_FLAG_PRESENT),
+        (DW_AT_LOW_PC, DW_FORM_ADDR),
+        (DW_AT_HIGH_PC, DW_FORM_DATA4),
+    ]);
+    abbrev.push(0);
+
+    let mut info = Vec::new();
+    let mut info_relocations = Vec::new();
+    try!(info.write_u32::<LittleEndian>(0)); // unit length, filled in below
+    try!(info.write_u16::<LittleEndian>(4)); // version
+    info_relocations.push(Relocation{ offset: info.len() as u64, symbol: abbrev_symbol, relocation_type: offset_relocation, addend: 0 });
+    try!(info.write_u32::<LittleEndian>(0)); // abbreviation table offset
+    try!(info.write_u8(elf.word_bytes() as u8));
+
+    write_uleb128(&mut info, ABBREV_COMPILE_UNIT);
+    write_string(&mut info, debug_info.producer);
+    try!(info.write_u16::<LittleEndian>(DW_LANG_MIPS_ASSEMBLER));
+    write_string(&mut info, debug_info.files.first().cloned().unwrap_or(elf.file_name));
+    info_relocations.push(Relocation{ offset: info.len() as u64, symbol: line_symbol, relocation_type: offset_relocation, addend: 0 });
+    try!(info.write_u32::<LittleEndian>(0)); // line program offset
+    info_relocations.push(Relocation{ offset: info.len() as u64, symbol: text_symbol, relocation_type: address_relocation, addend: 0 });
+    try!(elf.write_word(&mut info, 0));
+    try!(info.write_u32::<LittleEndian>(text_length as u32));
+
+    // Each function extends to the start of the next one
+    let mut starts: Vec<usize> = elf.functions.iter().map(|function| function.offset).collect();
+    starts.push(text_length);
+    starts.sort();
+    for function in elf.functions.iter() {
+        let end = starts.iter().cloned().find(|&start| start > function.offset).unwrap_or(text_length);
+        write_uleb128(&mut info, ABBREV_SUBPROGRAM);
+        write_string(&mut info, function.name);
+        info_relocations.push(Relocation{ offset: info.len() as u64, symbol: text_symbol, relocation_type: address_relocation, addend: function.offset as i64 });
+        try!(elf.write_word(&mut info, 0));
+        try!(info.write_u32::<LittleEndian>((end - function.offset) as u32));
+    }
+    info.push(0); // end of the compilation unit's children
+
+    let unit_length = info.len() as u32 - 4;
+    try!((&mut info[0..4]).write_u32::<LittleEndian>(unit_length));
+
+    let (line, line_relocations) = try!(line_program(elf, debug_info, text_symbol, address_relocation));
+
+    Ok(vec![
+        (".debug_abbrev", abbrev, Vec::new()),
+        (".debug_info", info, info_relocations),
+        (".debug_line", line, line_relocations),
+    ])
+}
+
+fn line_program(elf: &Elf, debug_info: &DebugInfo, text_symbol: u32, address_relocation: u32) -> io::Result<(Vec<u8>, Vec<Relocation>)> {
+    let mut line = Vec::new();
+    let mut relocations = Vec::new();
+
+    try!(line.write_u32::<LittleEndian>(0)); // unit length, filled in below
+    try!(line.write_u16::<LittleEndian>(4)); // version
+    try!(line.write_u32::<LittleEndian>(0)); // header length, filled in below
+    let header_start = line.len();
+    try!(line.write_u8(1)); // minimum instruction length
+    try!(line.write_u8(1)); // maximum operations per instruction
+    try!(line.write_u8(1)); // default is_stmt
+    try!(line.write_i8(-5)); // line base
+    try!(line.write_u8(14)); // line range
+    try!(line.write_u8(13)); // opcode base
+    try!(line.write_all(&[0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1][..])); // standard opcode lengths
+    line.push(0); // no include directories beyond the compilation directory
+    for file in debug_info.files.iter() {
+        write_string(&mut line, file);
+        write_uleb128(&mut line, 0); // directory
+        write_uleb128(&mut line, 0); // modification time
+        write_uleb128(&mut line, 0); // length
+    }
+    line.push(0);
+    let header_length = (line.len() - header_start) as u32;
+    try!((&mut line[6..10]).write_u32::<LittleEndian>(header_length));
+
+    line.push(0);
+    write_uleb128(&mut line, 1 + elf.word_bytes());
+    line.push(DW_LNE_SET_ADDRESS);
+    relocations.push(Relocation{ offset: line.len() as u64, symbol: text_symbol, relocation_type: address_relocation, addend: 0 });
+    try!(elf.write_word(&mut line, 0));
+
+    let (mut address, mut file, mut line_number, mut column) = (0, 0, 1i64, 0);
+    for row in debug_info.rows.iter() {
+        if row.offset < address || row.offset > elf.text_content.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "line rows must be in order and within .text"));
+        }
+        if row.file >= debug_info.files.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "line row refers to an unknown file"));
+        }
+        if row.file != file {
+            line.push(DW_LNS_SET_FILE);
+            write_uleb128(&mut line, row.file as u64 + 1);
+            file = row.file;
+        }
+        if row.column != column {
+            line.push(DW_LNS_SET_COLUMN);
+            write_uleb128(&mut line, row.column as u64);
+            column = row.column;
+        }
+        if row.line as i64 != line_number {
+            line.push(DW_LNS_ADVANCE_LINE);
+            write_sleb128(&mut line, row.line as i64 - line_number);
+            line_number = row.line as i64;
+        }
+        if row.offset != address {
+            line.push(DW_LNS_ADVANCE_PC);
+            write_uleb128(&mut line, (row.offset - address) as u64);
+            address = row.offset;
+        }
+        line.push(DW_LNS_COPY);
+    }
+
+    if elf.text_content.len() != address {
+        line.push(DW_LNS_ADVANCE_PC);
+        write_uleb128(&mut line, (elf.text_content.len() - address) as u64);
+    }
+    line.push(0);
+    write_uleb128(&mut line, 1);
+    line.push(DW_LNE_END_SEQUENCE);
+
+    let unit_length = line.len() as u32 - 4;
+    try!((&mut line[0..4]).write_u32::<LittleEndian>(unit_length));
+    Ok((line, relocations))
+}
+
+pub fn read_uleb128(data: &[u8], position: &mut usize) -> io::Result<u64> {
+    let mut value = 0u64;
+    let mut shift = 0;
+    loop {
+        let byte = match data.get(*position) {
+            Some(byte) => *byte,
+            None => return Err(io::Error::new(io::ErrorKind::InvalidData, "truncated LEB128 value")),
+        };
+        *position += 1;
+        if shift < 64 {
+            value |= ((byte & 0x7f) as u64) << shift;
+        }
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+pub fn read_sleb128(data: &[u8], position: &mut usize) -> io::Result<i64> {
+    let mut value = 0i64;
+    let mut shift = 0;
+    loop {
+        let byte = match data.get(*position) {
+            Some(byte) => *byte,
+            None => return Err(io::Error::new(io::ErrorKind::InvalidData, "truncated LEB128 value")),
+        };
+        *position += 1;
+        if shift < 64 {
+            value |= ((byte & 0x7f) as i64) << shift;
+        }
+        shift += 7;
+        if byte & 0x80 == 0 {
+            if shift < 64 && byte & 0x40 != 0 {
+                value |= -1i64 << shift;
+            }
+            return Ok(value);
+        }
+    }
+}
+
+fn read_fixed(data: &[u8], position: &mut usize, size: usize) -> io::Result<u64> {
+    if *position + size > data.len() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "truncated call frame instruction"));
+    }
+    let value = LittleEndian::read_uint(&data[*position..], size);
+    *position += size;
+    Ok(value)
+}
+
+fn decode_call_frame_instructions(data: &[u8], code_alignment: u64, data_alignment: i64)
+        -> io::Result<Vec<(usize, CallFrameInstruction)>> {
+    let mut instructions = Vec::new();
+    let mut location = 0;
+    let mut position = 0;
+    while position < data.len() {
+        let opcode = data[position];
+        position += 1;
+        let instruction = match (opcode >> 6, opcode & 0x3f) {
+            (1, delta) => {
+                location += delta as u64 * code_alignment;
+                continue;
+            }
+            (2, register) => CallFrameInstruction::Offset(register, try!(read_uleb128(data, &mut position)) as i64 * data_alignment),
+            (3, register) => CallFrameInstruction::Restore(register),
+            (_, 0x00) => continue, // DW_CFA_nop
+            (_, 0x02) | (_, 0x03) | (_, 0x04) => {
+                let size = 1 << (opcode - 2);
+                location += try!(read_fixed(data, &mut position, size)) * code_alignment;
+                continue;
+            }
+            (_, 0x05) => {
+                let register = try!(read_uleb128(data, &mut position)) as u8;
+                CallFrameInstruction::Offset(register, try!(read_uleb128(data, &mut position)) as i64 * data_alignment)
+            }
+            (_, 0x06) => CallFrameInstruction::Restore(try!(read_uleb128(data, &mut position)) as u8),
+            (_, 0x0a) => CallFrameInstruction::RememberState,
+            (_, 0x0b) => CallFrameInstruction::RestoreState,
+            (_, 0x0c) => {
+                let register = try!(read_uleb128(data, &mut position)) as u8;
+                CallFrameInstruction::DefCfa(register, try!(read_uleb128(data, &mut position)))
+            }
+            (_, 0x0d) => CallFrameInstruction::DefCfaRegister(try!(read_uleb128(data, &mut position)) as u8),
+            (_, 0x0e) => CallFrameInstruction::DefCfaOffset(try!(read_uleb128(data, &mut position))),
+            (_, 0x11) => {
+                let register = try!(read_uleb128(data, &mut position)) as u8;
+                CallFrameInstruction::Offset(register, try!(read_sleb128(data, &mut position)) * data_alignment)
+            }
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unsupported call frame instruction 0x{:02x}", opcode))),
+        };
+        instructions.push((location as usize, instruction));
+    }
+    Ok(instructions)
+}
+
+// Decodes the FDEs of an .eh_frame section. function_start gives the .text offset that the PC begin
+// field at a given offset in the section is relocated to. The CIEs are expected to use the "zR"
+// augmentation with pc-relative 4-byte pointers, as written by eh_frame; their initial instructions
+// are assumed to be the usual ones and are not returned.
+pub fn read_eh_frame<F>(contents: &[u8], function_start: F) -> io::Result<Vec<FrameDescription>>
+        where F: Fn(usize) -> Option<usize> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    let mut frames = Vec::new();
+    // start of each CIE -> (code alignment, data alignment)
+    let mut cies: Vec<(usize, u64, i64)> = Vec::new();
+    let mut position = 0;
+    while position + 4 <= contents.len() {
+        let length = LittleEndian::read_u32(&contents[position..]) as usize;
+        if length == 0 {
+            break;
+        }
+        let start = position + 4;
+        let end = start + length;
+        if length == 0xffffffff || end > contents.len() || length < 4 {
+            return Err(invalid("malformed .eh_frame record"));
+        }
+        let record = &contents[start..end];
+        let id = LittleEndian::read_u32(record) as usize;
+        let mut cursor = 4;
+        if id == 0 {
+            let version = record.get(cursor).cloned().unwrap_or(0);
+            cursor += 1;
+            let augmentation_length = match record[cursor..].iter().position(|b| *b == 0) {
+                Some(length) => length,
+                None => return Err(invalid("unterminated CIE augmentation")),
+            };
+            if &record[cursor..cursor + augmentation_length] != b"zR" {
+                return Err(invalid("unsupported CIE augmentation"));
+            }
+            cursor += augmentation_length + 1;
+            let code_alignment = try!(read_uleb128(record, &mut cursor));
+            let data_alignment = try!(read_sleb128(record, &mut cursor));
+            if version == 1 {
+                cursor += 1;
+            } else {
+                try!(read_uleb128(record, &mut cursor));
+            }
+            try!(read_uleb128(record, &mut cursor)); // augmentation data length
+            if record.get(cursor) != Some(&0x1b) {
+                return Err(invalid("unsupported FDE pointer encoding"));
+            }
+            cies.push((position, code_alignment, data_alignment));
+        } else {
+            let (code_alignment, data_alignment) = match cies.iter().find(|cie| cie.0 + id == start) {
+                Some(&(_, code_alignment, data_alignment)) => (code_alignment, data_alignment),
+                None => return Err(invalid("FDE refers to an unknown CIE")),
+            };
+            if record.len() < 12 {
+                return Err(invalid("truncated FDE"));
+            }
+            let offset = match function_start(start + 4) {
+                Some(offset) => offset,
+                None => return Err(invalid("FDE without a relocation for its function start")),
+            };
+            let length = LittleEndian::read_u32(&record[8..]) as usize;
+            cursor = 12;
+            let augmentation_length = try!(read_uleb128(record, &mut cursor)) as usize;
+            cursor += augmentation_length;
+            if cursor > record.len() {
+                return Err(invalid("truncated FDE"));
+            }
+            frames.push(FrameDescription{
+                offset: offset,
+                length: length,
+                instructions: try!(decode_call_frame_instructions(&record[cursor..], code_alignment, data_alignment)),
+            });
+        }
+        position = end;
+    }
+    Ok(frames)
+}
@@ -5,7 +5,10 @@ use std::io::Write;
 
 use byteorder::{LittleEndian, WriteBytesExt};
 
-#[derive(Copy, Clone)]
+mod dwarf;
+mod reader;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum WordSize {
     Bits32, Bits64
 }
@@ -30,7 +33,7 @@ impl Endianness {
         }
     }
 }
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Architecture {
     X86,
     Arm,
@@ -48,27 +51,149 @@ impl Architecture {
     }
 }
 
+pub const SHF_WRITE: u64 = 0x1;
+pub const SHF_ALLOC: u64 = 0x2;
+pub const SHF_EXECINSTR: u64 = 0x4;
+pub const SHF_TLS: u64 = 0x400;
+
+pub const PF_X: u32 = 0x1;
+pub const PF_W: u32 = 0x2;
+pub const PF_R: u32 = 0x4;
+
+const ET_REL: u16 = 1;
+const ET_EXEC: u16 = 2;
+const ET_DYN: u16 = 3;
+
+const PT_LOAD: u32 = 1;
+const PT_GNU_STACK: u32 = 0x6474e551;
+
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_RELA: u32 = 4;
+const SHT_NOTE: u32 = 7;
+const SHT_NOBITS: u32 = 8;
+const SHT_REL: u32 = 9;
+const SHT_X86_64_UNWIND: u32 = 0x70000001;
+
+const SHF_INFO_LINK: u64 = 0x40;
+
+pub const R_X86_64_64: u32 = 1;
+pub const R_X86_64_PC32: u32 = 2;
+pub const R_X86_64_PLT32: u32 = 4;
+pub const R_X86_64_GOTPCREL: u32 = 9;
+pub const R_X86_64_32: u32 = 10;
+pub const R_X86_64_32S: u32 = 11;
+pub const R_X86_64_GOTTPOFF: u32 = 22;
+pub const R_X86_64_TPOFF32: u32 = 23;
+
+pub const R_386_32: u32 = 1;
+pub const R_386_PC32: u32 = 2;
+pub const R_386_PLT32: u32 = 4;
+
+const NT_GNU_PROPERTY_TYPE_0: u32 = 5;
+const GNU_PROPERTY_X86_FEATURE_1_AND: u32 = 0xc0000002;
+
+// Bits of the x86 feature property, set when the code is compatible with the corresponding CET feature.
+pub const GNU_PROPERTY_X86_FEATURE_1_IBT: u32 = 0x1;
+pub const GNU_PROPERTY_X86_FEATURE_1_SHSTK: u32 = 0x2;
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct Function<'a> {
     pub offset: usize,
     pub name: &'a str,
 }
 
+// DWARF call frame instructions. Registers use the DWARF numbering of the target architecture and
+// offsets are in bytes; the writer takes care of factoring them by the alignment factors.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CallFrameInstruction {
+    DefCfa(u8, u64),
+    DefCfaRegister(u8),
+    DefCfaOffset(u64),
+    Offset(u8, i64), // register is saved at CFA + offset
+    Restore(u8),
+    RememberState,
+    RestoreState,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrameDescription {
+    pub offset: usize, // start of the function in .text
+    pub length: usize,
+    pub instructions: Vec<(usize, CallFrameInstruction)>, // (offset from the function start, instruction)
+}
+
+// Maps an offset in .text to a position in one of the source files of a DebugInfo
+#[derive(Clone, Debug, PartialEq)]
+pub struct LineRow {
+    pub offset: usize,
+    pub file: usize, // index into DebugInfo::files
+    pub line: u32,
+    pub column: u32,
+}
+
+pub struct DebugInfo<'a> {
+    pub producer: &'a str,
+    pub files: Vec<&'a str>, // written as given, there is no compilation directory to keep builds reproducible
+    pub rows: Vec<LineRow>, // in order of offset
+}
+
+// A relocation of .text against a global symbol, or against the start of .text itself if symbol is None.
+// Symbols that are not among the functions are written as undefined.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextRelocation<'a> {
+    pub offset: u64,
+    pub symbol: Option<&'a str>,
+    pub relocation_type: u32, // R_X86_64_*, or R_386_* in a 32-bit file
+    pub addend: i64,
+}
+
 pub struct Elf<'a> {
     pub word_size: WordSize,
     pub endianness: Endianness,
     pub architecture: Architecture,
     pub file_name: &'a str,
     pub functions: Vec<Function<'a>>,
+    // Labels in .text that are only named for debuggers and disassemblers, written as STB_LOCAL symbols
+    pub local_symbols: Vec<Function<'a>>,
     pub text_content: &'a [u8],
+    // Without a .note.GNU-stack section, GNU ld assumes the object needs an executable stack.
+    pub executable_stack: bool,
+    // GNU_PROPERTY_X86_FEATURE_1_* bits. If nonzero, a .note.gnu.property section is emitted.
+    pub x86_features: u32,
+    // Emitted as CIE/FDE records in .eh_frame
+    pub frames: Vec<FrameDescription>,
+    // Emitted as .debug_info, .debug_abbrev and .debug_line
+    pub debug_info: Option<DebugInfo<'a>>,
+    pub text_relocations: Vec<TextRelocation<'a>>,
+    pub thread_local_storage: Option<ThreadLocalStorage<'a>>,
+}
+
+// Thread-local variables. The initial values in .tdata are followed by zeroed bytes in .tbss, and the
+// symbols are STT_TLS symbols at offsets into the two together.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ThreadLocalStorage<'a> {
+    pub data: &'a [u8],
+    pub bss_size: u64,
+    pub align: u64,
+    pub symbols: Vec<Function<'a>>,
+}
+
+struct Relocation {
+    offset: u64,
+    symbol: u32,
+    relocation_type: u32,
+    addend: i64,
 }
 
 
 struct SectionHeader<'a> {
     name: &'a str,
     section_type: u32,
-    flags: u32,
+    flags: u64,
     address: u64,
-    content: &'a [u8],
+    content: Vec<u8>,
     link: u32,
     info: u32,
     align: u64,
@@ -110,16 +235,240 @@ struct Symbol<'a> {
 
 impl<'a> Elf<'a> {
     pub fn write<W: Write>(&self, w: &mut W) -> Result<(), io::Error> {
+        let mut section_headers = vec![
+            SectionHeader{
+                name: "",
+                section_type: 0,
+                flags: 0,
+                address: 0,
+                content: Vec::new(),
+                link: 0,
+                info: 0,
+                align: 0,
+                entsize: 0,
+            },
+        ];
         let mut symbols = vec![
             Symbol { name: "", offset: 0, size: 0, info: 0, other: 0, shndx: 0 }, // blank entry
             Symbol { name: self.file_name, offset: 0, size: 0, info: 4, other: 0, shndx: 0xfff1 }, // file
-            Symbol { name: "", offset: 0, size: 0, info: 3, other: 0, shndx: 1 }, // section
         ];
-        for function in self.functions.iter() {
-            symbols.push(Symbol { name: function.name, offset: function.offset as u64, size: 0, info: 0x10, other: 0, shndx: 1 });
+        // (index of the relocated section, relocations)
+        let mut relocated_sections: Vec<(usize, Vec<Relocation>)> = Vec::new();
+
+        let text_symbol = symbols.len() as u32;
+        let index_of_text = self.push_section(&mut section_headers, &mut symbols, SectionHeader{
+            name: ".text",
+            section_type: SHT_PROGBITS,
+            flags: SHF_ALLOC | SHF_EXECINSTR,
+            address: 0,
+            content: self.text_content.to_vec(),
+            link: 0,
+            info: 0,
+            align: 16,
+            entsize: 0,
+        });
+
+        let mut index_of_tdata = 0;
+        let mut index_of_tbss = 0;
+        if let Some(ref tls) = self.thread_local_storage {
+            if !tls.data.is_empty() {
+                index_of_tdata = self.push_section(&mut section_headers, &mut symbols, SectionHeader{
+                    name: ".tdata",
+                    section_type: SHT_PROGBITS,
+                    flags: SHF_ALLOC | SHF_WRITE | SHF_TLS,
+                    address: 0,
+                    content: tls.data.to_vec(),
+                    link: 0,
+                    info: 0,
+                    align: tls.align,
+                    entsize: 0,
+                });
+            }
+            if tls.bss_size != 0 {
+                // only the size of a NOBITS section is written
+                index_of_tbss = self.push_section(&mut section_headers, &mut symbols, SectionHeader{
+                    name: ".tbss",
+                    section_type: SHT_NOBITS,
+                    flags: SHF_ALLOC | SHF_WRITE | SHF_TLS,
+                    address: 0,
+                    content: vec![0; tls.bss_size as usize],
+                    link: 0,
+                    info: 0,
+                    align: tls.align,
+                    entsize: 0,
+                });
+            }
+        }
+
+        section_headers.push(SectionHeader{
+            name: ".note.GNU-stack",
+            section_type: SHT_PROGBITS,
+            flags: if self.executable_stack { SHF_EXECINSTR } else { 0 },
+            address: 0,
+            content: Vec::new(),
+            link: 0,
+            info: 0,
+            align: 1,
+            entsize: 0,
+        });
+
+        if let Some(note) = try!(self.gnu_property_note()) {
+            section_headers.push(SectionHeader{
+                name: ".note.gnu.property",
+                section_type: SHT_NOTE,
+                flags: SHF_ALLOC,
+                address: 0,
+                content: note,
+                link: 0,
+                info: 0,
+                align: self.word_bytes(),
+                entsize: 0,
+            });
+        }
+
+        if !self.frames.is_empty() {
+            let (eh_frame, relocations) = try!(dwarf::eh_frame(self, text_symbol));
+            relocated_sections.push((section_headers.len(), relocations));
+            section_headers.push(SectionHeader{
+                name: ".eh_frame",
+                section_type: SHT_X86_64_UNWIND,
+                flags: SHF_ALLOC,
+                address: 0,
+                content: eh_frame,
+                link: 0,
+                info: 0,
+                align: self.word_bytes(),
+                entsize: 0,
+            });
+        }
+
+        if let Some(ref debug_info) = self.debug_info {
+            // the sections refer to each other through their section symbols
+            let abbrev_symbol = symbols.len() as u32;
+            let line_symbol = abbrev_symbol + 1;
+            let sections = try!(dwarf::debug_sections(self, debug_info, text_symbol, abbrev_symbol, line_symbol));
+            for (name, content, relocations) in sections {
+                let header = SectionHeader{
+                    name: name,
+                    section_type: SHT_PROGBITS,
+                    flags: 0,
+                    address: 0,
+                    content: content,
+                    link: 0,
+                    info: 0,
+                    align: 1,
+                    entsize: 0,
+                };
+                let index = if name == ".debug_info" {
+                    section_headers.push(header);
+                    section_headers.len() - 1
+                } else {
+                    self.push_section(&mut section_headers, &mut symbols, header) as usize
+                };
+                if !relocations.is_empty() {
+                    relocated_sections.push((index, relocations));
+                }
+            }
+        }
+
+        for label in self.local_symbols.iter() {
+            symbols.push(Symbol { name: label.name, offset: label.offset as u64, size: 0, info: 0, other: 0, shndx: index_of_text });
+        }
+
+        // Global symbols follow the local ones: first the functions, then the thread-local variables, then
+        // the symbols that .text refers to without defining them.
+        let first_global_symbol = symbols.len() as u32;
+        let thread_locals: &[Function] = self.thread_local_storage.as_ref().map_or(&[], |tls| &tls.symbols[..]);
+        let first_undefined_symbol = first_global_symbol + (self.functions.len() + thread_locals.len()) as u32;
+        let mut undefined_symbols: Vec<&str> = Vec::new();
+        let mut text_relocations = Vec::new();
+        for relocation in self.text_relocations.iter() {
+            let symbol = match relocation.symbol {
+                None => text_symbol,
+                Some(name) => match self.functions.iter().chain(thread_locals.iter()).position(|function| function.name == name) {
+                    Some(index) => first_global_symbol + index as u32,
+                    None => {
+                        let index = match undefined_symbols.iter().position(|undefined| *undefined == name) {
+                            Some(index) => index,
+                            None => {
+                                undefined_symbols.push(name);
+                                undefined_symbols.len() - 1
+                            }
+                        };
+                        first_undefined_symbol + index as u32
+                    }
+                },
+            };
+            text_relocations.push(Relocation{
+                offset: relocation.offset,
+                symbol: symbol,
+                relocation_type: relocation.relocation_type,
+                addend: relocation.addend,
+            });
+        }
+        if !text_relocations.is_empty() {
+            relocated_sections.insert(0, (index_of_text as usize, text_relocations));
+        }
+
+        // i386 linkers only accept SHT_REL, which keeps the addend in the relocated field
+        let explicit_addends = match self.architecture {
+            Architecture::X86 => false,
+            _ => true,
         };
+        let index_of_symbol_table = (section_headers.len() + relocated_sections.len()) as u32;
+        for (section_index, relocations) in relocated_sections {
+            let (rel_name, rela_name) = match section_headers[section_index].name {
+                ".text" => (".rel.text", ".rela.text"),
+                ".eh_frame" => (".rel.eh_frame", ".rela.eh_frame"),
+                ".debug_info" => (".rel.debug_info", ".rela.debug_info"),
+                ".debug_line" => (".rel.debug_line", ".rela.debug_line"),
+                name => panic!("no relocation section name for {}", name),
+            };
+            if !explicit_addends {
+                let content = &mut section_headers[section_index].content;
+                for relocation in relocations.iter() {
+                    let at = relocation.offset as usize;
+                    if at + 4 > content.len() {
+                        return Err(io::Error::new(io::ErrorKind::InvalidInput, "relocation outside of its section"));
+                    }
+                    try!((&mut content[at..at + 4]).write_i32::<LittleEndian>(relocation.addend as i32));
+                }
+            }
+            let content = try!(self.relocation_contents(&relocations[..], explicit_addends));
+            section_headers.push(SectionHeader{
+                name: if explicit_addends { rela_name } else { rel_name },
+                section_type: if explicit_addends { SHT_RELA } else { SHT_REL },
+                flags: SHF_INFO_LINK,
+                address: 0,
+                content: content,
+                link: index_of_symbol_table,
+                info: section_index as u32,
+                align: self.word_bytes(),
+                entsize: if explicit_addends { 3 } else { 2 } * self.word_bytes(),
+            });
+        }
 
-        let index_of_section_name_table = 2u16; // of .shstrtab
+        for function in self.functions.iter() {
+            symbols.push(Symbol { name: function.name, offset: function.offset as u64, size: 0, info: 0x10, other: 0, shndx: index_of_text });
+        };
+        if let Some(ref tls) = self.thread_local_storage {
+            for variable in tls.symbols.iter() {
+                let (shndx, offset) = if variable.offset < tls.data.len() {
+                    (index_of_tdata, variable.offset)
+                } else {
+                    (index_of_tbss, variable.offset - tls.data.len())
+                };
+                symbols.push(Symbol { name: variable.name, offset: offset as u64, size: 0, info: 0x16, other: 0, shndx: shndx });
+            }
+        }
+        for name in undefined_symbols {
+            // the linker insists that thread-local variables are typed STT_TLS
+            let thread_local = self.architecture == Architecture::X8664 && self.text_relocations.iter().any(|relocation| {
+                relocation.symbol == Some(name) &&
+                    (relocation.relocation_type == R_X86_64_GOTTPOFF || relocation.relocation_type == R_X86_64_TPOFF32)
+            });
+            symbols.push(Symbol { name: name, offset: 0, size: 0, info: if thread_local { 0x16 } else { 0x10 }, other: 0, shndx: 0 });
+        }
 
         let mut symbol_string_table = StringTable::new();
         let symbol_table_contents = {
@@ -147,72 +496,58 @@ impl<'a> Elf<'a> {
             }
             symbol_table_contents
         };
- 
-        let mut section_strings = StringTable::new();
-        let mut section_headers = [
-            SectionHeader{
-                name: "",
-                section_type: 0,
-                flags: 0,
-                address: 0,
-                content: &[][..],
-                link: 0,
-                info: 0,
-                align: 0,
-                entsize: 0,
-            },
-            SectionHeader{
-                name: ".text",
-                section_type: 1,
-                flags: 6, // ???
-                address: 0,
-                content: self.text_content,
-                link: 0,
-                info: 0,
-                align: 16,
-                entsize: 0,
-            },
-            SectionHeader{
-                name: ".shstrtab",
-                section_type: 3,
-                flags: 0,
-                address: 0,
-                content: &[][..], // we'll fill this in shortly
-                link: 0,
-                info: 0,
-                align: 1,
-                entsize: 0,
-            },
-            SectionHeader{
-                name: ".symtab",
-                section_type: 2,
-                flags: 0,
-                address: 0,
-                content: &symbol_table_contents[..],
-                link: 4,
-                info: 3,
-                align: 4,
-                entsize: 0x18,
-            },
-            SectionHeader{
-                name: ".strtab",
-                section_type: 3,
-                flags: 0,
-                address: 0,
-                content: &symbol_string_table.inner[..],
-                link: 0,
-                info: 0,
-                align: 1,
-                entsize: 0,
+
+        section_headers.push(SectionHeader{
+            name: ".symtab",
+            section_type: SHT_SYMTAB,
+            flags: 0,
+            address: 0,
+            content: symbol_table_contents,
+            link: index_of_symbol_table + 1,
+            info: first_global_symbol,
+            align: self.word_bytes(),
+            entsize: match self.word_size {
+                WordSize::Bits32 => 0x10,
+                WordSize::Bits64 => 0x18,
             },
-        ];
+        });
+        section_headers.push(SectionHeader{
+            name: ".strtab",
+            section_type: SHT_STRTAB,
+            flags: 0,
+            address: 0,
+            content: symbol_string_table.inner,
+            link: 0,
+            info: 0,
+            align: 1,
+            entsize: 0,
+        });
 
+        let index_of_section_name_table = section_headers.len() as u16; // of .shstrtab
+        section_headers.push(SectionHeader{
+            name: ".shstrtab",
+            section_type: SHT_STRTAB,
+            flags: 0,
+            address: 0,
+            content: Vec::new(), // we'll fill this in shortly
+            link: 0,
+            info: 0,
+            align: 1,
+            entsize: 0,
+        });
+
+        let mut section_strings = StringTable::new();
         let mut section_name_offsets = Vec::new();
         for section_header in section_headers.iter() {
             section_name_offsets.push(section_strings.append(section_header.name));
         }
 
-        section_headers[index_of_section_name_table as usize].content = &section_strings.inner[..];
+        section_headers[index_of_section_name_table as usize].content = section_strings.inner;
+
+        let (header_size, section_header_size) = match self.word_size {
+            WordSize::Bits32 => (0x34, 0x28),
+            WordSize::Bits64 => (0x40, 0x40),
+        };
 
         try!(w.write_all(b"\x7fELF"));
         try!(w.write_u8(self.word_size.as_u8()));
@@ -220,38 +555,27 @@ impl<'a> Elf<'a> {
         try!(w.write_u8(1));
         try!(w.write_u8(0)); // operating system... we will just leave this 0
         try!(w.write_all(&[0u8; 8][..]));
-        try!(w.write_u16::<LittleEndian>(1u16)); // relocatable 
+        try!(w.write_u16::<LittleEndian>(ET_REL));
         try!(w.write_u16::<LittleEndian>(self.architecture.as_u8() as u16));
         try!(w.write_u32::<LittleEndian>(1u32)); // original ELF version
         try!(self.write_word(w, 0)); // entry point -- since this is not an executable, just 0
         try!(self.write_word(w, 0)); // program header table offset
-        try!(self.write_word(w, match self.word_size { // section header start
-            WordSize::Bits32 => 0x34,
-            WordSize::Bits64 => 0x40,
-        }));
+        try!(self.write_word(w, header_size)); // section header start
         try!(w.write_u32::<LittleEndian>(0)); // flags
-        try!(w.write_u16::<LittleEndian>(match self.word_size {
-            WordSize::Bits32 => 0x34,
-            WordSize::Bits64 => 0x40,
-        }));
+        try!(w.write_u16::<LittleEndian>(header_size as u16));
         try!(w.write_u16::<LittleEndian>(0)); // program header entry size
         try!(w.write_u16::<LittleEndian>(0)); // program header entry count
-        try!(w.write_u16::<LittleEndian>(match self.word_size {
-            WordSize::Bits32 => 0x28,
-            WordSize::Bits64 => 0x40,
-        })); // section header entry size
+        try!(w.write_u16::<LittleEndian>(section_header_size as u16)); // section header entry size
         try!(w.write_u16::<LittleEndian>(section_headers.len() as u16)); // section header entry count
-        try!(w.write_u16::<LittleEndian>(index_of_section_name_table)); // not sure yet...
+        try!(w.write_u16::<LittleEndian>(index_of_section_name_table));
 
-
-        let mut offset = 0x180;
+        let headers_end = header_size + section_header_size * section_headers.len() as u64;
+        let contents_start = (headers_end + 15) & !15;
+        let mut offset = contents_start;
         for (section_header, name_offset) in section_headers.iter().zip(section_name_offsets.iter()) {
-            if section_header.content.len() != 0 {
-                offset = (offset + 15) & !15;
-            }
             try!(w.write_u32::<LittleEndian>(*name_offset as u32));
             try!(w.write_u32::<LittleEndian>(section_header.section_type));
-            try!(self.write_word(w, section_header.flags as u64));
+            try!(self.write_word(w, section_header.flags));
             try!(self.write_word(w, section_header.address));
             try!(self.write_word(w, if section_header.content.len() > 0 { offset } else { 0 }));
             try!(self.write_word(w, section_header.content.len() as u64));
@@ -259,17 +583,191 @@ impl<'a> Elf<'a> {
             try!(w.write_u32::<LittleEndian>(section_header.info));
             try!(self.write_word(w, section_header.align));
             try!(self.write_word(w, section_header.entsize));
-            offset += section_header.content.len() as u64;
+            if section_header.section_type != SHT_NOBITS {
+                offset += (section_header.content.len() as u64 + 15) & !15;
+            }
         }
 
-        for section in section_headers.iter() {
-            try!(w.write_all(section.content));
+        try!(w.write_all(&[0u8; 16][.. (contents_start - headers_end) as usize]));
+        for section in section_headers.iter().filter(|section| section.section_type != SHT_NOBITS) {
+            try!(w.write_all(&section.content[..]));
             try!(w.write_all(&[0u8; 16][.. (16 - section.content.len()%16) % 16]));
         }
 
         Ok( () )
     }
 
+    // Adds a section along with a section symbol for relocations to refer to
+    fn push_section(&self, section_headers: &mut Vec<SectionHeader<'a>>, symbols: &mut Vec<Symbol<'a>>, header: SectionHeader<'a>) -> u16 {
+        let index = section_headers.len() as u16;
+        section_headers.push(header);
+        symbols.push(Symbol { name: "", offset: 0, size: 0, info: 3, other: 0, shndx: index });
+        index
+    }
+
+    fn gnu_property_note(&self) -> io::Result<Option<Vec<u8>>> {
+        if self.x86_features == 0 {
+            return Ok(None);
+        }
+
+        // A single GNU_PROPERTY_X86_FEATURE_1_AND property, padded out to the word size.
+        let property_size = (8 + 4 + self.word_bytes() - 1) & !(self.word_bytes() - 1);
+        let mut note = Vec::new();
+        try!(note.write_u32::<LittleEndian>(4)); // name size
+        try!(note.write_u32::<LittleEndian>(property_size as u32)); // descriptor size
+        try!(note.write_u32::<LittleEndian>(NT_GNU_PROPERTY_TYPE_0));
+        try!(note.write_all(b"GNU\0"));
+        try!(note.write_u32::<LittleEndian>(GNU_PROPERTY_X86_FEATURE_1_AND));
+        try!(note.write_u32::<LittleEndian>(4)); // property data size
+        try!(note.write_u32::<LittleEndian>(self.x86_features));
+        while note.len() % self.word_bytes() as usize != 0 {
+            note.push(0);
+        }
+        Ok(Some(note))
+    }
+
+    fn relocation_contents(&self, relocations: &[Relocation], explicit_addends: bool) -> io::Result<Vec<u8>> {
+        let mut contents = Vec::new();
+        for relocation in relocations {
+            try!(self.write_word(&mut contents, relocation.offset));
+            match self.word_size {
+                WordSize::Bits32 => try!(contents.write_u32::<LittleEndian>(relocation.symbol << 8 | (relocation.relocation_type & 0xff))),
+                WordSize::Bits64 => try!(contents.write_u64::<LittleEndian>((relocation.symbol as u64) << 32 | relocation.relocation_type as u64)),
+            }
+            if explicit_addends {
+                try!(self.write_word(&mut contents, relocation.addend as u64));
+            }
+        }
+        Ok(contents)
+    }
+
+    // Relocation types for a word-sized address, a 32-bit absolute value and a 32-bit pc-relative value
+    fn relocation_types(&self) -> (u32, u32, u32) {
+        match (self.architecture, self.word_size) {
+            (Architecture::X86, _) => (R_386_32, R_386_32, R_386_PC32),
+            (_, WordSize::Bits32) => (R_X86_64_32, R_X86_64_32, R_X86_64_PC32),
+            (_, WordSize::Bits64) => (R_X86_64_64, R_X86_64_32, R_X86_64_PC32),
+        }
+    }
+
+    fn word_bytes(&self) -> u64 {
+        match self.word_size {
+            WordSize::Bits32 => 4,
+            WordSize::Bits64 => 8,
+        }
+    }
+
+    fn write_word<W: Write>(&self, w: &mut W, value: u64) -> io::Result<()> {
+        match self.word_size {
+            WordSize::Bits64 => w.write_u64::<LittleEndian>(value),
+            WordSize::Bits32 => w.write_u32::<LittleEndian>(value as u32),
+        }
+    }
+}
+
+// A loadable segment of an executable. Segments are written in order, each starting at a file offset
+// congruent to its address modulo the page size.
+pub struct Segment<'a> {
+    pub address: u64,
+    pub flags: u32, // PF_* bits
+    pub content: &'a [u8],
+    pub memory_size: u64, // at least content.len(); the rest is zero filled
+}
+
+pub struct Executable<'a> {
+    pub word_size: WordSize,
+    pub endianness: Endianness,
+    pub architecture: Architecture,
+    pub entry: u64,
+    // Written as ET_DYN, to be loaded at an address of the kernel's choosing. Segment addresses and the
+    // entry point are then relative to that address, and nothing gets relocated.
+    pub position_independent: bool,
+    // The ELF and program headers are mapped read-only at this address, which must be page aligned
+    // and below the first segment.
+    pub headers_address: u64,
+    pub segments: Vec<Segment<'a>>,
+}
+
+const PAGE_SIZE: u64 = 0x1000;
+
+impl<'a> Executable<'a> {
+    pub fn write<W: Write>(&self, w: &mut W) -> Result<(), io::Error> {
+        let (header_size, program_header_size) = match self.word_size {
+            WordSize::Bits32 => (0x34, 0x20),
+            WordSize::Bits64 => (0x40, 0x38),
+        };
+        if self.headers_address % PAGE_SIZE != 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "headers must be mapped at a page aligned address"));
+        }
+
+        let headers_end = header_size + program_header_size * (self.segments.len() as u64 + 2);
+        // (file offset, address, file size, memory size, flags) of each PT_LOAD
+        let mut loads = vec![(0, self.headers_address, headers_end, headers_end, PF_R)];
+        let mut offset = headers_end;
+        let mut previous_end = self.headers_address + headers_end;
+        for segment in self.segments.iter() {
+            if segment.address < previous_end || (segment.memory_size as usize) < segment.content.len() {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "segments must be in order and not overlap"));
+            }
+            // the file offset has to share the address' position within a page
+            offset += (segment.address.wrapping_sub(offset)) % PAGE_SIZE;
+            loads.push((offset, segment.address, segment.content.len() as u64, segment.memory_size, segment.flags));
+            offset += segment.content.len() as u64;
+            previous_end = segment.address + segment.memory_size;
+        }
+
+        try!(w.write_all(b"\x7fELF"));
+        try!(w.write_u8(self.word_size.as_u8()));
+        try!(w.write_u8(self.endianness.as_u8()));
+        try!(w.write_u8(1));
+        try!(w.write_u8(0)); // operating system... we will just leave this 0
+        try!(w.write_all(&[0u8; 8][..]));
+        try!(w.write_u16::<LittleEndian>(if self.position_independent { ET_DYN } else { ET_EXEC }));
+        try!(w.write_u16::<LittleEndian>(self.architecture.as_u8() as u16));
+        try!(w.write_u32::<LittleEndian>(1u32)); // original ELF version
+        try!(self.write_word(w, self.entry));
+        try!(self.write_word(w, header_size)); // program header table offset
+        try!(self.write_word(w, 0)); // no section headers
+        try!(w.write_u32::<LittleEndian>(0)); // flags
+        try!(w.write_u16::<LittleEndian>(header_size as u16));
+        try!(w.write_u16::<LittleEndian>(program_header_size as u16));
+        try!(w.write_u16::<LittleEndian>(loads.len() as u16 + 1));
+        try!(w.write_u16::<LittleEndian>(0)); // section header entry size
+        try!(w.write_u16::<LittleEndian>(0)); // section header entry count
+        try!(w.write_u16::<LittleEndian>(0)); // section name table index
+
+        for &(file_offset, address, file_size, memory_size, flags) in loads.iter() {
+            try!(self.write_program_header(w, PT_LOAD, flags, file_offset, address, file_size, memory_size, PAGE_SIZE));
+        }
+        // without this, the stack would be executable
+        try!(self.write_program_header(w, PT_GNU_STACK, PF_R | PF_W, 0, 0, 0, 0, 16));
+
+        let mut position = headers_end;
+        for (segment, &(file_offset, _, _, _, _)) in self.segments.iter().zip(loads[1..].iter()) {
+            try!(w.write_all(&vec![0u8; (file_offset - position) as usize][..]));
+            try!(w.write_all(segment.content));
+            position = file_offset + segment.content.len() as u64;
+        }
+
+        Ok( () )
+    }
+
+    fn write_program_header<W: Write>(&self, w: &mut W, segment_type: u32, flags: u32, offset: u64, address: u64, file_size: u64, memory_size: u64, align: u64) -> io::Result<()> {
+        try!(w.write_u32::<LittleEndian>(segment_type));
+        if let WordSize::Bits64 = self.word_size {
+            try!(w.write_u32::<LittleEndian>(flags));
+        }
+        try!(self.write_word(w, offset));
+        try!(self.write_word(w, address)); // virtual address
+        try!(self.write_word(w, address)); // physical address
+        try!(self.write_word(w, file_size));
+        try!(self.write_word(w, memory_size));
+        if let WordSize::Bits32 = self.word_size {
+            try!(w.write_u32::<LittleEndian>(flags));
+        }
+        self.write_word(w, align)
+    }
+
     fn write_word<W: Write>(&self, w: &mut W, value: u64) -> io::Result<()> {
         match self.word_size {
             WordSize::Bits64 => w.write_u64::<LittleEndian>(value),
@@ -280,19 +778,60 @@ impl<'a> Elf<'a> {
 
 #[cfg(test)]
 mod tests {
-    use super::{Elf, Architecture, WordSize, Endianness, Function};
+    use super::{Elf, Architecture, WordSize, Endianness, Function, FrameDescription, CallFrameInstruction, DebugInfo, LineRow, TextRelocation, ThreadLocalStorage};
+    use super::{R_X86_64_PC32, R_X86_64_PLT32, R_X86_64_64, R_X86_64_TPOFF32, R_386_32, R_386_PC32};
+    use super::{SHF_ALLOC, SHF_EXECINSTR, GNU_PROPERTY_X86_FEATURE_1_IBT, GNU_PROPERTY_X86_FEATURE_1_SHSTK};
+    use byteorder::{LittleEndian, ByteOrder};
     use std::fs::File;
     use std::io::Write;
 
-    #[test]
-    fn it_works() {
-        let mut xs = Vec::new();
-        let e = Elf {
+    fn test_elf<'a>(functions: Vec<Function<'a>>, text_content: &'a [u8]) -> Elf<'a> {
+        Elf {
             architecture: Architecture::X8664,
             word_size: WordSize::Bits64,
             endianness: Endianness::LittleEndian,
             file_name: "fooasm.asm",
-            functions: vec![
+            functions: functions,
+            local_symbols: vec![],
+            text_content: text_content,
+            executable_stack: false,
+            x86_features: 0,
+            frames: vec![],
+            debug_info: None,
+            text_relocations: vec![],
+            thread_local_storage: None,
+        }
+    }
+
+    // (name, type, flags, content) for each section of a 64-bit ELF file
+    fn sections(xs: &[u8]) -> Vec<(String, u32, u64, Vec<u8>)> {
+        let shoff = LittleEndian::read_u64(&xs[0x28..]) as usize;
+        let shnum = LittleEndian::read_u16(&xs[0x3C..]) as usize;
+        let shstrndx = LittleEndian::read_u16(&xs[0x3E..]) as usize;
+        let header = |idx: usize| &xs[shoff + idx*0x40 .. shoff + (idx+1)*0x40];
+        let content = |idx: usize| {
+            let offset = LittleEndian::read_u64(&header(idx)[0x18..]) as usize;
+            let size = LittleEndian::read_u64(&header(idx)[0x20..]) as usize;
+            xs[offset..offset + size].to_vec()
+        };
+        let names = content(shstrndx);
+        (0..shnum).map(|idx| {
+            let name_start = LittleEndian::read_u32(&header(idx)[0..]) as usize;
+            let name_len = names[name_start..].iter().position(|b| *b == 0).unwrap();
+            (
+                String::from_utf8(names[name_start..name_start + name_len].to_vec()).unwrap(),
+                LittleEndian::read_u32(&header(idx)[4..]),
+                LittleEndian::read_u64(&header(idx)[8..]),
+                content(idx),
+            )
+        }).collect()
+    }
+
+    #[test]
+    fn it_works() {
+        let mut xs = Vec::new();
+        let e = test_elf(
+            vec![
                 Function{
                     name: "foo",
                     offset: 0,
@@ -302,9 +841,215 @@ mod tests {
                     offset: 6,
                 },
             ],
-            text_content: &[0xb8, 0x04, 0x00, 0x00, 0x00, 0xc3, 0xb8, 0x09, 0x00, 0x00, 0x00, 0xc3][..],
-        };
+            &[0xb8, 0x04, 0x00, 0x00, 0x00, 0xc3, 0xb8, 0x09, 0x00, 0x00, 0x00, 0xc3][..],
+        );
         e.write(&mut xs).unwrap();
         File::create("out.elf").expect("open failed").write_all(&xs[..]).expect("write failed");
     }
+
+    #[test]
+    fn section_flags() {
+        let mut xs = Vec::new();
+        test_elf(vec![], &[0xc3][..]).write(&mut xs).unwrap();
+        let sections = sections(&xs[..]);
+
+        let text = sections.iter().find(|s| s.0 == ".text").expect("no .text");
+        assert_eq!(text.2, SHF_ALLOC | SHF_EXECINSTR);
+        assert_eq!(text.3, vec![0xc3]);
+
+        let stack = sections.iter().find(|s| s.0 == ".note.GNU-stack").expect("no .note.GNU-stack");
+        assert_eq!(stack.2, 0);
+        assert!(sections.iter().all(|s| s.0 != ".note.gnu.property"));
+    }
+
+    #[test]
+    fn cet_property_note() {
+        let mut xs = Vec::new();
+        let mut e = test_elf(vec![], &[0xc3][..]);
+        e.executable_stack = true;
+        e.x86_features = GNU_PROPERTY_X86_FEATURE_1_IBT | GNU_PROPERTY_X86_FEATURE_1_SHSTK;
+        e.write(&mut xs).unwrap();
+        let sections = sections(&xs[..]);
+
+        let stack = sections.iter().find(|s| s.0 == ".note.GNU-stack").expect("no .note.GNU-stack");
+        assert_eq!(stack.2, SHF_EXECINSTR);
+
+        let note = sections.iter().find(|s| s.0 == ".note.gnu.property").expect("no .note.gnu.property");
+        assert_eq!(note.1, 7);
+        assert_eq!(note.2, SHF_ALLOC);
+        assert_eq!(&note.3[..], &[
+            4, 0, 0, 0, 16, 0, 0, 0, 5, 0, 0, 0, b'G', b'N', b'U', 0,
+            2, 0, 0, 0xc0, 4, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0,
+        ][..]);
+    }
+
+    #[test]
+    fn eh_frame() {
+        let mut xs = Vec::new();
+        let mut e = test_elf(vec![], &[0x90, 0x55, 0x48, 0x89, 0xe5, 0x5d, 0xc3][..]);
+        e.frames.push(FrameDescription{
+            offset: 1,
+            length: 6,
+            instructions: vec![
+                (1, CallFrameInstruction::DefCfaOffset(16)),
+                (1, CallFrameInstruction::Offset(6, -16)),
+                (4, CallFrameInstruction::DefCfaRegister(6)),
+            ],
+        });
+        e.write(&mut xs).unwrap();
+        let sections = sections(&xs[..]);
+
+        let eh_frame = sections.iter().find(|s| s.0 == ".eh_frame").expect("no .eh_frame");
+        assert_eq!(eh_frame.2, SHF_ALLOC);
+        assert_eq!(&eh_frame.3[..], &[
+            // CIE
+            0x14, 0, 0, 0, 0, 0, 0, 0, 1, b'z', b'R', 0, 1, 0x78, 0x10, 1, 0x1b, 0x0c, 7, 8, 0x90, 1, 0, 0,
+            // FDE
+            0x1c, 0, 0, 0, 0x1c, 0, 0, 0, 0, 0, 0, 0, 6, 0, 0, 0, 0,
+            0x41, 0x0e, 0x10, 0x86, 0x02, 0x43, 0x0d, 0x06, 0, 0, 0, 0, 0, 0, 0,
+        ][..]);
+
+        let rela = sections.iter().find(|s| s.0 == ".rela.eh_frame").expect("no .rela.eh_frame");
+        assert_eq!(&rela.3[..], &[
+            0x20, 0, 0, 0, 0, 0, 0, 0, // offset
+            2, 0, 0, 0, 2, 0, 0, 0, // R_X86_64_PC32 against the .text section symbol
+            1, 0, 0, 0, 0, 0, 0, 0, // addend
+        ][..]);
+    }
+
+    #[test]
+    fn debug_info() {
+        let mut xs = Vec::new();
+        let mut elf = test_elf(vec![Function{ name: "foo", offset: 0 }], &[0x90, 0xc3][..]);
+        elf.debug_info = Some(DebugInfo{
+            producer: "rassembler",
+            files: vec!["foo.rs"],
+            rows: vec![
+                LineRow{ offset: 0, file: 0, line: 3, column: 5 },
+                LineRow{ offset: 1, file: 0, line: 4, column: 5 },
+            ],
+        });
+        elf.write(&mut xs).unwrap();
+        let sections = sections(&xs[..]);
+        let names: Vec<&str> = sections.iter().map(|s| &s.0[..]).collect();
+        for name in &[".debug_abbrev", ".debug_info", ".debug_line", ".rela.debug_info", ".rela.debug_line"] {
+            assert!(names.contains(name), "missing {}", name);
+        }
+
+        let line = &sections.iter().find(|s| s.0 == ".debug_line").unwrap().3;
+        assert_eq!(LittleEndian::read_u32(&line[0..]) as usize, line.len() - 4);
+        assert_eq!(LittleEndian::read_u16(&line[4..]), 4);
+        let header_end = 10 + LittleEndian::read_u32(&line[6..]) as usize;
+        assert_eq!(&line[header_end - 11..header_end], &b"foo.rs\0\0\0\0\0"[..]); // the single file, then the terminator
+        assert_eq!(&line[header_end + 11..], &[
+            0x05, 5, // column 5
+            0x03, 2, // line 3
+            0x01,
+            0x03, 1, // line 4
+            0x02, 1, // address 1
+            0x01,
+            0x02, 1, // end of .text
+            0x00, 1, 0x01,
+        ][..]);
+    }
+
+    #[test]
+    fn read_round_trip() {
+        let text = [0xe8, 0, 0, 0, 0, 0xe8, 0, 0, 0, 0, 0x48, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0xc3, 0x55, 0x5d, 0xc3];
+        let mut elf = test_elf(vec![Function{ name: "foo", offset: 0 }, Function{ name: "bar", offset: 21 }], &text[..]);
+        elf.local_symbols = vec![Function{ name: "bar_body", offset: 22 }];
+        elf.x86_features = GNU_PROPERTY_X86_FEATURE_1_IBT;
+        elf.frames.push(FrameDescription{
+            offset: 21,
+            length: 3,
+            instructions: vec![
+                (1, CallFrameInstruction::DefCfaOffset(16)),
+                (1, CallFrameInstruction::Offset(6, -16)),
+                (2, CallFrameInstruction::DefCfaOffset(8)),
+            ],
+        });
+        elf.text_relocations = vec![
+            TextRelocation{ offset: 1, symbol: Some("bar"), relocation_type: R_X86_64_PC32, addend: -4 },
+            TextRelocation{ offset: 6, symbol: Some("elsewhere"), relocation_type: R_X86_64_PLT32, addend: -4 },
+            TextRelocation{ offset: 12, symbol: None, relocation_type: R_X86_64_64, addend: 21 },
+        ];
+        let mut xs = Vec::new();
+        elf.write(&mut xs).unwrap();
+
+        let read = Elf::read(&xs[..]).unwrap();
+        assert_eq!(read.word_size, WordSize::Bits64);
+        assert_eq!(read.architecture, Architecture::X8664);
+        assert_eq!(read.file_name, "fooasm.asm");
+        assert_eq!(read.text_content, &text[..]);
+        assert_eq!(read.functions, elf.functions);
+        assert_eq!(read.local_symbols, elf.local_symbols);
+        assert_eq!(read.executable_stack, false);
+        assert_eq!(read.x86_features, GNU_PROPERTY_X86_FEATURE_1_IBT);
+        assert_eq!(read.frames, elf.frames);
+        assert_eq!(read.text_relocations, elf.text_relocations);
+        assert!(read.debug_info.is_none());
+    }
+
+    #[test]
+    fn thread_local_storage() {
+        let text = [0x64, 0x8b, 0x04, 0x25, 0, 0, 0, 0, 0xc3];
+        let data = [1, 0, 0, 0, 0, 0, 0, 0];
+        let mut elf = test_elf(vec![Function{ name: "foo", offset: 0 }], &text[..]);
+        elf.thread_local_storage = Some(ThreadLocalStorage{
+            data: &data[..],
+            bss_size: 16,
+            align: 8,
+            symbols: vec![Function{ name: "initialized", offset: 0 }, Function{ name: "zeroed", offset: 16 }],
+        });
+        elf.text_relocations = vec![
+            TextRelocation{ offset: 4, symbol: Some("zeroed"), relocation_type: R_X86_64_TPOFF32, addend: 0 },
+        ];
+        let mut xs = Vec::new();
+        elf.write(&mut xs).unwrap();
+
+        // .tbss takes no room in the file, so everything after it still lines up
+        let read = Elf::read(&xs[..]).unwrap();
+        assert_eq!(read.text_content, &text[..]);
+        assert_eq!(read.thread_local_storage, elf.thread_local_storage);
+        assert_eq!(read.text_relocations, elf.text_relocations);
+    }
+
+    #[test]
+    fn i386_relocations() {
+        let text = [0xe8, 0, 0, 0, 0, 0xb8, 0, 0, 0, 0, 0xc3];
+        let mut elf = test_elf(vec![Function{ name: "foo", offset: 0 }], &text[..]);
+        elf.architecture = Architecture::X86;
+        elf.word_size = WordSize::Bits32;
+        elf.frames.push(FrameDescription{ offset: 0, length: 11, instructions: vec![] });
+        elf.text_relocations = vec![
+            TextRelocation{ offset: 1, symbol: Some("bar"), relocation_type: R_386_PC32, addend: -4 },
+            TextRelocation{ offset: 6, symbol: None, relocation_type: R_386_32, addend: 10 },
+        ];
+        let mut xs = Vec::new();
+        elf.write(&mut xs).unwrap();
+        assert_eq!(xs[4], 1); // ELFCLASS32
+        assert_eq!(LittleEndian::read_u16(&xs[0x12..]), 3); // EM_386
+
+        // section headers of a 32-bit file are smaller, and the relocations have no explicit addend
+        let shoff = LittleEndian::read_u32(&xs[0x20..]) as usize;
+        let shnum = LittleEndian::read_u16(&xs[0x30..]) as usize;
+        let types: Vec<u32> = (0..shnum).map(|idx| LittleEndian::read_u32(&xs[shoff + idx*0x28 + 4..])).collect();
+        assert_eq!(types.iter().filter(|t| **t == 9).count(), 2); // .rel.text and .rel.eh_frame
+        assert!(!types.contains(&4));
+
+        // the addends end up in the code, and read back from there
+        let read = Elf::read(&xs[..]).unwrap();
+        assert_eq!(read.text_content, &[0xe8, 0xfc, 0xff, 0xff, 0xff, 0xb8, 10, 0, 0, 0, 0xc3][..]);
+        assert_eq!(read.text_relocations, elf.text_relocations);
+        assert_eq!(read.frames, elf.frames);
+    }
+
+    #[test]
+    fn read_rejects_other_files() {
+        assert!(Elf::read(&b"MZ\x90\x00"[..]).is_err());
+        let mut xs = Vec::new();
+        test_elf(vec![], &[0xc3][..]).write(&mut xs).unwrap();
+        xs.truncate(0x80);
+        assert!(Elf::read(&xs[..]).is_err());
+    }
 }
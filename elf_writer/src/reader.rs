@@ -0,0 +1,376 @@
+// Parses relocatable files such as the ones Elf::write produces back into an Elf.
+
+use std::io;
+use std::str;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use {Elf, WordSize, Endianness, Architecture, Function, TextRelocation, ThreadLocalStorage, dwarf};
+use {ET_REL, SHT_SYMTAB, SHT_RELA, SHT_NOTE, SHF_EXECINSTR, NT_GNU_PROPERTY_TYPE_0, GNU_PROPERTY_X86_FEATURE_1_AND};
+
+const SHT_NOBITS: u32 = 8;
+const SHT_REL: u32 = 9;
+
+const STT_NOTYPE: u8 = 0;
+const STT_SECTION: u8 = 3;
+const STT_FILE: u8 = 4;
+const STT_TLS: u8 = 6;
+const STB_LOCAL: u8 = 0;
+
+struct RawSection<'a> {
+    name: &'a str,
+    section_type: u32,
+    flags: u64,
+    content: &'a [u8],
+    size: u64, // differs from the length of the content for NOBITS sections
+    link: u32,
+    info: u32,
+    align: u64,
+}
+
+struct RawSymbol<'a> {
+    name: &'a str,
+    value: u64,
+    info: u8,
+    shndx: u16,
+}
+
+struct RawRelocation {
+    offset: u64,
+    symbol: u32,
+    relocation_type: u32,
+    addend: i64,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn range(data: &[u8], offset: u64, size: u64) -> io::Result<&[u8]> {
+    let end = offset.checked_add(size);
+    match end {
+        Some(end) if end <= data.len() as u64 => Ok(&data[offset as usize..end as usize]),
+        _ => Err(invalid("section or table extends past the end of the file")),
+    }
+}
+
+fn string_at(table: &[u8], offset: u32) -> io::Result<&str> {
+    let tail = match table.get(offset as usize..) {
+        Some(tail) => tail,
+        None => return Err(invalid("string table offset out of range")),
+    };
+    let length = match tail.iter().position(|b| *b == 0) {
+        Some(length) => length,
+        None => return Err(invalid("unterminated string")),
+    };
+    str::from_utf8(&tail[..length]).map_err(|_| invalid("string is not valid UTF-8"))
+}
+
+impl<'a> Elf<'a> {
+    // Reads a little-endian relocatable file. The functions are the global symbols defined in .text, the
+    // local symbols the named local ones, and only relocations of .text become text_relocations; a
+    // relocation against a local symbol in .text is turned into one against .text itself. Debug sections
+    // are not read, so debug_info is always None. The thread-local variables are the global STT_TLS
+    // symbols in .tdata and .tbss.
+    pub fn read(data: &'a [u8]) -> io::Result<Elf<'a>> {
+        if data.len() < 0x34 || &data[0..4] != b"\x7fELF" {
+            return Err(invalid("not an ELF file"));
+        }
+        let word_size = match data[4] {
+            1 => WordSize::Bits32,
+            2 => WordSize::Bits64,
+            _ => return Err(invalid("unknown ELF class")),
+        };
+        if data[5] != Endianness::LittleEndian.as_u8() {
+            return Err(invalid("only little-endian ELF files can be read"));
+        }
+        if LittleEndian::read_u16(&data[0x10..]) != ET_REL {
+            return Err(invalid("not a relocatable ELF file"));
+        }
+        let machine = LittleEndian::read_u16(&data[0x12..]);
+        let architecture = match [Architecture::X86, Architecture::Arm, Architecture::X8664, Architecture::Avr].iter()
+                .find(|architecture| architecture.as_u8() as u16 == machine) {
+            Some(architecture) => *architecture,
+            None => return Err(invalid("unsupported machine")),
+        };
+
+        let word = |bytes: &[u8]| match word_size {
+            WordSize::Bits32 => LittleEndian::read_u32(bytes) as u64,
+            WordSize::Bits64 => LittleEndian::read_u64(bytes),
+        };
+        let w = word_size_bytes(word_size);
+        if data.len() < 0x40 && w == 8 {
+            return Err(invalid("truncated ELF header"));
+        }
+        let (section_header_offset, entry_size, count, names_index) = match word_size {
+            WordSize::Bits32 => (word(&data[0x20..]), LittleEndian::read_u16(&data[0x2E..]), LittleEndian::read_u16(&data[0x30..]), LittleEndian::read_u16(&data[0x32..])),
+            WordSize::Bits64 => (word(&data[0x28..]), LittleEndian::read_u16(&data[0x3A..]), LittleEndian::read_u16(&data[0x3C..]), LittleEndian::read_u16(&data[0x3E..])),
+        };
+        if entry_size as usize != 16 + 6 * w {
+            return Err(invalid("unexpected section header size"));
+        }
+        let headers = try!(range(data, section_header_offset, entry_size as u64 * count as u64));
+
+        // (name offset, section) pairs, named once the section name table is known
+        let mut sections = Vec::new();
+        for header in headers.chunks(entry_size as usize) {
+            let section_type = LittleEndian::read_u32(&header[4..]);
+            let fields = &header[8..];
+            let size = word(&fields[3 * w..]);
+            let content = if section_type == SHT_NOBITS || size == 0 {
+                &data[0..0]
+            } else {
+                try!(range(data, word(&fields[2 * w..]), size))
+            };
+            sections.push((LittleEndian::read_u32(header), RawSection{
+                name: "",
+                section_type: section_type,
+                flags: word(fields),
+                content: content,
+                size: size,
+                link: LittleEndian::read_u32(&fields[4 * w..]),
+                info: LittleEndian::read_u32(&fields[4 * w + 4..]),
+                align: word(&fields[4 * w + 8..]),
+            }));
+        }
+        let names = match sections.get(names_index as usize) {
+            Some(&(_, ref section)) => section.content,
+            None => return Err(invalid("section name table index out of range")),
+        };
+        let mut named_sections = Vec::new();
+        for (name_offset, mut section) in sections {
+            section.name = try!(string_at(names, name_offset));
+            named_sections.push(section);
+        }
+        let sections = named_sections;
+
+        let index_of_text = sections.iter().position(|section| section.name == ".text");
+        let text_content = match index_of_text {
+            Some(index) => sections[index].content,
+            None => &data[0..0],
+        };
+
+        let symbols = match sections.iter().find(|section| section.section_type == SHT_SYMTAB) {
+            Some(symbol_table) => {
+                let strings = match sections.get(symbol_table.link as usize) {
+                    Some(section) => section.content,
+                    None => return Err(invalid("symbol string table index out of range")),
+                };
+                try!(read_symbols(symbol_table.content, strings, word_size))
+            }
+            None => Vec::new(),
+        };
+        let in_text = |symbol: &RawSymbol| Some(symbol.shndx as usize) == index_of_text;
+
+        let file_name = symbols.iter().find(|symbol| symbol.info & 0xf == STT_FILE).map(|symbol| symbol.name).unwrap_or("");
+        let functions = symbols.iter()
+            .filter(|symbol| symbol.info >> 4 != STB_LOCAL && in_text(symbol))
+            .map(|symbol| Function{ name: symbol.name, offset: symbol.value as usize })
+            .collect();
+        let local_symbols = symbols.iter()
+            .filter(|symbol| symbol.info >> 4 == STB_LOCAL && symbol.info & 0xf == STT_NOTYPE && !symbol.name.is_empty() && in_text(symbol))
+            .map(|symbol| Function{ name: symbol.name, offset: symbol.value as usize })
+            .collect();
+
+        let index_of_tdata = sections.iter().position(|section| section.name == ".tdata");
+        let index_of_tbss = sections.iter().position(|section| section.name == ".tbss");
+        let thread_local_storage = if index_of_tdata.is_some() || index_of_tbss.is_some() {
+            let data = index_of_tdata.map_or(&data[0..0], |index| sections[index].content);
+            let bss_size = index_of_tbss.map_or(0, |index| sections[index].size);
+            let align = [index_of_tdata, index_of_tbss].iter().filter_map(|index| index.map(|index| sections[index].align)).max().unwrap_or(1);
+            let symbols = symbols.iter()
+                .filter(|symbol| symbol.info >> 4 != STB_LOCAL && symbol.info & 0xf == STT_TLS)
+                .filter_map(|symbol| {
+                    let shndx = Some(symbol.shndx as usize);
+                    if shndx == index_of_tdata {
+                        Some(Function{ name: symbol.name, offset: symbol.value as usize })
+                    } else if shndx == index_of_tbss {
+                        Some(Function{ name: symbol.name, offset: data.len() + symbol.value as usize })
+                    } else {
+                        None
+                    }
+                })
+                .collect();
+            Some(ThreadLocalStorage{ data: data, bss_size: bss_size, align: align, symbols: symbols })
+        } else {
+            None
+        };
+
+        // Without the note, GNU ld assumes the object needs an executable stack
+        let executable_stack = match sections.iter().find(|section| section.name == ".note.GNU-stack") {
+            Some(section) => section.flags & SHF_EXECINSTR != 0,
+            None => true,
+        };
+
+        let mut x86_features = 0;
+        for section in sections.iter().filter(|section| section.section_type == SHT_NOTE) {
+            if let Some(features) = try!(read_x86_features(section.content, w)) {
+                x86_features = features;
+            }
+        }
+
+        let mut text_relocations = Vec::new();
+        let mut frames = Vec::new();
+        for section in sections.iter().filter(|section| section.section_type == SHT_RELA || section.section_type == SHT_REL) {
+            let target = match sections.get(section.info as usize) {
+                Some(target) => target,
+                None => return Err(invalid("relocated section index out of range")),
+            };
+            let relocations = try!(read_relocations(section, target, word_size));
+            let symbol = |index: u32| match symbols.get(index as usize) {
+                Some(symbol) => Ok(symbol),
+                None => Err(invalid("relocation symbol index out of range")),
+            };
+            if target.name == ".text" {
+                for relocation in relocations {
+                    let symbol = try!(symbol(relocation.symbol));
+                    text_relocations.push(if symbol.info >> 4 == STB_LOCAL {
+                        if !in_text(symbol) {
+                            return Err(invalid("relocation of .text against a local symbol outside of .text"));
+                        }
+                        TextRelocation{
+                            offset: relocation.offset,
+                            symbol: None,
+                            relocation_type: relocation.relocation_type,
+                            addend: relocation.addend + symbol.value as i64,
+                        }
+                    } else {
+                        TextRelocation{
+                            offset: relocation.offset,
+                            symbol: Some(symbol.name),
+                            relocation_type: relocation.relocation_type,
+                            addend: relocation.addend,
+                        }
+                    });
+                }
+            } else if target.name == ".eh_frame" {
+                // the start of each function, keyed by the offset of the FDE field that refers to it
+                let mut starts = Vec::new();
+                for relocation in relocations {
+                    let symbol = try!(symbol(relocation.symbol));
+                    if in_text(symbol) {
+                        starts.push((relocation.offset as usize, (symbol.value as i64 + relocation.addend) as usize));
+                    }
+                }
+                frames = try!(dwarf::read_eh_frame(target.content, |field| {
+                    starts.iter().find(|start| start.0 == field).map(|start| start.1)
+                }));
+            }
+        }
+
+        Ok(Elf{
+            word_size: word_size,
+            endianness: Endianness::LittleEndian,
+            architecture: architecture,
+            file_name: file_name,
+            functions: functions,
+            local_symbols: local_symbols,
+            text_content: text_content,
+            executable_stack: executable_stack,
+            x86_features: x86_features,
+            frames: frames,
+            debug_info: None,
+            text_relocations: text_relocations,
+            thread_local_storage: thread_local_storage,
+        })
+    }
+}
+
+fn word_size_bytes(word_size: WordSize) -> usize {
+    match word_size {
+        WordSize::Bits32 => 4,
+        WordSize::Bits64 => 8,
+    }
+}
+
+fn read_symbols<'a>(table: &'a [u8], strings: &'a [u8], word_size: WordSize) -> io::Result<Vec<RawSymbol<'a>>> {
+    let entry_size = match word_size {
+        WordSize::Bits32 => 0x10,
+        WordSize::Bits64 => 0x18,
+    };
+    let mut symbols = Vec::new();
+    for entry in table.chunks(entry_size) {
+        if entry.len() < entry_size {
+            return Err(invalid("truncated symbol table"));
+        }
+        let (value, info, shndx) = match word_size {
+            WordSize::Bits32 => (LittleEndian::read_u32(&entry[4..]) as u64, entry[12], LittleEndian::read_u16(&entry[14..])),
+            WordSize::Bits64 => (LittleEndian::read_u64(&entry[8..]), entry[4], LittleEndian::read_u16(&entry[6..])),
+        };
+        symbols.push(RawSymbol{
+            name: if info & 0xf == STT_SECTION { "" } else { try!(string_at(strings, LittleEndian::read_u32(entry))) },
+            value: value,
+            info: info,
+            shndx: shndx,
+        });
+    }
+    Ok(symbols)
+}
+
+// SHT_REL relocations keep their addend in the relocated field, which is read back as a 32-bit value.
+fn read_relocations(section: &RawSection, target: &RawSection, word_size: WordSize) -> io::Result<Vec<RawRelocation>> {
+    let w = word_size_bytes(word_size);
+    let explicit_addend = section.section_type == SHT_RELA;
+    let entry_size = if explicit_addend { 3 * w } else { 2 * w };
+    let mut relocations = Vec::new();
+    for entry in section.content.chunks(entry_size) {
+        if entry.len() < entry_size {
+            return Err(invalid("truncated relocation table"));
+        }
+        let (offset, symbol, relocation_type) = match word_size {
+            WordSize::Bits32 => {
+                let info = LittleEndian::read_u32(&entry[4..]);
+                (LittleEndian::read_u32(entry) as u64, info >> 8, info & 0xff)
+            }
+            WordSize::Bits64 => {
+                let info = LittleEndian::read_u64(&entry[8..]);
+                (LittleEndian::read_u64(entry), (info >> 32) as u32, info as u32)
+            }
+        };
+        let addend = if explicit_addend {
+            match word_size {
+                WordSize::Bits32 => LittleEndian::read_i32(&entry[8..]) as i64,
+                WordSize::Bits64 => LittleEndian::read_i64(&entry[16..]),
+            }
+        } else {
+            LittleEndian::read_i32(try!(range(target.content, offset, 4))) as i64
+        };
+        relocations.push(RawRelocation{
+            offset: offset,
+            symbol: symbol,
+            relocation_type: relocation_type,
+            addend: addend,
+        });
+    }
+    Ok(relocations)
+}
+
+// Looks for a GNU_PROPERTY_X86_FEATURE_1_AND property among the notes of a section
+fn read_x86_features(notes: &[u8], word_bytes: usize) -> io::Result<Option<u32>> {
+    let align = |n: usize, alignment: usize| (n + alignment - 1) & !(alignment - 1);
+    let mut position = 0;
+    while position + 12 <= notes.len() {
+        let name_size = LittleEndian::read_u32(&notes[position..]) as usize;
+        let descriptor_size = LittleEndian::read_u32(&notes[position + 4..]) as usize;
+        let note_type = LittleEndian::read_u32(&notes[position + 8..]);
+        let name = try!(range(notes, position as u64 + 12, name_size as u64));
+        let descriptor_start = position + 12 + align(name_size, 4);
+        let descriptor = try!(range(notes, descriptor_start as u64, descriptor_size as u64));
+        position = descriptor_start + align(descriptor_size, 4);
+
+        if note_type != NT_GNU_PROPERTY_TYPE_0 || name != b"GNU\0" {
+            continue;
+        }
+        let mut property = 0;
+        while property + 8 <= descriptor.len() {
+            let property_type = LittleEndian::read_u32(&descriptor[property..]);
+            let size = LittleEndian::read_u32(&descriptor[property + 4..]) as usize;
+            let value = try!(range(descriptor, property as u64 + 8, size as u64));
+            if property_type == GNU_PROPERTY_X86_FEATURE_1_AND && size == 4 {
+                return Ok(Some(LittleEndian::read_u32(value)));
+            }
+            property += 8 + align(size, word_bytes);
+        }
+    }
+    Ok(None)
+}
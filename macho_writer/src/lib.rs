@@ -0,0 +1,362 @@
+extern crate byteorder;
+
+use std::io;
+use std::io::Write;
+use byteorder::{LittleEndian, WriteBytesExt};
+
+pub const CPU_TYPE_X86_64: u32 = 0x01000007;
+pub const CPU_SUBTYPE_X86_64_ALL: u32 = 3;
+
+pub const PLATFORM_MACOS: u32 = 1;
+
+pub const S_REGULAR: u32 = 0x0;
+pub const S_ZEROFILL: u32 = 0x1;
+pub const S_ATTR_PURE_INSTRUCTIONS: u32 = 0x80000000;
+pub const S_ATTR_SOME_INSTRUCTIONS: u32 = 0x400;
+
+pub const X86_64_RELOC_UNSIGNED: u8 = 0;
+pub const X86_64_RELOC_SIGNED: u8 = 1;
+pub const X86_64_RELOC_BRANCH: u8 = 2;
+pub const X86_64_RELOC_GOT_LOAD: u8 = 3;
+pub const X86_64_RELOC_GOT: u8 = 4;
+
+const MH_MAGIC_64: u32 = 0xfeedfacf;
+const MH_OBJECT: u32 = 0x1;
+const MH_SUBSECTIONS_VIA_SYMBOLS: u32 = 0x2000;
+
+const LC_SYMTAB: u32 = 0x2;
+const LC_DYSYMTAB: u32 = 0xb;
+const LC_SEGMENT_64: u32 = 0x19;
+const LC_BUILD_VERSION: u32 = 0x32;
+
+const N_UNDF: u8 = 0x0;
+const N_EXT: u8 = 0x1;
+const N_SECT: u8 = 0xe;
+
+const HEADER_SIZE: u32 = 32;
+const SEGMENT_COMMAND_SIZE: u32 = 72;
+const SECTION_HEADER_SIZE: u32 = 80;
+const BUILD_VERSION_COMMAND_SIZE: u32 = 24;
+const SYMTAB_COMMAND_SIZE: u32 = 24;
+const DYSYMTAB_COMMAND_SIZE: u32 = 80;
+const RELOCATION_SIZE: u32 = 8;
+
+pub struct Relocation {
+    pub offset: u32, // within the section
+    // Index into Macho::symbols if external, otherwise the 1-based number of the section referred to
+    pub symbol: u32,
+    pub external: bool,
+    pub pc_relative: bool,
+    pub length: u8, // log2 of the size of the relocated field
+    pub relocation_type: u8,
+}
+
+pub struct Section {
+    pub segment_name: String, // e.g. "__TEXT"
+    pub section_name: String, // e.g. "__text"
+    pub data: Vec<u8>,
+    pub align: u32, // log2 of the alignment
+    pub flags: u32,
+    pub relocations: Vec<Relocation>,
+}
+
+pub struct Symbol {
+    pub name: String, // C symbols are expected to carry their leading underscore already
+    pub section: u8, // 1-based section number, or 0 if the symbol is undefined
+    pub value: u64, // address; the first section starts at address 0
+    pub external: bool,
+}
+
+// Versions are encoded as xxxx.yy.zz nibbles, e.g. 0x000a0c00 for 10.12
+pub struct BuildVersion {
+    pub platform: u32,
+    pub minimum_os: u32,
+    pub sdk: u32,
+}
+
+pub struct Macho {
+    pub cpu_type: u32,
+    pub cpu_subtype: u32,
+    pub sections: Vec<Section>,
+    pub symbols: Vec<Symbol>,
+    pub build_version: Option<BuildVersion>,
+}
+
+fn align_to(value: u32, alignment: u32) -> u32 {
+    (value + alignment - 1) & !(alignment - 1)
+}
+
+fn write_name<W: Write>(w: &mut W, name: &str) -> io::Result<()> {
+    let bytes = name.as_bytes();
+    if bytes.len() > 16 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Mach-O segment and section names are limited to 16 bytes"));
+    }
+    try!(w.write_all(bytes));
+    w.write_all(&[0u8; 16][bytes.len()..])
+}
+
+impl Macho {
+    pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        // The symbol table must list local symbols, then defined external ones, then undefined ones.
+        let mut symbol_order: Vec<usize> = (0..self.symbols.len()).collect();
+        symbol_order.sort_by_key(|&index| {
+            let symbol = &self.symbols[index];
+            match (symbol.external, symbol.section) {
+                (false, _) => 0,
+                (true, 0) => 2,
+                (true, _) => 1,
+            }
+        });
+        let mut new_symbol_index = vec![0; self.symbols.len()];
+        for (new_index, &old_index) in symbol_order.iter().enumerate() {
+            new_symbol_index[old_index] = new_index as u32;
+        }
+        let local_count = self.symbols.iter().filter(|symbol| !symbol.external).count() as u32;
+        let undefined_count = self.symbols.iter().filter(|symbol| symbol.external && symbol.section == 0).count() as u32;
+        let defined_external_count = self.symbols.len() as u32 - local_count - undefined_count;
+
+        let section_count = self.sections.len() as u32;
+        let mut command_count = 3;
+        let mut commands_size = SEGMENT_COMMAND_SIZE + SECTION_HEADER_SIZE * section_count + SYMTAB_COMMAND_SIZE + DYSYMTAB_COMMAND_SIZE;
+        if self.build_version.is_some() {
+            command_count += 1;
+            commands_size += BUILD_VERSION_COMMAND_SIZE;
+        }
+
+        // Section contents follow the load commands, each at its own alignment. The segment starts at
+        // address 0, so aligning it for the most aligned section keeps addresses and file offsets in step.
+        let max_alignment = self.sections.iter().map(|section| 1 << section.align).max().unwrap_or(1);
+        let contents_start = align_to(HEADER_SIZE + commands_size, max_alignment);
+        let mut section_offsets = Vec::new();
+        let mut end = contents_start;
+        for section in self.sections.iter() {
+            end = align_to(end, 1 << section.align);
+            section_offsets.push(end);
+            end += section.data.len() as u32;
+        }
+        let segment_size = end - contents_start;
+
+        let mut relocation_offsets = Vec::new();
+        end = align_to(end, 4);
+        for section in self.sections.iter() {
+            relocation_offsets.push(end);
+            end += RELOCATION_SIZE * section.relocations.len() as u32;
+        }
+
+        let mut strings = vec![0u8];
+        let mut symbol_table = Vec::new();
+        for &index in symbol_order.iter() {
+            let symbol = &self.symbols[index];
+            try!(symbol_table.write_u32::<LittleEndian>(strings.len() as u32));
+            strings.extend_from_slice(symbol.name.as_bytes());
+            strings.push(0);
+            let symbol_type = if symbol.section == 0 { N_UNDF } else { N_SECT };
+            try!(symbol_table.write_u8(symbol_type | if symbol.external { N_EXT } else { 0 }));
+            try!(symbol_table.write_u8(symbol.section));
+            try!(symbol_table.write_u16::<LittleEndian>(0)); // description
+            try!(symbol_table.write_u64::<LittleEndian>(symbol.value));
+        }
+        while strings.len() % 8 != 0 {
+            strings.push(0);
+        }
+        let symbol_table_offset = align_to(end, 8);
+        let string_table_offset = symbol_table_offset + symbol_table.len() as u32;
+
+        try!(w.write_u32::<LittleEndian>(MH_MAGIC_64));
+        try!(w.write_u32::<LittleEndian>(self.cpu_type));
+        try!(w.write_u32::<LittleEndian>(self.cpu_subtype));
+        try!(w.write_u32::<LittleEndian>(MH_OBJECT));
+        try!(w.write_u32::<LittleEndian>(command_count));
+        try!(w.write_u32::<LittleEndian>(commands_size));
+        try!(w.write_u32::<LittleEndian>(MH_SUBSECTIONS_VIA_SYMBOLS));
+        try!(w.write_u32::<LittleEndian>(0)); // reserved
+
+        // Object files put all their sections in a single unnamed segment
+        try!(w.write_u32::<LittleEndian>(LC_SEGMENT_64));
+        try!(w.write_u32::<LittleEndian>(SEGMENT_COMMAND_SIZE + SECTION_HEADER_SIZE * section_count));
+        try!(write_name(w, ""));
+        try!(w.write_u64::<LittleEndian>(0)); // address
+        try!(w.write_u64::<LittleEndian>(segment_size as u64));
+        try!(w.write_u64::<LittleEndian>(contents_start as u64));
+        try!(w.write_u64::<LittleEndian>(segment_size as u64));
+        try!(w.write_u32::<LittleEndian>(7)); // maximum protection: rwx
+        try!(w.write_u32::<LittleEndian>(7)); // initial protection
+        try!(w.write_u32::<LittleEndian>(section_count));
+        try!(w.write_u32::<LittleEndian>(0)); // flags
+
+        for (i, section) in self.sections.iter().enumerate() {
+            try!(write_name(w, &section.section_name));
+            try!(write_name(w, &section.segment_name));
+            try!(w.write_u64::<LittleEndian>((section_offsets[i] - contents_start) as u64)); // address
+            try!(w.write_u64::<LittleEndian>(section.data.len() as u64));
+            try!(w.write_u32::<LittleEndian>(section_offsets[i]));
+            try!(w.write_u32::<LittleEndian>(section.align));
+            try!(w.write_u32::<LittleEndian>(if section.relocations.is_empty() { 0 } else { relocation_offsets[i] }));
+            try!(w.write_u32::<LittleEndian>(section.relocations.len() as u32));
+            try!(w.write_u32::<LittleEndian>(section.flags));
+            try!(w.write_all(&[0u8; 12][..])); // reserved
+        }
+
+        if let Some(ref build_version) = self.build_version {
+            try!(w.write_u32::<LittleEndian>(LC_BUILD_VERSION));
+            try!(w.write_u32::<LittleEndian>(BUILD_VERSION_COMMAND_SIZE));
+            try!(w.write_u32::<LittleEndian>(build_version.platform));
+            try!(w.write_u32::<LittleEndian>(build_version.minimum_os));
+            try!(w.write_u32::<LittleEndian>(build_version.sdk));
+            try!(w.write_u32::<LittleEndian>(0)); // no tool entries
+        }
+
+        try!(w.write_u32::<LittleEndian>(LC_SYMTAB));
+        try!(w.write_u32::<LittleEndian>(SYMTAB_COMMAND_SIZE));
+        try!(w.write_u32::<LittleEndian>(symbol_table_offset));
+        try!(w.write_u32::<LittleEndian>(self.symbols.len() as u32));
+        try!(w.write_u32::<LittleEndian>(string_table_offset));
+        try!(w.write_u32::<LittleEndian>(strings.len() as u32));
+
+        try!(w.write_u32::<LittleEndian>(LC_DYSYMTAB));
+        try!(w.write_u32::<LittleEndian>(DYSYMTAB_COMMAND_SIZE));
+        try!(w.write_u32::<LittleEndian>(0)); // first local symbol
+        try!(w.write_u32::<LittleEndian>(local_count));
+        try!(w.write_u32::<LittleEndian>(local_count)); // first defined external symbol
+        try!(w.write_u32::<LittleEndian>(defined_external_count));
+        try!(w.write_u32::<LittleEndian>(local_count + defined_external_count)); // first undefined symbol
+        try!(w.write_u32::<LittleEndian>(undefined_count));
+        try!(w.write_all(&[0u8; 48][..])); // no tables of contents, modules, indirect symbols or local relocations
+
+        let mut position = HEADER_SIZE + commands_size;
+        for (i, section) in self.sections.iter().enumerate() {
+            try!(w.write_all(&vec![0u8; (section_offsets[i] - position) as usize][..]));
+            try!(w.write_all(&section.data[..]));
+            position = section_offsets[i] + section.data.len() as u32;
+        }
+
+        try!(w.write_all(&vec![0u8; (align_to(position, 4) - position) as usize][..]));
+        for section in self.sections.iter() {
+            for relocation in section.relocations.iter() {
+                let symbol = if relocation.external {
+                    try!(new_symbol_index.get(relocation.symbol as usize).cloned().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "relocation refers to an unknown symbol")))
+                } else {
+                    relocation.symbol
+                };
+                if symbol > 0xffffff || relocation.length > 3 || relocation.relocation_type > 0xf {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, "relocation fields out of range"));
+                }
+                try!(w.write_u32::<LittleEndian>(relocation.offset));
+                try!(w.write_u32::<LittleEndian>(
+                    symbol |
+                    (relocation.pc_relative as u32) << 24 |
+                    (relocation.length as u32) << 25 |
+                    (relocation.external as u32) << 27 |
+                    (relocation.relocation_type as u32) << 28));
+            }
+        }
+
+        try!(w.write_all(&vec![0u8; (symbol_table_offset - end) as usize][..]));
+        try!(w.write_all(&symbol_table[..]));
+        try!(w.write_all(&strings[..]));
+
+        Ok( () )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Macho, Section, Symbol, Relocation, BuildVersion, CPU_TYPE_X86_64, CPU_SUBTYPE_X86_64_ALL, PLATFORM_MACOS};
+    use super::{S_REGULAR, S_ATTR_PURE_INSTRUCTIONS, S_ATTR_SOME_INSTRUCTIONS, X86_64_RELOC_BRANCH};
+    use byteorder::{LittleEndian, ByteOrder};
+
+    fn name(bytes: &[u8]) -> String {
+        let len = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+        String::from_utf8(bytes[..len].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn parse_object() {
+        let macho = Macho {
+            cpu_type: CPU_TYPE_X86_64,
+            cpu_subtype: CPU_SUBTYPE_X86_64_ALL,
+            sections: vec![
+                Section {
+                    segment_name: "__TEXT".to_string(),
+                    section_name: "__text".to_string(),
+                    data: vec![0xe8, 0, 0, 0, 0, 0xc3],
+                    align: 4,
+                    flags: S_ATTR_PURE_INSTRUCTIONS | S_ATTR_SOME_INSTRUCTIONS,
+                    relocations: vec![
+                        Relocation { offset: 1, symbol: 0, external: true, pc_relative: true, length: 2, relocation_type: X86_64_RELOC_BRANCH },
+                    ],
+                },
+                Section {
+                    segment_name: "__DATA".to_string(),
+                    section_name: "__data".to_string(),
+                    data: vec![1, 2, 3, 4],
+                    align: 3,
+                    flags: S_REGULAR,
+                    relocations: vec![],
+                },
+            ],
+            symbols: vec![
+                Symbol { name: "_callee".to_string(), section: 0, value: 0, external: true },
+                Symbol { name: "_caller".to_string(), section: 1, value: 0, external: true },
+                Symbol { name: "ltmp".to_string(), section: 2, value: 16, external: false },
+            ],
+            build_version: Some(BuildVersion { platform: PLATFORM_MACOS, minimum_os: 0x000a0c00, sdk: 0 }),
+        };
+        let mut xs = Vec::new();
+        macho.write(&mut xs).unwrap();
+
+        assert_eq!(LittleEndian::read_u32(&xs[0..]), 0xfeedfacf);
+        assert_eq!(LittleEndian::read_u32(&xs[4..]), CPU_TYPE_X86_64);
+        assert_eq!(LittleEndian::read_u32(&xs[12..]), 1); // MH_OBJECT
+        let command_count = LittleEndian::read_u32(&xs[16..]);
+        assert_eq!(command_count, 4);
+
+        let mut sections = Vec::new();
+        let mut symbols = Vec::new();
+        let mut build_version = None;
+        let mut command = 32;
+        for _ in 0..command_count {
+            let size = LittleEndian::read_u32(&xs[command + 4..]) as usize;
+            match LittleEndian::read_u32(&xs[command..]) {
+                0x19 => {
+                    for i in 0..LittleEndian::read_u32(&xs[command + 64..]) as usize {
+                        let header = &xs[command + 72 + 80*i..];
+                        let offset = LittleEndian::read_u32(&header[48..]) as usize;
+                        let size = LittleEndian::read_u64(&header[40..]) as usize;
+                        let reloc_offset = LittleEndian::read_u32(&header[56..]) as usize;
+                        let relocs = (0..LittleEndian::read_u32(&header[60..]) as usize)
+                            .map(|r| (LittleEndian::read_u32(&xs[reloc_offset + 8*r..]), LittleEndian::read_u32(&xs[reloc_offset + 8*r + 4..])))
+                            .collect::<Vec<_>>();
+                        assert_eq!(offset % (1 << LittleEndian::read_u32(&header[52..])), 0);
+                        sections.push((name(&header[16..32]), name(&header[0..16]), xs[offset..offset + size].to_vec(), relocs));
+                    }
+                }
+                0x2 => {
+                    let symbol_offset = LittleEndian::read_u32(&xs[command + 8..]) as usize;
+                    let string_offset = LittleEndian::read_u32(&xs[command + 16..]) as usize;
+                    for i in 0..LittleEndian::read_u32(&xs[command + 12..]) as usize {
+                        let nlist = &xs[symbol_offset + 16*i..];
+                        let string = LittleEndian::read_u32(nlist) as usize;
+                        symbols.push((name(&xs[string_offset + string..]), nlist[4], nlist[5], LittleEndian::read_u64(&nlist[8..])));
+                    }
+                }
+                0x32 => build_version = Some((LittleEndian::read_u32(&xs[command + 8..]), LittleEndian::read_u32(&xs[command + 12..]))),
+                0xb => assert_eq!(&xs[command + 8..command + 32], &[0, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0][..]),
+                other => panic!("unexpected load command {:x}", other),
+            }
+            command += size;
+        }
+
+        assert_eq!(build_version, Some((PLATFORM_MACOS, 0x000a0c00)));
+        assert_eq!(sections, vec![
+            ("__TEXT".to_string(), "__text".to_string(), vec![0xe8, 0, 0, 0, 0, 0xc3], vec![(1, 2 | 1 << 24 | 2 << 25 | 1 << 27 | 2 << 28)]),
+            ("__DATA".to_string(), "__data".to_string(), vec![1, 2, 3, 4], vec![]),
+        ]);
+        // locals first, then defined externals, then undefined symbols
+        assert_eq!(symbols, vec![
+            ("ltmp".to_string(), 0x0e, 2, 16),
+            ("_caller".to_string(), 0x0f, 1, 0),
+            ("_callee".to_string(), 0x01, 0, 0),
+        ]);
+    }
+}
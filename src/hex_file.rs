@@ -0,0 +1,146 @@
+// Text formats understood by flash programmers and boot loaders: Intel HEX and Motorola S-records.
+
+use std::io;
+use std::io::Write;
+
+fn invalid_input(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message.to_string())
+}
+
+fn write_intel_hex_record<W: Write>(w: &mut W, address: u16, record_type: u8, data: &[u8]) -> io::Result<()> {
+    let mut checksum = (data.len() as u8)
+        .wrapping_add((address >> 8) as u8)
+        .wrapping_add(address as u8)
+        .wrapping_add(record_type);
+    try!(write!(w, ":{:02X}{:04X}{:02X}", data.len(), address, record_type));
+    for byte in data {
+        checksum = checksum.wrapping_add(*byte);
+        try!(write!(w, "{:02X}", byte));
+    }
+    write!(w, "{:02X}\r\n", checksum.wrapping_neg())
+}
+
+// Writes data to be loaded at base_address with at most record_length bytes per data record.
+// Extended linear address records are emitted whenever the upper 16 bits of the address change.
+pub fn write_intel_hex<W: Write>(w: &mut W, data: &[u8], base_address: u64, record_length: u8) -> io::Result<()> {
+    if record_length == 0 {
+        return Err(invalid_input("record length must be at least 1"));
+    }
+    match base_address.checked_add(data.len() as u64) {
+        Some(end) if end <= 0x1_0000_0000 => (),
+        _ => return Err(invalid_input("Intel HEX addresses are limited to 32 bits")),
+    }
+
+    let mut upper_address = 0;
+    let mut offset = 0;
+    while offset < data.len() {
+        let address = base_address + offset as u64;
+        if address >> 16 != upper_address {
+            upper_address = address >> 16;
+            try!(write_intel_hex_record(w, 0, 0x04, &[(upper_address >> 8) as u8, upper_address as u8]));
+        }
+        // records may not wrap around a 64K segment
+        let to_segment_end = 0x10000 - (address & 0xffff) as usize;
+        let length = *[record_length as usize, data.len() - offset, to_segment_end].iter().min().unwrap();
+        try!(write_intel_hex_record(w, address as u16, 0x00, &data[offset..offset + length]));
+        offset += length;
+    }
+    write_intel_hex_record(w, 0, 0x01, &[])
+}
+
+fn write_srecord_record<W: Write>(w: &mut W, record_type: u8, address: u64, address_bytes: usize, data: &[u8]) -> io::Result<()> {
+    let count = address_bytes + data.len() + 1;
+    let mut checksum = count as u8;
+    try!(write!(w, "S{}{:02X}", record_type, count));
+    for i in (0..address_bytes).rev() {
+        let byte = (address >> (8 * i)) as u8;
+        checksum = checksum.wrapping_add(byte);
+        try!(write!(w, "{:02X}", byte));
+    }
+    for byte in data {
+        checksum = checksum.wrapping_add(*byte);
+        try!(write!(w, "{:02X}", byte));
+    }
+    write!(w, "{:02X}\r\n", !checksum)
+}
+
+// Writes data to be loaded at base_address with at most record_length bytes per data record. The
+// narrowest of S1, S2 or S3 records that can hold the addresses is used, and base_address is given
+// as the start address in the termination record.
+pub fn write_srecord<W: Write>(w: &mut W, data: &[u8], base_address: u64, record_length: u8) -> io::Result<()> {
+    let end = match base_address.checked_add(data.len() as u64) {
+        Some(end) => end,
+        None => return Err(invalid_input("S-record addresses are limited to 32 bits")),
+    };
+    let (data_type, termination_type, address_bytes) = if end <= 0x1_0000 {
+        (1, 9, 2)
+    } else if end <= 0x100_0000 {
+        (2, 8, 3)
+    } else if end <= 0x1_0000_0000 {
+        (3, 7, 4)
+    } else {
+        return Err(invalid_input("S-record addresses are limited to 32 bits"));
+    };
+    if record_length == 0 || record_length as usize + address_bytes + 1 > 0xff {
+        return Err(invalid_input("record length does not fit in an S-record"));
+    }
+
+    try!(write_srecord_record(w, 0, 0, 2, b"rassembler"));
+    let mut record_count = 0;
+    for (i, chunk) in data.chunks(record_length as usize).enumerate() {
+        try!(write_srecord_record(w, data_type, base_address + (i * record_length as usize) as u64, address_bytes, chunk));
+        record_count += 1;
+    }
+    // the count record is optional, there is none for counts beyond 24 bits
+    if record_count <= 0xffff {
+        try!(write_srecord_record(w, 5, record_count, 2, &[]));
+    } else if record_count <= 0xff_ffff {
+        try!(write_srecord_record(w, 6, record_count, 3, &[]));
+    }
+    write_srecord_record(w, termination_type, base_address, address_bytes, &[])
+}
+
+#[test]
+fn intel_hex() {
+    let mut out = Vec::new();
+    write_intel_hex(&mut out, &[0x02, 0x33, 0x7a, 0x01, 0x02], 0x0030, 4).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(),
+        ":0400300002337A011C\r\n\
+         :0100340002C9\r\n\
+         :00000001FF\r\n");
+
+    // records are split at, and addresses extended past, 64K boundaries
+    let mut out = Vec::new();
+    write_intel_hex(&mut out, &[1, 2, 3, 4], 0x1fffe, 16).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(),
+        ":020000040001F9\r\n\
+         :02FFFE000102FE\r\n\
+         :020000040002F8\r\n\
+         :020000000304F7\r\n\
+         :00000001FF\r\n");
+}
+
+#[test]
+fn srecord() {
+    let mut out = Vec::new();
+    write_srecord(&mut out, &[0x7c, 0x08, 0x02, 0xa6], 0x1000, 2).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(),
+        "S00D000072617373656D626C6572C2\r\n\
+         S10510007C0866\r\n\
+         S105100202A640\r\n\
+         S5030002FA\r\n\
+         S9031000EC\r\n");
+
+    let mut out = Vec::new();
+    write_srecord(&mut out, &[0xff], 0x123456, 16).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap().lines().nth(1), Some("S205123456FF5F"));
+    assert!(write_srecord(&mut Vec::new(), &[0], 0, 255).is_err());
+    assert!(write_srecord(&mut Vec::new(), &[0], u64::MAX, 16).is_err());
+    assert!(write_intel_hex(&mut Vec::new(), &[0], u64::MAX, 16).is_err());
+
+    // more than 0xffff data records are counted by an S6 record
+    let mut out = Vec::new();
+    write_srecord(&mut out, &[0; 0x10000], 0, 1).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(out.lines().rev().nth(1), Some("S604010000FA"));
+}
@@ -1,8 +1,11 @@
 extern crate byteorder;
 extern crate coff_writer;
 extern crate elf_writer;
+extern crate macho_writer;
 
-mod object_file;
+pub mod object_file;
+pub mod hex_file;
+pub mod linker;
 pub mod x64;
 
 pub enum Arch {
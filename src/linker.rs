@@ -0,0 +1,190 @@
+// A minimal static linker, turning ObjectFiles into an ELF executable that runs without ld.
+
+use elf_writer::{self, Executable, Segment, PF_R, PF_X};
+use object_file::{ObjectFile, Architecture, SymbolReferenceKind};
+use std::collections::HashMap;
+use std::io::{self, Write, ErrorKind};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ExecutableKind {
+    Static,     // ET_EXEC loaded at a fixed address
+    StaticPie,  // ET_DYN loaded anywhere; the code must not contain absolute references
+}
+
+const EXECUTABLE_BASE: u64 = 0x400000;
+const PIE_BASE: u64 = 0;
+const PAGE_SIZE: u64 = 0x1000;
+
+// The code of all objects goes into a single executable segment, one after another, each aligned to 16
+// bytes. Global labels and references to them are resolved across all the objects; entry has to name one of them.
+// The objects must all be for the same architecture.
+pub fn link_elf_executable<W: Write>(out: &mut W, objects: &[&ObjectFile], entry: &str, kind: ExecutableKind) -> io::Result<()> {
+    let architecture = objects.first().map(|object| object.architecture).unwrap_or(Architecture::X8664);
+    if objects.iter().any(|object| object.architecture != architecture) {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "cannot link objects of different architectures"));
+    }
+    let base = match kind {
+        ExecutableKind::Static => EXECUTABLE_BASE,
+        ExecutableKind::StaticPie => PIE_BASE,
+    };
+    let text_address = base + PAGE_SIZE;
+
+    // every object's symbols have to be known before references between them can be filled in
+    let mut symbols = HashMap::new();
+    let mut addresses = Vec::new();
+    let mut end = text_address;
+    for object in objects {
+        let address = (end + 15) & !15;
+        let absolute = !object.absolute_references.is_empty() ||
+            object.symbol_references.iter().any(|reference| reference.kind == SymbolReferenceKind::Absolute);
+        if kind == ExecutableKind::StaticPie && absolute {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "position independent executables cannot contain absolute references"));
+        }
+        if !object.thread_locals.is_empty() || !object.tls_data.is_empty() || object.tls_bss_size != 0 {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "executables cannot have thread-local variables"));
+        }
+        for function in object.functions.iter() {
+            if symbols.insert(function.name.clone(), address + function.offset as u64).is_some() {
+                return Err(io::Error::new(ErrorKind::InvalidInput, format!("duplicate symbol {}", function.name)));
+            }
+        }
+        addresses.push(address);
+        end = address + object.code.len() as u64;
+    }
+
+    let mut text = Vec::new();
+    for (object, &address) in objects.iter().zip(addresses.iter()) {
+        while text_address + (text.len() as u64) < address {
+            text.push(0xcc);
+        }
+        text.extend(try!(object.flat_binary_with_symbols(address, &symbols)));
+    }
+
+    let entry_address = try!(symbols.get(entry).cloned().ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, format!("entry point {} is not defined", entry))));
+
+    let executable = Executable{
+        word_size: match architecture {
+            Architecture::X86 => elf_writer::WordSize::Bits32,
+            Architecture::X8664 => elf_writer::WordSize::Bits64,
+        },
+        endianness: elf_writer::Endianness::LittleEndian,
+        architecture: match architecture {
+            Architecture::X86 => elf_writer::Architecture::X86,
+            Architecture::X8664 => elf_writer::Architecture::X8664,
+        },
+        entry: entry_address,
+        position_independent: kind == ExecutableKind::StaticPie,
+        headers_address: base,
+        segments: vec![
+            Segment{
+                address: text_address,
+                flags: PF_R | PF_X,
+                content: &text[..],
+                memory_size: text.len() as u64,
+            },
+        ],
+    };
+    executable.write(out)
+}
+
+#[cfg(all(test, target_os = "linux", target_arch = "x86_64"))]
+fn run(name: &str, objects: &[&ObjectFile], kind: ExecutableKind) -> i32 {
+    use std::fs::{self, File};
+    use std::os::unix::fs::PermissionsExt;
+    use std::process::Command;
+
+    let path = ::std::env::temp_dir().join(name);
+    {
+        let mut file = File::create(&path).unwrap();
+        link_elf_executable(&mut file, objects, "_start", kind).unwrap();
+    }
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    let status = Command::new(&path).status().unwrap();
+    fs::remove_file(&path).unwrap();
+    status.code().unwrap()
+}
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+#[test]
+fn static_executable() {
+    use x64::{Assembler, DWord, QWord, rip_nonrelative};
+
+    let mut other = Assembler::new();
+    other.global("other");
+    other.ret(None::<i32>);
+
+    // jumps to the exit through an absolute address, which needs relocating
+    let mut x = Assembler::new();
+    let exit = x.allocate_local();
+    x.global("_start");
+    x.mov(QWord::Rax, rip_nonrelative(exit));
+    x.jmp(QWord::Rax);
+    x.place_local(exit);
+    x.mov(DWord::Edi, 42i32);
+    x.mov(DWord::Eax, 60i32);
+    x.syscall();
+
+    let (other, start) = (other.dump(), x.dump());
+    assert_eq!(run("rassembler_static_executable", &[&other, &start], ExecutableKind::Static), 42);
+    assert!(link_elf_executable(&mut Vec::new(), &[&other, &start], "missing", ExecutableKind::Static).is_err());
+    assert!(link_elf_executable(&mut Vec::new(), &[&start, &start], "_start", ExecutableKind::Static).is_err());
+    assert!(link_elf_executable(&mut Vec::new(), &[&start], "_start", ExecutableKind::StaticPie).is_err());
+}
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+#[test]
+fn references_between_objects() {
+    use x64::{Assembler, DWord, QWord, extern_symbol, extern_rip_relative};
+
+    let mut other = Assembler::new();
+    other.global("exit_code");
+    other.constant(&[9, 0, 0, 0]);
+    other.global("exit");
+    other.mov(DWord::Eax, 60i32);
+    other.syscall();
+
+    let mut x = Assembler::new();
+    x.global("_start");
+    x.mov(DWord::Edi, extern_rip_relative("exit_code"));
+    x.mov(QWord::Rax, extern_symbol("exit"));
+    x.call(QWord::Rax);
+
+    let (other, start) = (other.dump(), x.dump());
+    assert_eq!(run("rassembler_references_between_objects", &[&start, &other], ExecutableKind::Static), 9);
+    assert!(link_elf_executable(&mut Vec::new(), &[&start], "_start", ExecutableKind::Static).is_err());
+    assert!(link_elf_executable(&mut Vec::new(), &[&start, &other], "_start", ExecutableKind::StaticPie).is_err());
+}
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+#[test]
+fn static_pie_executable() {
+    use x64::{Assembler, DWord};
+
+    let mut x = Assembler::new();
+    x.global("_start");
+    x.mov(DWord::Edi, 7i32);
+    x.mov(DWord::Eax, 60i32);
+    x.syscall();
+
+    assert_eq!(run("rassembler_static_pie_executable", &[&x.dump()], ExecutableKind::StaticPie), 7);
+}
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+#[test]
+fn i386_static_executable() {
+    use object_file::Architecture;
+    use x64::{Assembler, DWord};
+
+    let mut x = Assembler::new();
+    x.global("_start");
+    x.mov(DWord::Ebx, 5i32);
+    x.mov(DWord::Eax, 1i32); // exit
+    x.constant(&[0xcd, 0x80]); // int 0x80
+
+    let mut start = x.dump();
+    start.architecture = Architecture::X86;
+    assert_eq!(run("rassembler_i386_static_executable", &[&start], ExecutableKind::Static), 5);
+
+    let other = Assembler::new().dump();
+    assert!(link_elf_executable(&mut Vec::new(), &[&start, &other], "_start", ExecutableKind::Static).is_err());
+}
@@ -1,19 +1,120 @@
-use coff_writer::{Coff, Section, Symbol, MACHINE_AMD64};
+use coff_writer::{self, Coff, Section, Symbol, Relocation, MACHINE_AMD64, IMAGE_REL_AMD64_ADDR32NB};
+use coff_writer::{IMAGE_REL_AMD64_ADDR64, IMAGE_REL_AMD64_ADDR32, IMAGE_REL_AMD64_REL32, IMAGE_REL_AMD64_REL32_5, IMAGE_SYM_CLASS_EXTERNAL};
+use coff_writer::{MACHINE_I386, IMAGE_REL_I386_DIR32, IMAGE_REL_I386_REL32, IMAGE_FILE_32BIT_MACHINE};
+use coff_writer::{IMAGE_REL_AMD64_SECREL, IMAGE_REL_I386_SECREL};
+use byteorder::{LittleEndian, ByteOrder, WriteBytesExt};
 use elf_writer::{self, Elf};
+use macho_writer::{self, Macho};
+use hex_file;
 use std::io::Write;
 use std::io::ErrorKind;
 use std::io;
+use std::collections::HashMap;
 
 
+#[derive(Debug)]
 pub struct ExportedFunction {
     pub offset: u32,
     pub name: String,
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ObjectFormat {
+    Elf,
+    Coff,
+    MachO,
+}
+
+impl ObjectFormat {
+    pub fn for_target(target: &str) -> ObjectFormat {
+        if target.contains("windows") {
+            ObjectFormat::Coff
+        } else if target.contains("apple") {
+            ObjectFormat::MachO
+        } else {
+            ObjectFormat::Elf
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Architecture {
+    X86, // i386, written as 32-bit ELF and COFF
+    X8664,
+}
+
+impl Architecture {
+    pub fn for_target(target: &str) -> Architecture {
+        if target.starts_with("i386") || target.starts_with("i586") || target.starts_with("i686") {
+            Architecture::X86
+        } else {
+            Architecture::X8664
+        }
+    }
+}
+
+// Maps an offset in the code to a position in one of ObjectFile::source_files
+#[derive(Clone, Debug, PartialEq)]
+pub struct LineRow {
+    pub offset: u32,
+    pub file: usize,
+    pub line: u32,
+    pub column: u32,
+}
+
+// A field in the code holding the offset of a label, which needs the code's load address added to it
+#[derive(Clone, Debug, PartialEq)]
+pub struct AbsoluteReference {
+    pub offset: u32,
+    pub size: u8, // 2, 4 or 8 bytes
+}
+
+// How the field of a SymbolReference is filled in
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SymbolReferenceKind {
+    Branch,   // displacement of a call or jmp, through the PLT if the symbol is in a shared library
+    Relative, // rip-relative displacement
+    Absolute, // address of the symbol
+    Got,      // rip-relative displacement of the GOT entry holding the address of the symbol
+    TpOff,    // offset of a thread-local symbol from the thread pointer
+    GotTpOff, // rip-relative displacement of the GOT entry holding the TpOff of the symbol
+}
+
+// A field in the code referring to a symbol the code does not define. Relative fields end the instruction.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SymbolReference {
+    pub offset: u32,
+    pub size: u8,
+    pub symbol: String,
+    pub kind: SymbolReferenceKind,
+}
+
+// How a relocated field of imported code is filled in
+#[derive(Copy, Clone)]
+enum ImportedRelocation {
+    Relative(u32), // displacement from the given number of bytes past the start of the field; ELF addends already include it
+    Absolute(u8), // field size
+}
+
+#[derive(Debug)]
 pub struct ObjectFile {
+    pub architecture: Architecture,
     pub functions: Vec<ExportedFunction>,
+    pub local_symbols: Vec<ExportedFunction>, // named labels, visible to debuggers but not to the linker
     pub code: Vec<u8>,
-
+    pub x86_features: u32, // elf_writer::GNU_PROPERTY_X86_FEATURE_1_* bits
+    pub frames: Vec<elf_writer::FrameDescription>,
+    pub unwind_infos: Vec<coff_writer::UnwindInfo>,
+    pub source_files: Vec<String>,
+    pub line_rows: Vec<LineRow>, // in order of offset; if empty, no debug information is written
+    pub absolute_references: Vec<AbsoluteReference>,
+    pub symbol_references: Vec<SymbolReference>,
+    // Thread-local variables: the initial values are followed by tls_bss_size zeroed bytes, and
+    // thread_locals holds the offsets of the variables into both together.
+    pub tls_data: Vec<u8>,
+    pub tls_bss_size: u32,
+    pub tls_alignment: u32,
+    pub thread_locals: Vec<ExportedFunction>,
 }
 
 fn write_archive_header<W: Write>(w: &mut W, singleton_file_contents: &[u8]) -> io::Result<()> {
@@ -46,42 +147,543 @@ fn write_archive_footer<W: Write>(w: &mut W, singleton_file_contents: &[u8]) ->
 
 
 impl ObjectFile {
-    pub fn write<W: Write>(&self, out: &mut W) {
-        let template_bytes =
-            if false {
-                self.make_elf_file()
+    pub fn write<W: Write>(&self, out: &mut W, format: ObjectFormat) -> io::Result<()> {
+        let template_bytes = match format {
+            ObjectFormat::Elf => try!(self.make_elf_file()),
+            ObjectFormat::Coff => try!(self.make_object_file()),
+            ObjectFormat::MachO => try!(self.make_macho_file()),
+        };
+ 
+        try!(write_archive_header(out, &template_bytes[..]));
+        try!(out.write_all(&template_bytes[..]));
+        write_archive_footer(out, &template_bytes[..])
+    }
+    
+    // The code as a raw image to be loaded at origin, with absolute references resolved against it
+    pub fn flat_binary(&self, origin: u64) -> io::Result<Vec<u8>> {
+        self.flat_binary_with_symbols(origin, &HashMap::new())
+    }
+    
+    // Like flat_binary, with symbol references resolved against the given addresses
+    pub fn flat_binary_with_symbols(&self, origin: u64, symbols: &HashMap<String, u64>) -> io::Result<Vec<u8>> {
+        let mut image = try!(self.rebased_code(origin));
+        for reference in self.symbol_references.iter() {
+            let address = try!(symbols.get(&reference.symbol).cloned()
+                .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, format!("undefined symbol {}", reference.symbol))));
+            try!(self.resolve_symbol_reference(&mut image, origin, reference, address));
+        }
+        Ok(image)
+    }
+    
+    fn rebased_code(&self, origin: u64) -> io::Result<Vec<u8>> {
+        let mut image = self.code.clone();
+        for reference in self.absolute_references.iter() {
+            let at = reference.offset as usize;
+            let offset = match reference.size {
+                2 => LittleEndian::read_u16(&image[at..]) as u64,
+                4 => LittleEndian::read_u32(&image[at..]) as u64,
+                8 => LittleEndian::read_u64(&image[at..]),
+                _ => return Err(io::Error::new(ErrorKind::InvalidInput, "absolute references must be 2, 4 or 8 bytes")),
+            };
+            try!(self.write_address(&mut image[at..], reference.size, origin.wrapping_add(offset)));
+        }
+        Ok(image)
+    }
+    
+    fn resolve_symbol_reference(&self, image: &mut [u8], origin: u64, reference: &SymbolReference, address: u64) -> io::Result<()> {
+        let at = reference.offset as usize;
+        match reference.kind {
+            SymbolReferenceKind::Branch | SymbolReferenceKind::Relative => {
+                let displacement = address as i64 - (origin + at as u64 + 4) as i64;
+                if displacement < i32::MIN as i64 || displacement > i32::MAX as i64 {
+                    return Err(io::Error::new(ErrorKind::InvalidInput, format!("{} is out of range of a 32-bit displacement", reference.symbol)));
+                }
+                LittleEndian::write_i32(&mut image[at..], displacement as i32);
+                Ok( () )
+            }
+            SymbolReferenceKind::Absolute => self.write_address(&mut image[at..], reference.size, address),
+            SymbolReferenceKind::Got => Err(io::Error::new(ErrorKind::InvalidInput,
+                format!("{} is referenced through the GOT, which needs a dynamic linker", reference.symbol))),
+            SymbolReferenceKind::TpOff | SymbolReferenceKind::GotTpOff => Err(io::Error::new(ErrorKind::InvalidInput,
+                format!("{} is thread-local, which needs a TLS segment", reference.symbol))),
+        }
+    }
+    
+    fn write_address(&self, field: &mut [u8], size: u8, address: u64) -> io::Result<()> {
+        match size {
+            2 => {
+                if address > u16::MAX as u64 {
+                    return Err(io::Error::new(ErrorKind::InvalidInput, "absolute reference does not fit in 16 bits at this origin"));
+                }
+                LittleEndian::write_u16(field, address as u16);
+            }
+            4 => {
+                // in 64-bit code 32-bit immediates are sign extended, so the address must stay below 2GB
+                let limit = if self.architecture == Architecture::X86 { u32::MAX as u64 } else { i32::MAX as u64 };
+                if address > limit {
+                    return Err(io::Error::new(ErrorKind::InvalidInput, "absolute reference does not fit in 32 bits at this origin"));
+                }
+                LittleEndian::write_u32(field, address as u32);
+            }
+            8 => LittleEndian::write_u64(field, address),
+            _ => return Err(io::Error::new(ErrorKind::InvalidInput, "absolute references must be 2, 4 or 8 bytes")),
+        }
+        Ok( () )
+    }
+    
+    pub fn write_flat_binary<W: Write>(&self, out: &mut W, origin: u64) -> io::Result<()> {
+        let image = try!(self.flat_binary(origin));
+        out.write_all(&image[..])
+    }
+    
+    pub fn write_intel_hex<W: Write>(&self, out: &mut W, base_address: u64, record_length: u8) -> io::Result<()> {
+        let image = try!(self.flat_binary(base_address));
+        hex_file::write_intel_hex(out, &image[..], base_address, record_length)
+    }
+    
+    pub fn write_srecord<W: Write>(&self, out: &mut W, base_address: u64, record_length: u8) -> io::Result<()> {
+        let image = try!(self.flat_binary(base_address));
+        hex_file::write_srecord(out, &image[..], base_address, record_length)
+    }
+    
+    // A PE32+ console executable, or a DLL exporting the global labels. Absolute references are resolved
+    // against the default image base, as the image carries no base relocations.
+    pub fn write_pe_image<W: Write>(&self, out: &mut W, name: &str, entry: Option<&str>, imports: Vec<coff_writer::Import>, dll: bool) -> io::Result<()> {
+        if self.architecture != Architecture::X8664 {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "PE images are only supported for x86-64"));
+        }
+        let image_base = if dll { 0x180000000 } else { 0x140000000 };
+        let entry = match entry {
+            Some(entry) => match self.functions.iter().find(|f| f.name == entry) {
+                Some(function) => Some(function.offset),
+                None => return Err(io::Error::new(ErrorKind::InvalidInput, format!("entry point {} is not defined", entry))),
+            },
+            None => None,
+        };
+        // GOT references of imported functions go to their import address table slots
+        let mut import_references = Vec::new();
+        for reference in self.symbol_references.iter() {
+            let slot = imports.iter().enumerate().filter_map(|(dll, import)| {
+                import.functions.iter().position(|function| *function == reference.symbol).map(|function| (dll, function))
+            }).next();
+            match (reference.kind, slot) {
+                (SymbolReferenceKind::Got, Some((dll, function))) => import_references.push(coff_writer::ImportReference{
+                    offset: reference.offset,
+                    dll: dll,
+                    function: function,
+                }),
+                (_, None) =>
+                    return Err(io::Error::new(ErrorKind::InvalidInput, format!("undefined symbol {}", reference.symbol))),
+                (_, Some(_)) =>
+                    return Err(io::Error::new(ErrorKind::InvalidInput, format!("imported function {} can only be referenced through its slot", reference.symbol))),
+            }
+        }
+        let image = coff_writer::PeImage{
+            machine: MACHINE_AMD64,
+            image_base: image_base,
+            dll: dll,
+            subsystem: coff_writer::IMAGE_SUBSYSTEM_WINDOWS_CUI,
+            name: name.to_string(),
+            entry: entry,
+            code: try!(self.rebased_code(image_base + coff_writer::PE_CODE_RVA as u64)),
+            imports: imports,
+            import_references: import_references,
+            exports: if dll {
+                self.functions.iter().map(|f| coff_writer::Export{ name: f.name.clone(), offset: f.offset }).collect()
             } else {
-                self.make_object_file()
+                vec![]
+            },
+        };
+        image.write(out)
+    }
+    
+    // Appends the .text of a relocatable ELF file, along with its global symbols and call frame information.
+    // Relocations are resolved against the symbols of the same file; absolute ones become absolute references.
+    pub fn import_elf(&mut self, data: &[u8]) -> io::Result<()> {
+        let elf = try!(Elf::read(data));
+        let architecture = match elf.architecture {
+            elf_writer::Architecture::X86 => Architecture::X86,
+            elf_writer::Architecture::X8664 => Architecture::X8664,
+            _ => return Err(io::Error::new(ErrorKind::InvalidData, "only x86 and x86-64 objects can be imported")),
+        };
+        try!(self.adopt_architecture(architecture));
+        let base = self.append_code(elf.text_content);
+        if base == 0 {
+            self.x86_features = elf.x86_features;
+        } else {
+            self.x86_features &= elf.x86_features; // a feature is only usable if every part supports it
+        }
+        
+        for relocation in elf.text_relocations.iter() {
+            let target = match relocation.symbol {
+                None => 0,
+                Some(name) => match elf.functions.iter().find(|f| f.name == name) {
+                    Some(function) => function.offset as i64,
+                    None => return Err(io::Error::new(ErrorKind::InvalidData, format!("undefined symbol {}", name))),
+                },
             };
- 
-        write_archive_header(out, &template_bytes[..]).unwrap();
-        out.write_all(&template_bytes[..]).unwrap();
-        write_archive_footer(out, &template_bytes[..]).unwrap();
+            let kind = match (architecture, relocation.relocation_type) {
+                (Architecture::X8664, elf_writer::R_X86_64_PC32) | (Architecture::X8664, elf_writer::R_X86_64_PLT32) => ImportedRelocation::Relative(0),
+                (Architecture::X8664, elf_writer::R_X86_64_64) => ImportedRelocation::Absolute(8),
+                (Architecture::X8664, elf_writer::R_X86_64_32) | (Architecture::X8664, elf_writer::R_X86_64_32S) => ImportedRelocation::Absolute(4),
+                (Architecture::X86, elf_writer::R_386_PC32) | (Architecture::X86, elf_writer::R_386_PLT32) => ImportedRelocation::Relative(0),
+                (Architecture::X86, elf_writer::R_386_32) => ImportedRelocation::Absolute(4),
+                (_, other) => return Err(io::Error::new(ErrorKind::InvalidData, format!("unsupported relocation type {}", other))),
+            };
+            try!(self.apply_imported_relocation(base, relocation.offset as u32, kind, target + relocation.addend));
+        }
+        
+        for function in elf.functions.iter() {
+            try!(self.add_imported_function(function.name, base + function.offset as u32));
+        }
+        for label in elf.local_symbols.iter() {
+            self.local_symbols.push(ExportedFunction{ offset: base + label.offset as u32, name: label.name.to_string() });
+        }
+        for frame in elf.frames.iter() {
+            let mut frame = frame.clone();
+            frame.offset += base as usize;
+            self.frames.push(frame);
+        }
+        Ok( () )
+    }
+    
+    // Appends the .text section of a COFF object file along with its external symbols. As with import_elf,
+    // relocations must refer to .text. Unwind information is not carried over.
+    pub fn import_coff(&mut self, data: &[u8]) -> io::Result<()> {
+        let coff = try!(Coff::read(data));
+        let architecture = match coff.machine {
+            MACHINE_I386 => Architecture::X86,
+            MACHINE_AMD64 => Architecture::X8664,
+            _ => return Err(io::Error::new(ErrorKind::InvalidData, "only x86 and x86-64 objects can be imported")),
+        };
+        try!(self.adopt_architecture(architecture));
+        let text_number = match coff.sections.iter().position(|section| section.name == ".text") {
+            Some(index) => index as i16 + 1,
+            None => return Ok( () ),
+        };
+        let text = &coff.sections[text_number as usize - 1];
+        let base = self.append_code(&text.data[..]);
+        
+        // symbol table index (counting auxiliary records) -> symbol
+        let mut symbols = Vec::new();
+        for symbol in coff.symbols.iter() {
+            symbols.push(Some(symbol));
+            symbols.extend(symbol.aux_symbols.iter().map(|_| None));
+        }
+        
+        for relocation in text.relocations.iter() {
+            let symbol = match symbols.get(relocation.symbol_index as usize) {
+                Some(&Some(symbol)) => symbol,
+                _ => return Err(io::Error::new(ErrorKind::InvalidData, "relocation symbol index out of range")),
+            };
+            if symbol.section_number != text_number {
+                return Err(io::Error::new(ErrorKind::InvalidData, if symbol.section_number == 0 {
+                    format!("undefined symbol {}", symbol.name)
+                } else {
+                    format!("relocation against {}, which is outside of .text", symbol.name)
+                }));
+            }
+            // COFF relocations keep their addend in the relocated field
+            let at = (base + relocation.virtual_address) as usize;
+            if at + 4 > self.code.len() {
+                return Err(io::Error::new(ErrorKind::InvalidData, "relocation outside of .text"));
+            }
+            let (kind, addend) = match (architecture, relocation.type_flags) {
+                (Architecture::X8664, IMAGE_REL_AMD64_REL32 ..= IMAGE_REL_AMD64_REL32_5) => {
+                    let bytes_after_field = 4 + (relocation.type_flags - IMAGE_REL_AMD64_REL32) as u32;
+                    (ImportedRelocation::Relative(bytes_after_field), LittleEndian::read_i32(&self.code[at..]) as i64)
+                }
+                (Architecture::X8664, IMAGE_REL_AMD64_ADDR64) if at + 8 <= self.code.len() => {
+                    (ImportedRelocation::Absolute(8), LittleEndian::read_i64(&self.code[at..]))
+                }
+                (Architecture::X8664, IMAGE_REL_AMD64_ADDR32) | (Architecture::X86, IMAGE_REL_I386_DIR32) => {
+                    (ImportedRelocation::Absolute(4), LittleEndian::read_i32(&self.code[at..]) as i64)
+                }
+                (Architecture::X86, IMAGE_REL_I386_REL32) => (ImportedRelocation::Relative(4), LittleEndian::read_i32(&self.code[at..]) as i64),
+                (_, other) => return Err(io::Error::new(ErrorKind::InvalidData, format!("unsupported relocation type {}", other))),
+            };
+            try!(self.apply_imported_relocation(base, relocation.virtual_address, kind, symbol.value as i64 + addend));
+        }
+        
+        for symbol in coff.symbols.iter() {
+            if symbol.storage_class == IMAGE_SYM_CLASS_EXTERNAL && symbol.section_number == text_number {
+                try!(self.add_imported_function(self.undecorated_name(&symbol.name), base + symbol.value));
+            }
+        }
+        Ok( () )
+    }
+    
+    // Imported code has to be for the same architecture as what is already here
+    fn adopt_architecture(&mut self, architecture: Architecture) -> io::Result<()> {
+        if self.code.is_empty() && self.functions.is_empty() {
+            self.architecture = architecture;
+        } else if self.architecture != architecture {
+            return Err(io::Error::new(ErrorKind::InvalidData, "cannot import an object of a different architecture"));
+        }
+        Ok( () )
+    }
+    
+    // C symbols in 32-bit COFF are prefixed with an underscore
+    fn decorated_name(&self, name: &str) -> String {
+        match self.architecture {
+            Architecture::X86 => format!("_{}", name),
+            Architecture::X8664 => name.to_string(),
+        }
+    }
+    
+    fn undecorated_name<'a>(&self, name: &'a str) -> &'a str {
+        match self.architecture {
+            Architecture::X86 if name.starts_with('_') => &name[1..],
+            _ => name,
+        }
+    }
+    
+    // Appends code at a 16-byte boundary, returning where it starts
+    fn append_code(&mut self, code: &[u8]) -> u32 {
+        if !code.is_empty() {
+            while self.code.len() % 16 != 0 {
+                self.code.push(0x90);
+            }
+        }
+        let base = self.code.len() as u32;
+        self.code.extend_from_slice(code);
+        base
+    }
+    
+    // Fills in a relocated field of code appended at base. target is relative to the start of that code.
+    fn apply_imported_relocation(&mut self, base: u32, offset: u32, kind: ImportedRelocation, target: i64) -> io::Result<()> {
+        let at = (base + offset) as usize;
+        let size = match kind {
+            ImportedRelocation::Relative(_) | ImportedRelocation::Absolute(4) => 4,
+            _ => 8,
+        };
+        if at + size > self.code.len() {
+            return Err(io::Error::new(ErrorKind::InvalidData, "relocation outside of .text"));
+        }
+        let target = base as i64 + target;
+        match kind {
+            ImportedRelocation::Relative(bytes_after_field) => {
+                let displacement = target - (at as i64 + bytes_after_field as i64);
+                LittleEndian::write_i32(&mut self.code[at..], displacement as i32);
+            }
+            ImportedRelocation::Absolute(size) => {
+                if size == 4 {
+                    LittleEndian::write_u32(&mut self.code[at..], target as u32);
+                } else {
+                    LittleEndian::write_u64(&mut self.code[at..], target as u64);
+                }
+                self.absolute_references.push(AbsoluteReference{ offset: at as u32, size: size });
+            }
+        }
+        Ok( () )
+    }
+    
+    // a 4-byte field in 64-bit code is an immediate or displacement, which get sign-extended
+    fn elf_absolute_relocation(&self, size: u8) -> io::Result<u32> {
+        match (self.architecture, size) {
+            (Architecture::X8664, 8) => Ok(elf_writer::R_X86_64_64),
+            (Architecture::X8664, 4) => Ok(elf_writer::R_X86_64_32S),
+            (Architecture::X86, 4) => Ok(elf_writer::R_386_32),
+            _ => Err(io::Error::new(ErrorKind::InvalidInput, format!("{}-byte absolute references can only be written to a flat binary", size))),
+        }
     }
     
-    fn make_elf_file(&self) -> Vec<u8> {
+    // the offset in the code an absolute reference points at
+    fn absolute_reference_target(&self, reference: &AbsoluteReference) -> u64 {
+        let field = &self.code[reference.offset as usize..];
+        match reference.size {
+            2 => LittleEndian::read_u16(field) as u64,
+            4 => LittleEndian::read_u32(field) as u64,
+            _ => LittleEndian::read_u64(field),
+        }
+    }
+    
+    fn add_imported_function(&mut self, name: &str, offset: u32) -> io::Result<()> {
+        if self.functions.iter().any(|f| f.name == name) {
+            return Err(io::Error::new(ErrorKind::InvalidData, format!("symbol {} is already defined", name)));
+        }
+        self.functions.push(ExportedFunction{ offset: offset, name: name.to_string() });
+        Ok( () )
+    }
+    
+    fn make_elf_file(&self) -> io::Result<Vec<u8>> {
+        let (architecture, word_size) = match self.architecture {
+            Architecture::X86 => (elf_writer::Architecture::X86, elf_writer::WordSize::Bits32),
+            Architecture::X8664 => (elf_writer::Architecture::X8664, elf_writer::WordSize::Bits64),
+        };
         let e = Elf{
-            architecture: elf_writer::Architecture::X8664,
-            word_size: elf_writer::WordSize::Bits64,
+            architecture: architecture,
+            word_size: word_size,
             endianness: elf_writer::Endianness::LittleEndian,
             file_name: "fooasm.asm",
             functions: self.functions.iter().map(|f| elf_writer::Function{
                     name: &f.name,
                     offset: f.offset as usize,
                 }).collect(),
+            local_symbols: self.local_symbols.iter().map(|label| elf_writer::Function{
+                    name: &label.name,
+                    offset: label.offset as usize,
+                }).collect(),
             text_content: &self.code[..], 
+            executable_stack: false,
+            x86_features: self.x86_features,
+            frames: self.frames.clone(),
+            debug_info: if self.line_rows.is_empty() {
+                None
+            } else {
+                Some(elf_writer::DebugInfo{
+                    producer: "rassembler",
+                    files: self.source_files.iter().map(|file| &file[..]).collect(),
+                    rows: self.line_rows.iter().map(|row| elf_writer::LineRow{
+                        offset: row.offset as usize,
+                        file: row.file,
+                        line: row.line,
+                        column: row.column,
+                    }).collect(),
+                })
+            },
+            text_relocations: try!(self.absolute_references.iter().map(|reference| Ok(elf_writer::TextRelocation{
+                    offset: reference.offset as u64,
+                    symbol: None,
+                    relocation_type: try!(self.elf_absolute_relocation(reference.size)),
+                    addend: self.absolute_reference_target(reference) as i64,
+                })).chain(self.symbol_references.iter().map(|reference| Ok(elf_writer::TextRelocation{
+                    offset: reference.offset as u64,
+                    symbol: Some(&reference.symbol),
+                    relocation_type: match (self.architecture, reference.kind) {
+                        (_, SymbolReferenceKind::Absolute) => try!(self.elf_absolute_relocation(reference.size)),
+                        (Architecture::X8664, SymbolReferenceKind::Branch) => elf_writer::R_X86_64_PLT32,
+                        (Architecture::X8664, SymbolReferenceKind::Relative) => elf_writer::R_X86_64_PC32,
+                        (Architecture::X8664, SymbolReferenceKind::Got) => elf_writer::R_X86_64_GOTPCREL,
+                        (Architecture::X8664, SymbolReferenceKind::TpOff) => elf_writer::R_X86_64_TPOFF32,
+                        (Architecture::X8664, SymbolReferenceKind::GotTpOff) => elf_writer::R_X86_64_GOTTPOFF,
+                        (Architecture::X86, SymbolReferenceKind::Branch) |
+                        (Architecture::X86, SymbolReferenceKind::Relative) => elf_writer::R_386_PC32,
+                        (Architecture::X86, _) => return Err(io::Error::new(ErrorKind::InvalidInput, "GOT and TLS references need 64-bit code")),
+                    },
+                    // relative fields are measured from their end
+                    addend: match reference.kind {
+                        SymbolReferenceKind::Absolute | SymbolReferenceKind::TpOff => 0,
+                        _ => -4,
+                    },
+                }))).collect::<io::Result<_>>()),
+            thread_local_storage: if self.has_thread_locals() {
+                Some(elf_writer::ThreadLocalStorage{
+                    data: &self.tls_data[..],
+                    bss_size: self.tls_bss_size as u64,
+                    align: self.tls_alignment as u64,
+                    symbols: self.thread_locals.iter().map(|variable| elf_writer::Function{
+                        name: &variable.name,
+                        offset: variable.offset as usize,
+                    }).collect(),
+                })
+            } else {
+                None
+            },
         };
         let mut result = Vec::new();
-        e.write(&mut result).unwrap();
-        result
+        try!(e.write(&mut result));
+        Ok(result)
     }
     
-    fn make_object_file(&self) -> Vec<u8> {
+    fn make_macho_file(&self) -> io::Result<Vec<u8>> {
+        if self.architecture == Architecture::X86 {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "Mach-O output is only supported for x86-64"));
+        }
+        if self.has_thread_locals() {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "thread-local variables are not supported in Mach-O output"));
+        }
+        // C symbols get a leading underscore on Apple platforms
+        let mut symbols: Vec<macho_writer::Symbol> = self.functions.iter().map(|f| macho_writer::Symbol{
+                name: format!("_{}", f.name),
+                section: 1,
+                value: f.offset as u64,
+                external: true,
+            }).collect();
+        // the field already holds the target's address, as __text starts at address 0
+        let mut relocations: Vec<macho_writer::Relocation> = try!(self.absolute_references.iter().map(|reference| {
+                if reference.size != 8 {
+                    return Err(io::Error::new(ErrorKind::InvalidInput, "Mach-O only supports 8-byte absolute references"));
+                }
+                Ok(macho_writer::Relocation{
+                    offset: reference.offset,
+                    symbol: 1,
+                    external: false,
+                    pc_relative: false,
+                    length: 3,
+                    relocation_type: macho_writer::X86_64_RELOC_UNSIGNED,
+                })
+            }).collect());
+        for reference in self.symbol_references.iter() {
+            let name = format!("_{}", reference.symbol);
+            let symbol = match symbols.iter().position(|symbol| symbol.name == name) {
+                Some(index) => index,
+                None => {
+                    symbols.push(macho_writer::Symbol{ name: name, section: 0, value: 0, external: true });
+                    symbols.len() - 1
+                }
+            };
+            let relocation_type = match reference.kind {
+                SymbolReferenceKind::Branch => macho_writer::X86_64_RELOC_BRANCH,
+                SymbolReferenceKind::Relative => macho_writer::X86_64_RELOC_SIGNED,
+                SymbolReferenceKind::Got => macho_writer::X86_64_RELOC_GOT_LOAD,
+                SymbolReferenceKind::Absolute if reference.size == 8 => macho_writer::X86_64_RELOC_UNSIGNED,
+                SymbolReferenceKind::Absolute => return Err(io::Error::new(ErrorKind::InvalidInput, "Mach-O only supports 8-byte absolute references")),
+                SymbolReferenceKind::TpOff | SymbolReferenceKind::GotTpOff =>
+                    return Err(io::Error::new(ErrorKind::InvalidInput, "Mach-O thread-local variables are accessed through descriptors")),
+            };
+            relocations.push(macho_writer::Relocation{
+                offset: reference.offset,
+                symbol: symbol as u32,
+                external: true,
+                pc_relative: reference.kind != SymbolReferenceKind::Absolute,
+                length: if reference.size == 8 { 3 } else { 2 },
+                relocation_type: relocation_type,
+            });
+        }
+        let m = Macho{
+            cpu_type: macho_writer::CPU_TYPE_X86_64,
+            cpu_subtype: macho_writer::CPU_SUBTYPE_X86_64_ALL,
+            sections: vec![
+                macho_writer::Section{
+                    segment_name: "__TEXT".to_string(),
+                    section_name: "__text".to_string(),
+                    data: self.code.clone(),
+                    align: 4,
+                    flags: macho_writer::S_ATTR_PURE_INSTRUCTIONS | macho_writer::S_ATTR_SOME_INSTRUCTIONS,
+                    relocations: relocations,
+                },
+                macho_writer::Section{
+                    segment_name: "__DATA".to_string(),
+                    section_name: "__data".to_string(),
+                    data: vec![],
+                    align: 3,
+                    flags: macho_writer::S_REGULAR,
+                    relocations: vec![],
+                },
+            ],
+            symbols: symbols,
+            build_version: Some(macho_writer::BuildVersion{
+                platform: macho_writer::PLATFORM_MACOS,
+                minimum_os: 0x000a0c00, // 10.12, the oldest version Rust supports
+                sdk: 0,
+            }),
+        };
+        let mut result = Vec::new();
+        try!(m.write(&mut result));
+        Ok(result)
+    }
+    
+    fn make_object_file(&self) -> io::Result<Vec<u8>> {
+        let (machine, characteristics) = match self.architecture {
+            Architecture::X86 => (MACHINE_I386, 0x0004 | IMAGE_FILE_32BIT_MACHINE),
+            Architecture::X8664 => (MACHINE_AMD64, 0x0004),
+        };
         let mut c = Coff{
-            machine: MACHINE_AMD64,
+            machine: machine,
             timestamp: 0,
             optional_header: Vec::new(),
-            characteristics: 0x0004,
+            characteristics: characteristics,
             sections: vec![
                 Section{
                     name: ".text".to_string(),
@@ -131,10 +733,38 @@ impl ObjectFile {
             
         };
         
+        // the fields hold the offset of their target in .text, which COFF takes as the addend
+        for reference in self.absolute_references.iter() {
+            let type_flags = match (self.architecture, reference.size) {
+                (Architecture::X8664, 8) => IMAGE_REL_AMD64_ADDR64,
+                (Architecture::X8664, 4) => IMAGE_REL_AMD64_ADDR32,
+                (Architecture::X86, 4) => IMAGE_REL_I386_DIR32,
+                _ => return Err(io::Error::new(ErrorKind::InvalidInput, format!("{}-byte absolute references can only be written to a flat binary", reference.size))),
+            };
+            c.sections[0].relocations.push(Relocation{
+                virtual_address: reference.offset,
+                symbol_index: 0,
+                type_flags: type_flags,
+            });
+        }
+        
+        if !self.unwind_infos.is_empty() {
+            if self.architecture != Architecture::X8664 {
+                return Err(io::Error::new(ErrorKind::InvalidInput, "unwind information is only supported for x86-64"));
+            }
+            self.add_unwind_sections(&mut c);
+        }
+        if !self.line_rows.is_empty() {
+            self.add_debug_section(&mut c);
+        }
+        if self.has_thread_locals() {
+            self.add_tls_section(&mut c);
+        }
+        
         for function in self.functions.iter() {
             c.symbols.push(
                 Symbol {
-                    name: function.name.clone(),
+                    name: self.decorated_name(&function.name),
                     value: function.offset,
                     section_number: 1,
                     type_flags: 0x20,
@@ -145,10 +775,468 @@ impl ObjectFile {
                 }
             )
         }
+        for label in self.local_symbols.iter() {
+            c.symbols.push(Symbol {
+                name: label.name.clone(),
+                value: label.offset,
+                section_number: 1,
+                type_flags: 0,
+                storage_class: 3, // IMAGE_SYM_CLASS_STATIC
+                aux_symbols: vec![],
+            });
+        }
+        try!(self.add_symbol_relocations(&mut c));
         
         
         let mut result = Vec::new();
-        c.write(&mut result).unwrap();
-        result
+        try!(c.write(&mut result));
+        Ok(result)
     }
-}
\ No newline at end of file
+    
+    // References to other symbols become undefined symbols. Without a GOT, a GOT reference goes to
+    // the import address table slot __imp_symbol instead.
+    fn add_symbol_relocations(&self, c: &mut Coff) -> io::Result<()> {
+        for reference in self.symbol_references.iter() {
+            let name = match reference.kind {
+                SymbolReferenceKind::Got => format!("__imp_{}", self.decorated_name(&reference.symbol)),
+                _ => self.decorated_name(&reference.symbol),
+            };
+            let mut symbol_index = 0;
+            let mut found = false;
+            for symbol in c.symbols.iter() {
+                if symbol.name == name {
+                    found = true;
+                    break;
+                }
+                symbol_index += 1 + symbol.aux_symbols.len() as u32;
+            }
+            if !found {
+                c.symbols.push(Symbol {
+                    name: name,
+                    value: 0,
+                    section_number: 0,
+                    type_flags: if reference.kind == SymbolReferenceKind::Branch { 0x20 } else { 0 },
+                    storage_class: IMAGE_SYM_CLASS_EXTERNAL,
+                    aux_symbols: vec![],
+                });
+            }
+            let type_flags = match (self.architecture, reference.kind, reference.size) {
+                (Architecture::X8664, SymbolReferenceKind::Absolute, 8) => IMAGE_REL_AMD64_ADDR64,
+                (Architecture::X8664, SymbolReferenceKind::Absolute, 4) => IMAGE_REL_AMD64_ADDR32,
+                (Architecture::X8664, SymbolReferenceKind::Absolute, _) =>
+                    return Err(io::Error::new(ErrorKind::InvalidInput, format!("{}-byte absolute references cannot be relocated", reference.size))),
+                // the offset in the TLS template, which the code adds to the thread's block
+                (Architecture::X8664, SymbolReferenceKind::TpOff, _) => IMAGE_REL_AMD64_SECREL,
+                (_, SymbolReferenceKind::GotTpOff, _) => return Err(io::Error::new(ErrorKind::InvalidInput, "COFF has no initial-exec thread-local variables")),
+                (Architecture::X8664, _, _) => IMAGE_REL_AMD64_REL32,
+                (Architecture::X86, SymbolReferenceKind::Absolute, 4) => IMAGE_REL_I386_DIR32,
+                (Architecture::X86, SymbolReferenceKind::Absolute, _) =>
+                    return Err(io::Error::new(ErrorKind::InvalidInput, format!("{}-byte absolute references cannot be relocated", reference.size))),
+                (Architecture::X86, SymbolReferenceKind::TpOff, _) => IMAGE_REL_I386_SECREL,
+                (Architecture::X86, SymbolReferenceKind::Got, _) => return Err(io::Error::new(ErrorKind::InvalidInput, "GOT references need 64-bit code")),
+                (Architecture::X86, _, _) => IMAGE_REL_I386_REL32,
+            };
+            c.sections[0].relocations.push(Relocation{
+                virtual_address: reference.offset,
+                symbol_index: symbol_index,
+                type_flags: type_flags,
+            });
+        }
+        Ok( () )
+    }
+    
+    // .xdata holds an UNWIND_INFO for each function and .pdata the RUNTIME_FUNCTION entries pointing at them
+    fn add_unwind_sections(&self, c: &mut Coff) {
+        let text_symbol_index = 0;
+        let xdata_symbol_index = c.symbols.iter().fold(0, |sum, symbol| sum + 1 + symbol.aux_symbols.len()) as u32;
+        
+        let mut xdata = Vec::new();
+        let mut pdata = Vec::new();
+        let mut pdata_relocations = Vec::new();
+        for unwind_info in self.unwind_infos.iter() {
+            let relocated_words = [
+                (unwind_info.begin, text_symbol_index),
+                (unwind_info.end, text_symbol_index),
+                (xdata.len() as u32, xdata_symbol_index),
+            ];
+            for &(value, symbol_index) in relocated_words.iter() {
+                pdata_relocations.push(Relocation{
+                    virtual_address: pdata.len() as u32,
+                    symbol_index: symbol_index,
+                    type_flags: IMAGE_REL_AMD64_ADDR32NB,
+                });
+                pdata.write_u32::<LittleEndian>(value).unwrap();
+            }
+            xdata.extend(unwind_info.encode().expect("invalid unwind information"));
+        }
+        
+        let xdata_section_number = c.sections.len() as i16 + 1;
+        c.symbols.push(section_symbol(".xdata", xdata_section_number, &xdata, 0));
+        c.symbols.push(section_symbol(".pdata", xdata_section_number + 1, &pdata, pdata_relocations.len()));
+        c.sections.push(Section{
+            name: ".xdata".to_string(),
+            characteristics: 0x40300040,
+            data: xdata,
+            relocations: vec![],
+        });
+        c.sections.push(Section{
+            name: ".pdata".to_string(),
+            characteristics: 0x40300040,
+            data: pdata,
+            relocations: pdata_relocations,
+        });
+    }
+    
+    // The linker gathers .tls$ sections between the start and end of the TLS template that _tls_used
+    // describes. Zeroed variables are written out, as the template has no uninitialized part here.
+    fn add_tls_section(&self, c: &mut Coff) {
+        let mut data = self.tls_data.clone();
+        data.resize(self.tls_data.len() + self.tls_bss_size as usize, 0);
+        let section_number = c.sections.len() as i16 + 1;
+        c.symbols.push(section_symbol(".tls$", section_number, &data, 0));
+        for variable in self.thread_locals.iter() {
+            c.symbols.push(Symbol {
+                name: self.decorated_name(&variable.name),
+                value: variable.offset,
+                section_number: section_number,
+                type_flags: 0,
+                storage_class: IMAGE_SYM_CLASS_EXTERNAL,
+                aux_symbols: vec![],
+            });
+        }
+        // IMAGE_SCN_ALIGN_*BYTES counts powers of two from 1
+        let alignment = (self.tls_alignment.trailing_zeros() + 1) << 20;
+        c.sections.push(Section{
+            name: ".tls$".to_string(),
+            characteristics: 0xC0000040 | alignment,
+            data: data,
+            relocations: vec![],
+        });
+    }
+    
+    fn has_thread_locals(&self) -> bool {
+        !self.thread_locals.is_empty() || !self.tls_data.is_empty() || self.tls_bss_size != 0
+    }
+    
+    // CodeView line information in .debug$S
+    fn add_debug_section(&self, c: &mut Coff) {
+        let text_symbol_index = 0;
+        let line_info = coff_writer::LineInfo{
+            machine: c.machine,
+            files: self.source_files.clone(),
+            code_size: self.code.len() as u32,
+            rows: self.line_rows.iter().map(|row| coff_writer::LineRow{
+                offset: row.offset,
+                file: row.file,
+                line: row.line,
+                column: row.column as u16,
+            }).collect(),
+        };
+        let (data, relocations) = line_info.encode(text_symbol_index).expect("invalid line information");
+
+        let section_number = c.sections.len() as i16 + 1;
+        c.symbols.push(section_symbol(".debug$S", section_number, &data, relocations.len()));
+        c.sections.push(Section{
+            name: ".debug$S".to_string(),
+            characteristics: 0x42100040,
+            data: data,
+            relocations: relocations,
+        });
+    }
+}
+
+fn section_symbol(name: &str, section_number: i16, data: &[u8], relocation_count: usize) -> Symbol {
+    let mut aux = [0u8; 18];
+    LittleEndian::write_u32(&mut aux[0..4], data.len() as u32);
+    LittleEndian::write_u16(&mut aux[4..6], relocation_count as u16);
+    Symbol {
+        name: name.to_string(),
+        value: 0,
+        section_number: section_number,
+        type_flags: 0,
+        storage_class: 3,
+        aux_symbols: vec![aux],
+    }
+}
+
+#[cfg(test)]
+fn empty_object() -> ObjectFile {
+    ObjectFile{
+        architecture: Architecture::X8664,
+        functions: vec![],
+        local_symbols: vec![],
+        code: vec![],
+        x86_features: 0,
+        frames: vec![],
+        unwind_infos: vec![],
+        source_files: vec![],
+        line_rows: vec![],
+        absolute_references: vec![],
+        symbol_references: vec![],
+        tls_data: vec![],
+        tls_bss_size: 0,
+        tls_alignment: 1,
+        thread_locals: vec![],
+    }
+}
+
+#[test]
+fn import_elf() {
+    let text = [
+        0xe8, 0, 0, 0, 0, // call bar
+        0x48, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, // mov rax, address of bar
+        0xc3,
+        0x31, 0xc0, 0xc3, // bar
+    ];
+    let mut elf = Elf{
+        architecture: elf_writer::Architecture::X8664,
+        word_size: elf_writer::WordSize::Bits64,
+        endianness: elf_writer::Endianness::LittleEndian,
+        file_name: "imported.asm",
+        functions: vec![elf_writer::Function{ name: "foo", offset: 0 }, elf_writer::Function{ name: "bar", offset: 16 }],
+        local_symbols: vec![elf_writer::Function{ name: "bar_done", offset: 18 }],
+        text_content: &text[..],
+        executable_stack: false,
+        x86_features: elf_writer::GNU_PROPERTY_X86_FEATURE_1_IBT,
+        frames: vec![elf_writer::FrameDescription{ offset: 16, length: 3, instructions: vec![] }],
+        debug_info: None,
+        text_relocations: vec![
+            elf_writer::TextRelocation{ offset: 1, symbol: Some("bar"), relocation_type: elf_writer::R_X86_64_PLT32, addend: -4 },
+            elf_writer::TextRelocation{ offset: 7, symbol: None, relocation_type: elf_writer::R_X86_64_64, addend: 16 },
+        ],
+        thread_local_storage: None,
+    };
+    let mut data = Vec::new();
+    elf.write(&mut data).unwrap();
+    
+    let mut object = empty_object();
+    object.code = vec![0xc3];
+    object.x86_features = elf_writer::GNU_PROPERTY_X86_FEATURE_1_IBT | elf_writer::GNU_PROPERTY_X86_FEATURE_1_SHSTK;
+    object.import_elf(&data[..]).unwrap();
+    
+    assert_eq!(object.code.len(), 16 + text.len());
+    assert_eq!(&object.code[16..21], &[0xe8, 0x0b, 0, 0, 0][..]);
+    assert_eq!(LittleEndian::read_u64(&object.code[23..]), 32);
+    assert_eq!(object.absolute_references, vec![AbsoluteReference{ offset: 23, size: 8 }]);
+    assert_eq!(object.functions.iter().map(|f| (&f.name[..], f.offset)).collect::<Vec<_>>(), vec![("foo", 16), ("bar", 32)]);
+    assert_eq!(object.local_symbols.iter().map(|f| (&f.name[..], f.offset)).collect::<Vec<_>>(), vec![("bar_done", 34)]);
+    assert_eq!(object.frames[0].offset, 32);
+    assert_eq!(object.x86_features, elf_writer::GNU_PROPERTY_X86_FEATURE_1_IBT);
+    
+    assert!(object.import_elf(&data[..]).is_err(), "foo and bar are defined twice");
+    
+    elf.text_relocations[0].symbol = Some("elsewhere");
+    data.clear();
+    elf.write(&mut data).unwrap();
+    assert!(empty_object().import_elf(&data[..]).is_err());
+}
+
+#[test]
+fn import_coff() {
+    let mut object = empty_object();
+    object.functions.push(ExportedFunction{ offset: 0, name: "foo".to_string() });
+    object.functions.push(ExportedFunction{ offset: 5, name: "bar".to_string() });
+    object.code = vec![0xe8, 0, 0, 0, 0, 0xc3];
+    let mut coff = Coff::read(&object.make_object_file().unwrap()[..]).unwrap();
+    let foo_index = coff.symbols.iter().fold(0, |sum, symbol| sum + 1 + symbol.aux_symbols.len()) as u32 - 4;
+    coff.sections[0].relocations.push(Relocation{ virtual_address: 1, symbol_index: foo_index, type_flags: IMAGE_REL_AMD64_REL32 });
+    let mut data = Vec::new();
+    coff.write(&mut data).unwrap();
+    
+    let mut imported = empty_object();
+    imported.code = vec![0xc3];
+    imported.import_coff(&data[..]).unwrap();
+    assert_eq!(&imported.code[16..], &[0xe8, 0xfb, 0xff, 0xff, 0xff, 0xc3][..]);
+    assert_eq!(imported.functions.iter().map(|f| (&f.name[..], f.offset)).collect::<Vec<_>>(), vec![("foo", 16), ("bar", 21)]);
+}
+
+#[test]
+fn i386_objects() {
+    let mut object = empty_object();
+    object.architecture = Architecture::X86;
+    object.functions.push(ExportedFunction{ offset: 0, name: "foo".to_string() });
+    object.code = vec![0x31, 0xc0, 0xc3];
+    
+    let elf = object.make_elf_file().unwrap();
+    let read = Elf::read(&elf[..]).unwrap();
+    assert_eq!(read.word_size, elf_writer::WordSize::Bits32);
+    assert_eq!(read.architecture, elf_writer::Architecture::X86);
+    assert_eq!(read.functions, vec![elf_writer::Function{ name: "foo", offset: 0 }]);
+    
+    let coff = object.make_object_file().unwrap();
+    let read = Coff::read(&coff[..]).unwrap();
+    assert_eq!(read.machine, MACHINE_I386);
+    assert!(read.symbols.iter().any(|symbol| symbol.name == "_foo"));
+    
+    let mut imported = empty_object();
+    imported.import_coff(&coff[..]).unwrap();
+    assert_eq!(imported.architecture, Architecture::X86);
+    assert_eq!(imported.functions[0].name, "foo");
+    assert!(imported.import_elf(&empty_object().make_elf_file().unwrap()[..]).is_err(), "x86-64 code cannot join i386 code");
+}
+
+#[test]
+fn absolute_relocations() {
+    let mut object = empty_object();
+    object.functions.push(ExportedFunction{ offset: 0, name: "foo".to_string() });
+    object.code = vec![
+        0x48, 0xb8, 11, 0, 0, 0, 0, 0, 0, 0, // mov rax, address of the data
+        0xc3,
+        0xaa,
+    ];
+    object.absolute_references.push(AbsoluteReference{ offset: 2, size: 8 });
+    
+    let elf = object.make_elf_file().unwrap();
+    let read = Elf::read(&elf[..]).unwrap();
+    assert_eq!(read.text_relocations, vec![
+        elf_writer::TextRelocation{ offset: 2, symbol: None, relocation_type: elf_writer::R_X86_64_64, addend: 11 },
+    ]);
+    let coff = object.make_object_file().unwrap();
+    let read = Coff::read(&coff[..]).unwrap();
+    assert_eq!(read.sections[0].relocations.len(), 1);
+    assert_eq!(read.sections[0].relocations[0].type_flags, IMAGE_REL_AMD64_ADDR64);
+    
+    // importing either moves the code and its references along
+    for imported in [elf, coff].iter() {
+        let mut merged = empty_object();
+        merged.code = vec![0xc3];
+        if imported[..4] == b"\x7fELF"[..] {
+            merged.import_elf(&imported[..]).unwrap();
+        } else {
+            merged.import_coff(&imported[..]).unwrap();
+        }
+        assert_eq!(merged.absolute_references, vec![AbsoluteReference{ offset: 18, size: 8 }]);
+        assert_eq!(&merged.code[18..26], &[27, 0, 0, 0, 0, 0, 0, 0]);
+    }
+    
+    let mut out = Vec::new();
+    object.write(&mut out, ObjectFormat::MachO).unwrap();
+}
+
+#[test]
+fn unsupported_output_is_an_error() {
+    let mut object = empty_object();
+    object.code = vec![0x66, 0xb8, 0, 0, 0xc3]; // mov ax, address of the code
+    object.absolute_references.push(AbsoluteReference{ offset: 2, size: 2 });
+    for &format in [ObjectFormat::Elf, ObjectFormat::Coff, ObjectFormat::MachO].iter() {
+        assert_eq!(object.write(&mut Vec::new(), format).unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+    
+    let mut object = empty_object();
+    object.architecture = Architecture::X86;
+    assert_eq!(object.write(&mut Vec::new(), ObjectFormat::MachO).unwrap_err().kind(), ErrorKind::InvalidInput);
+    
+    object.code = vec![0x8b, 0x05, 0, 0, 0, 0, 0xc3]; // mov eax, [puts@GOT]
+    object.symbol_references.push(SymbolReference{ offset: 2, size: 4, symbol: "puts".to_string(), kind: SymbolReferenceKind::Got });
+    for &format in [ObjectFormat::Elf, ObjectFormat::Coff].iter() {
+        assert_eq!(object.write(&mut Vec::new(), format).unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+}
+
+#[test]
+fn symbol_relocations() {
+    let mut object = empty_object();
+    object.functions.push(ExportedFunction{ offset: 0, name: "foo".to_string() });
+    object.code = vec![
+        0xe8, 0, 0, 0, 0, // call bar
+        0x48, 0x8b, 0x05, 0, 0, 0, 0, // mov rax, [rip + bar@GOTPCREL]
+        0xc3,
+    ];
+    object.symbol_references = vec![
+        SymbolReference{ offset: 1, size: 4, symbol: "bar".to_string(), kind: SymbolReferenceKind::Branch },
+        SymbolReference{ offset: 8, size: 4, symbol: "bar".to_string(), kind: SymbolReferenceKind::Got },
+    ];
+    
+    let mut tls = empty_object();
+    tls.symbol_references = vec![
+        SymbolReference{ offset: 3, size: 4, symbol: "counter".to_string(), kind: SymbolReferenceKind::GotTpOff },
+        SymbolReference{ offset: 11, size: 4, symbol: "counter".to_string(), kind: SymbolReferenceKind::TpOff },
+    ];
+    tls.code = vec![0; 16];
+    let elf = tls.make_elf_file().unwrap();
+    let read = Elf::read(&elf[..]).unwrap();
+    assert_eq!(read.text_relocations.iter().map(|r| (r.relocation_type, r.addend)).collect::<Vec<_>>(),
+               vec![(elf_writer::R_X86_64_GOTTPOFF, -4), (elf_writer::R_X86_64_TPOFF32, 0)]);
+    assert_eq!(tls.write(&mut Vec::new(), ObjectFormat::Coff).unwrap_err().kind(), ErrorKind::InvalidInput);
+    assert_eq!(tls.write(&mut Vec::new(), ObjectFormat::MachO).unwrap_err().kind(), ErrorKind::InvalidInput);
+    
+
+    let elf = object.make_elf_file().unwrap();
+    let read = Elf::read(&elf[..]).unwrap();
+    assert_eq!(read.text_relocations, vec![
+        elf_writer::TextRelocation{ offset: 1, symbol: Some("bar"), relocation_type: elf_writer::R_X86_64_PLT32, addend: -4 },
+        elf_writer::TextRelocation{ offset: 8, symbol: Some("bar"), relocation_type: elf_writer::R_X86_64_GOTPCREL, addend: -4 },
+    ]);
+    
+    let coff = Coff::read(&object.make_object_file().unwrap()[..]).unwrap();
+    let undefined: Vec<&str> = coff.symbols.iter().filter(|symbol| symbol.section_number == 0).map(|symbol| &symbol.name[..]).collect();
+    assert_eq!(undefined, vec!["bar", "__imp_bar"]);
+    assert_eq!(coff.sections[0].relocations.iter().map(|r| r.type_flags).collect::<Vec<_>>(), vec![IMAGE_REL_AMD64_REL32; 2]);
+    
+    // only a linker can fill them in
+    assert!(object.flat_binary(0).is_err());
+    let mut symbols = HashMap::new();
+    symbols.insert("bar".to_string(), 0x1000);
+    assert!(object.flat_binary_with_symbols(0, &symbols).is_err(), "there is no GOT");
+    object.symbol_references.pop();
+    assert_eq!(&object.flat_binary_with_symbols(0, &symbols).unwrap()[..5], &[0xe8, 0xfb, 0x0f, 0x00, 0x00]);
+}
+
+#[test]
+fn thread_local_sections() {
+    let mut object = empty_object();
+    object.functions.push(ExportedFunction{ offset: 0, name: "foo".to_string() });
+    object.code = vec![
+        0x64, 0x8b, 0x04, 0x25, 0, 0, 0, 0, // mov eax, fs:[zeroed@TPOFF]
+        0xc3,
+    ];
+    object.symbol_references = vec![
+        SymbolReference{ offset: 4, size: 4, symbol: "zeroed".to_string(), kind: SymbolReferenceKind::TpOff },
+    ];
+    object.tls_data = vec![7, 0, 0, 0];
+    object.tls_bss_size = 4;
+    object.tls_alignment = 4;
+    object.thread_locals = vec![
+        ExportedFunction{ offset: 0, name: "initialized".to_string() },
+        ExportedFunction{ offset: 4, name: "zeroed".to_string() },
+    ];
+    
+    let elf = object.make_elf_file().unwrap();
+    let read = Elf::read(&elf[..]).unwrap();
+    let tls = read.thread_local_storage.unwrap();
+    assert_eq!(tls.data, &[7, 0, 0, 0]);
+    assert_eq!((tls.bss_size, tls.align), (4, 4));
+    assert_eq!(tls.symbols, vec![
+        elf_writer::Function{ name: "initialized", offset: 0 },
+        elf_writer::Function{ name: "zeroed", offset: 4 },
+    ]);
+    assert_eq!(read.text_relocations[0].symbol, Some("zeroed"));
+    
+    // COFF has no uninitialized TLS, the zeroed variables are part of .tls$
+    let coff = Coff::read(&object.make_object_file().unwrap()[..]).unwrap();
+    let tls = coff.sections.iter().position(|section| section.name == ".tls$").unwrap();
+    assert_eq!(coff.sections[tls].data, vec![7, 0, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(coff.sections[tls].characteristics, 0xC0300040);
+    let zeroed = coff.symbols.iter().find(|symbol| symbol.name == "zeroed").unwrap();
+    assert_eq!((zeroed.section_number, zeroed.value), (tls as i16 + 1, 4));
+    assert_eq!(coff.sections[0].relocations[0].type_flags, IMAGE_REL_AMD64_SECREL);
+    
+    assert_eq!(object.write(&mut Vec::new(), ObjectFormat::MachO).unwrap_err().kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn local_symbols() {
+    let mut object = empty_object();
+    object.functions.push(ExportedFunction{ offset: 0, name: "foo".to_string() });
+    object.local_symbols.push(ExportedFunction{ offset: 1, name: "foo_exit".to_string() });
+    object.code = vec![0x90, 0xc3];
+    
+    let elf = object.make_elf_file().unwrap();
+    let read = Elf::read(&elf[..]).unwrap();
+    assert_eq!(read.local_symbols, vec![elf_writer::Function{ name: "foo_exit", offset: 1 }]);
+    assert_eq!(read.functions, vec![elf_writer::Function{ name: "foo", offset: 0 }]);
+    
+    let coff = Coff::read(&object.make_object_file().unwrap()[..]).unwrap();
+    let exit = coff.symbols.iter().find(|symbol| symbol.name == "foo_exit").unwrap();
+    assert_eq!((exit.value, exit.section_number, exit.storage_class), (1, 1, 3));
+}
@@ -1,13 +1,21 @@
-use x64::compiler::{StmtBuffer, Stmt, Opdata, compile_op};
-use x64::parser::{Ident, Arg, Size, JumpTarget, ImmediateValue};
+use x64::compiler::{StmtBuffer, Stmt, Opdata, CfiDirective, SehDirective, Mode, Decorations, Rounding, compile_op};
+use x64::regs::{Mask, rip_relative};
+use x64::features::Features;
+use x64::parser::{Ident, Arg, Size, JumpTarget, JumpType, ImmediateValue, Register, RegKind, RegFamily, MemoryRef};
+use x64::listing::instruction_text;
 use std::ops::{Deref, DerefMut};
-use object_file::{ObjectFile, ExportedFunction};
+use std::mem;
+use std::cmp;
+use object_file::{ObjectFile, ObjectFormat, Architecture, ExportedFunction, LineRow, AbsoluteReference, SymbolReference, SymbolReferenceKind};
 use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use std::fs::File;
 use std::env;
 use std::convert::Into;
 use byteorder::{LittleEndian, ByteOrder};
+use elf_writer::{FrameDescription, CallFrameInstruction, GNU_PROPERTY_X86_FEATURE_1_IBT, GNU_PROPERTY_X86_FEATURE_1_SHSTK};
+use coff_writer::{UnwindInfo, UnwindCode};
 
 
 
@@ -15,16 +23,136 @@ pub struct Assembler {
     inner: FlaggedAssembler,
 }
 
+static NEXT_ASSEMBLER_ID: AtomicUsize = AtomicUsize::new(0);
+
 pub struct FlaggedAssembler {
     buffer: StmtBuffer,
     prefixes: Vec<Ident>,
-    jump_target_counter: JumpTarget,
-    allocated_jump_targets: HashSet<JumpTarget>,
+    id: u32,
+    labels: Vec<LabelState>, // indexed by the labels of this assembler
+    emit_local_symbols: bool,
+    x86_features: u32, // GNU_PROPERTY_X86_FEATURE_1_* bits
+    mode: Mode,
+    decorations: Decorations,
+    target_features: Features,
+    position_independent: bool,
+    const_pool: Vec<PoolConstant>,
+    thread_locals: Vec<ThreadLocalVariable>,
+    instructions: Vec<ListedInstruction>,
+}
+
+// A constant waiting to be placed in the pool
+#[derive(Debug)]
+struct PoolConstant {
+    label: JumpTarget,
+    bytes: Vec<u8>,
+    alignment: u64,
+}
+
+#[derive(Debug, Clone)]
+struct LabelState {
+    name: Option<Ident>,
+    placed: bool,
+}
+
+// An instruction as it was given, and the statements it was compiled to, for the listing
+#[derive(Debug)]
+struct ListedInstruction {
+    start: usize,
+    end: usize,
+    name: Ident,
+    prefixes: Vec<Ident>,
+    args: Vec<Arg>,
+    decorations: Decorations,
+}
+
+// Where dump placed things: the offset in the code of every statement, and of every label
+struct Layout {
+    offsets: Vec<usize>,
+    labels: Vec<Option<usize>>,
+}
+
+// A thread-local variable, without an initial value it goes into .tbss
+#[derive(Debug)]
+struct ThreadLocalVariable {
+    name: Ident,
+    initial_value: Option<Vec<u8>>,
+    size: u64,
+    alignment: u64,
 }
 
 impl FlaggedAssembler {
     fn encode(&mut self, name: &str, data: &'static [Opdata], args: Vec<Arg>) {
-        compile_op(&mut self.buffer, name.to_string(), self.prefixes.clone(), args, data).expect("compile_op failed")
+        // prefixes and decorations only apply to the instruction that follows them
+        let prefixes = mem::replace(&mut self.prefixes, Vec::new());
+        let decorations = mem::replace(&mut self.decorations, Decorations::default());
+        let start = self.buffer.len();
+        let mut listed = ListedInstruction{
+            start: start,
+            end: start,
+            name: name.to_string(),
+            prefixes: prefixes.clone(),
+            args: args.clone(),
+            decorations: decorations.clone(),
+        };
+        compile_op(&mut self.buffer, name.to_string(), prefixes, args, data, self.mode, decorations, self.target_features).expect("compile_op failed");
+        listed.end = self.buffer.len();
+        self.instructions.push(listed);
+        for stmt in &self.buffer[start..] {
+            match *stmt {
+                Stmt::ForwardJumpTarget(target, _) | Stmt::BackwardJumpTarget(target, _) => self.check_label(target),
+                Stmt::AbsoluteJumpTarget(target, _) => {
+                    self.check_label(target);
+                    if self.position_independent {
+                        panic!("'{}': absolute references are not position independent", name);
+                    }
+                }
+                Stmt::GlobalJumpTarget(_, _, SymbolReferenceKind::Absolute) if self.position_independent =>
+                    panic!("'{}': absolute references are not position independent", name),
+                // R_X86_64_TPOFF32 assumes the variable is in the executable, which a shared object cannot
+                Stmt::GlobalJumpTarget(ref symbol, _, SymbolReferenceKind::TpOff) if self.position_independent =>
+                    panic!("'{}': {} is referenced through the thread pointer, which is not position independent", name, symbol),
+                _ => (),
+            }
+        }
+    }
+    
+    fn check_label(&self, target: JumpTarget) {
+        if target.assembler != self.id || target.index as usize >= self.labels.len() {
+            panic!("{} was allocated by another assembler", target);
+        }
+    }
+    
+    // How diagnostics refer to a label: by its name if it has one
+    fn label_name(&self, target: JumpTarget) -> String {
+        match self.labels.get(target.index as usize).and_then(|label| label.name.as_ref()) {
+            Some(name) if target.assembler == self.id => format!("label '{}'", name),
+            _ => target.to_string(),
+        }
+    }
+    
+    // AVX-512 decorations for the next instruction. A write mask merges into the destination ({k}),
+    // a zeroing mask clears the elements that are masked out ({k}{z}).
+    pub fn with_mask(&mut self, mask: Mask) -> &mut FlaggedAssembler {
+        self.decorations.mask = Some(mask.into());
+        self
+    }
+    
+    pub fn with_zeroing_mask(&mut self, mask: Mask) -> &mut FlaggedAssembler {
+        self.decorations.mask = Some(mask.into());
+        self.decorations.zeroing = true;
+        self
+    }
+    
+    // Loads a single element from the memory operand and broadcasts it to the whole vector ({1toN})
+    pub fn with_broadcast(&mut self) -> &mut FlaggedAssembler {
+        self.decorations.broadcast = true;
+        self
+    }
+    
+    pub fn with_rounding(&mut self, rounding: Rounding) -> &mut FlaggedAssembler {
+        self.decorations.rounding = Some(rounding);
+        self
     }
 }
 
@@ -35,6 +163,15 @@ struct JumpToResolve {
     target: JumpTarget,
     from: usize,
     size: Size,
+    absolute: bool,
+}
+
+#[derive(Debug)]
+struct SymbolToResolve {
+    symbol: Ident,
+    from: usize,
+    size: Size,
+    kind: SymbolReferenceKind,
 }
 
 impl Assembler {
@@ -43,13 +180,63 @@ impl Assembler {
             inner: FlaggedAssembler{
                 buffer: Vec::new(),
                 prefixes: Vec::new(),
-                jump_target_counter: 5050,
-                allocated_jump_targets: HashSet::new(),
+                id: NEXT_ASSEMBLER_ID.fetch_add(1, Ordering::Relaxed) as u32,
+                labels: Vec::new(),
+                emit_local_symbols: false,
+                x86_features: 0,
+                mode: Mode::Bits64,
+                decorations: Decorations::default(),
+                target_features: Features::ALL,
+                position_independent: false,
+                const_pool: Vec::new(),
+                thread_locals: Vec::new(),
+                instructions: Vec::new(),
             },
         }
     }
 
+    // Selects the processor mode the following instructions are encoded for. The object file
+    // is 32-bit if the assembler is not in 64-bit mode at the end.
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.inner.mode = mode;
+    }
+    
+    // Restricts the instructions that can be encoded to those supported by the target processor.
+    // Encoding anything else is an error. All features are allowed by default.
+    pub fn set_target_features(&mut self, features: Features) {
+        self.inner.target_features = features;
+    }
+    
+    // Marks the ELF object as ready for Control-flow Enforcement. With indirect_branch_tracking every
+    // function reached through an indirect call or jump must start with endbr64 (endbr32), with
+    // shadow_stack calls and returns must stay paired. The linker only enables a feature in the
+    // executable if every object it links is marked. Off by default.
+    pub fn set_cet(&mut self, indirect_branch_tracking: bool, shadow_stack: bool) {
+        self.inner.x86_features = 0;
+        if indirect_branch_tracking {
+            self.inner.x86_features |= GNU_PROPERTY_X86_FEATURE_1_IBT;
+        }
+        if shadow_stack {
+            self.inner.x86_features |= GNU_PROPERTY_X86_FEATURE_1_SHSTK;
+        }
+    }
+    
+    // Writes the named labels into the object file as local symbols, for debuggers and disassemblers.
+    // Off by default.
+    pub fn set_local_symbols(&mut self, emit_local_symbols: bool) {
+        self.inner.emit_local_symbols = emit_local_symbols;
+    }
+    
+    // Rejects instructions that need an absolute address, which would keep shared libraries and
+    // position independent executables from loading anywhere, and thread-local variables reached
+    // through extern_tpoff, which only works in the executable. Off by default.
+    pub fn set_position_independent(&mut self, position_independent: bool) {
+        self.inner.position_independent = position_independent;
+    }
+    
+    // Starting a new function places the constants the previous one referenced
     pub fn global(&mut self, name: &str) {
+        self.flush_const_pool();
         self.inner.buffer.push(Stmt::GlobalLabel(name.to_string()));
     }
     
@@ -59,21 +246,29 @@ impl Assembler {
         }
     }
     
+    // Every label must be placed exactly once before the code is dumped
     pub fn allocate_local(&mut self) -> JumpTarget {
-        loop {
-            let ret = self.jump_target_counter;
-            
-            // I want this to be deterministic but not terribly likely to be duplicated between different Assembler instances
-            self.jump_target_counter = self.jump_target_counter.wrapping_add(47).wrapping_add(self.buffer.len() as u64).wrapping_mul(199);
-            
-            if !self.allocated_jump_targets.contains(&ret) {
-                self.allocated_jump_targets.insert(ret);
-                return ret;
-            }
+        self.allocate_label(None)
+    }
+    
+    // A named label shows up by its name in panics, and in the symbol table if set_local_symbols is on
+    pub fn allocate_named_local(&mut self, name: &str) -> JumpTarget {
+        self.allocate_label(Some(name.to_string()))
+    }
+    
+    fn allocate_label(&mut self, name: Option<Ident>) -> JumpTarget {
+        self.inner.labels.push(LabelState{ name: name, placed: false });
+        JumpTarget{
+            assembler: self.inner.id,
+            index: self.inner.labels.len() as u32 - 1,
         }
     }
     
     pub fn place_local(&mut self, target: JumpTarget) {
+        self.inner.check_label(target);
+        if mem::replace(&mut self.inner.labels[target.index as usize].placed, true) {
+            panic!("{} is placed twice", self.inner.label_name(target));
+        }
         self.inner.buffer.push(Stmt::LocalLabel(target));
     }
     
@@ -83,35 +278,316 @@ impl Assembler {
         ret
     }
     
+    pub fn named_local(&mut self, name: &str) -> JumpTarget {
+        let ret = self.allocate_named_local(name);
+        self.place_local(ret);
+        ret
+    }
+    
     pub fn align(&mut self, alignment_bytes: u64) {
         self.inner.buffer.push(Stmt::Align(ImmediateValue::U64(alignment_bytes)));
     }
     
+    // Returns a rip-relative memory operand for a constant in the pool. Equal constants share
+    // one entry. The pool is placed by flush_const_pool, the next global or the end of the code.
+    pub fn const_pool_bytes(&mut self, bytes: &[u8], alignment: u64) -> Arg {
+        if alignment == 0 || !alignment.is_power_of_two() {
+            panic!("Constant alignment must be a power of two: {}", alignment);
+        }
+        if let Some(constant) = self.inner.const_pool.iter_mut().find(|c| c.bytes == bytes) {
+            constant.alignment = cmp::max(constant.alignment, alignment);
+            return rip_relative(constant.label);
+        }
+        let label = self.allocate_local();
+        self.inner.const_pool.push(PoolConstant{
+            label: label,
+            bytes: bytes.to_vec(),
+            alignment: alignment,
+        });
+        rip_relative(label)
+    }
+    
+    pub fn const_pool_f32(&mut self, x: f32) -> Arg {
+        let mut bytes = [0u8; 4];
+        LittleEndian::write_f32(&mut bytes, x);
+        self.const_pool_bytes(&bytes, 4)
+    }
+    
+    pub fn const_pool_f64(&mut self, x: f64) -> Arg {
+        let mut bytes = [0u8; 8];
+        LittleEndian::write_f64(&mut bytes, x);
+        self.const_pool_bytes(&bytes, 8)
+    }
+    
+    // Places the pending constants here, most aligned first so they need the least padding.
+    // Nothing may fall through into the pool.
+    pub fn flush_const_pool(&mut self) {
+        let mut pool = mem::replace(&mut self.inner.const_pool, Vec::new());
+        pool.sort_by_key(|constant| cmp::Reverse(constant.alignment));
+        for constant in pool {
+            self.align(constant.alignment);
+            self.place_local(constant.label);
+            self.constant(&constant.bytes);
+        }
+    }
+    
+    // Defines a thread-local variable with an initial value. The code reaches it through the
+    // thread pointer, e.g. `x.fs().mov(DWord::Eax, extern_tpoff(name))` in an executable.
+    pub fn thread_local(&mut self, name: &str, initial_value: &[u8], alignment: u64) {
+        self.add_thread_local(name, Some(initial_value.to_vec()), initial_value.len() as u64, alignment);
+    }
+    
+    pub fn thread_local_zeroed(&mut self, name: &str, size: u64, alignment: u64) {
+        self.add_thread_local(name, None, size, alignment);
+    }
+    
+    fn add_thread_local(&mut self, name: &str, initial_value: Option<Vec<u8>>, size: u64, alignment: u64) {
+        if alignment == 0 || !alignment.is_power_of_two() {
+            panic!("Thread-local alignment must be a power of two: {}", alignment);
+        }
+        if self.inner.thread_locals.iter().any(|v| v.name == name) {
+            panic!("Thread-local variable {} is defined twice", name);
+        }
+        self.inner.thread_locals.push(ThreadLocalVariable{
+            name: name.to_string(),
+            initial_value: initial_value,
+            size: size,
+            alignment: alignment,
+        });
+    }
+    
+    // The thread pointer lives in fs on x86-64 and in gs on 32-bit x86. Its first word points to itself.
+    pub fn fs(&mut self) -> &mut FlaggedAssembler {
+        self.with_prefixes(vec!["fs".to_string()])
+    }
+    
+    pub fn gs(&mut self) -> &mut FlaggedAssembler {
+        self.with_prefixes(vec!["gs".to_string()])
+    }
+    
+    pub fn load_thread_pointer<R: Into<Arg>>(&mut self, reg: R) {
+        let self_pointer = Arg::Indirect(MemoryRef{
+            index: None,
+            scale: 0,
+            base: None,
+            disp: Some(ImmediateValue::I64(0)),
+            size: None,
+        });
+        if self.inner.mode == Mode::Bits64 {
+            self.fs().mov(reg, self_pointer);
+        } else {
+            self.gs().mov(reg, self_pointer);
+        }
+    }
+    
+    // Call frame information, following the .cfi_* directives of gas. Every function described
+    // this way must be bracketed by cfi_startproc and cfi_endproc.
+    pub fn cfi_startproc(&mut self) {
+        self.inner.buffer.push(Stmt::Cfi(CfiDirective::StartProc));
+    }
+    
+    pub fn cfi_endproc(&mut self) {
+        self.inner.buffer.push(Stmt::Cfi(CfiDirective::EndProc));
+    }
+    
+    pub fn cfi_def_cfa<R: Into<Register>>(&mut self, reg: R, offset: u64) {
+        let reg = dwarf_register(reg.into(), self.inner.mode);
+        self.inner.buffer.push(Stmt::Cfi(CfiDirective::DefCfa(reg, offset)));
+    }
+    
+    pub fn cfi_def_cfa_register<R: Into<Register>>(&mut self, reg: R) {
+        let reg = dwarf_register(reg.into(), self.inner.mode);
+        self.inner.buffer.push(Stmt::Cfi(CfiDirective::DefCfaRegister(reg)));
+    }
+    
+    pub fn cfi_def_cfa_offset(&mut self, offset: u64) {
+        self.inner.buffer.push(Stmt::Cfi(CfiDirective::DefCfaOffset(offset)));
+    }
+    
+    pub fn cfi_adjust_cfa_offset(&mut self, delta: i64) {
+        self.inner.buffer.push(Stmt::Cfi(CfiDirective::AdjustCfaOffset(delta)));
+    }
+    
+    // reg has been saved at CFA + offset
+    pub fn cfi_offset<R: Into<Register>>(&mut self, reg: R, offset: i64) {
+        let reg = dwarf_register(reg.into(), self.inner.mode);
+        self.inner.buffer.push(Stmt::Cfi(CfiDirective::Offset(reg, offset)));
+    }
+    
+    pub fn cfi_restore<R: Into<Register>>(&mut self, reg: R) {
+        let reg = dwarf_register(reg.into(), self.inner.mode);
+        self.inner.buffer.push(Stmt::Cfi(CfiDirective::Restore(reg)));
+    }
+    
+    pub fn cfi_remember_state(&mut self) {
+        self.inner.buffer.push(Stmt::Cfi(CfiDirective::RememberState));
+    }
+    
+    pub fn cfi_restore_state(&mut self) {
+        self.inner.buffer.push(Stmt::Cfi(CfiDirective::RestoreState));
+    }
+    
+    // Windows x64 unwind information, following the .seh_* directives of gas. Each directive
+    // describing the prologue goes directly after the instruction it describes.
+    pub fn seh_proc(&mut self) {
+        self.inner.buffer.push(Stmt::Seh(SehDirective::Proc));
+    }
+    
+    pub fn seh_endproc(&mut self) {
+        self.inner.buffer.push(Stmt::Seh(SehDirective::EndProc));
+    }
+    
+    pub fn seh_pushreg<R: Into<Register>>(&mut self, reg: R) {
+        let reg = seh_register(reg.into(), RegFamily::LEGACY);
+        self.inner.buffer.push(Stmt::Seh(SehDirective::PushReg(reg)));
+    }
+    
+    pub fn seh_stackalloc(&mut self, size: u32) {
+        self.inner.buffer.push(Stmt::Seh(SehDirective::StackAlloc(size)));
+    }
+    
+    // reg has been set to the stack pointer plus offset
+    pub fn seh_setframe<R: Into<Register>>(&mut self, reg: R, offset: u8) {
+        let reg = seh_register(reg.into(), RegFamily::LEGACY);
+        self.inner.buffer.push(Stmt::Seh(SehDirective::SetFrame(reg, offset)));
+    }
+    
+    pub fn seh_savereg<R: Into<Register>>(&mut self, reg: R, offset: u32) {
+        let reg = seh_register(reg.into(), RegFamily::LEGACY);
+        self.inner.buffer.push(Stmt::Seh(SehDirective::SaveReg(reg, offset)));
+    }
+    
+    pub fn seh_savexmm<R: Into<Register>>(&mut self, reg: R, offset: u32) {
+        let reg = seh_register(reg.into(), RegFamily::XMM);
+        self.inner.buffer.push(Stmt::Seh(SehDirective::SaveXmm(reg, offset)));
+    }
+    
+    pub fn seh_endprologue(&mut self) {
+        self.inner.buffer.push(Stmt::Seh(SehDirective::EndPrologue));
+    }
+    
+    // Attributes the instructions that follow to a position in a source file, for debug information.
+    // Typically called as `set_location(file!(), line!(), column!())`.
+    pub fn set_location(&mut self, file: &str, line: u32, column: u32) {
+        self.inner.buffer.push(Stmt::Location(file.to_string(), line, column));
+    }
+    
+    // Moves the code of another assembler to the end of this one, so both end up in one object file
+    // and the references between their globals are resolved. The labels of the other assembler are
+    // renumbered as labels of this one; the returned map gives their new names.
+    pub fn append(&mut self, other: Assembler) -> HashMap<JumpTarget, JumpTarget> {
+        let other = other.inner;
+        if other.mode != self.inner.mode {
+            panic!("Appending {:?} code to {:?} code", other.mode, self.inner.mode);
+        }
+        if other.position_independent != self.inner.position_independent {
+            panic!("Only one of the assemblers checks for position independent code");
+        }
+        let globals: HashSet<&Ident> = self.inner.buffer.iter().filter_map(|stmt| match *stmt {
+            Stmt::GlobalLabel(ref name) => Some(name),
+            _ => None,
+        }).collect();
+        for stmt in &other.buffer {
+            if let Stmt::GlobalLabel(ref name) = *stmt {
+                if globals.contains(name) {
+                    panic!("Global {} is defined by both assemblers", name);
+                }
+            }
+        }
+        
+        let (id, first) = (self.inner.id, self.inner.labels.len() as u32);
+        self.inner.labels.extend(other.labels.iter().cloned());
+        let rename = |target: JumpTarget| JumpTarget{ assembler: id, index: first + target.index };
+        let renamed = (0..other.labels.len() as u32).map(|index| {
+            let target = JumpTarget{ assembler: other.id, index: index };
+            (target, rename(target))
+        }).collect();
+        
+        let base = self.inner.buffer.len();
+        for mut instruction in other.instructions {
+            instruction.start += base;
+            instruction.end += base;
+            for arg in instruction.args.iter_mut() {
+                match *arg {
+                    Arg::JumpTarget(JumpType::Forward(ref mut target), _) |
+                    Arg::JumpTarget(JumpType::Backward(ref mut target), _) |
+                    Arg::IndirectJumpTarget(JumpType::Forward(ref mut target), _) |
+                    Arg::IndirectJumpTarget(JumpType::Backward(ref mut target), _) => *target = rename(*target),
+                    _ => (),
+                }
+            }
+            self.inner.instructions.push(instruction);
+        }
+        for stmt in other.buffer {
+            self.inner.buffer.push(match stmt {
+                Stmt::LocalLabel(target) => Stmt::LocalLabel(rename(target)),
+                Stmt::ForwardJumpTarget(target, size) => Stmt::ForwardJumpTarget(rename(target), size),
+                Stmt::BackwardJumpTarget(target, size) => Stmt::BackwardJumpTarget(rename(target), size),
+                Stmt::AbsoluteJumpTarget(target, size) => Stmt::AbsoluteJumpTarget(rename(target), size),
+                stmt => stmt,
+            });
+        }
+        for constant in other.const_pool {
+            self.inner.const_pool.push(PoolConstant{
+                label: rename(constant.label),
+                bytes: constant.bytes,
+                alignment: constant.alignment,
+            });
+        }
+        for variable in other.thread_locals {
+            self.add_thread_local(&variable.name, variable.initial_value, variable.size, variable.alignment);
+        }
+        // the object is only marked for the features both assemblers were built for
+        self.inner.x86_features &= other.x86_features;
+        renamed
+    }
+    
     pub fn dump(&self) -> ObjectFile {
+        self.assemble().0
+    }
+    
+    fn assemble(&self) -> (ObjectFile, Layout) {
         let mut result = ObjectFile{
+            architecture: if self.inner.mode == Mode::Bits64 { Architecture::X8664 } else { Architecture::X86 },
             code: Vec::new(),
             functions: Vec::new(),
+            local_symbols: Vec::new(),
+            x86_features: self.inner.x86_features,
+            frames: Vec::new(),
+            unwind_infos: Vec::new(),
+            source_files: Vec::new(),
+            line_rows: Vec::new(),
+            absolute_references: Vec::new(),
+            symbol_references: Vec::new(),
+            tls_data: Vec::new(),
+            tls_bss_size: 0,
+            tls_alignment: 1,
+            thread_locals: Vec::new(),
         };
+        self.lay_out_thread_locals(&mut result);
         
-        let mut labels = HashMap::new();
+        let mut labels = vec![None; self.inner.labels.len()];
+        let mut offsets = Vec::with_capacity(self.inner.buffer.len() + 1);
         let mut jumps_to_resolve = Vec::new();
+        let mut symbols_to_resolve = Vec::new();
+        let mut frame: Option<FrameBuilder> = None;
+        let mut unwind: Option<UnwindBuilder> = None;
         
         for stmt in &self.inner.buffer {
-            println!("{:?}", stmt);
+            offsets.push(result.code.len());
             match *stmt {
                 Stmt::Const(x) => { result.code.push(x); }
-                Stmt::Var(ImmediateValue::I64(x), Size::BYTE) => {
-                    result.code.push(x as u8);
-                }
-                Stmt::Var(ImmediateValue::I64(x), Size::DWORD) => {
-                    let mut xs = [0u8; 4];
-                    LittleEndian::write_i32(&mut xs[..], x as i32);
-                    result.code.extend(xs.iter());
-                }
-                Stmt::Var(ImmediateValue::U64(x), Size::QWORD) => {
+                Stmt::Var(ref value, size) => {
+                    let x = match *value {
+                        ImmediateValue::I64(x) => x as u64,
+                        ImmediateValue::U64(x) => x,
+                    };
                     let mut xs = [0u8; 8];
                     LittleEndian::write_u64(&mut xs[..], x);
-                    result.code.extend(xs.iter());
+                    match size {
+                        Size::BYTE | Size::WORD | Size::DWORD | Size::QWORD => result.code.extend(xs[..size.in_bytes() as usize].iter()),
+                        _ => panic!("Unimplemented immediate size: {:?}", size),
+                    }
                 }
                 Stmt::GlobalLabel(ref ident) => {
                     result.functions.push(ExportedFunction{
@@ -120,15 +596,90 @@ impl Assembler {
                     });
                 }
                 Stmt::LocalLabel(target) => {
-                    labels.insert(target, result.code.len());
+                    labels[target.index as usize] = Some(result.code.len());
+                    match self.inner.labels[target.index as usize].name {
+                        Some(ref name) if self.inner.emit_local_symbols => result.local_symbols.push(ExportedFunction{
+                            offset: result.code.len() as u32,
+                            name: name.clone(),
+                        }),
+                        _ => (),
+                    }
                 }
                 Stmt::ForwardJumpTarget(target, size) => {
                     jumps_to_resolve.push(JumpToResolve{
                         target: target,
                         size: size,
                         from: result.code.len(),
+                        absolute: false,
+                    });
+                }
+                Stmt::AbsoluteJumpTarget(target, size) => {
+                    jumps_to_resolve.push(JumpToResolve{
+                        target: target,
+                        size: size,
+                        from: result.code.len(),
+                        absolute: true,
+                    });
+                }
+                Stmt::GlobalJumpTarget(ref symbol, size, kind) => {
+                    symbols_to_resolve.push(SymbolToResolve{
+                        symbol: symbol.clone(),
+                        from: result.code.len(),
+                        size: size,
+                        kind: kind,
                     });
                 }
+                Stmt::Cfi(CfiDirective::StartProc) => {
+                    if frame.is_some() {
+                        panic!("cfi_startproc inside of another cfi_startproc");
+                    }
+                    frame = Some(FrameBuilder::new(result.code.len(), self.inner.mode));
+                }
+                Stmt::Cfi(CfiDirective::EndProc) => {
+                    match frame.take() {
+                        Some(frame) => result.frames.push(frame.finish(result.code.len())),
+                        None => panic!("cfi_endproc without cfi_startproc"),
+                    }
+                }
+                Stmt::Cfi(ref directive) => {
+                    match frame {
+                        Some(ref mut frame) => frame.add(result.code.len(), directive),
+                        None => panic!("CFI directive outside of cfi_startproc/cfi_endproc: {:?}", directive),
+                    }
+                }
+                Stmt::Seh(SehDirective::Proc) => {
+                    if unwind.is_some() {
+                        panic!("seh_proc inside of another seh_proc");
+                    }
+                    unwind = Some(UnwindBuilder::new(result.code.len()));
+                }
+                Stmt::Seh(SehDirective::EndProc) => {
+                    match unwind.take() {
+                        Some(unwind) => result.unwind_infos.push(unwind.finish(result.code.len())),
+                        None => panic!("seh_endproc without seh_proc"),
+                    }
+                }
+                Stmt::Seh(ref directive) => {
+                    match unwind {
+                        Some(ref mut unwind) => unwind.add(result.code.len(), directive),
+                        None => panic!("SEH directive outside of seh_proc/seh_endproc: {:?}", directive),
+                    }
+                }
+                Stmt::Location(ref file, line, column) => {
+                    let file = match result.source_files.iter().position(|f| f == file) {
+                        Some(index) => index,
+                        None => {
+                            result.source_files.push(file.clone());
+                            result.source_files.len() - 1
+                        }
+                    };
+                    let row = LineRow{ offset: result.code.len() as u32, file: file, line: line, column: column };
+                    // a later location for the same offset replaces the earlier one
+                    if result.line_rows.last().map_or(false, |last| last.offset == row.offset) {
+                        result.line_rows.pop();
+                    }
+                    result.line_rows.push(row);
+                }
                 Stmt::Align(ImmediateValue::U64(x)) => {
                     if x > 1024 {
                         panic!("Excessive alignment request: {}", x);
@@ -142,16 +693,65 @@ impl Assembler {
             }
         }
         
-        println!("Jumps = {:?}", jumps_to_resolve);
+        offsets.push(result.code.len());
+        
+        // constants that were not flushed yet go at the end of the code
+        let mut pool: Vec<&PoolConstant> = self.inner.const_pool.iter().collect();
+        pool.sort_by_key(|constant| cmp::Reverse(constant.alignment));
+        for constant in pool {
+            while result.code.len() % constant.alignment as usize != 0 {
+                result.code.push(0x90);
+            }
+            labels[constant.label.index as usize] = Some(result.code.len());
+            result.code.extend(constant.bytes.iter());
+        }
+        
+        if frame.is_some() {
+            panic!("cfi_startproc without cfi_endproc");
+        }
+        if unwind.is_some() {
+            panic!("seh_proc without seh_endproc");
+        }
+        
+        if let Some(index) = labels.iter().position(|label| label.is_none()) {
+            let target = JumpTarget{ assembler: self.inner.id, index: index as u32 };
+            panic!("{} is allocated but never placed", self.inner.label_name(target));
+        }
         
         for jump_to_resolve in jumps_to_resolve {
-            let target_addr = match labels.get(&jump_to_resolve.target) {
-                Some(target_addr) => *target_addr,
-                None => panic!("Unresolved address: {}", jump_to_resolve.target)
-            };
+            let target_addr = labels[jump_to_resolve.target.index as usize].unwrap();
+            
+            if jump_to_resolve.absolute {
+                // the offset of the label is stored for now; it gets rebased once the code's address is known
+                let size = jump_to_resolve.size.in_bytes() as usize;
+                let at = jump_to_resolve.from - size;
+                match jump_to_resolve.size {
+                    Size::WORD  => LittleEndian::write_u16(&mut result.code[at..], target_addr as u16),
+                    Size::DWORD => LittleEndian::write_u32(&mut result.code[at..], target_addr as u32),
+                    Size::QWORD => LittleEndian::write_u64(&mut result.code[at..], target_addr as u64),
+                    _ => panic!("Absolute references must be 2, 4 or 8 bytes"),
+                }
+                result.absolute_references.push(AbsoluteReference{
+                    offset: at as u32,
+                    size: size as u8,
+                });
+                continue;
+            }
             
             let jump_amount = (target_addr as i32) - ((jump_to_resolve.from) as i32);
             match jump_to_resolve.size {
+                Size::BYTE => {
+                    if jump_amount < i8::MIN as i32 || jump_amount > i8::MAX as i32 {
+                        panic!("Jump to {} is out of range for an 8-bit offset", self.inner.label_name(jump_to_resolve.target));
+                    }
+                    result.code[jump_to_resolve.from - 1] = jump_amount as u8;
+                },
+                Size::WORD => {
+                    if jump_amount < i16::MIN as i32 || jump_amount > i16::MAX as i32 {
+                        panic!("Jump to {} is out of range for a 16-bit offset", self.inner.label_name(jump_to_resolve.target));
+                    }
+                    LittleEndian::write_i16(&mut result.code[jump_to_resolve.from - 2..], jump_amount as i16);
+                },
                 Size::DWORD => {
                     LittleEndian::write_i32(&mut result.code[jump_to_resolve.from - 4..], jump_amount);
                 },
@@ -161,7 +761,132 @@ impl Assembler {
             }
         }
         
-        result
+        // symbols defined here are resolved right away, unless they are referenced through the GOT
+        for reference in symbols_to_resolve {
+            let size = reference.size.in_bytes() as usize;
+            let at = reference.from - size;
+            let defined = result.functions.iter().find(|f| f.name == reference.symbol).map(|f| f.offset as usize);
+            match (defined, reference.kind) {
+                (Some(target_addr), SymbolReferenceKind::Branch) | (Some(target_addr), SymbolReferenceKind::Relative) => {
+                    let jump_amount = (target_addr as i64) - (reference.from as i64);
+                    match reference.size {
+                        Size::BYTE if jump_amount >= i8::MIN as i64 && jump_amount <= i8::MAX as i64 =>
+                            result.code[at] = jump_amount as u8,
+                        Size::WORD if jump_amount >= i16::MIN as i64 && jump_amount <= i16::MAX as i64 =>
+                            LittleEndian::write_i16(&mut result.code[at..], jump_amount as i16),
+                        Size::DWORD => LittleEndian::write_i32(&mut result.code[at..], jump_amount as i32),
+                        _ => panic!("Jump to {} is out of range for a {}-bit offset", reference.symbol, size * 8),
+                    }
+                }
+                (Some(target_addr), SymbolReferenceKind::Absolute) => {
+                    match reference.size {
+                        Size::WORD  => LittleEndian::write_u16(&mut result.code[at..], target_addr as u16),
+                        Size::DWORD => LittleEndian::write_u32(&mut result.code[at..], target_addr as u32),
+                        Size::QWORD => LittleEndian::write_u64(&mut result.code[at..], target_addr as u64),
+                        _ => panic!("Absolute references must be 2, 4 or 8 bytes"),
+                    }
+                    result.absolute_references.push(AbsoluteReference{
+                        offset: at as u32,
+                        size: size as u8,
+                    });
+                }
+                _ => {
+                    if reference.kind != SymbolReferenceKind::Absolute && reference.size != Size::DWORD {
+                        panic!("Relative references to {} must be 4 bytes", reference.symbol);
+                    }
+                    result.symbol_references.push(SymbolReference{
+                        offset: at as u32,
+                        size: size as u8,
+                        symbol: reference.symbol,
+                        kind: reference.kind,
+                    });
+                }
+            }
+        }
+        
+        (result, Layout{ offsets: offsets, labels: labels })
+    }
+    
+    // Renders the code as dump lays it out: `offset  bytes  instruction` lines with the labels in
+    // between. Instructions are shown as they were given, fields left to the linker are still zero.
+    pub fn listing(&self) -> String {
+        let (object, layout) = self.assemble();
+        let label = |target: JumpTarget| match self.inner.labels[target.index as usize].name {
+            Some(ref name) => name.clone(),
+            None => format!(".L{}", target.index),
+        };
+        let buffer = &self.inner.buffer;
+        let mut lines = Vec::new();
+        let mut instructions = self.inner.instructions.iter().peekable();
+        let mut i = 0;
+        while i < buffer.len() {
+            let start = layout.offsets[i];
+            if instructions.peek().map_or(false, |instruction| instruction.start == i) {
+                let instruction = instructions.next().unwrap();
+                let text = instruction_text(&instruction.name, &instruction.prefixes, &instruction.args, &instruction.decorations, label);
+                lines.push(listing_line(start, &object.code[start..layout.offsets[instruction.end]], &text));
+                i = instruction.end;
+                continue;
+            }
+            match buffer[i] {
+                Stmt::GlobalLabel(ref name) => lines.push(format!("{}:", name)),
+                Stmt::LocalLabel(target) => lines.push(format!("{}:", label(target))),
+                Stmt::Location(ref file, line, column) => lines.push(format!("; {}:{}:{}", file, line, column)),
+                Stmt::Align(ImmediateValue::U64(alignment)) if layout.offsets[i + 1] > start =>
+                    lines.push(listing_line(start, &object.code[start..layout.offsets[i + 1]], &format!(".align {}", alignment))),
+                Stmt::Const(_) => {
+                    // bytes from constant() or the pool, 8 to a line
+                    let mut end = i + 1;
+                    while end < buffer.len() && end - i < 8 && instructions.peek().map_or(true, |instruction| instruction.start != end) {
+                        match buffer[end] {
+                            Stmt::Const(_) => end += 1,
+                            _ => break,
+                        }
+                    }
+                    lines.push(listing_line(start, &object.code[start..layout.offsets[end]], ".byte"));
+                    i = end;
+                    continue;
+                }
+                _ => (),
+            }
+            i += 1;
+        }
+        
+        // the constants dump placed after the code
+        let mut pool: Vec<&PoolConstant> = self.inner.const_pool.iter().collect();
+        pool.sort_by_key(|constant| cmp::Reverse(constant.alignment));
+        for constant in pool {
+            let offset = layout.labels[constant.label.index as usize].unwrap();
+            lines.push(format!("{}:", label(constant.label)));
+            for (n, bytes) in constant.bytes.chunks(8).enumerate() {
+                lines.push(listing_line(offset + n * 8, bytes, ".byte"));
+            }
+        }
+        lines.push(String::new());
+        lines.join("\n")
+    }
+    
+    // The initialized variables come first, the template is padded to the largest alignment so the
+    // zeroed ones that follow it keep theirs.
+    fn lay_out_thread_locals(&self, result: &mut ObjectFile) {
+        let alignment = self.inner.thread_locals.iter().map(|v| v.alignment).max().unwrap_or(1);
+        let mut offset = 0;
+        for variable in self.inner.thread_locals.iter().filter(|v| v.initial_value.is_some()) {
+            offset = (offset + variable.alignment - 1) & !(variable.alignment - 1);
+            result.tls_data.resize(offset as usize, 0);
+            result.tls_data.extend(variable.initial_value.as_ref().unwrap().iter());
+            result.thread_locals.push(ExportedFunction{ offset: offset as u32, name: variable.name.clone() });
+            offset += variable.size;
+        }
+        offset = (offset + alignment - 1) & !(alignment - 1);
+        result.tls_data.resize(offset as usize, 0);
+        for variable in self.inner.thread_locals.iter().filter(|v| v.initial_value.is_none()) {
+            offset = (offset + variable.alignment - 1) & !(variable.alignment - 1);
+            result.thread_locals.push(ExportedFunction{ offset: offset as u32, name: variable.name.clone() });
+            offset += variable.size;
+        }
+        result.tls_bss_size = (offset - result.tls_data.len() as u64) as u32;
+        result.tls_alignment = alignment as u32;
     }
     
     pub fn with_prefixes(&mut self, prefixes: Vec<Ident>) -> &mut FlaggedAssembler {
@@ -172,22 +897,1184 @@ impl Assembler {
     pub fn output(&mut self) {
         let out_dir = env::var("OUT_DIR").unwrap();
         let lib_name = "foo";
+        let format = ObjectFormat::for_target(&env::var("TARGET").unwrap_or_default());
         
         let mut out = File::create(format!("{}/lib{}.a", out_dir, lib_name)).unwrap();
-        self.dump().write(&mut out);
+        self.dump().write(&mut out, format).unwrap();
         println!("cargo:rustc-flags=-L native={} -l static={}", out_dir, lib_name);
     }
 
 }
 
 #[test]
-fn thing() {
-    use parser::{Register, Size, RegId, RegKind, ImmediateValue};
+fn call_frame_information() {
+    use x64::regs::QWord;
+    let mut x = Assembler::new();
+    x.global("framed");
+    x.cfi_startproc();
+    x.push(QWord::Rbp);
+    x.cfi_adjust_cfa_offset(8);
+    x.cfi_offset(QWord::Rbp, -16);
+    x.mov(QWord::Rbp, QWord::Rsp);
+    x.cfi_def_cfa_register(QWord::Rbp);
+    x.pop(QWord::Rbp);
+    x.cfi_def_cfa(QWord::Rsp, 8);
+    x.ret(None::<i32>);
+    x.cfi_endproc();
+    
+    let object = x.dump();
+    assert_eq!(object.frames.len(), 1);
+    assert_eq!(object.frames[0].offset, 0);
+    assert_eq!(object.frames[0].length, object.code.len());
+    assert_eq!(object.frames[0].instructions, vec![
+        (1, CallFrameInstruction::DefCfaOffset(16)),
+        (1, CallFrameInstruction::Offset(6, -16)),
+        (4, CallFrameInstruction::DefCfaRegister(6)),
+        (5, CallFrameInstruction::DefCfa(7, 8)),
+    ]);
+}
+
+#[test]
+fn windows_unwind_information() {
+    use x64::regs::{QWord, OWord};
+    let mut x = Assembler::new();
+    x.global("framed");
+    x.seh_proc();
+    x.push(QWord::Rbp);
+    x.seh_pushreg(QWord::Rbp);
+    x.sub(QWord::Rsp, 0x30i32);
+    x.seh_stackalloc(0x30);
+    x.lea(QWord::Rbp, QWord::Rsp.value_at_offset(0x20));
+    x.seh_setframe(QWord::Rbp, 0x20);
+    x.movdqa(QWord::Rbp.value_at(), OWord::Xmm6);
+    x.seh_savexmm(OWord::Xmm6, 0x20);
+    x.seh_endprologue();
+    x.ret(None::<i32>);
+    x.seh_endproc();
+    
+    let object = x.dump();
+    assert_eq!(object.unwind_infos.len(), 1);
+    let info = &object.unwind_infos[0];
+    assert_eq!((info.begin, info.end as usize), (0, object.code.len()));
+    assert_eq!((info.frame_register, info.frame_offset), (5, 0x20));
+    assert_eq!(info.prolog_size as usize, object.code.len() - 1);
+    assert_eq!(info.codes, vec![
+        (1, UnwindCode::PushNonvol(5)),
+        (8, UnwindCode::AllocStack(0x30)),
+        (16, UnwindCode::SetFrame),
+        (info.prolog_size, UnwindCode::SaveXmm128(6, 0x20)),
+    ]);
+}
+
+#[test]
+fn function_builder_sysv() {
+    use x64::regs::QWord;
+    use x64::function::{FunctionBuilder, CallingConvention};
+    let mut x = Assembler::new();
+    x.global("framed");
+    let frame = FunctionBuilder::new(CallingConvention::SysV)
+        .frame_pointer().save(QWord::Rbx).save(QWord::R12).locals(20).calls(8)
+        .prologue(&mut x);
+    x.mov(QWord::Rax, frame.stack_argument(0));
+    x.mov(frame.local(4), QWord::Rax);
+    frame.epilogue(&mut x);
+    frame.end(&mut x);
+    
+    let object = x.dump();
+    assert_eq!(frame.size(), 0x48);
+    assert_eq!(object.code, vec![
+        0x55, 0x48, 0x89, 0xe5, 0x53, 0x41, 0x54, 0x48, 0x81, 0xec, 0x30, 0x00, 0x00, 0x00, // prologue
+        0x48, 0x8b, 0x85, 0x10, 0x00, 0x00, 0x00, 0x48, 0x89, 0x85, 0xd4, 0xff, 0xff, 0xff, // body
+        0x48, 0x8d, 0xa5, 0xf0, 0xff, 0xff, 0xff, 0x41, 0x5c, 0x5b, 0x5d, 0xc3]); // epilogue
+    assert_eq!(object.frames[0].instructions, vec![
+        (1, CallFrameInstruction::DefCfaOffset(16)),
+        (1, CallFrameInstruction::Offset(6, -16)),
+        (4, CallFrameInstruction::DefCfaRegister(6)),
+        (5, CallFrameInstruction::Offset(3, -24)),
+        (7, CallFrameInstruction::Offset(12, -32)),
+        (28, CallFrameInstruction::RememberState),
+        (37, CallFrameInstruction::Restore(12)),
+        (38, CallFrameInstruction::Restore(3)),
+        (39, CallFrameInstruction::DefCfa(7, 8)),
+        (39, CallFrameInstruction::Restore(6)),
+        (40, CallFrameInstruction::RestoreState),
+    ]);
+}
+
+#[test]
+fn function_builder_sysv_leaf() {
+    use x64::regs::QWord;
+    use x64::function::{FunctionBuilder, CallingConvention};
+    let mut x = Assembler::new();
+    x.global("leaf");
+    let frame = FunctionBuilder::new(CallingConvention::SysV).save(QWord::Rbx).locals(8).prologue(&mut x);
+    x.mov(QWord::Rax, frame.stack_argument(1));
+    frame.epilogue(&mut x);
+    frame.end(&mut x);
+    
+    let object = x.dump();
+    // the return address plus rbx plus 16 bytes keeps rsp aligned
+    assert_eq!(&object.code[..8], &[0x53, 0x48, 0x81, 0xec, 0x10, 0x00, 0x00, 0x00]);
+    assert_eq!(&object.code[8..16], &[0x48, 0x8b, 0x84, 0x24, 0x28, 0x00, 0x00, 0x00]);
+    assert_eq!(object.frames[0].instructions, vec![
+        (1, CallFrameInstruction::DefCfaOffset(16)),
+        (1, CallFrameInstruction::Offset(3, -16)),
+        (8, CallFrameInstruction::DefCfaOffset(32)),
+        (16, CallFrameInstruction::RememberState),
+        (23, CallFrameInstruction::DefCfaOffset(16)),
+        (24, CallFrameInstruction::DefCfaOffset(8)),
+        (24, CallFrameInstruction::Restore(3)),
+        (25, CallFrameInstruction::RestoreState),
+    ]);
+}
+
+#[test]
+fn function_builder_win64() {
+    use x64::regs::{QWord, OWord};
+    use x64::function::{FunctionBuilder, CallingConvention};
+    let mut x = Assembler::new();
+    x.global("framed");
+    let frame = FunctionBuilder::new(CallingConvention::Win64)
+        .frame_pointer().save(QWord::Rsi).save_xmm(OWord::Xmm6).save_xmm(OWord::Xmm7).calls(0)
+        .prologue(&mut x);
+    x.mov(QWord::Rax, frame.stack_argument(0));
+    frame.epilogue(&mut x);
+    frame.end(&mut x);
+    
+    let object = x.dump();
+    // the shadow space of calls made sits below the xmm save area
+    assert_eq!(&object.code[2..9], &[0x48, 0x81, 0xec, 0x48, 0x00, 0x00, 0x00]);
+    assert_eq!(&object.code[29..36], &[0x48, 0x8b, 0x85, 0x80, 0x00, 0x00, 0x00]);
+    assert_eq!(&object.code[50..], &[0x48, 0x8d, 0xa5, 0x48, 0x00, 0x00, 0x00, 0x5e, 0x5d, 0xc3]);
+    let info = &object.unwind_infos[0];
+    assert_eq!((info.frame_register, info.frame_offset, info.prolog_size), (5, 0, 29));
+    assert_eq!(info.codes, vec![
+        (1, UnwindCode::PushNonvol(5)),
+        (2, UnwindCode::PushNonvol(6)),
+        (9, UnwindCode::AllocStack(0x48)),
+        (17, UnwindCode::SaveXmm128(6, 0x20)),
+        (25, UnwindCode::SaveXmm128(7, 0x30)),
+        (29, UnwindCode::SetFrame),
+    ]);
+}
+
+#[test]
+fn constant_pool() {
+    use x64::regs::{QWord, OWord};
+    let mut x = Assembler::new();
+    x.global("scale");
+    let half = x.const_pool_f64(1.5);
+    x.mulsd(OWord::Xmm0, half);
+    let half_again = x.const_pool_f64(1.5);
+    x.addsd(OWord::Xmm0, half_again);
+    let mask = x.const_pool_bytes(&[0xff; 16], 16);
+    x.andpd(OWord::Xmm0, mask);
+    x.ret(None::<i32>);
+    x.global("next");
+    let one = x.const_pool_f32(1.0);
+    x.movss(OWord::Xmm1, one);
+    x.mov(QWord::Rax, QWord::Rcx);
+    
+    let object = x.dump();
+    assert_eq!(object.functions[1].offset, 56);
+    assert_eq!(object.code[..25].to_vec(), vec![
+        0xf2, 0x0f, 0x59, 0x05, 40, 0, 0, 0,
+        0xf2, 0x0f, 0x58, 0x05, 32, 0, 0, 0,
+        0x66, 0x0f, 0x54, 0x05, 8, 0, 0, 0,
+        0xc3]);
+    // the pool is aligned for the 16 byte mask, which comes first
+    assert_eq!(&object.code[25..32], &[0x90; 7]);
+    assert_eq!(&object.code[32..48], &[0xff; 16]);
+    assert_eq!(&object.code[48..56], &[0, 0, 0, 0, 0, 0, 0xf8, 0x3f]);
+    // the rest of the pool is placed at the end of the code
+    assert_eq!(&object.code[56..64], &[0xf3, 0x0f, 0x10, 0x0d, 4, 0, 0, 0]);
+    assert_eq!(&object.code[67..], &[0x90, 0, 0, 0x80, 0x3f]);
+}
+
+#[test]
+fn extern_symbols() {
+    use x64::regs::{QWord, extern_symbol, extern_rip_relative, extern_got};
+    let mut x = Assembler::new();
+    x.global("caller");
+    x.lea(QWord::Rdi, extern_rip_relative("message"));
+    x.call(extern_symbol("puts"));
+    x.mov(QWord::Rax, extern_got("puts"));
+    x.mov(QWord::Rcx, extern_symbol("table"));
+    x.call(extern_symbol("helper"));
+    x.ret(None::<i32>);
+    x.global("helper");
+    x.ret(None::<i32>);
+    
+    let object = x.dump();
+    assert_eq!(object.symbol_references, vec![
+        SymbolReference{ offset: 3, size: 4, symbol: "message".to_string(), kind: SymbolReferenceKind::Relative },
+        SymbolReference{ offset: 8, size: 4, symbol: "puts".to_string(), kind: SymbolReferenceKind::Branch },
+        SymbolReference{ offset: 15, size: 4, symbol: "puts".to_string(), kind: SymbolReferenceKind::Got },
+        SymbolReference{ offset: 22, size: 4, symbol: "table".to_string(), kind: SymbolReferenceKind::Absolute },
+    ]);
+    // helper is defined here, so the call to it needs no relocation
+    assert_eq!(&object.code[26..], &[0xe8, 0x01, 0x00, 0x00, 0x00, 0xc3, 0xc3]);
+}
+
+#[test]
+#[should_panic]
+fn extern_got_is_only_an_address() {
+    use x64::regs::extern_got;
+    let mut x = Assembler::new();
+    x.jmp(extern_got("puts"));
+}
+
+#[test]
+fn versioned_symbol_names() {
+    use x64::regs::extern_symbol;
+    let mut x = Assembler::new();
+    x.call(extern_symbol("memcpy@GLIBC_2.2.5"));
+    let object = x.dump();
+    assert_eq!(object.symbol_references.len(), 1);
+    assert_eq!(object.symbol_references[0].symbol, "memcpy@GLIBC_2.2.5");
+    assert_eq!(object.symbol_references[0].kind, SymbolReferenceKind::Branch);
+}
+
+#[test]
+fn position_independent_code() {
+    use x64::regs::{QWord, extern_plt, extern_got, extern_gottpoff};
+    let mut x = Assembler::new();
+    x.set_position_independent(true);
+    x.global("pic");
+    x.call(extern_plt("puts"));
+    x.mov(QWord::Rax, extern_got("stdout"));
+    x.mov(QWord::Rcx, extern_gottpoff("errno"));
+    x.ret(None::<i32>);
+    
+    let object = x.dump();
+    let kinds: Vec<(u32, &str, SymbolReferenceKind)> = object.symbol_references.iter().map(|r| (r.offset, &r.symbol[..], r.kind)).collect();
+    assert_eq!(kinds, vec![
+        (1, "puts", SymbolReferenceKind::Branch),
+        (8, "stdout", SymbolReferenceKind::Got),
+        (15, "errno", SymbolReferenceKind::GotTpOff),
+    ]);
+}
+
+#[test]
+#[should_panic]
+fn thread_pointer_offsets_are_not_position_independent() {
+    use x64::regs::{DWord, extern_tpoff};
+    let mut x = Assembler::new();
+    x.set_position_independent(true);
+    x.mov(DWord::Edx, extern_tpoff("counter"));
+}
+
+#[test]
+fn thread_local_variables() {
+    use x64::regs::{DWord, QWord, extern_tpoff};
+    let mut x = Assembler::new();
+    x.thread_local_zeroed("buffer", 16, 16);
+    x.thread_local("counter", &[1, 0, 0, 0], 4);
+    x.thread_local("flag", &[1], 1);
+    x.global("tls");
+    x.load_thread_pointer(QWord::Rax);
+    x.fs().mov(DWord::Ecx, extern_tpoff("counter"));
+    x.ret(None::<i32>);
+    
+    let object = x.dump();
+    assert_eq!(&object.code[..9], &[0x64, 0x48, 0x8b, 0x04, 0x25, 0x00, 0x00, 0x00, 0x00]);
+    assert_eq!(&object.code[9..13], &[0x64, 0x8b, 0x0c, 0x25]);
+    assert_eq!(object.symbol_references[0].kind, SymbolReferenceKind::TpOff);
+    // the initial values come first, padded to the largest alignment
+    assert_eq!(object.tls_data, vec![1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    assert_eq!((object.tls_bss_size, object.tls_alignment), (16, 16));
+    let offsets: Vec<(&str, u32)> = object.thread_locals.iter().map(|v| (&v.name[..], v.offset)).collect();
+    assert_eq!(offsets, vec![("counter", 0), ("flag", 4), ("buffer", 16)]);
+}
+
+#[test]
+fn append_assemblers() {
+    use x64::regs::{QWord, extern_symbol, rip_nonrelative};
+    let mut a = Assembler::new();
+    a.global("first");
+    let done = a.allocate_local();
+    a.jmp(rip_nonrelative(done));
+    a.call(extern_symbol("second"));
+    a.place_local(done);
+    a.ret(None::<i32>);
+    
+    let mut b = Assembler::new();
+    b.global("second");
+    let skip = b.allocate_local();
+    assert_eq!(skip.index, done.index, "both assemblers start from the first label");
+    b.jmp(rip_nonrelative(skip));
+    let one = b.const_pool_f64(1.0);
+    b.mov(QWord::Rax, one);
+    b.place_local(skip);
+    b.call(extern_symbol("first"));
+    b.ret(None::<i32>);
+    
+    let renamed = a.append(b);
+    assert_eq!(renamed.len(), 2); // the label of the constant is renamed as well
+    assert_eq!(renamed[&skip].index, 1);
+    assert!(a.listing().contains("jmp .L1\n"), "b's labels are renamed in the listing too");
+    
+    let object = a.dump();
+    assert!(object.symbol_references.is_empty());
+    assert_eq!(&object.code[..11], &[
+        0xe9, 0x05, 0x00, 0x00, 0x00, // jmp done
+        0xe8, 0x01, 0x00, 0x00, 0x00, // call second
+        0xc3,
+    ]);
+    assert_eq!(&object.code[11..16], &[0xe9, 0x07, 0x00, 0x00, 0x00]);
+    assert_eq!(&object.code[19..23], &[0x09, 0x00, 0x00, 0x00]); // the constant of b at 32
+    assert_eq!(&object.code[23..28], &[0xe8, 0xe4, 0xff, 0xff, 0xff]);
+}
+
+#[test]
+#[should_panic]
+fn append_assemblers_with_the_same_global() {
+    let mut a = Assembler::new();
+    a.global("f");
+    let mut b = Assembler::new();
+    b.global("f");
+    a.append(b);
+}
+
+#[test]
+#[should_panic(expected = "label 0 is placed twice")]
+fn label_placed_twice() {
+    let mut x = Assembler::new();
+    let label = x.local();
+    x.place_local(label);
+}
+
+#[test]
+#[should_panic(expected = "label 1 is allocated but never placed")]
+fn label_never_placed() {
+    use x64::regs::{QWord, rip_relative};
+    let mut x = Assembler::new();
+    x.local();
+    let missing = x.allocate_local();
+    x.lea(QWord::Rax, rip_relative(missing));
+    x.dump();
+}
+
+#[test]
+fn named_labels() {
+    use x64::regs::rip_nonrelative;
+    let mut x = Assembler::new();
+    x.set_local_symbols(true);
+    x.global("count");
+    let done = x.allocate_named_local("done");
+    let head = x.named_local("loop_head");
+    x.jmp(rip_nonrelative(done));
+    x.jmp(rip_nonrelative(head));
+    x.local(); // unnamed labels stay out of the symbol table
+    x.place_local(done);
+    x.ret(None::<i32>);
+    
+    let object = x.dump();
+    let symbols: Vec<(&str, u32)> = object.local_symbols.iter().map(|label| (&label.name[..], label.offset)).collect();
+    assert_eq!(symbols, vec![("loop_head", 0), ("done", 10)]);
+    
+    let mut x = Assembler::new();
+    x.named_local("loop_head");
+    assert!(x.dump().local_symbols.is_empty(), "local symbols are off by default");
+}
+
+#[test]
+fn listing() {
+    use x64::regs::{DWord, QWord, OWord, rip_nonrelative, extern_symbol, extern_tpoff};
+    let mut x = Assembler::new();
+    x.global("sum");
+    x.set_location("sum.rs", 3, 5);
+    x.xor(DWord::Eax, DWord::Eax);
+    let head = x.named_local("loop_head");
+    let done = x.allocate_local();
+    x.test(QWord::Rsi, QWord::Rsi);
+    x.jz(rip_nonrelative(done));
+    x.add(QWord::Rax, QWord::Rdi.value_at_offset(-8));
+    let scale = x.const_pool_f64(1.5);
+    x.mulsd(OWord::Xmm0, scale);
+    x.call(extern_symbol("helper"));
+    x.fs().mov(DWord::Ecx, extern_tpoff("counter"));
+    x.jmp(rip_nonrelative(head));
+    x.place_local(done);
+    x.ret(None::<i32>);
+    x.constant(&[1, 2]);
+    
+    assert_eq!(x.listing(), "\
+sum:
+; sum.rs:3:5
+00000000  31 c0                          xor eax, eax
+loop_head:
+00000002  48 85 f6                       test rsi, rsi
+00000005  0f 84 21 00 00 00              jz .L1
+0000000b  48 03 87 f8 ff ff ff           add rax, [rdi - 0x8]
+00000012  f2 0f 59 05 16 00 00 00        mulsd xmm0, [rip + .L2]
+0000001a  e8 00 00 00 00                 call helper
+0000001f  64 8b 0c 25 00 00 00 00        mov ecx, fs:[counter@TPOFF]
+00000027  e9 d6 ff ff ff                 jmp loop_head
+.L1:
+0000002c  c3                             ret
+0000002d  01 02                          .byte
+.L2:
+00000030  00 00 00 00 00 00 f8 3f        .byte
+");
+}
+
+#[test]
+#[should_panic(expected = "label 'loop_head' is placed twice")]
+fn named_label_placed_twice() {
+    let mut x = Assembler::new();
+    let head = x.named_local("loop_head");
+    x.place_local(head);
+}
+
+#[test]
+#[should_panic(expected = "label 'done' is allocated but never placed")]
+fn named_label_never_placed() {
+    let mut x = Assembler::new();
+    x.allocate_named_local("done");
+    x.dump();
+}
+
+#[test]
+#[should_panic(expected = "label 0 was allocated by another assembler")]
+fn label_of_another_assembler() {
+    use x64::regs::rip_nonrelative;
+    let mut a = Assembler::new();
+    let mut b = Assembler::new();
+    b.local();
+    let label = a.local();
+    b.jmp(rip_nonrelative(label));
+}
+
+#[test]
+#[should_panic]
+fn append_assemblers_in_different_modes() {
+    let mut a = Assembler::new();
+    let mut b = Assembler::new();
+    b.set_mode(Mode::Bits32);
+    a.append(b);
+}
+
+#[test]
+fn append_keeps_the_common_cet_features() {
+    let mut a = Assembler::new();
+    a.set_cet(true, true);
+    let mut b = Assembler::new();
+    b.set_cet(true, false);
+    a.append(b);
+    assert_eq!(a.dump().x86_features, GNU_PROPERTY_X86_FEATURE_1_IBT);
+}
+
+#[test]
+#[should_panic]
+fn position_independent_code_has_no_absolute_references() {
+    use x64::regs::{QWord, rip_nonrelative};
+    let mut x = Assembler::new();
+    x.set_position_independent(true);
+    let table = x.allocate_local();
+    x.mov(QWord::Rax, rip_nonrelative(table));
+}
+
+#[test]
+fn source_locations() {
+    use x64::regs::QWord;
+    let mut x = Assembler::new();
+    x.global("located");
+    x.set_location("first.rs", 10, 5);
+    x.set_location("first.rs", 11, 5);
+    x.mov(QWord::Rax, QWord::Rdi);
+    x.set_location("second.rs", 3, 1);
+    x.ret(None::<i32>);
+    
+    let object = x.dump();
+    assert_eq!(object.source_files, vec!["first.rs".to_string(), "second.rs".to_string()]);
+    assert_eq!(object.line_rows, vec![
+        LineRow{ offset: 0, file: 0, line: 11, column: 5 },
+        LineRow{ offset: 3, file: 1, line: 3, column: 1 },
+    ]);
+}
+
+#[test]
+fn flat_binary() {
+    use x64::regs::{QWord, rip_nonrelative};
+    use x64::parser::JumpType;
+    let mut x = Assembler::new();
+    let data = x.allocate_local();
+    x.mov(QWord::Rax, rip_nonrelative(data));
+    x.mov(QWord::Rcx, Arg::JumpTarget(JumpType::Forward(data), Some(Size::QWORD)));
+    x.ret(None::<i32>);
+    x.place_local(data);
+    x.constant(&[0xaa]);
+    
+    let object = x.dump();
+    assert_eq!(object.flat_binary(0x7c00).unwrap(), vec![
+        0x48, 0xc7, 0xc0, 0x12, 0x7c, 0x00, 0x00,
+        0x48, 0xb9, 0x12, 0x7c, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0xc3,
+        0xaa,
+    ]);
+    assert!(object.flat_binary(0x80000000).is_err());
+}
+
+#[test]
+fn protected_mode() {
+    use x64::regs::{Word, DWord, rip_relative};
+    use x64::parser::JumpType;
+    let mut x = Assembler::new();
+    x.set_mode(Mode::Bits32);
+    let data = x.allocate_local();
+    x.global("i686");
+    x.cfi_startproc();
+    x.push(DWord::Ebp);
+    x.cfi_adjust_cfa_offset(4);
+    x.cfi_offset(DWord::Ebp, -8);
+    x.mov(DWord::Eax, DWord::Esp.value_at_offset(8));
+    x.mov(Word::Cx, Word::Bx.value_at());
+    x.mov(DWord::Edx, rip_relative(data));
+    x.call(Arg::JumpTarget(JumpType::Forward(data), None));
+    x.cdq();
+    x.pop(DWord::Ebp);
+    x.ret(None::<i32>);
+    x.cfi_endproc();
+    x.place_local(data);
+    
+    let object = x.dump();
+    assert_eq!(object.architecture, Architecture::X86);
+    assert_eq!(object.code, vec![
+        0x55,
+        0x8b, 0x84, 0x24, 0x08, 0x00, 0x00, 0x00,
+        0x67, 0x66, 0x8b, 0x0f,
+        0x8b, 0x15, 0x1a, 0x00, 0x00, 0x00,
+        0xe8, 0x03, 0x00, 0x00, 0x00,
+        0x99,
+        0x5d,
+        0xc3,
+    ]);
+    assert_eq!(object.absolute_references, vec![AbsoluteReference{ offset: 14, size: 4 }]);
+    assert_eq!(object.frames[0].instructions, vec![
+        (1, CallFrameInstruction::DefCfaOffset(8)),
+        (1, CallFrameInstruction::Offset(5, -8)),
+    ]);
+}
+
+#[test]
+fn real_mode() {
+    use x64::regs::{Word, DWord, rip_nonrelative};
+    use x64::parser::{JumpType, MemoryRef, RegId};
+    let mut x = Assembler::new();
+    x.set_mode(Mode::Bits16);
+    let message = x.allocate_local();
+    x.mov(Word::Si, rip_nonrelative(message));
+    x.mov(Word::Ax, Arg::Indirect(MemoryRef{
+        index: Some(Register::new_static(Size::WORD, RegId::RSI)),
+        scale: 1,
+        base: Some(Register::new_static(Size::WORD, RegId::RBX)),
+        disp: Some(ImmediateValue::I64(2)),
+        size: None,
+    }));
+    x.mov(Word::Dx, Word::Bp.value_at());
+    x.mov(DWord::Eax, 1);
+    x.mov(DWord::Ecx, DWord::Ebx.value_at());
+    x.lodsb();
+    x.jmp(Arg::JumpTarget(JumpType::Forward(message), None));
+    x.place_local(message);
+    x.constant(b"hi");
+    
+    let object = x.dump();
+    assert_eq!(object.flat_binary(0x7c00).unwrap(), vec![
+        0xbe, 0x18, 0x7c,
+        0x8b, 0x80, 0x02, 0x00,
+        0x8b, 0x56, 0x00,
+        0x66, 0xb8, 0x01, 0x00, 0x00, 0x00,
+        0x67, 0x66, 0x8b, 0x0b,
+        0xac,
+        0xe9, 0x00, 0x00,
+        b'h', b'i',
+    ]);
+    assert!(object.flat_binary(0xffff).is_err());
+}
+
+#[test]
+fn real_mode_dword_operands() {
+    use x64::regs::{Word, DWord, OWord};
+    use x64::parser::RegId;
+    let mut x = Assembler::new();
+    x.set_mode(Mode::Bits16);
+    x.movd(OWord::Xmm0, DWord::Eax);
+    x.movd(Arg::Direct(Register::new_static(Size::QWORD, RegId::MMX0)), DWord::Eax);
+    x.adcx(DWord::Eax, DWord::Ebx);
+    x.movzx(DWord::Eax, Word::Bx);
+    x.andn(DWord::Eax, DWord::Ebx, DWord::Ecx);
+    assert_eq!(x.dump().code, vec![
+        0x66, 0x0f, 0x6e, 0xc0,
+        0x0f, 0x6e, 0xc0,
+        0x66, 0x0f, 0x38, 0xf6, 0xc3,
+        0x66, 0x0f, 0xb7, 0xc3,
+        0xc4, 0xe2, 0x60, 0xf2, 0xc1,
+    ]);
+}
+
+#[test]
+#[should_panic(expected = "only available in 64-bit mode")]
+fn extended_registers_need_long_mode() {
+    use x64::regs::DWord;
+    let mut x = Assembler::new();
+    x.set_mode(Mode::Bits32);
+    x.mov(DWord::R8d, 1);
+}
+
+#[test]
+#[should_panic(expected = "64-bit operands are only available in 64-bit mode")]
+fn qword_operands_need_long_mode() {
+    use x64::regs::QWord;
+    let mut x = Assembler::new();
+    x.set_mode(Mode::Bits32);
+    x.add(QWord::Rax, QWord::Rbx);
+}
+
+#[test]
+fn push_immediates() {
+    let sized = |value: i64, size: Size| Arg::Immediate(ImmediateValue::I64(value), Some(size));
+    let mut x = Assembler::new();
+    x.push(5);
+    x.push(sized(5, Size::WORD));
+    x.push(sized(5, Size::BYTE));
+    assert_eq!(x.dump().code, vec![0x68, 0x05, 0x00, 0x00, 0x00, 0x66, 0x68, 0x05, 0x00, 0x6a, 0x05]);
+    
+    let mut x = Assembler::new();
+    x.set_mode(Mode::Bits32);
+    x.push(5);
+    x.push(sized(5, Size::DWORD));
+    x.push(sized(5, Size::WORD));
+    assert_eq!(x.dump().code, vec![0x68, 0x05, 0x00, 0x00, 0x00, 0x68, 0x05, 0x00, 0x00, 0x00, 0x66, 0x68, 0x05, 0x00]);
+    
+    let mut x = Assembler::new();
+    x.set_mode(Mode::Bits16);
+    x.push(5);
+    x.push(sized(5, Size::DWORD));
+    assert_eq!(x.dump().code, vec![0x68, 0x05, 0x00, 0x66, 0x68, 0x05, 0x00, 0x00, 0x00]);
+}
+
+#[test]
+fn count_register_jumps() {
+    use x64::parser::JumpType;
+    let jumps = |mode: Mode| {
+        let mut x = Assembler::new();
+        x.set_mode(mode);
+        let top = x.local();
+        let target = Arg::JumpTarget(JumpType::Forward(top), None);
+        match mode {
+            Mode::Bits64 => x.jrcxz(target.clone()),
+            _ => x.jcxz(target.clone()),
+        }
+        x.jecxz(target);
+        x.dump().code
+    };
+    assert_eq!(jumps(Mode::Bits16), vec![0xe3, 0xfe, 0x67, 0xe3, 0xfb]);
+    assert_eq!(jumps(Mode::Bits32), vec![0x67, 0xe3, 0xfd, 0xe3, 0xfb]);
+    assert_eq!(jumps(Mode::Bits64), vec![0xe3, 0xfe, 0x67, 0xe3, 0xfb]);
+}
+
+#[test]
+#[should_panic(expected = "only available in 64-bit mode")]
+fn jrcxz_needs_long_mode() {
+    use x64::parser::JumpType;
+    let mut x = Assembler::new();
+    x.set_mode(Mode::Bits32);
+    let top = x.local();
+    x.jrcxz(Arg::JumpTarget(JumpType::Forward(top), None));
+}
+
+#[test]
+fn flags_register() {
+    let mut x = Assembler::new();
+    x.set_mode(Mode::Bits16);
+    x.pushf();
+    x.pushfd();
+    x.popfd();
+    x.popf();
+    assert_eq!(x.dump().code, vec![0x9c, 0x66, 0x9c, 0x66, 0x9d, 0x9d]);
+    
+    let mut x = Assembler::new();
+    x.set_mode(Mode::Bits32);
+    x.pushf();
+    x.pushfd();
+    x.popfd();
+    x.popf();
+    assert_eq!(x.dump().code, vec![0x66, 0x9c, 0x9c, 0x9d, 0x66, 0x9d]);
+    
     let mut x = Assembler::new();
-    x.cpuid();
-    x.add(Arg::Direct(Register{size: Size::BYTE, kind: RegKind::Static(RegId::RBX)}), Arg::Immediate(ImmediateValue::I64(8), None)); 
-    println!("{:?}", x.dump());
-    panic!("see");
+    x.pushf();
+    x.pushfq();
+    x.popfq();
+    x.popf();
+    assert_eq!(x.dump().code, vec![0x66, 0x9c, 0x9c, 0x9d, 0x66, 0x9d]);
+}
+
+#[test]
+fn evex() {
+    use x64::regs::{OWord, HWord, ZWord, QWord, DWord, Mask};
+    let mut x = Assembler::new();
+    x.with_zeroing_mask(Mask::K1).with_broadcast().vaddps(ZWord::Zmm0, ZWord::Zmm1, QWord::Rax.value_at_offset(64));
+    x.vaddpd(ZWord::Zmm31, ZWord::Zmm17, ZWord::Zmm2);
+    x.vaddps(OWord::Xmm16, OWord::Xmm1, QWord::Rax.value_at_offset(64));
+    x.vmovdqu64(ZWord::Zmm5, QWord::Rsp.value_at_offset(-128));
+    x.vmovdqu64(ZWord::Zmm5, QWord::Rsp.value_at_offset(100));
+    x.with_rounding(Rounding::RzSae).vaddps(ZWord::Zmm0, ZWord::Zmm1, ZWord::Zmm2);
+    x.with_mask(Mask::K2).vpaddd(HWord::Ymm20, HWord::Ymm21, HWord::Ymm3);
+    x.vpcmpd(Mask::K3, ZWord::Zmm1, ZWord::Zmm2, 4);
+    x.vpsraq(ZWord::Zmm1, QWord::Rax.value_at(), 3);
+    x.vextracti64x4(HWord::Ymm1, ZWord::Zmm2, 1);
+    x.kmovw(Mask::K1, DWord::Eax);
+    x.kshiftlq(Mask::K1, Mask::K2, 5);
+    // without decorations or EVEX only registers the shorter VEX encoding is still picked
+    x.vaddps(HWord::Ymm0, HWord::Ymm1, HWord::Ymm2);
+
+    let object = x.dump();
+    assert_eq!(object.code, vec![
+        0x62, 0xf1, 0x74, 0xd9, 0x58, 0x40, 0x10,
+        0x62, 0x61, 0xf5, 0x40, 0x58, 0xfa,
+        0x62, 0xe1, 0x74, 0x08, 0x58, 0x40, 0x04,
+        0x62, 0xf1, 0xfe, 0x48, 0x6f, 0x6c, 0x24, 0xfe,
+        0x62, 0xf1, 0xfe, 0x48, 0x6f, 0xac, 0x24, 0x64, 0x00, 0x00, 0x00,
+        0x62, 0xf1, 0x74, 0x78, 0x58, 0xc2,
+        0x62, 0xe1, 0x55, 0x22, 0xfe, 0xe3,
+        0x62, 0xf3, 0x75, 0x48, 0x1f, 0xda, 0x04,
+        0x62, 0xf1, 0xf5, 0x48, 0x72, 0x20, 0x03,
+        0x62, 0xf3, 0xfd, 0x48, 0x3b, 0xd1, 0x01,
+        0xc5, 0xf8, 0x92, 0xc8,
+        0xc4, 0xe3, 0xf9, 0x33, 0xca, 0x05,
+        0xc5, 0xf4, 0x58, 0xc2,
+    ]);
+}
+
+#[test]
+fn aes_sha_and_carryless_multiplication() {
+    use x64::regs::{OWord, HWord, ZWord, QWord};
+    let mut x = Assembler::new();
+    x.aesenc(OWord::Xmm1, OWord::Xmm2);
+    x.vaesdeclast(OWord::Xmm1, OWord::Xmm2, OWord::Xmm3);
+    x.vaesenc(HWord::Ymm1, HWord::Ymm2, HWord::Ymm3);
+    x.vaesenc(ZWord::Zmm1, ZWord::Zmm2, ZWord::Zmm3);
+    x.pclmulqdq(OWord::Xmm1, OWord::Xmm2, 0x11);
+    x.vpclmulqdq(ZWord::Zmm1, ZWord::Zmm2, ZWord::Zmm3, 0x01);
+    x.sha256rnds2(OWord::Xmm1, OWord::Xmm2);
+    x.sha1rnds4(OWord::Xmm1, QWord::Rax.value_at(), 3);
+    x.sha1msg1(OWord::Xmm3, OWord::Xmm4);
+
+    let object = x.dump();
+    assert_eq!(object.code, vec![
+        0x66, 0x0f, 0x38, 0xdc, 0xca,
+        0xc4, 0xe2, 0x69, 0xdf, 0xcb,
+        0xc4, 0xe2, 0x6d, 0xdc, 0xcb,
+        0x62, 0xf2, 0x6d, 0x48, 0xdc, 0xcb,
+        0x66, 0x0f, 0x3a, 0x44, 0xca, 0x11,
+        0x62, 0xf3, 0x6d, 0x48, 0x44, 0xcb, 0x01,
+        0x0f, 0x38, 0xcb, 0xca,
+        0x0f, 0x3a, 0xcc, 0x08, 0x03,
+        0x0f, 0x38, 0xc9, 0xdc,
+    ]);
+}
+
+#[test]
+fn gfni() {
+    use x64::regs::{OWord, HWord, ZWord};
+    let mut x = Assembler::new();
+    x.gf2p8affineqb(OWord::Xmm1, OWord::Xmm2, 0);
+    x.vgf2p8affineqb(HWord::Ymm1, HWord::Ymm2, HWord::Ymm3, 0);
+    x.gf2p8mulb(OWord::Xmm1, OWord::Xmm2);
+    x.vgf2p8mulb(ZWord::Zmm1, ZWord::Zmm2, ZWord::Zmm3);
+
+    let object = x.dump();
+    assert_eq!(object.code, vec![
+        0x66, 0x0f, 0x3a, 0xce, 0xca, 0x00,
+        0xc4, 0xe3, 0xed, 0xce, 0xcb, 0x00,
+        0x66, 0x0f, 0x38, 0xcf, 0xca,
+        0x62, 0xf2, 0x6d, 0x48, 0xcf, 0xcb,
+    ]);
+}
+
+#[test]
+fn general_purpose_extensions() {
+    use x64::regs::{DWord, QWord};
+    let mut x = Assembler::new();
+    x.adcx(QWord::Rax, QWord::Rbx);
+    x.adox(DWord::Eax, QWord::Rbx.value_at());
+    x.rdrand(DWord::Eax);
+    x.rdseed(QWord::Rcx);
+    x.movbe(DWord::Eax, QWord::Rdi.value_at());
+    x.movbe(QWord::Rdi.value_at(), QWord::Rax);
+    x.clflushopt(QWord::Rax.value_at());
+    x.clwb(QWord::Rax.value_at());
+
+    let object = x.dump();
+    assert_eq!(object.code, vec![
+        0x66, 0x48, 0x0f, 0x38, 0xf6, 0xc3,
+        0xf3, 0x0f, 0x38, 0xf6, 0x03,
+        0x0f, 0xc7, 0xf0,
+        0x48, 0x0f, 0xc7, 0xf9,
+        0x0f, 0x38, 0xf0, 0x07,
+        0x48, 0x0f, 0x38, 0xf1, 0x07,
+        0x66, 0x0f, 0xae, 0x38,
+        0x66, 0x0f, 0xae, 0x30,
+    ]);
+}
+
+#[test]
+fn transactional_memory() {
+    use x64::parser::JumpType;
+    let mut x = Assembler::new();
+    let abort = x.allocate_local();
+    let retry = x.local();
+    x.xbegin(Arg::JumpTarget(JumpType::Forward(abort), None));
+    x.xtest();
+    x.xend();
+    x.place_local(abort);
+    x.xabort(0xff);
+    x.xbegin(Arg::JumpTarget(JumpType::Forward(retry), None));
+
+    let object = x.dump();
+    assert_eq!(object.code, vec![
+        0xc7, 0xf8, 0x06, 0x00, 0x00, 0x00,
+        0x0f, 0x01, 0xd6,
+        0x0f, 0x01, 0xd5,
+        0xc6, 0xf8, 0xff,
+        0xc7, 0xf8, 0xeb, 0xff, 0xff, 0xff,
+    ]);
+}
+
+#[test]
+fn control_flow_enforcement() {
+    use x64::regs::{DWord, QWord};
+    let mut x = Assembler::new();
+    x.endbr64();
+    x.endbr32();
+    x.incsspq(QWord::Rax);
+    x.incsspd(DWord::Eax);
+    x.rdsspq(QWord::Rax);
+    x.wrssq(QWord::Rax.value_at(), QWord::Rcx);
+    x.setssbsy();
+
+    let object = x.dump();
+    assert_eq!(object.code, vec![
+        0xf3, 0x0f, 0x1e, 0xfa,
+        0xf3, 0x0f, 0x1e, 0xfb,
+        0xf3, 0x48, 0x0f, 0xae, 0xe8,
+        0xf3, 0x0f, 0xae, 0xe8,
+        0xf3, 0x48, 0x0f, 0x1e, 0xc8,
+        0x48, 0x0f, 0x38, 0xf6, 0x08,
+        0xf3, 0x0f, 0x01, 0xe8,
+    ]);
+}
+
+#[test]
+fn target_features() {
+    use x64::regs::{DWord, OWord, HWord, ZWord};
+    let mut x = Assembler::new();
+    x.set_target_features(Features::X86_64 | Features::SSE3 | Features::SSSE3 | Features::SSE4_1 | Features::AVX);
+    x.pshufb(OWord::Xmm0, OWord::Xmm1);
+    x.vpaddd(OWord::Xmm0, OWord::Xmm1, OWord::Xmm2);
+    x.vaddps(HWord::Ymm0, HWord::Ymm1, HWord::Ymm2);
+    x.set_target_features(Features::X86_64_V4);
+    x.andn(DWord::Eax, DWord::Ebx, DWord::Ecx);
+    x.vpaddd(HWord::Ymm0, HWord::Ymm1, HWord::Ymm2);
+    x.vpaddd(OWord::Xmm16, OWord::Xmm1, OWord::Xmm2);
+    x.vpaddb(ZWord::Zmm0, ZWord::Zmm1, ZWord::Zmm2);
+    assert_eq!(x.dump().code, vec![
+        0x66, 0x0f, 0x38, 0x00, 0xc1,
+        0xc5, 0xf1, 0xfe, 0xc2,
+        0xc5, 0xf4, 0x58, 0xc2,
+        0xc4, 0xe2, 0x60, 0xf2, 0xc1,
+        0xc5, 0xf5, 0xfe, 0xc2,
+        0x62, 0xe1, 0x75, 0x08, 0xfe, 0xc2, // xmm16 needs the EVEX form
+        0x62, 0xf1, 0x75, 0x48, 0xfc, 0xc2,
+    ]);
+}
+
+#[test]
+#[should_panic(expected = "'blcfill': requires TBM")]
+fn target_features_tbm_on_skylake() {
+    use x64::regs::DWord;
+    let mut x = Assembler::new();
+    x.set_target_features(Features::SKYLAKE);
+    x.blcfill(DWord::Eax, DWord::Ebx);
+}
+
+#[test]
+#[should_panic(expected = "'vpaddd': requires AVX2")]
+fn target_features_256_bit_integer_avx() {
+    use x64::regs::HWord;
+    let mut x = Assembler::new();
+    x.set_target_features(Features::X86_64 | Features::AVX);
+    x.vpaddd(HWord::Ymm0, HWord::Ymm1, HWord::Ymm2);
+}
+
+#[test]
+#[should_panic(expected = "'vaddps': requires AVX512VL")]
+fn target_features_evex_vector_length() {
+    use x64::regs::HWord;
+    let mut x = Assembler::new();
+    x.set_target_features(Features::X86_64_V3 | Features::AVX512F);
+    x.vaddps(HWord::Ymm20, HWord::Ymm1, HWord::Ymm2);
+}
+
+#[test]
+#[should_panic(expected = "k0 cannot be used as write mask")]
+fn evex_k0_is_no_write_mask() {
+    use x64::regs::{ZWord, Mask};
+    let mut x = Assembler::new();
+    x.with_mask(Mask::K0).vaddps(ZWord::Zmm0, ZWord::Zmm1, ZWord::Zmm2);
+}
+
+#[test]
+#[should_panic(expected = "broadcast")]
+fn evex_broadcast_needs_support() {
+    use x64::regs::{ZWord, QWord};
+    let mut x = Assembler::new();
+    x.with_broadcast().vpaddb(ZWord::Zmm0, ZWord::Zmm1, QWord::Rax.value_at());
+}
+
+#[test]
+#[should_panic(expected = "rounding")]
+fn evex_rounding_needs_registers() {
+    use x64::regs::{ZWord, QWord};
+    let mut x = Assembler::new();
+    x.with_rounding(Rounding::RnSae).vaddps(ZWord::Zmm0, ZWord::Zmm1, QWord::Rax.value_at());
+}
+
+#[test]
+#[should_panic(expected = "suppressing exceptions requires 512-bit vectors")]
+fn evex_suppressing_exceptions_needs_zmm() {
+    use x64::regs::HWord;
+    let mut x = Assembler::new();
+    x.with_rounding(Rounding::Sae).vmaxps(HWord::Ymm1, HWord::Ymm2, HWord::Ymm3);
+}
+
+#[test]
+fn evex_gather_scatter() {
+    use x64::regs::{OWord, HWord, ZWord, DWord, QWord, Mask};
+    use x64::parser::{MemoryRef, RegId};
+    let vsib = |base: Register, index: Register, scale: isize, disp: i64| Arg::Indirect(MemoryRef{
+        index: Some(index),
+        scale: scale,
+        base: Some(base),
+        disp: if disp != 0 { Some(ImmediateValue::I64(disp)) } else { None },
+        size: None,
+    });
+    let rax = || QWord::Rax.into();
+    let vector = |size: Size, id: RegId| Register::new_static(size, id);
+    let mut x = Assembler::new();
+    x.with_mask(Mask::K1).vpgatherdd(ZWord::Zmm1, vsib(rax(), vector(Size::ZWORD, RegId::XMM2), 4, 0), None::<Arg>);
+    x.with_mask(Mask::K2).vpgatherdq(ZWord::Zmm1, vsib(rax(), vector(Size::HWORD, RegId::XMM2), 8, 64), None::<Arg>);
+    x.with_mask(Mask::K1).vgatherqps(HWord::Ymm1, vsib(QWord::Rdi.into(), vector(Size::ZWORD, RegId::XMM20), 4, 0), None::<Arg>);
+    x.with_mask(Mask::K5).vpscatterqq(vsib(QWord::Rsp.into(), vector(Size::ZWORD, RegId::XMM3), 8, 0), ZWord::Zmm30);
+    x.with_mask(Mask::K1).vscatterdps(vsib(rax(), vector(Size::OWORD, RegId::XMM2), 4, 0), OWord::Xmm1);
+    x.with_mask(Mask::K1).vpgatherqd(OWord::Xmm1, vsib(rax(), vector(Size::HWORD, RegId::XMM2), 4, 0), None::<Arg>);
+    x.vmovd(OWord::Xmm16, DWord::Eax);
+    x.vmovd(DWord::Eax, OWord::Xmm17);
+    x.vmovd(OWord::Xmm16, QWord::Rax);
+    
+    let object = x.dump();
+    assert_eq!(object.code, vec![
+        0x62, 0xf2, 0x7d, 0x49, 0x90, 0x0c, 0x90,
+        0x62, 0xf2, 0xfd, 0x4a, 0x90, 0x4c, 0xd0, 0x08,
+        0x62, 0xf2, 0x7d, 0x41, 0x93, 0x0c, 0xa7,
+        0x62, 0x62, 0xfd, 0x4d, 0xa1, 0x34, 0xdc,
+        0x62, 0xf2, 0x7d, 0x09, 0xa2, 0x0c, 0x90,
+        0x62, 0xf2, 0x7d, 0x29, 0x91, 0x0c, 0x90,
+        0x62, 0xe1, 0x7d, 0x08, 0x6e, 0xc0,
+        0x62, 0xe1, 0x7d, 0x08, 0x7e, 0xc8,
+        0x62, 0xe1, 0xfd, 0x08, 0x6e, 0xc0,
+    ]);
+    
+    let mut x = Assembler::new();
+    x.set_mode(Mode::Bits32);
+    x.with_mask(Mask::K1).vpgatherdd(ZWord::Zmm1, vsib(DWord::Eax.into(), vector(Size::ZWORD, RegId::XMM2), 4, 0), None::<Arg>);
+    x.vaddps(ZWord::Zmm7, ZWord::Zmm1, ZWord::Zmm2);
+    assert_eq!(x.dump().code, vec![
+        0x62, 0xf2, 0x7d, 0x49, 0x90, 0x0c, 0x90,
+        0x62, 0xf1, 0x74, 0x48, 0x58, 0xfa,
+    ]);
+}
+
+#[test]
+#[should_panic(expected = "require a write mask")]
+fn evex_gather_needs_mask() {
+    use x64::regs::{ZWord, QWord};
+    use x64::parser::{MemoryRef, RegId};
+    let mut x = Assembler::new();
+    x.vpgatherdd(ZWord::Zmm1, Arg::Indirect(MemoryRef{
+        index: Some(Register::new_static(Size::ZWORD, RegId::XMM2)),
+        scale: 4,
+        base: Some(QWord::Rax.into()),
+        disp: None,
+        size: None,
+    }), None::<Arg>);
+}
+
+#[test]
+#[should_panic(expected = "xmm8-31, ymm8-31 and zmm8-31 are only available in 64-bit mode")]
+fn extended_vector_registers_need_long_mode() {
+    use x64::regs::ZWord;
+    let mut x = Assembler::new();
+    x.set_mode(Mode::Bits32);
+    x.vaddps(ZWord::Zmm16, ZWord::Zmm1, ZWord::Zmm9);
+}
+
+#[test]
+fn control_flow_enforcement_properties() {
+    let mut x = Assembler::new();
+    x.global("indirect_target");
+    x.ret(None::<i32>);
+    assert_eq!(x.dump().x86_features, 0);
+    
+    x.set_cet(true, false);
+    assert_eq!(x.dump().x86_features, GNU_PROPERTY_X86_FEATURE_1_IBT);
+    x.set_cet(true, true);
+    let object = x.dump();
+    assert_eq!(object.x86_features, GNU_PROPERTY_X86_FEATURE_1_IBT | GNU_PROPERTY_X86_FEATURE_1_SHSTK);
+    assert_eq!(&object.code[..], &[0xc3]);
+}
+
+fn listing_line(offset: usize, bytes: &[u8], text: &str) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("{:08x}  {:<30} {}", offset, bytes.join(" "), text).trim_end().to_string()
+}
+
+fn dwarf_register(reg: Register, mode: Mode) -> u8 {
+    let number = match reg.kind {
+        RegKind::Static(id) => if mode == Mode::Bits64 { id.dwarf_number() } else { id.dwarf_number_i386() },
+    };
+    number.expect("register cannot be described by call frame information")
+}
+
+// Accumulates the call frame instructions of a single function while dumping
+struct FrameBuilder {
+    start: usize,
+    instructions: Vec<(usize, CallFrameInstruction)>,
+    cfa_offset: u64,
+    remembered_cfa_offsets: Vec<u64>,
+}
+
+impl FrameBuilder {
+    fn new(start: usize, mode: Mode) -> FrameBuilder {
+        FrameBuilder {
+            start: start,
+            instructions: Vec::new(),
+            cfa_offset: if mode == Mode::Bits64 { 8 } else { 4 }, // the return address
+            remembered_cfa_offsets: Vec::new(),
+        }
+    }
+    
+    fn add(&mut self, location: usize, directive: &CfiDirective) {
+        let instruction = match *directive {
+            CfiDirective::DefCfa(reg, offset) => {
+                self.cfa_offset = offset;
+                CallFrameInstruction::DefCfa(reg, offset)
+            }
+            CfiDirective::DefCfaRegister(reg) => CallFrameInstruction::DefCfaRegister(reg),
+            CfiDirective::DefCfaOffset(offset) => {
+                self.cfa_offset = offset;
+                CallFrameInstruction::DefCfaOffset(offset)
+            }
+            CfiDirective::AdjustCfaOffset(delta) => {
+                let offset = self.cfa_offset as i64 + delta;
+                if offset < 0 {
+                    panic!("cfi_adjust_cfa_offset makes the CFA offset negative");
+                }
+                self.cfa_offset = offset as u64;
+                CallFrameInstruction::DefCfaOffset(self.cfa_offset)
+            }
+            CfiDirective::Offset(reg, offset) => CallFrameInstruction::Offset(reg, offset),
+            CfiDirective::Restore(reg) => CallFrameInstruction::Restore(reg),
+            CfiDirective::RememberState => {
+                self.remembered_cfa_offsets.push(self.cfa_offset);
+                CallFrameInstruction::RememberState
+            }
+            CfiDirective::RestoreState => {
+                self.cfa_offset = self.remembered_cfa_offsets.pop().expect("cfi_restore_state without cfi_remember_state");
+                CallFrameInstruction::RestoreState
+            }
+            CfiDirective::StartProc | CfiDirective::EndProc => unreachable!(),
+        };
+        self.instructions.push((location - self.start, instruction));
+    }
+    
+    fn finish(self, end: usize) -> FrameDescription {
+        FrameDescription {
+            offset: self.start,
+            length: end - self.start,
+            instructions: self.instructions,
+        }
+    }
+}
+
+fn seh_register(reg: Register, family: RegFamily) -> u8 {
+    if reg.kind.family() != family {
+        panic!("register {:?} cannot be described by this unwind directive", reg);
+    }
+    reg.kind.encode()
+}
+
+// Accumulates the unwind codes of a single function while dumping
+struct UnwindBuilder {
+    start: usize,
+    prolog_size: Option<u8>,
+    frame_register: u8,
+    frame_offset: u8,
+    codes: Vec<(u8, UnwindCode)>,
+}
+
+impl UnwindBuilder {
+    fn new(start: usize) -> UnwindBuilder {
+        UnwindBuilder {
+            start: start,
+            prolog_size: None,
+            frame_register: 0,
+            frame_offset: 0,
+            codes: Vec::new(),
+        }
+    }
+    
+    fn add(&mut self, location: usize, directive: &SehDirective) {
+        if self.prolog_size.is_some() {
+            panic!("SEH directive after seh_endprologue: {:?}", directive);
+        }
+        let offset = location - self.start;
+        if offset > 0xff {
+            panic!("function prologue is longer than 255 bytes");
+        }
+        let offset = offset as u8;
+        
+        let code = match *directive {
+            SehDirective::PushReg(reg) => UnwindCode::PushNonvol(reg),
+            SehDirective::StackAlloc(size) => UnwindCode::AllocStack(size),
+            SehDirective::SetFrame(reg, frame_offset) => {
+                self.frame_register = reg;
+                self.frame_offset = frame_offset;
+                UnwindCode::SetFrame
+            }
+            SehDirective::SaveReg(reg, at) => UnwindCode::SaveNonvol(reg, at),
+            SehDirective::SaveXmm(reg, at) => UnwindCode::SaveXmm128(reg, at),
+            SehDirective::EndPrologue => {
+                self.prolog_size = Some(offset);
+                return;
+            }
+            SehDirective::Proc | SehDirective::EndProc => unreachable!(),
+        };
+        self.codes.push((offset, code));
+    }
+    
+    fn finish(self, end: usize) -> UnwindInfo {
+        UnwindInfo {
+            begin: self.start as u32,
+            end: end as u32,
+            prolog_size: self.prolog_size.expect("seh_endproc without seh_endprologue"),
+            frame_register: self.frame_register,
+            frame_offset: self.frame_offset,
+            codes: self.codes,
+        }
+    }
 }
 
 impl Deref for Assembler {
@@ -1,5 +1,7 @@
 use x64::parser::{self, Item, Arg, Ident, MemoryRef, Register, RegKind, RegFamily, RegId, Size, LabelType, JumpType, JumpTarget, ImmediateValue};
 use x64::x64data::flags::*;
+use x64::features::Features;
+use object_file::SymbolReferenceKind;
 
 use std::mem::swap;
 use std::slice;
@@ -7,6 +9,55 @@ use std::iter;
 
 pub type Flags = u32;
 
+// The processor mode instructions are encoded for. This determines the default operand and
+// address sizes, and whether REX prefixes (and with them 64-bit operands) are available.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    Bits16,
+    Bits32,
+    Bits64,
+}
+
+impl Mode {
+    pub fn address_size(self) -> Size {
+        match self {
+            Mode::Bits16 => Size::WORD,
+            Mode::Bits32 => Size::DWORD,
+            Mode::Bits64 => Size::QWORD,
+        }
+    }
+
+    // default size of immediates and relative jump offsets
+    fn immediate_size(self) -> Size {
+        if self == Mode::Bits16 { Size::WORD } else { Size::DWORD }
+    }
+}
+
+// AVX-512 decorations of an EVEX encoded instruction, mirroring the {k}, {z}, {1toN} and
+// {rn-sae} style decorations of the Intel syntax.
+#[derive(Clone, Debug, Default)]
+pub struct Decorations {
+    pub mask: Option<Register>,
+    pub zeroing: bool,
+    pub broadcast: bool,
+    pub rounding: Option<Rounding>,
+}
+
+impl Decorations {
+    fn is_empty(&self) -> bool {
+        self.mask.is_none() && !self.zeroing && !self.broadcast && self.rounding.is_none()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rounding {
+    RnSae, // round to nearest
+    RdSae, // round down
+    RuSae, // round up
+    RzSae, // round towards zero
+    Sae,   // only suppress exceptions
+}
+
 /*
  * Compilation output
  */
@@ -31,10 +82,46 @@ pub enum Stmt {
     LocalLabel(JumpTarget),
     //DynamicLabel(P<ast::Expr>),
 
-    GlobalJumpTarget(Ident, Size),
+    GlobalJumpTarget(Ident, Size, SymbolReferenceKind),
     ForwardJumpTarget(JumpTarget, Size),
     BackwardJumpTarget(JumpTarget, Size),
+    AbsoluteJumpTarget(JumpTarget, Size),
     //DynamicJumpTarget(P<ast::Expr>, Size)
+
+    Cfi(CfiDirective),
+    Seh(SehDirective),
+
+    Location(Ident, u32, u32), // source file, line, column of the statements that follow
+}
+
+// Call frame information directives, mirroring the .cfi_* directives of gas.
+// Registers are given by their DWARF register number.
+#[derive(Clone, Debug)]
+pub enum CfiDirective {
+    StartProc,
+    EndProc,
+    DefCfa(u8, u64),
+    DefCfaRegister(u8),
+    DefCfaOffset(u64),
+    AdjustCfaOffset(i64),
+    Offset(u8, i64),
+    Restore(u8),
+    RememberState,
+    RestoreState,
+}
+
+// Windows x64 unwind directives, mirroring the .seh_* directives of gas.
+// Registers are given by their number in the instruction encoding.
+#[derive(Clone, Debug)]
+pub enum SehDirective {
+    Proc,
+    EndProc,
+    PushReg(u8),
+    StackAlloc(u32),
+    SetFrame(u8, u8),
+    SaveReg(u8, u32),
+    SaveXmm(u8, u32),
+    EndPrologue,
 }
 
 /*
@@ -45,7 +132,9 @@ pub struct Opdata {
     pub args:  &'static [u8],  // format string of arg format
     pub ops:   &'static [u8],
     pub reg:   u8,
-    pub flags: Flags
+    pub flags: Flags,
+    pub features: Features,     // CPUID features this form needs
+    pub features_256: Features, // the same, for 256-bit vectors
 }
 
 pub struct FormatStringIterator<'a> {
@@ -80,6 +169,7 @@ const MOD_NODISP: u8 = 0b00; // normal addressing
 const MOD_NOBASE: u8 = 0b00; // VSIB addressing
 const MOD_DISP8:  u8 = 0b01;
 const MOD_DISP32: u8 = 0b10;
+const MOD_DISP16: u8 = 0b10; // 16-bit addressing
 
 /*
  * Implmementation
@@ -237,12 +327,29 @@ fn compile_label(stmts: &mut StmtBuffer, label: LabelType) {
 }
 */
 
-pub fn compile_op(buffer: &mut StmtBuffer, op: Ident, prefixes: Vec<Ident>, mut args: Vec<Arg>, data: &'static [Opdata]) -> Result<(), Option<String>> {
+#[allow(clippy::too_many_arguments)]
+pub fn compile_op(buffer: &mut StmtBuffer, op: Ident, prefixes: Vec<Ident>, mut args: Vec<Arg>, data: &'static [Opdata], mode: Mode, decorations: Decorations, target_features: Features) -> Result<(), Option<String>> {
     // sanitize memory references and determine address size
-    let pref_addr = try!(sanitize_addresses(&mut args));
+    let addr_size = try!(sanitize_addresses(&mut args, mode));
+    let pref_addr = addr_size != mode.address_size();
+    let long_mode = mode == Mode::Bits64;
+
+    // a broadcast memory operand is sized by its element, which would not match the vector sized formats
+    let broadcast_size = if decorations.broadcast {
+        match args.iter_mut().filter_map(|arg| if let Arg::Indirect(ref mut mem) = *arg { Some(mem) } else { None }).next() {
+            Some(mem) => Some(mem.size.take()),
+            None => return Err(Some(format!("'{}': broadcast requires a memory operand", op.as_str())))
+        }
+    } else {
+        None
+    };
+
+    // zmm registers, xmm16-31 and decorations can only be encoded with EVEX
+    let needs_evex = !decorations.is_empty() || args.iter().any(requires_evex);
 
     // this call also inserts more size information in the AST if applicable.
-    let data = try!(match_op_format(op.clone(), data, &mut args));
+    let data = try!(match_op_format(op.clone(), data, &mut args, needs_evex));
+    let evex = (data.flags & EVEX_OP) == EVEX_OP;
 
     // determine legacy prefixes
     let (mut pref_mod, pref_seg) = try!(get_legacy_prefixes(data, prefixes));
@@ -251,54 +358,124 @@ pub fn compile_op(buffer: &mut StmtBuffer, op: Ident, prefixes: Vec<Ident>, mut
     let mut pref_size = false;
     let mut rex_w = false;
     let mut vex_l = false;
+    let mut vex_l_prime = false;
 
     // determine if size prefixes are necessary
     if (data.flags & (AUTO_SIZE | AUTO_NO32 | AUTO_REXW | AUTO_VEXL)) != 0 {
         // determine operand size
-        op_size = try!(get_operand_size(data, &args));
+        op_size = try!(get_operand_size(data, &args, mode));
+
+        if op_size == Size::QWORD && mode != Mode::Bits64 && (data.flags & AUTO_VEXL) == 0 {
+            return Err(Some(format!("'{}': 64-bit operands are only available in 64-bit mode", op.as_str())));
+        }
 
         if (data.flags & AUTO_NO32) == AUTO_NO32 {
-            if op_size == Size::WORD {
-                pref_size = true;
-            } else if op_size != Size::QWORD {
-                return Err(Some(format!("'{}': Does not support 32 bit operands in 64-bit mode", &*op.as_str())));
+            // the default operand size of these is the stack width
+            match (mode, op_size) {
+                (Mode::Bits16, Size::WORD)  |
+                (Mode::Bits32, Size::DWORD) |
+                (Mode::Bits64, Size::QWORD) => (),
+                (Mode::Bits64, Size::DWORD) => return Err(Some(format!("'{}': Does not support 32 bit operands in 64-bit mode", op.as_str()))),
+                _ => pref_size = true
             }
         } else if (data.flags & AUTO_REXW) == AUTO_REXW {
             if op_size == Size::QWORD {
                 rex_w = true;
             } else if op_size != Size::DWORD {
                 return Err(Some(format!("'{}': Does not support 16-bit operands", &*op.as_str())));
+            } else if mode == Mode::Bits16 {
+                // general purpose instructions need the operand size prefix, which a mandatory 0x66 already
+                // is. VEX encoded and MMX/SSE instructions always take 32-bit general purpose operands, there
+                // a 0x66 would select another instruction.
+                let vector = FormatStringIterator::new(data.args).any(|(code, _)| matches!(code, b'x' | b'y' | b'u' | b'w'));
+                pref_size = !vector && (data.flags & (VEX_OP | XOP_OP | EVEX_OP)) == 0;
             }
         } else if (data.flags & AUTO_VEXL) == AUTO_VEXL {
             if op_size == Size::HWORD {
                 vex_l = true;
+            } else if op_size == Size::ZWORD && evex {
+                vex_l_prime = true;
             } else if op_size != Size::OWORD {
                 panic!("bad formatting data");
             }
         } else {
             if op_size == Size::WORD {
-                pref_size = true;
+                pref_size = mode != Mode::Bits16;
             } else if op_size == Size::QWORD {
                 rex_w = true;
-            } else if op_size != Size::DWORD {
+            } else if op_size == Size::DWORD {
+                pref_size = mode == Mode::Bits16;
+            } else {
                 panic!("bad formatting data");
             }
         }
     }
 
+    // fixed operand sizes are relative to the default operand size of the mode
+    if (data.flags & WORD_SIZE) == WORD_SIZE {
+        pref_size = mode != Mode::Bits16;
+    } else if (data.flags & DWORD_SIZE) == DWORD_SIZE {
+        pref_size = mode == Mode::Bits16;
+    }
+    if (data.flags & (WITH_REXW | VEX_OP | XOP_OP | EVEX_OP)) == WITH_REXW && mode != Mode::Bits64 {
+        return Err(Some(format!("'{}': 64-bit operands are only available in 64-bit mode", op.as_str())));
+    }
+    if (data.flags & LONG_ONLY) == LONG_ONLY && mode != Mode::Bits64 {
+        return Err(Some(format!("'{}': only available in 64-bit mode", op.as_str())));
+    } else if (data.flags & NO_LONG) == NO_LONG && mode == Mode::Bits64 {
+        return Err(Some(format!("'{}': not available in 64-bit mode", op.as_str())));
+    }
+
     // mandatory prefixes
-    let pref_size = pref_size || (data.flags & WORD_SIZE) == WORD_SIZE;
+    let pref_66   = (data.flags & PREF_66) == PREF_66;
     let rex_w     = rex_w     || (data.flags & WITH_REXW) == WITH_REXW;
     let vex_l     = vex_l     || (data.flags & WITH_VEXL) == WITH_VEXL;
-    let pref_addr = pref_addr || (data.flags & PREF_67) == PREF_67;
+    let vex_l_prime = vex_l_prime || (data.flags & WITH_EVEXL) == WITH_EVEXL;
+    let pref_addr = pref_addr || (data.flags & PREF_67) == PREF_67
+        // fixed address sizes are relative to the default address size of the mode
+        || ((data.flags & WORD_ADDR) == WORD_ADDR && mode == Mode::Bits32)
+        || ((data.flags & DWORD_ADDR) == DWORD_ADDR && mode != Mode::Bits32);
+
+    // check that the target processor supports this instruction form
+    let mut required = if vex_l && !evex { data.features_256 } else { data.features };
+    if evex && !vex_l_prime && ((data.flags & AUTO_VEXL) == AUTO_VEXL || args.iter().any(vsib_operand)) {
+        required = required | Features::AVX512VL;
+    }
+    let missing = required & !target_features;
+    if !missing.is_empty() {
+        return Err(Some(format!("'{}': requires {}, which the target does not support", op.as_str(), missing.names().join(", "))));
+    }
 
     if        (data.flags & PREF_F0) == PREF_F0 { pref_mod = Some(0xF0);
     } else if (data.flags & PREF_F2) == PREF_F2 { pref_mod = Some(0xF2);
     } else if (data.flags & PREF_F3) == PREF_F3 { pref_mod = Some(0xF3);
     }
 
+    // EVEX compresses 8-bit displacements by the size of the memory access
+    let mut disp8_scale = None;
+    if evex {
+        disp8_scale = args.iter().filter_map(|arg| if let Arg::Indirect(ref mem) = *arg { Some(mem.size) } else { None }).next()
+            .and_then(|size| size.or(if (data.flags & (AUTO_SIZE | AUTO_NO32 | AUTO_REXW | AUTO_VEXL)) != 0 { Some(op_size) } else { None }));
+        try!(validate_decorations(&op, data, &args, &decorations, op_size));
+    }
+    if let Some(size) = broadcast_size {
+        // the element size follows EVEX.W
+        let element = if rex_w { Size::QWORD } else { Size::DWORD };
+        if (data.flags & BCST) == 0 {
+            return Err(Some(format!("'{}': does not support embedded broadcast", op.as_str())));
+        } else if size.map_or(false, |size| size != element) {
+            return Err(Some(format!("'{}': broadcast element size mismatch", op.as_str())));
+        }
+        disp8_scale = Some(element);
+    }
+
     // check if this combination of args can actually be encoded and whether a rex prefix is necessary
     let need_rex = try!(validate_args(data, &args, rex_w));
+    if mode != Mode::Bits64 && args.iter().any(extended_vector_register) {
+        return Err(Some(format!("'{}': xmm8-31, ymm8-31 and zmm8-31 are only available in 64-bit mode", op.as_str())));
+    } else if mode != Mode::Bits64 && try!(validate_args(data, &args, false)) {
+        return Err(Some(format!("'{}': registers r8-r15, spl, bpl, sil and dil are only available in 64-bit mode", op.as_str())));
+    }
 
     // split args
     let (mut rm, reg, vvvv, ireg, mut args) = extract_args(data, args);
@@ -313,23 +490,35 @@ pub fn compile_op(buffer: &mut StmtBuffer, op: Ident, prefixes: Vec<Ident>, mut
         buffer.push(Stmt::Const(0x67));
     }
 
-    // VEX/XOP prefixes embed the operand size prefix / modification prefixes in them.
-    if data.flags & (VEX_OP | XOP_OP) != 0 {
-        let prefix = if pref_size        { 0b01
-        } else if pref_mod == Some(0xF3) { 0b10
-        } else if pref_mod == Some(0xF2) { 0b11
-        } else                           { 0
+    // VEX/XOP/EVEX prefixes embed the operand size prefix / modification prefixes in them.
+    if data.flags & (VEX_OP | XOP_OP | EVEX_OP) != 0 {
+        let prefix = if pref_66 || pref_size { 0b01
+        } else if pref_mod == Some(0xF3)     { 0b10
+        } else if pref_mod == Some(0xF2)     { 0b11
+        } else                               { 0
         };
         // map_sel is stored in the first byte of the opcode
         let (map_sel, tail) = ops.split_first().expect("bad formatting data");
         ops = tail;
-        compile_vex_xop(buffer, data, &reg, &rm, *map_sel, rex_w, &vvvv, vex_l, prefix);
+        if evex {
+            // embedded rounding control takes the place of the vector length
+            let vector_len = match decorations.rounding {
+                Some(Rounding::RnSae) => 0b00,
+                Some(Rounding::RdSae) => 0b01,
+                Some(Rounding::RuSae) => 0b10,
+                Some(Rounding::RzSae) => 0b11,
+                _ => (vex_l_prime as u8) << 1 | vex_l as u8
+            };
+            compile_evex(buffer, &reg, &rm, *map_sel, rex_w, &vvvv, vector_len, prefix, &decorations);
+        } else {
+            compile_vex_xop(buffer, data, &reg, &rm, *map_sel, rex_w, &vvvv, vex_l, prefix);
+        }
     // otherwise, the size/mod prefixes have to be pushed and check if a rex prefix has to be generated.
     } else {
         if let Some(pref) = pref_mod {
             buffer.push(Stmt::Const(pref));
         }
-        if pref_size {
+        if pref_66 || pref_size {
             buffer.push(Stmt::Const(0x66));
         }
         if need_rex {
@@ -376,11 +565,21 @@ pub fn compile_op(buffer: &mut StmtBuffer, op: Ident, prefixes: Vec<Ident>, mut
         } else {
             RegKind::from_number(data.reg)
         };
+        // 16-bit addressing has its own modrm table and no SIB byte
+        if addr_size == Size::WORD {
+            compile_modrm16(buffer, reg_k, mem);
         // VSIB has different mode rules
-        if mem.index.as_ref().map_or(false, |x| x.kind.family() == RegFamily::XMM) {
+        } else if mem.index.as_ref().map_or(false, |x| x.kind.family() == RegFamily::XMM) {
             let index = mem.index.unwrap().kind;
+            let disp8 = match (disp8_scale, mem.disp.as_ref()) {
+                (Some(scale), Some(disp)) => compress_disp8(disp, scale),
+                _ => None
+            };
+            // like RBP, R13 can only be encoded as base with a displacement
+            let rbp_relative = mem.base == RegId::RBP || mem.base == RegId::R13;
+            let no_base = mem.base.is_none();
             let (base, mode) = if let Some(base) = mem.base {
-                (base.kind, if mem.disp.is_some() {MOD_DISP32} else {MOD_DISP8})
+                (base.kind, if mem.disp.is_none() && !rbp_relative {MOD_NODISP} else if disp8.is_none() && mem.disp.is_some() {MOD_DISP32} else {MOD_DISP8})
             } else {
                 (RegKind::Static(RegId::RBP), MOD_NOBASE)
             };
@@ -392,10 +591,10 @@ pub fn compile_op(buffer: &mut StmtBuffer, op: Ident, prefixes: Vec<Ident>, mut
             }
 
             if mode == MOD_DISP8 {
-                buffer.push(Stmt::Const(0));
-            } else  if let Some(disp) = mem.disp {
+                buffer.push(Stmt::Const(disp8.unwrap_or(0) as u8));
+            } else if let Some(disp) = mem.disp {
                 buffer.push(Stmt::Var(disp, Size::DWORD));
-            } else {
+            } else if no_base {
                 for _ in 0..4 {
                     buffer.push(Stmt::Const(0));
                 }
@@ -408,6 +607,10 @@ pub fn compile_op(buffer: &mut StmtBuffer, op: Ident, prefixes: Vec<Ident>, mut
             let rip_relative = mem.base == RegId::RIP;
             let rbp_relative = mem.base == RegId::RBP || mem.base == RegId::R13;
             let no_base      = mem.base.is_none();
+            let disp8        = match (disp8_scale, mem.disp.as_ref()) {
+                (Some(scale), Some(disp)) => compress_disp8(disp, scale),
+                _ => None
+            };
 
             // RBP can only be encoded as base if a displacement is present.
             let mode = if rbp_relative && mem.disp.is_none() {
@@ -415,6 +618,8 @@ pub fn compile_op(buffer: &mut StmtBuffer, op: Ident, prefixes: Vec<Ident>, mut
             // mode_nodisp has to be selected if RIP is encoded, or if no base is to be encoded. note that in these scenarions the disp should actually be encoded
             } else if mem.disp.is_none() || rip_relative || no_base {
                 MOD_NODISP
+            } else if disp8.is_some() {
+                MOD_DISP8
             } else {
                 MOD_DISP32
             };
@@ -439,6 +644,10 @@ pub fn compile_op(buffer: &mut StmtBuffer, op: Ident, prefixes: Vec<Ident>, mut
             } else if let Some(base) = mem.base {
                 compile_modrm_sib(buffer, mode, reg_k, base.kind);
 
+            // no base, no index. outside of 64-bit mode this is just an absolute displacement
+            } else if !long_mode {
+                compile_modrm_sib(buffer, MOD_NODISP, reg_k, RegKind::Static(RegId::RBP));
+
             // no base, no index. only disp. escape, use RBP as base and RSP as index
             } else {
                 compile_modrm_sib(buffer, mode, reg_k, RegKind::Static(RegId::RSP));
@@ -446,7 +655,9 @@ pub fn compile_op(buffer: &mut StmtBuffer, op: Ident, prefixes: Vec<Ident>, mut
             }
 
             // Disp
-            if let Some(disp) = mem.disp {
+            if let (MOD_DISP8, Some(disp)) = (mode, disp8) {
+                buffer.push(Stmt::Const(disp as u8));
+            } else if let Some(disp) = mem.disp {
                 buffer.push(Stmt::Var(disp, Size::DWORD));
             } else if no_base || rip_relative {
                 for _ in 0..4 {
@@ -463,7 +674,42 @@ pub fn compile_op(buffer: &mut StmtBuffer, op: Ident, prefixes: Vec<Ident>, mut
         } else {
             RegKind::from_number(data.reg)
         };
-        compile_modrm_sib(buffer, MOD_NODISP, reg_k, RegKind::Static(RegId::RBP));
+        // without rip-relative addressing the absolute address of the label has to be encoded
+        if mode != Mode::Bits64 {
+            let size = mode.address_size();
+            let rm_k = if size == Size::WORD { RM16_DISP } else { RegId::RBP.code() };
+            compile_modrm_sib(buffer, MOD_NODISP, reg_k, RegKind::from_number(rm_k));
+            for _ in 0..size.in_bytes() {
+                buffer.push(Stmt::Const(0));
+            }
+            buffer.push(match target {
+                JumpType::Forward(target) => Stmt::AbsoluteJumpTarget(target, size),
+                JumpType::Global(ident, kind) => {
+                    let kind = try!(symbol_reference(&ident, kind, SymbolReferenceKind::Absolute));
+                    Stmt::GlobalJumpTarget(ident, size, kind)
+                },
+                _ => return Err(Some("absolute references are only supported for forward labels".to_string())),
+            });
+            return Ok(());
+        }
+
+        let target = match target {
+            JumpType::Global(ident, kind) => {
+                let kind = try!(symbol_reference(&ident, kind, SymbolReferenceKind::Relative));
+                Stmt::GlobalJumpTarget(ident, Size::DWORD, kind)
+            },
+            JumpType::Forward(target)  => Stmt::ForwardJumpTarget(target, Size::DWORD),
+            JumpType::Backward(target) => Stmt::BackwardJumpTarget(target, Size::DWORD),
+            //JumpType::Dynamic(expr)   => Stmt::DynamicJumpTarget(expr, Size::DWORD)
+        };
+
+        // offsets from the thread pointer are plain displacements, which need a SIB byte to escape rip
+        if let Stmt::GlobalJumpTarget(_, _, SymbolReferenceKind::TpOff) = target {
+            compile_modrm_sib(buffer, MOD_NODISP, reg_k, RegKind::Static(RegId::RSP));
+            compile_modrm_sib(buffer, 0, RegKind::Static(RegId::RSP), RegKind::Static(RegId::RBP));
+        } else {
+            compile_modrm_sib(buffer, MOD_NODISP, reg_k, RegKind::Static(RegId::RBP));
+        }
 
         // note: validate_args ensures that no immediates are encoded afterwards.
         // they potentially could be, but currently the runtime doens't support it
@@ -471,12 +717,7 @@ pub fn compile_op(buffer: &mut StmtBuffer, op: Ident, prefixes: Vec<Ident>, mut
             buffer.push(Stmt::Const(0));
         }
 
-        buffer.push(match target {
-            JumpType::Global(ident)   => Stmt::GlobalJumpTarget(ident, Size::DWORD),
-            JumpType::Forward(target)  => Stmt::ForwardJumpTarget(target, Size::DWORD),
-            JumpType::Backward(target) => Stmt::BackwardJumpTarget(target, Size::DWORD),
-            //JumpType::Dynamic(expr)   => Stmt::DynamicJumpTarget(expr, Size::DWORD)
-        });
+        buffer.push(target);
     }
 
     // register in immediate argument
@@ -501,12 +742,31 @@ pub fn compile_op(buffer: &mut StmtBuffer, op: Ident, prefixes: Vec<Ident>, mut
     }*/
 
     // immediates
-    for arg in args {
+    let immediate_codes = FormatStringIterator::new(data.args).map(|(code, _)| code).filter(|&code| code == b'i' || code == b'o');
+    for (arg, code) in args.into_iter().zip(immediate_codes) {
         let stmt = match arg {
             Arg::Immediate(expr, Some(size)) => Stmt::Var(expr, size),
             Arg::Immediate(expr, None)       => Stmt::Var(expr, if op_size != Size::QWORD {op_size} else {Size::DWORD}),
+            // a jump target in place of an immediate stands for the absolute address of the label
+            Arg::JumpTarget(target, size) if code == b'i' => {
+                let size = size.unwrap_or(if op_size != Size::QWORD {op_size} else {Size::DWORD});
+
+                // placeholder
+                for _ in 0..size.in_bytes() {
+                    buffer.push(Stmt::Const(0));
+                }
+
+                match target {
+                    JumpType::Forward(target) => Stmt::AbsoluteJumpTarget(target, size),
+                    JumpType::Global(ident, kind) => {
+                        let kind = try!(symbol_reference(&ident, kind, SymbolReferenceKind::Absolute));
+                        Stmt::GlobalJumpTarget(ident, size, kind)
+                    },
+                    _ => return Err(Some("absolute references are only supported for forward labels".to_string())),
+                }
+            }
             Arg::JumpTarget(target, size)    => {
-                let size = size.unwrap_or(Size::DWORD);
+                let size = size.unwrap_or(mode.immediate_size());
 
                 // placeholder
                 for _ in 0..size.in_bytes() {
@@ -514,7 +774,10 @@ pub fn compile_op(buffer: &mut StmtBuffer, op: Ident, prefixes: Vec<Ident>, mut
                 }
 
                 match target {
-                    JumpType::Global(ident)   => Stmt::GlobalJumpTarget(ident, size),
+                    JumpType::Global(ident, kind) => {
+                        let kind = try!(symbol_reference(&ident, kind, SymbolReferenceKind::Branch));
+                        Stmt::GlobalJumpTarget(ident, size, kind)
+                    },
                     JumpType::Forward(ident)  => Stmt::ForwardJumpTarget(ident, size),
                     JumpType::Backward(ident) => Stmt::BackwardJumpTarget(ident, size),
                     //JumpType::Dynamic(expr)   => Stmt::DynamicJumpTarget(expr, size)
@@ -528,13 +791,26 @@ pub fn compile_op(buffer: &mut StmtBuffer, op: Ident, prefixes: Vec<Ident>, mut
     Ok(())
 }
 
-fn sanitize_addresses(args: &mut [Arg]) -> Result<bool, Option<String>> {
-    // determine if an address size prefix is necessary, and sanitize the register choice for memoryrefs
-    let mut addr_size = None;
-    for arg in args {
-        if let Arg::Indirect(ref mut mem) = *arg {
-            try!(sanitize_memoryref(mem));
+// The way a symbol is referred to. Without an explicit kind, the operand it appears in decides; an
+// explicit one has to fit the operand: a GOT entry, for instance, is only reachable rip-relative.
+fn symbol_reference(name: &str, kind: Option<SymbolReferenceKind>, operand: SymbolReferenceKind) -> Result<SymbolReferenceKind, Option<String>> {
+    use object_file::SymbolReferenceKind::*;
+    let kind = match kind {
+        Some(kind) => kind,
+        None => return Ok(operand),
+    };
+    match (kind, operand) {
+        (kind, operand) if kind == operand => Ok(kind),
+        (Got, Relative) | (GotTpOff, Relative) | (TpOff, Relative) | (TpOff, Absolute) => Ok(kind),
+        _ => Err(Some(format!("'{}' cannot be referenced as {:?} by this operand", name, kind))),
+    }
+}
 
+fn sanitize_addresses(args: &mut [Arg], mode: Mode) -> Result<Size, Option<String>> {
+    // determine the address size, and sanitize the register choice for memoryrefs
+    let mut addr_size = None;
+    for arg in args.iter() {
+        if let Arg::Indirect(ref mem) = *arg {
             if let Some(ref reg) = mem.base {
                 if reg.kind.family() == RegFamily::LEGACY || reg.kind.family() == RegFamily::RIP {
                     if addr_size.is_some() && addr_size != Some(reg.size()) {
@@ -556,11 +832,46 @@ fn sanitize_addresses(args: &mut [Arg]) -> Result<bool, Option<String>> {
         }
     }
 
-    let addr_size = addr_size.unwrap_or(Size::QWORD);
-    if addr_size != Size::DWORD && addr_size != Size::QWORD {
+    let addr_size = addr_size.unwrap_or(mode.address_size());
+    let possible = match mode {
+        Mode::Bits64 => addr_size == Size::DWORD || addr_size == Size::QWORD,
+        _            => addr_size == Size::WORD  || addr_size == Size::DWORD,
+    };
+    if !possible {
         return Err(Some("Impossible address size".into()));
     }
-    Ok(addr_size != Size::QWORD)
+
+    for arg in args {
+        if let Arg::Indirect(ref mut mem) = *arg {
+            if mode != Mode::Bits64 && (mem.base == RegId::RIP || mem.index == RegId::RIP) {
+                return Err(Some("'rip' relative addressing is only available in 64-bit mode".into()));
+            }
+            if addr_size == Size::WORD {
+                try!(sanitize_memoryref16(mem));
+            } else {
+                try!(sanitize_memoryref(mem));
+            }
+        }
+    }
+    Ok(addr_size)
+}
+
+// 16-bit addressing only allows bx or bp as base, si or di as index and no scaling.
+fn sanitize_memoryref16(mem: &mut MemoryRef) -> Result<(), Option<String>> {
+    if mem.index.is_some() && mem.scale != 1 {
+        return Err(Some(format!("Scale '{}' cannot be encoded with 16-bit addressing", mem.scale)));
+    }
+    mem.scale = 0;
+
+    if mem.base == RegId::RSI || mem.base == RegId::RDI || mem.index == RegId::RBX || mem.index == RegId::RBP {
+        swap(&mut mem.base, &mut mem.index);
+    }
+
+    if !mem.base.as_ref().map_or(true, |x| x.kind == RegId::RBX || x.kind == RegId::RBP) ||
+       !mem.index.as_ref().map_or(true, |x| x.kind == RegId::RSI || x.kind == RegId::RDI) {
+        return Err(Some("16-bit addressing only supports bx or bp as base and si or di as index".into()));
+    }
+    Ok(())
 }
 
 fn sanitize_memoryref(mem: &mut MemoryRef) -> Result<(), Option<String>> {
@@ -662,20 +973,110 @@ fn sanitize_memoryref(mem: &mut MemoryRef) -> Result<(), Option<String>> {
     Ok(())
 }
 
-fn match_op_format(ident: Ident, data: &'static [Opdata], args: &mut [Arg]) -> Result<&'static Opdata, Option<String>> {
+fn match_op_format(ident: Ident, data: &'static [Opdata], args: &mut [Arg], needs_evex: bool) -> Result<&'static Opdata, Option<String>> {
     let name = &*ident.as_str();
 
+    // the shorter VEX forms come first, but they can't express every register and decoration
     for format in data {
+        if needs_evex && (format.flags & EVEX_OP) == 0 {
+            continue;
+        }
         if let Ok(_) = match_format_string(format.args, args) {
             return Ok(format)
         }
     }
 
+    if needs_evex && data.iter().all(|format| (format.flags & EVEX_OP) == 0) {
+        return Err(Some(format!("'{}': zmm registers, xmm16-31 and AVX-512 decorations require an EVEX encoded instruction", name)));
+    }
     Err(Some(
         format!("'{}': argument type/size mismatch", name)
     ))
 }
 
+fn requires_evex(arg: &Arg) -> bool {
+    let high_xmm = |reg: &Register| reg.kind.family() == RegFamily::XMM && (reg.size() == Size::ZWORD || reg.kind.encode() > 15);
+    match *arg {
+        Arg::Direct(ref reg) => high_xmm(reg),
+        Arg::Indirect(MemoryRef {ref index, size, ..}) => size == Some(Size::ZWORD) || index.as_ref().map_or(false, high_xmm),
+        _ => false
+    }
+}
+
+// gathers and scatters address their elements through a vector of indices
+fn vsib_operand(arg: &Arg) -> bool {
+    match *arg {
+        Arg::Indirect(MemoryRef {index: Some(ref index), ..}) => index.kind.family() == RegFamily::XMM,
+        _ => false
+    }
+}
+
+// vector registers that need the REX or EVEX extension bits, which only exist in 64-bit mode
+fn extended_vector_register(arg: &Arg) -> bool {
+    let extended = |reg: &Register| reg.kind.family() == RegFamily::XMM && reg.kind.encode() > 7;
+    match *arg {
+        Arg::Direct(ref reg) => extended(reg),
+        Arg::Indirect(MemoryRef {ref index, ..}) => index.as_ref().map_or(false, extended),
+        _ => false
+    }
+}
+
+fn validate_decorations(op: &Ident, fmt: &'static Opdata, args: &[Arg], decorations: &Decorations, op_size: Size) -> Result<(), Option<String>> {
+    let name = op.as_str();
+
+    if let Some(ref mask) = decorations.mask {
+        if mask.kind.family() != RegFamily::MASK {
+            return Err(Some(format!("'{}': the write mask has to be a mask register", name)));
+        } else if mask.kind == RegId::K0 {
+            return Err(Some(format!("'{}': k0 cannot be used as write mask", name)));
+        }
+    }
+    if decorations.zeroing {
+        if decorations.mask.is_none() {
+            return Err(Some(format!("'{}': zeroing requires a write mask", name)));
+        }
+        match args.first() {
+            Some(Arg::Indirect(_)) => return Err(Some(format!("'{}': zeroing is not possible with a memory destination", name))),
+            Some(Arg::Direct(reg)) if reg.kind.family() == RegFamily::MASK => return Err(Some(format!("'{}': zeroing is not possible with a mask destination", name))),
+            _ => ()
+        }
+    }
+    // the write mask tracks which elements a gather or scatter has yet to transfer
+    if args.iter().any(vsib_operand) {
+        if decorations.mask.is_none() {
+            return Err(Some(format!("'{}': gathers and scatters require a write mask", name)));
+        } else if decorations.zeroing {
+            return Err(Some(format!("'{}': zeroing is not possible with gathers and scatters", name)));
+        }
+    }
+    if let Some(rounding) = decorations.rounding {
+        let (allowed, what) = if rounding == Rounding::Sae { (EVEX_ER | EVEX_SAE, "suppressing exceptions") } else { (EVEX_ER, "embedded rounding") };
+        if (fmt.flags & allowed) == 0 {
+            return Err(Some(format!("'{}': does not support {}", name, what)));
+        } else if args.iter().any(|arg| matches!(*arg, Arg::Indirect(_))) {
+            return Err(Some(format!("'{}': {} is only possible with register operands", name, what)));
+        // both take the place of the vector length, so packed forms have to be 512 bits wide
+        } else if (fmt.flags & AUTO_VEXL) == AUTO_VEXL && op_size != Size::ZWORD {
+            return Err(Some(format!("'{}': {} requires 512-bit vectors", name, what)));
+        }
+    }
+    Ok(())
+}
+
+// an 8-bit displacement of an EVEX instruction is implicitly multiplied by the size of the memory access
+fn compress_disp8(disp: &ImmediateValue, scale: Size) -> Option<i8> {
+    let disp = match *disp {
+        ImmediateValue::I64(x) => x,
+        ImmediateValue::U64(x) => x as i64,
+    };
+    let scale = scale.in_bytes() as i64;
+    if disp % scale == 0 && disp / scale >= i8::MIN as i64 && disp / scale <= i8::MAX as i64 {
+        Some((disp / scale) as i8)
+    } else {
+        None
+    }
+}
+
 
 fn match_format_string(fmtstr: &'static [u8], mut args: &mut [Arg]) -> Result<(), &'static str> {
     if fmtstr.len() != args.len() * 2 {
@@ -691,7 +1092,8 @@ fn match_format_string(fmtstr: &'static [u8], mut args: &mut [Arg]) -> Result<()
     // r : legacy reg
     // f : fp reg
     // x : mmx reg
-    // y : xmm/ymm reg
+    // y : xmm/ymm/zmm reg
+    // j : mask reg
     // s : segment reg
     // c : control reg
     // d : debug reg
@@ -706,7 +1108,8 @@ fn match_format_string(fmtstr: &'static [u8], mut args: &mut [Arg]) -> Result<()
     // X: matches st0
 
     // b, w, d, q match a byte, word, doubleword and quadword.
-    // * matches all possible sizes for this operand (w/d for i/o, w/d/q for r/v, o/h for y/w and everything for m)
+    // o, h and z match an oword, hword and zword (xmm, ymm and zmm sized).
+    // * matches all possible sizes for this operand (w/d for i/o, w/d/q for r/v, o/h/z for y/w and everything for m)
     // ! matches a lack of size, only useful in combination of m and i
     // ? matches any size and doesn't participate in the operand size calculation
     {
@@ -718,6 +1121,7 @@ fn match_format_string(fmtstr: &'static [u8], mut args: &mut [Arg]) -> Result<()
                 // immediates
                 (b'i', &Arg::Immediate(_, size))  |
                 (b'o', &Arg::Immediate(_, size))  |
+                (b'i', &Arg::JumpTarget(_, size)) |
                 (b'o', &Arg::JumpTarget(_, size)) => size,
 
                 // specific legacy regs
@@ -761,6 +1165,8 @@ fn match_format_string(fmtstr: &'static [u8], mut args: &mut [Arg]) -> Result<()
                     reg.kind.family() == RegFamily::CONTROL => Some(reg.size()),
                 (b'd', &Arg::Direct(ref reg)) if
                     reg.kind.family() == RegFamily::DEBUG => Some(reg.size()),
+                (b'j', Arg::Direct(reg)) if
+                    reg.kind.family() == RegFamily::MASK => Some(reg.size()),
 
                 // memory offsets
                 (b'm',          &Arg::Indirect(MemoryRef {size, ref index, ..} )) |
@@ -790,12 +1196,13 @@ fn match_format_string(fmtstr: &'static [u8], mut args: &mut [Arg]) -> Result<()
                     (b'p', _)    => size == Size::PWORD,
                     (b'o', _)    => size == Size::OWORD,
                     (b'h', _)    => size == Size::HWORD,
+                    (b'z', _)    => size == Size::ZWORD,
                     (b'*', b'i') |
                     (b'*', b'o') => size == Size::WORD || size == Size::DWORD,
                     (b'*', b'k') |
                     (b'*', b'l') |
                     (b'*', b'y') |
-                    (b'*', b'w') => size == Size::OWORD || size == Size::HWORD,
+                    (b'*', b'w') => size == Size::OWORD || size == Size::HWORD || size == Size::ZWORD,
                     (b'*', b'r') |
                     (b'*', b'A' ... b'P') |
                     (b'*', b'v') => size == Size::WORD || size == Size::DWORD || size == Size::QWORD,
@@ -830,6 +1237,7 @@ fn match_format_string(fmtstr: &'static [u8], mut args: &mut [Arg]) -> Result<()
                     (b'p', _) => Some(Size::PWORD),
                     (b'o', _) => Some(Size::OWORD),
                     (b'h', _) => Some(Size::HWORD),
+                    (b'z', _) => Some(Size::ZWORD),
                     (b'*', _) |
                     (b'!', _) => None,
                     _ => unreachable!()
@@ -893,7 +1301,7 @@ fn get_legacy_prefixes(fmt: &'static Opdata, idents: Vec<Ident>) -> Result<(Opti
     Ok((group1, group2))
 }
 
-fn get_operand_size(fmt: &'static Opdata, args: &[Arg]) -> Result<Size, Option<String>> {
+fn get_operand_size(fmt: &'static Opdata, args: &[Arg], mode: Mode) -> Result<Size, Option<String>> {
     // determine operand size to automatically determine appropriate prefixes
     // ensures that all operands have the same size, and that the immediate size is smaller or equal.
 
@@ -960,8 +1368,8 @@ fn get_operand_size(fmt: &'static Opdata, args: &[Arg]) -> Result<Size, Option<S
             Err(Some("Unknown operand size".to_string()))
         }
     } else {
-        // largest usual immediate size is assumed
-        Ok(im_size.unwrap_or(Size::DWORD))
+        // largest usual immediate size is assumed, for stack operations that of the stack width
+        Ok(im_size.unwrap_or(if (fmt.flags & AUTO_NO32) == AUTO_NO32 { mode.address_size() } else { mode.immediate_size() }))
     }
 }
 
@@ -1044,7 +1452,7 @@ fn extract_args(fmt: &'static Opdata, args: Vec<Arg>) -> (Option<Arg>, Option<Ar
                 memarg = Some(regs.len());
                 regs.push(arg)
             },
-            b'f' | b'x' | b'r' | b'y' => regs.push(arg),
+            b'f' | b'x' | b'r' | b'y' | b'j' => regs.push(arg),
             b'c' | b'd' | b's'        => if regarg.is_some() {
                 panic!("multiple segment, debug or control registers in format string");
             } else {
@@ -1259,6 +1667,103 @@ rm: &Option<Arg>, map_sel: u8, rex_w: bool, vvvv: &Option<Arg>, vex_l: bool, pre
     }
 }
 
+// r/m field values for the 16-bit addressing forms
+const RM16_DISP: u8 = 0b110; // [disp16] at MOD_NODISP, [bp + disp] otherwise
+
+fn compile_modrm16(buffer: &mut StmtBuffer, reg_k: RegKind, mem: MemoryRef) {
+    let base  = mem.base.as_ref().map(|x| x.kind.clone());
+    let index = mem.index.as_ref().map(|x| x.kind.clone());
+
+    let rm = match (base, index) {
+        (Some(ref b), Some(ref i)) if *b == RegId::RBX && *i == RegId::RSI => 0b000,
+        (Some(ref b), Some(ref i)) if *b == RegId::RBX && *i == RegId::RDI => 0b001,
+        (Some(ref b), Some(ref i)) if *b == RegId::RBP && *i == RegId::RSI => 0b010,
+        (Some(_),     Some(_))                                             => 0b011,
+        (None,        Some(ref i)) if *i == RegId::RSI                     => 0b100,
+        (None,        Some(_))                                             => 0b101,
+        (Some(ref b), None)        if *b == RegId::RBP                     => RM16_DISP,
+        (Some(_),     None)                                                => 0b111,
+        (None,        None)                                                => RM16_DISP,
+    };
+
+    let no_base      = mem.base.is_none() && mem.index.is_none();
+    let bp_relative  = rm == RM16_DISP && !no_base;
+
+    // bp can only be encoded as sole base if a displacement is present
+    let mode = if no_base || (mem.disp.is_none() && !bp_relative) {
+        MOD_NODISP
+    } else if mem.disp.is_none() {
+        MOD_DISP8
+    } else {
+        MOD_DISP16
+    };
+    compile_modrm_sib(buffer, mode, reg_k, RegKind::from_number(rm));
+
+    if let Some(disp) = mem.disp {
+        buffer.push(Stmt::Var(disp, Size::WORD));
+    } else if no_base {
+        buffer.push(Stmt::Const(0));
+        buffer.push(Stmt::Const(0));
+    } else if bp_relative {
+        buffer.push(Stmt::Const(0));
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn compile_evex(buffer: &mut StmtBuffer, reg: &Option<Arg>, rm: &Option<Arg>, map_sel: u8, rex_w: bool,
+vvvv: &Option<Arg>, vector_len: u8, prefix: u8, decorations: &Decorations) {
+    let mut reg_k   = RegKind::from_number(0);
+    let mut index_k = RegKind::from_number(0);
+    let mut base_k  = RegKind::from_number(0);
+    let mut vvvv_k  = RegKind::from_number(0);
+    let mut vsib    = false;
+
+    if let Some(Arg::Direct(ref reg)) = *reg {
+        reg_k = reg.kind.clone();
+    }
+    // with a register as r/m, EVEX.X extends it to xmm16-31
+    if let Some(Arg::Direct(ref rm)) = *rm {
+        base_k = rm.kind.clone();
+        index_k = RegKind::from_number((rm.kind.encode() & 0x10) >> 1);
+    }
+    if let Some(Arg::Indirect(MemoryRef {ref base, ref index, ..} )) = *rm {
+        if let Some(ref base) = *base {
+            base_k = base.kind.clone();
+        }
+        if let Some(ref index) = *index {
+            index_k = index.kind.clone();
+            vsib = index.kind.family() == RegFamily::XMM;
+        }
+    }
+    if let Some(Arg::Direct(ref vvvv)) = *vvvv {
+        vvvv_k = vvvv.kind.clone();
+    }
+    // EVEX.V' extends either vvvv or a vsib index
+    let v_high = if vsib { index_k.encode() } else { vvvv_k.encode() } & 0x10;
+
+    let byte1 = (map_sel          & 0x07)      |
+                (!reg_k.encode()   & 0x10)      |
+                (!reg_k.encode()   & 8) << 4    |
+                (!index_k.encode() & 8) << 3    |
+                (!base_k.encode()  & 8) << 2    ;
+
+    let byte2 = (prefix           & 0x3)      |
+                0x04                          |
+                (rex_w            as u8) << 7 |
+                (!vvvv_k.encode() & 0xF) << 3 ;
+
+    let byte3 = decorations.mask.as_ref().map_or(0, |mask| mask.kind.encode() & 7) |
+                (!v_high & 0x10) >> 1                                            |
+                ((decorations.broadcast || decorations.rounding.is_some()) as u8) << 4 |
+                (vector_len & 3) << 5                                            |
+                (decorations.zeroing as u8) << 7                                 ;
+
+    buffer.push(Stmt::Const(0x62));
+    buffer.push(Stmt::Const(byte1));
+    buffer.push(Stmt::Const(byte2));
+    buffer.push(Stmt::Const(byte3));
+}
+
 fn compile_modrm_sib(buffer: &mut StmtBuffer, mode: u8, reg1: RegKind, reg2: RegKind) {
     let byte = mode                << 6 |
               (reg1.encode()  & 7) << 3 |
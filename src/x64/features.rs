@@ -0,0 +1,156 @@
+use std::ops::{BitOr, BitAnd, Not};
+
+// A set of CPUID features. Every instruction form knows the features it needs, an Assembler
+// refuses to encode instructions that need features outside of its target set. The baseline
+// (8086 to x86-64 including x87, cmov and cmpxchg8b) is not tracked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Features(pub u64);
+
+impl Features {
+    pub const NONE        : Features = Features(0);
+    pub const ALL         : Features = Features(!0);
+
+    pub const MMX         : Features = Features(1 << 0);
+    pub const SSE         : Features = Features(1 << 1);
+    pub const SSE2        : Features = Features(1 << 2);
+    pub const SSE3        : Features = Features(1 << 3);
+    pub const SSSE3       : Features = Features(1 << 4);
+    pub const SSE4_1      : Features = Features(1 << 5);
+    pub const SSE4_2      : Features = Features(1 << 6);
+    pub const SSE4A       : Features = Features(1 << 7);
+    pub const POPCNT      : Features = Features(1 << 8);
+    pub const LZCNT       : Features = Features(1 << 9);
+    pub const MOVBE       : Features = Features(1 << 10);
+    pub const CX16        : Features = Features(1 << 11);
+    pub const AES         : Features = Features(1 << 12);
+    pub const PCLMULQDQ   : Features = Features(1 << 13);
+    pub const SHA         : Features = Features(1 << 14);
+    pub const ADX         : Features = Features(1 << 15);
+    pub const RDRAND      : Features = Features(1 << 16);
+    pub const RDSEED      : Features = Features(1 << 17);
+    pub const BMI1        : Features = Features(1 << 18);
+    pub const BMI2        : Features = Features(1 << 19);
+    pub const TBM         : Features = Features(1 << 20);
+    pub const LWP         : Features = Features(1 << 21);
+    pub const AVX         : Features = Features(1 << 22);
+    pub const AVX2        : Features = Features(1 << 23);
+    pub const F16C        : Features = Features(1 << 24);
+    pub const FMA         : Features = Features(1 << 25);
+    pub const FMA4        : Features = Features(1 << 26);
+    pub const XOP         : Features = Features(1 << 27);
+    pub const AVX512F     : Features = Features(1 << 28);
+    pub const AVX512BW    : Features = Features(1 << 29);
+    pub const AVX512DQ    : Features = Features(1 << 30);
+    pub const AVX512VL    : Features = Features(1 << 31);
+    pub const VAES        : Features = Features(1 << 32);
+    pub const VPCLMULQDQ  : Features = Features(1 << 33);
+    pub const GFNI        : Features = Features(1 << 34);
+    pub const CLFLUSHOPT  : Features = Features(1 << 35);
+    pub const CLWB        : Features = Features(1 << 36);
+    pub const RTM         : Features = Features(1 << 37);
+    pub const SHSTK       : Features = Features(1 << 38);
+    pub const FSGSBASE    : Features = Features(1 << 39);
+    pub const XSAVE       : Features = Features(1 << 40);
+    pub const XSAVEOPT    : Features = Features(1 << 41);
+    pub const MONITORX    : Features = Features(1 << 42);
+    pub const RDTSCP      : Features = Features(1 << 43);
+    pub const SVM         : Features = Features(1 << 44);
+    pub const PRFCHW      : Features = Features(1 << 45);
+
+    // the x86-64 psABI microarchitecture levels
+    pub const X86_64      : Features = Features(Features::MMX.0 | Features::SSE.0 | Features::SSE2.0);
+    pub const X86_64_V2   : Features = Features(Features::X86_64.0 | Features::CX16.0 | Features::POPCNT.0 |
+                                                Features::SSE3.0 | Features::SSSE3.0 | Features::SSE4_1.0 | Features::SSE4_2.0);
+    pub const X86_64_V3   : Features = Features(Features::X86_64_V2.0 | Features::AVX.0 | Features::AVX2.0 | Features::BMI1.0 |
+                                                Features::BMI2.0 | Features::F16C.0 | Features::FMA.0 | Features::LZCNT.0 |
+                                                Features::MOVBE.0 | Features::XSAVE.0);
+    pub const X86_64_V4   : Features = Features(Features::X86_64_V3.0 | Features::AVX512F.0 | Features::AVX512BW.0 |
+                                                Features::AVX512DQ.0 | Features::AVX512VL.0);
+
+    pub const SKYLAKE     : Features = Features(Features::X86_64_V3.0 | Features::AES.0 | Features::PCLMULQDQ.0 |
+                                                Features::ADX.0 | Features::RDRAND.0 | Features::RDSEED.0 |
+                                                Features::CLFLUSHOPT.0 | Features::FSGSBASE.0 | Features::XSAVEOPT.0 |
+                                                Features::RDTSCP.0 | Features::PRFCHW.0);
+
+    pub fn contains(self, other: Features) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn names(self) -> Vec<&'static str> {
+        FEATURE_NAMES.iter().filter(|&&(feature, _)| self.contains(feature)).map(|&(_, name)| name).collect()
+    }
+}
+
+impl BitOr for Features {
+    type Output = Features;
+    fn bitor(self, other: Features) -> Features {
+        Features(self.0 | other.0)
+    }
+}
+
+impl BitAnd for Features {
+    type Output = Features;
+    fn bitand(self, other: Features) -> Features {
+        Features(self.0 & other.0)
+    }
+}
+
+impl Not for Features {
+    type Output = Features;
+    fn not(self) -> Features {
+        Features(!self.0)
+    }
+}
+
+const FEATURE_NAMES: &[(Features, &str)] = &[
+    (Features::MMX, "MMX"),
+    (Features::SSE, "SSE"),
+    (Features::SSE2, "SSE2"),
+    (Features::SSE3, "SSE3"),
+    (Features::SSSE3, "SSSE3"),
+    (Features::SSE4_1, "SSE4.1"),
+    (Features::SSE4_2, "SSE4.2"),
+    (Features::SSE4A, "SSE4A"),
+    (Features::POPCNT, "POPCNT"),
+    (Features::LZCNT, "LZCNT"),
+    (Features::MOVBE, "MOVBE"),
+    (Features::CX16, "CX16"),
+    (Features::AES, "AES"),
+    (Features::PCLMULQDQ, "PCLMULQDQ"),
+    (Features::SHA, "SHA"),
+    (Features::ADX, "ADX"),
+    (Features::RDRAND, "RDRAND"),
+    (Features::RDSEED, "RDSEED"),
+    (Features::BMI1, "BMI1"),
+    (Features::BMI2, "BMI2"),
+    (Features::TBM, "TBM"),
+    (Features::LWP, "LWP"),
+    (Features::AVX, "AVX"),
+    (Features::AVX2, "AVX2"),
+    (Features::F16C, "F16C"),
+    (Features::FMA, "FMA"),
+    (Features::FMA4, "FMA4"),
+    (Features::XOP, "XOP"),
+    (Features::AVX512F, "AVX512F"),
+    (Features::AVX512BW, "AVX512BW"),
+    (Features::AVX512DQ, "AVX512DQ"),
+    (Features::AVX512VL, "AVX512VL"),
+    (Features::VAES, "VAES"),
+    (Features::VPCLMULQDQ, "VPCLMULQDQ"),
+    (Features::GFNI, "GFNI"),
+    (Features::CLFLUSHOPT, "CLFLUSHOPT"),
+    (Features::CLWB, "CLWB"),
+    (Features::RTM, "RTM"),
+    (Features::SHSTK, "SHSTK"),
+    (Features::FSGSBASE, "FSGSBASE"),
+    (Features::XSAVE, "XSAVE"),
+    (Features::XSAVEOPT, "XSAVEOPT"),
+    (Features::MONITORX, "MONITORX"),
+    (Features::RDTSCP, "RDTSCP"),
+    (Features::SVM, "SVM"),
+    (Features::PRFCHW, "PRFCHW"),
+];
@@ -0,0 +1,343 @@
+use x64::assembler::Assembler;
+use x64::regs::{QWord, OWord};
+use x64::parser::Arg;
+
+// The two calling conventions of x86-64
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallingConvention {
+    SysV,  // System V AMD64 ABI: Linux, the BSDs, macOS
+    Win64, // Microsoft x64
+}
+
+impl CallingConvention {
+    pub fn integer_arguments(self) -> &'static [QWord] {
+        match self {
+            CallingConvention::SysV  => &[QWord::Rdi, QWord::Rsi, QWord::Rdx, QWord::Rcx, QWord::R8, QWord::R9],
+            CallingConvention::Win64 => &[QWord::Rcx, QWord::Rdx, QWord::R8, QWord::R9],
+        }
+    }
+
+    pub fn float_arguments(self) -> &'static [OWord] {
+        match self {
+            CallingConvention::SysV  => &[OWord::Xmm0, OWord::Xmm1, OWord::Xmm2, OWord::Xmm3, OWord::Xmm4, OWord::Xmm5, OWord::Xmm6, OWord::Xmm7],
+            CallingConvention::Win64 => &[OWord::Xmm0, OWord::Xmm1, OWord::Xmm2, OWord::Xmm3],
+        }
+    }
+
+    pub fn callee_saved(self) -> &'static [QWord] {
+        match self {
+            CallingConvention::SysV  => &[QWord::Rbx, QWord::Rbp, QWord::R12, QWord::R13, QWord::R14, QWord::R15],
+            CallingConvention::Win64 => &[QWord::Rbx, QWord::Rbp, QWord::Rdi, QWord::Rsi, QWord::R12, QWord::R13, QWord::R14, QWord::R15],
+        }
+    }
+
+    pub fn callee_saved_xmm(self) -> &'static [OWord] {
+        match self {
+            CallingConvention::SysV  => &[],
+            CallingConvention::Win64 => &[OWord::Xmm6, OWord::Xmm7, OWord::Xmm8, OWord::Xmm9, OWord::Xmm10, OWord::Xmm11,
+                                          OWord::Xmm12, OWord::Xmm13, OWord::Xmm14, OWord::Xmm15],
+        }
+    }
+
+    // space the caller reserves above the return address for the register arguments
+    pub fn shadow_space(self) -> u32 {
+        match self {
+            CallingConvention::SysV  => 0,
+            CallingConvention::Win64 => 32,
+        }
+    }
+}
+
+// Declares the stack frame of a function and generates its prologue and epilogues, along with the
+// call frame information (SysV) or unwind codes (Win64) describing them.
+//
+// The frame, from the return address down:
+//   return address
+//   saved rbp (with a frame pointer)
+//   pushed callee-saved registers
+//   padding that keeps rsp 16-byte aligned
+//   saved xmm registers
+//   locals
+//   outgoing stack arguments, above the shadow space for Win64   <- rsp
+pub struct FunctionBuilder {
+    convention: CallingConvention,
+    frame_pointer: bool,
+    saved: Vec<QWord>,
+    saved_xmm: Vec<OWord>,
+    locals: u32,
+    outgoing: Option<u32>,
+}
+
+impl FunctionBuilder {
+    pub fn new(convention: CallingConvention) -> FunctionBuilder {
+        FunctionBuilder {
+            convention: convention,
+            frame_pointer: false,
+            saved: Vec::new(),
+            saved_xmm: Vec::new(),
+            locals: 0,
+            outgoing: None,
+        }
+    }
+
+    // keep rbp pointing into the frame, so rsp can move while the function runs
+    pub fn frame_pointer(mut self) -> FunctionBuilder {
+        self.frame_pointer = true;
+        self
+    }
+
+    pub fn save(mut self, reg: QWord) -> FunctionBuilder {
+        if reg == QWord::Rsp || (reg == QWord::Rbp && self.frame_pointer) {
+            panic!("{:?} cannot be saved by the function body", reg);
+        }
+        if !self.saved.contains(&reg) {
+            self.saved.push(reg);
+        }
+        self
+    }
+
+    pub fn save_xmm(mut self, reg: OWord) -> FunctionBuilder {
+        if !self.saved_xmm.contains(&reg) {
+            self.saved_xmm.push(reg);
+        }
+        self
+    }
+
+    // saves every callee-saved register of the calling convention
+    pub fn save_all(mut self) -> FunctionBuilder {
+        for &reg in self.convention.callee_saved() {
+            if reg != QWord::Rbp || !self.frame_pointer {
+                self = self.save(reg);
+            }
+        }
+        for &reg in self.convention.callee_saved_xmm() {
+            self = self.save_xmm(reg);
+        }
+        self
+    }
+
+    pub fn locals(mut self, size: u32) -> FunctionBuilder {
+        self.locals = size;
+        self
+    }
+
+    // the function calls other functions, passing up to `stack_arguments` bytes on the stack
+    pub fn calls(mut self, stack_arguments: u32) -> FunctionBuilder {
+        self.outgoing = Some(stack_arguments);
+        self
+    }
+
+    pub fn prologue(self, x: &mut Assembler) -> Frame {
+        let shadow = if self.outgoing.is_some() { self.convention.shadow_space() } else { 0 };
+        let locals_offset = align16(self.outgoing.unwrap_or(0) + shadow);
+        let xmm_offset = locals_offset + align16(self.locals);
+        let mut allocation = xmm_offset + 16 * self.saved_xmm.len() as u32;
+        let pushed = self.saved.len() as u32 + if self.frame_pointer { 1 } else { 0 };
+        if (pushed * 8 + 8 + allocation) % 16 != 0 {
+            allocation += 8;
+        }
+
+        let frame = Frame {
+            convention: self.convention,
+            frame_pointer: self.frame_pointer,
+            saved: self.saved,
+            saved_xmm: self.saved_xmm,
+            locals_offset: locals_offset,
+            xmm_offset: xmm_offset,
+            allocation: allocation,
+        };
+        match frame.convention {
+            CallingConvention::SysV => frame.sysv_prologue(x),
+            CallingConvention::Win64 => frame.win64_prologue(x),
+        }
+        frame
+    }
+}
+
+// The layout of a function's stack frame, produced by FunctionBuilder::prologue
+pub struct Frame {
+    convention: CallingConvention,
+    frame_pointer: bool,
+    saved: Vec<QWord>,
+    saved_xmm: Vec<OWord>,
+    locals_offset: u32, // all offsets are relative to rsp after the prologue
+    xmm_offset: u32,
+    allocation: u32,
+}
+
+impl Frame {
+    // bytes between rsp after the prologue and the return address
+    pub fn size(&self) -> u32 {
+        self.allocation + 8 * (self.saved.len() as u32 + if self.frame_pointer { 1 } else { 0 })
+    }
+
+    // a location among the locals
+    pub fn local(&self, offset: i32) -> Arg {
+        self.frame_slot(self.locals_offset as i32 + offset)
+    }
+
+    // the index-th argument passed on the stack by the caller (not counting register arguments)
+    pub fn stack_argument(&self, index: u32) -> Arg {
+        self.frame_slot((self.size() + 8 + self.convention.shadow_space() + 8 * index) as i32)
+    }
+
+    // where to store the index-th stack argument of a call made by this function
+    pub fn outgoing_argument(&self, index: u32) -> Arg {
+        QWord::Rsp.value_at_offset((self.convention.shadow_space() + 8 * index) as i32)
+    }
+
+    // Restores the saved registers and returns. Can be emitted any number of times.
+    pub fn epilogue(&self, x: &mut Assembler) {
+        match self.convention {
+            CallingConvention::SysV => self.sysv_epilogue(x),
+            CallingConvention::Win64 => self.win64_epilogue(x),
+        }
+    }
+
+    // closes the call frame information or unwind codes after the last epilogue, followed by
+    // the function's constant pool
+    pub fn end(&self, x: &mut Assembler) {
+        match self.convention {
+            CallingConvention::SysV => x.cfi_endproc(),
+            CallingConvention::Win64 => x.seh_endproc(),
+        }
+        x.flush_const_pool();
+    }
+
+    // rsp relative offset of rbp
+    fn frame_pointer_offset(&self) -> u32 {
+        match self.convention {
+            CallingConvention::SysV => self.allocation + 8 * self.saved.len() as u32,
+            CallingConvention::Win64 => 0,
+        }
+    }
+
+    // slots are addressed through rbp when there is a frame pointer
+    fn frame_slot(&self, offset: i32) -> Arg {
+        if self.frame_pointer {
+            QWord::Rbp.value_at_offset(offset - self.frame_pointer_offset() as i32)
+        } else {
+            QWord::Rsp.value_at_offset(offset)
+        }
+    }
+
+    // the CFA is rsp before the call pushed the return address
+    fn cfa_offset(&self, offset: u32) -> i64 {
+        offset as i64 - (self.size() + 8) as i64
+    }
+
+    fn sysv_prologue(&self, x: &mut Assembler) {
+        x.cfi_startproc();
+        if self.frame_pointer {
+            x.push(QWord::Rbp);
+            x.cfi_adjust_cfa_offset(8);
+            x.cfi_offset(QWord::Rbp, -16);
+            x.mov(QWord::Rbp, QWord::Rsp);
+            x.cfi_def_cfa_register(QWord::Rbp);
+        }
+        let pushed_before = if self.frame_pointer { 1 } else { 0 };
+        for (i, &reg) in self.saved.iter().enumerate() {
+            x.push(reg);
+            if !self.frame_pointer {
+                x.cfi_adjust_cfa_offset(8);
+            }
+            x.cfi_offset(reg, -8 * (i as i64 + pushed_before + 2));
+        }
+        if self.allocation != 0 {
+            x.sub(QWord::Rsp, self.allocation as i32);
+            if !self.frame_pointer {
+                x.cfi_adjust_cfa_offset(self.allocation as i64);
+            }
+        }
+        for (i, &reg) in self.saved_xmm.iter().enumerate() {
+            let offset = self.xmm_offset + 16 * i as u32;
+            x.movaps(QWord::Rsp.value_at_offset(offset as i32), reg);
+            x.cfi_offset(reg, self.cfa_offset(offset));
+        }
+    }
+
+    fn sysv_epilogue(&self, x: &mut Assembler) {
+        // the code after this epilogue still runs with the full frame
+        x.cfi_remember_state();
+        for (i, &reg) in self.saved_xmm.iter().enumerate() {
+            let offset = self.xmm_offset + 16 * i as u32;
+            x.movaps(reg, self.frame_slot(offset as i32));
+            x.cfi_restore(reg);
+        }
+        if self.frame_pointer {
+            if self.saved.is_empty() {
+                x.mov(QWord::Rsp, QWord::Rbp);
+            } else {
+                x.lea(QWord::Rsp, QWord::Rbp.value_at_offset(-8 * self.saved.len() as i32));
+            }
+        } else if self.allocation != 0 {
+            x.add(QWord::Rsp, self.allocation as i32);
+            x.cfi_adjust_cfa_offset(-(self.allocation as i64));
+        }
+        for &reg in self.saved.iter().rev() {
+            x.pop(reg);
+            if !self.frame_pointer {
+                x.cfi_adjust_cfa_offset(-8);
+            }
+            x.cfi_restore(reg);
+        }
+        if self.frame_pointer {
+            x.pop(QWord::Rbp);
+            x.cfi_def_cfa(QWord::Rsp, 8);
+            x.cfi_restore(QWord::Rbp);
+        }
+        x.ret(None::<i32>);
+        x.cfi_restore_state();
+    }
+
+    fn win64_prologue(&self, x: &mut Assembler) {
+        x.seh_proc();
+        if self.frame_pointer {
+            x.push(QWord::Rbp);
+            x.seh_pushreg(QWord::Rbp);
+        }
+        for &reg in &self.saved {
+            x.push(reg);
+            x.seh_pushreg(reg);
+        }
+        if self.allocation != 0 {
+            x.sub(QWord::Rsp, self.allocation as i32);
+            x.seh_stackalloc(self.allocation);
+        }
+        for (i, &reg) in self.saved_xmm.iter().enumerate() {
+            let offset = self.xmm_offset + 16 * i as u32;
+            x.movaps(QWord::Rsp.value_at_offset(offset as i32), reg);
+            x.seh_savexmm(reg, offset);
+        }
+        if self.frame_pointer {
+            x.lea(QWord::Rbp, QWord::Rsp.value_at());
+            x.seh_setframe(QWord::Rbp, 0);
+        }
+        x.seh_endprologue();
+    }
+
+    // the unwinder recognizes epilogues by their shape: an add to rsp or an lea of rsp from the
+    // frame register, pops of the saved registers and the return
+    fn win64_epilogue(&self, x: &mut Assembler) {
+        for (i, &reg) in self.saved_xmm.iter().enumerate() {
+            let offset = self.xmm_offset + 16 * i as u32;
+            x.movaps(reg, self.frame_slot(offset as i32));
+        }
+        if self.frame_pointer {
+            x.lea(QWord::Rsp, QWord::Rbp.value_at_offset(self.allocation as i32));
+        } else if self.allocation != 0 {
+            x.add(QWord::Rsp, self.allocation as i32);
+        }
+        for &reg in self.saved.iter().rev() {
+            x.pop(reg);
+        }
+        if self.frame_pointer {
+            x.pop(QWord::Rbp);
+        }
+        x.ret(None::<i32>);
+    }
+}
+
+fn align16(x: u32) -> u32 {
+    (x + 15) & !15
+}
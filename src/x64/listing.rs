@@ -0,0 +1,150 @@
+// Intel syntax text of the instructions given to an Assembler, for Assembler::listing. Operands
+// are shown as they were passed, before encoding: labels by name and symbols with their suffix.
+
+use x64::parser::{Ident, Arg, MemoryRef, Register, RegKind, RegFamily, ImmediateValue, JumpType, JumpTarget, Size};
+use x64::compiler::{Decorations, Rounding};
+use object_file::SymbolReferenceKind;
+
+const SEGMENTS: [&str; 6] = ["es", "cs", "ss", "ds", "fs", "gs"];
+
+pub fn instruction_text<F: Fn(JumpTarget) -> String>(name: &str, prefixes: &[Ident], args: &[Arg], decorations: &Decorations, label: F) -> String {
+    // a segment override goes on the memory operand
+    let segment = prefixes.iter().find(|prefix| SEGMENTS.contains(&&prefix[..]));
+    let mut text = String::new();
+    for prefix in prefixes.iter().filter(|prefix| Some(*prefix) != segment) {
+        text.push_str(prefix);
+        text.push(' ');
+    }
+    text.push_str(name);
+
+    let mut operands: Vec<String> = args.iter().map(|arg| operand_text(arg, segment, decorations.broadcast, &label)).collect();
+    if let Some(first) = operands.first_mut() {
+        if let Some(ref mask) = decorations.mask {
+            first.push_str(&format!(" {{{}}}", register_name(mask)));
+        }
+        if decorations.zeroing {
+            first.push_str("{z}");
+        }
+    }
+    if let Some(rounding) = decorations.rounding {
+        operands.push(rounding_text(rounding).to_string());
+    }
+    if !operands.is_empty() {
+        text.push(' ');
+        text.push_str(&operands.join(", "));
+    }
+    text
+}
+
+fn operand_text<F: Fn(JumpTarget) -> String>(arg: &Arg, segment: Option<&Ident>, broadcast: bool, label: &F) -> String {
+    let segment = segment.map_or(String::new(), |segment| format!("{}:", segment));
+    match *arg {
+        Arg::Direct(ref reg) => register_name(reg),
+        Arg::Immediate(ref value, _) => immediate_text(value),
+        Arg::JumpTarget(ref target, _) => jump_text(target, label),
+        // the offset from the thread pointer is the only one that is not rip-relative
+        Arg::IndirectJumpTarget(ref target @ JumpType::Global(_, Some(SymbolReferenceKind::TpOff)), size) =>
+            format!("{}{}[{}]", size_text(size), segment, jump_text(target, label)),
+        Arg::IndirectJumpTarget(ref target, size) =>
+            format!("{}{}[rip + {}]", size_text(size), segment, jump_text(target, label)),
+        Arg::Indirect(ref memory) =>
+            format!("{}{}[{}]{}", size_text(memory.size), segment, address_text(memory), if broadcast { "{bcst}" } else { "" }),
+        Arg::Invalid => "?".to_string(),
+    }
+}
+
+fn address_text(memory: &MemoryRef) -> String {
+    let mut terms = Vec::new();
+    if let Some(ref base) = memory.base {
+        terms.push(register_name(base));
+    }
+    if let Some(ref index) = memory.index {
+        terms.push(if memory.scale > 1 { format!("{}*{}", register_name(index), memory.scale) } else { register_name(index) });
+    }
+    let mut text = terms.join(" + ");
+    match memory.disp {
+        Some(ImmediateValue::I64(disp)) if disp < 0 && !text.is_empty() => text.push_str(&format!(" - {:#x}", (disp as u64).wrapping_neg())),
+        Some(ImmediateValue::I64(0)) | Some(ImmediateValue::U64(0)) if !text.is_empty() => (),
+        Some(ref disp) if !text.is_empty() => text.push_str(&format!(" + {}", immediate_text(disp))),
+        Some(ref disp) => text.push_str(&immediate_text(disp)),
+        None => (),
+    }
+    text
+}
+
+fn jump_text<F: Fn(JumpTarget) -> String>(target: &JumpType, label: &F) -> String {
+    match *target {
+        JumpType::Global(ref name, kind) => format!("{}{}", name, match kind {
+            None | Some(SymbolReferenceKind::Relative) | Some(SymbolReferenceKind::Absolute) => "",
+            Some(SymbolReferenceKind::Branch) => "@PLT",
+            Some(SymbolReferenceKind::Got) => "@GOTPCREL",
+            Some(SymbolReferenceKind::TpOff) => "@TPOFF",
+            Some(SymbolReferenceKind::GotTpOff) => "@GOTTPOFF",
+        }),
+        JumpType::Forward(target) | JumpType::Backward(target) => label(target),
+    }
+}
+
+fn immediate_text(value: &ImmediateValue) -> String {
+    match *value {
+        ImmediateValue::I64(x) if x < 0 => format!("-{:#x}", (x as u64).wrapping_neg()),
+        ImmediateValue::I64(x) => format!("{:#x}", x),
+        ImmediateValue::U64(x) => format!("{:#x}", x),
+    }
+}
+
+fn size_text(size: Option<Size>) -> &'static str {
+    match size {
+        None => "",
+        Some(Size::BYTE) => "byte ptr ",
+        Some(Size::WORD) => "word ptr ",
+        Some(Size::DWORD) => "dword ptr ",
+        Some(Size::QWORD) => "qword ptr ",
+        Some(Size::PWORD) => "tbyte ptr ",
+        Some(Size::OWORD) => "xmmword ptr ",
+        Some(Size::HWORD) => "ymmword ptr ",
+        Some(Size::ZWORD) => "zmmword ptr ",
+    }
+}
+
+fn rounding_text(rounding: Rounding) -> &'static str {
+    match rounding {
+        Rounding::RnSae => "{rn-sae}",
+        Rounding::RdSae => "{rd-sae}",
+        Rounding::RuSae => "{ru-sae}",
+        Rounding::RzSae => "{rz-sae}",
+        Rounding::Sae => "{sae}",
+    }
+}
+
+pub fn register_name(reg: &Register) -> String {
+    let RegKind::Static(id) = reg.kind;
+    let code = id.code() as usize;
+    match id.family() {
+        RegFamily::LEGACY if code < 8 => match reg.size {
+            Size::BYTE => ["al", "cl", "dl", "bl", "spl", "bpl", "sil", "dil"][code].to_string(),
+            Size::WORD => ["ax", "cx", "dx", "bx", "sp", "bp", "si", "di"][code].to_string(),
+            Size::DWORD => ["eax", "ecx", "edx", "ebx", "esp", "ebp", "esi", "edi"][code].to_string(),
+            _ => ["rax", "rcx", "rdx", "rbx", "rsp", "rbp", "rsi", "rdi"][code].to_string(),
+        },
+        RegFamily::LEGACY => format!("r{}{}", code, match reg.size {
+            Size::BYTE => "b",
+            Size::WORD => "w",
+            Size::DWORD => "d",
+            _ => "",
+        }),
+        RegFamily::RIP => "rip".to_string(),
+        RegFamily::HIGHBYTE => ["ah", "ch", "dh", "bh"][code & 3].to_string(),
+        RegFamily::FP => format!("st{}", code),
+        RegFamily::MMX => format!("mm{}", code),
+        RegFamily::XMM => format!("{}{}", match reg.size {
+            Size::HWORD => "ymm",
+            Size::ZWORD => "zmm",
+            _ => "xmm",
+        }, code),
+        RegFamily::SEGMENT => SEGMENTS[code].to_string(),
+        RegFamily::CONTROL => format!("cr{}", code),
+        RegFamily::DEBUG => format!("dr{}", code),
+        RegFamily::MASK => format!("k{}", code),
+    }
+}
@@ -4,6 +4,13 @@ pub mod parser;
 mod x64data;
 mod compiler;
 mod regs;
+mod features;
+mod function;
+mod listing;
 
 pub use x64::assembler::{Assembler, FlaggedAssembler}; 
-pub use x64::regs::{Byte, DWord, QWord, OWord, HWord, rip_relative, rip_nonrelative};
+pub use x64::compiler::{Mode, Rounding};
+pub use x64::features::Features;
+pub use x64::function::{CallingConvention, FunctionBuilder, Frame};
+pub use x64::regs::{Byte, Word, DWord, QWord, OWord, HWord, ZWord, Mask, rip_relative, rip_nonrelative, extern_symbol, extern_rip_relative, extern_got,
+                        extern_plt, extern_tpoff, extern_gottpoff};
@@ -1,5 +1,7 @@
 
 use std::cmp::PartialEq;
+use std::fmt;
+use object_file::SymbolReferenceKind;
 
 pub type Ident = String;
 
@@ -65,14 +67,26 @@ pub enum LabelType {
     //Dynamic(P<ast::Expr>), // => expr :
 }
 
-pub type JumpTarget = u64;
+// A label of one Assembler. Labels are numbered from 0 in the order they are allocated, and carry
+// the id of their assembler so that using one with another assembler is caught.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct JumpTarget {
+    pub assembler: u32,
+    pub index: u32,
+}
+
+impl fmt::Display for JumpTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "label {}", self.index)
+    }
+}
 
 #[derive(Debug, Clone)]
 pub enum JumpType {
     // note: these symbol choices try to avoid stuff that is a valid starting symbol for parse_expr
     // in order to allow the full range of expressions to be used. the only currently existing ambiguity is
     // with the symbol <, as this symbol is also the starting symbol for the universal calling syntax <Type as Trait>.method(args)
-    Global(Ident),         // -> label
+    Global(Ident, Option<SymbolReferenceKind>), // -> label; without a kind, the operand decides how the symbol is referenced
     Backward(JumpTarget),       //  > label
     Forward(JumpTarget),        //  < label
     //Dynamic(P<ast::Expr>), // => expr
@@ -139,6 +153,16 @@ pub enum RegId {
     DR4  = 0x84, DR5  = 0x85, DR6  = 0x86, DR7  = 0x87,
     DR8  = 0x88, DR9  = 0x89, DR10 = 0x8A, DR11 = 0x8B,
     DR12 = 0x8C, DR13 = 0x8D, DR14 = 0x8E, DR15 = 0x8F,
+
+    // size: 16, 32 or 64 bytes. only encodable with EVEX
+    XMM16 = 0x90, XMM17 = 0x91, XMM18 = 0x92, XMM19 = 0x93,
+    XMM20 = 0x94, XMM21 = 0x95, XMM22 = 0x96, XMM23 = 0x97,
+    XMM24 = 0x98, XMM25 = 0x99, XMM26 = 0x9A, XMM27 = 0x9B,
+    XMM28 = 0x9C, XMM29 = 0x9D, XMM30 = 0x9E, XMM31 = 0x9F,
+
+    // size: 8 bytes. AVX-512 opmask registers
+    K0 = 0xA0, K1 = 0xA1, K2 = 0xA2, K3 = 0xA3,
+    K4 = 0xA4, K5 = 0xA5, K6 = 0xA6, K7 = 0xA7,
 }
 
 #[derive(Debug, PartialOrd, PartialEq, Ord, Eq, Hash, Clone, Copy)]
@@ -152,6 +176,7 @@ pub enum RegFamily {
     SEGMENT = 6,
     CONTROL = 7,
     DEBUG = 8,
+    MASK = 9,
 }
 
 #[derive(Debug, PartialOrd, PartialEq, Ord, Eq, Hash, Clone, Copy)]
@@ -162,7 +187,8 @@ pub enum Size {
     QWORD = 8,
     PWORD = 10,
     OWORD = 16,
-    HWORD = 32
+    HWORD = 32,
+    ZWORD = 64
 }
 
 /*
@@ -256,7 +282,10 @@ impl PartialEq<RegId> for Option<RegKind> {
 
 impl RegId {
     pub fn code(&self) -> u8 {
-        *self as u8 & 0xF
+        match *self as u8 >> 4 {
+            9 => (*self as u8 & 0xF) + 16, // xmm16-31
+            _ => *self as u8 & 0xF
+        }
     }
 
     pub fn family(&self) -> RegFamily {
@@ -270,10 +299,33 @@ impl RegId {
             6 => RegFamily::SEGMENT,
             7 => RegFamily::CONTROL,
             8 => RegFamily::DEBUG,
+            9 => RegFamily::XMM,
+            10 => RegFamily::MASK,
             _ => unreachable!()
         }
     }
 
+    // register numbers used by DWARF call frame information on x86-64
+    pub fn dwarf_number(&self) -> Option<u8> {
+        match self.family() {
+            RegFamily::LEGACY => Some([0, 2, 1, 3, 7, 6, 4, 5, 8, 9, 10, 11, 12, 13, 14, 15][self.code() as usize]),
+            RegFamily::XMM if self.code() < 16 => Some(17 + self.code()),
+            RegFamily::XMM => Some(51 + self.code()),
+            RegFamily::MASK => Some(118 + self.code()),
+            _ => None
+        }
+    }
+
+    // the i386 psABI numbers registers by their encoding
+    pub fn dwarf_number_i386(&self) -> Option<u8> {
+        match self.family() {
+            RegFamily::LEGACY if self.code() < 8 => Some(self.code()),
+            RegFamily::XMM if self.code() < 8 => Some(21 + self.code()),
+            RegFamily::MASK => Some(93 + self.code()),
+            _ => None
+        }
+    }
+
     pub fn from_number(id: u8) -> RegId {
         match id {
             0  => RegId::RAX,
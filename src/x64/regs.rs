@@ -1,4 +1,5 @@
 use x64::parser::{Register, MemoryRef, ImmediateValue, Size, RegId, RegKind, Arg, JumpType, JumpTarget};
+use object_file::SymbolReferenceKind;
 
 macro_rules! reg_enum {
     ( $name:ident: $size:ident = [
@@ -68,6 +69,28 @@ reg_enum! {
     ]
 }
 
+reg_enum! {
+    Word: WORD = [
+        Ax => RAX;
+        Cx => RCX;
+        Dx => RDX;
+        Bx => RBX;
+        Sp => RSP;
+        Bp => RBP;
+        Si => RSI;
+        Di => RDI;
+        
+        R8w => R8;
+        R9w => R9;
+        R10w => R10;
+        R11w => R11;
+        R12w => R12;
+        R13w => R13;
+        R14w => R14;
+        R15w => R15;
+    ]
+}
+
 reg_enum! {
     DWord: DWORD = [
         Eax => RAX;
@@ -128,26 +151,33 @@ reg_enum! {
     ]
 }
 
-impl QWord {
-    pub fn value_at(self) -> Arg {
-        self.value_at_offset(0)
-    }
-    
-    pub fn value_at_offset(self, offset: i32) -> Arg {
-        Arg::Indirect(MemoryRef{
-            index: None,
-            scale: 0,
-            base: Some(self.into()),
-            disp: if offset != 0 {
-                    Some(ImmediateValue::I64(offset as i64))
-                } else {
-                    None
-                },
-            size: None,
-        })
-    }
+// memory operands based on a general purpose register. the register size selects the address size.
+macro_rules! value_at {
+    ($($name:ident),*) => {$(
+        impl $name {
+            pub fn value_at(self) -> Arg {
+                self.value_at_offset(0)
+            }
+            
+            pub fn value_at_offset(self, offset: i32) -> Arg {
+                Arg::Indirect(MemoryRef{
+                    index: None,
+                    scale: 0,
+                    base: Some(self.into()),
+                    disp: if offset != 0 {
+                            Some(ImmediateValue::I64(offset as i64))
+                        } else {
+                            None
+                        },
+                    size: None,
+                })
+            }
+        }
+    )*}
 }
 
+value_at!(Word, DWord, QWord);
+
 reg_enum! {
     OWord: OWORD = [
         Xmm0 => XMM0;
@@ -166,6 +196,22 @@ reg_enum! {
         Xmm13 => XMM13;
         Xmm14 => XMM14;
         Xmm15 => XMM15;
+        Xmm16 => XMM16;
+        Xmm17 => XMM17;
+        Xmm18 => XMM18;
+        Xmm19 => XMM19;
+        Xmm20 => XMM20;
+        Xmm21 => XMM21;
+        Xmm22 => XMM22;
+        Xmm23 => XMM23;
+        Xmm24 => XMM24;
+        Xmm25 => XMM25;
+        Xmm26 => XMM26;
+        Xmm27 => XMM27;
+        Xmm28 => XMM28;
+        Xmm29 => XMM29;
+        Xmm30 => XMM30;
+        Xmm31 => XMM31;
     ]
 }
 
@@ -187,6 +233,72 @@ reg_enum! {
         Ymm13 => XMM13;
         Ymm14 => XMM14;
         Ymm15 => XMM15;
+        Ymm16 => XMM16;
+        Ymm17 => XMM17;
+        Ymm18 => XMM18;
+        Ymm19 => XMM19;
+        Ymm20 => XMM20;
+        Ymm21 => XMM21;
+        Ymm22 => XMM22;
+        Ymm23 => XMM23;
+        Ymm24 => XMM24;
+        Ymm25 => XMM25;
+        Ymm26 => XMM26;
+        Ymm27 => XMM27;
+        Ymm28 => XMM28;
+        Ymm29 => XMM29;
+        Ymm30 => XMM30;
+        Ymm31 => XMM31;
+    ]
+}
+
+reg_enum! {
+    ZWord: ZWORD = [
+        Zmm0 => XMM0;
+        Zmm1 => XMM1;
+        Zmm2 => XMM2;
+        Zmm3 => XMM3;
+        Zmm4 => XMM4;
+        Zmm5 => XMM5;
+        Zmm6 => XMM6;
+        Zmm7 => XMM7;
+        Zmm8 => XMM8;
+        Zmm9 => XMM9;
+        Zmm10 => XMM10;
+        Zmm11 => XMM11;
+        Zmm12 => XMM12;
+        Zmm13 => XMM13;
+        Zmm14 => XMM14;
+        Zmm15 => XMM15;
+        Zmm16 => XMM16;
+        Zmm17 => XMM17;
+        Zmm18 => XMM18;
+        Zmm19 => XMM19;
+        Zmm20 => XMM20;
+        Zmm21 => XMM21;
+        Zmm22 => XMM22;
+        Zmm23 => XMM23;
+        Zmm24 => XMM24;
+        Zmm25 => XMM25;
+        Zmm26 => XMM26;
+        Zmm27 => XMM27;
+        Zmm28 => XMM28;
+        Zmm29 => XMM29;
+        Zmm30 => XMM30;
+        Zmm31 => XMM31;
+    ]
+}
+
+reg_enum! {
+    Mask: QWORD = [
+        K0 => K0;
+        K1 => K1;
+        K2 => K2;
+        K3 => K3;
+        K4 => K4;
+        K5 => K5;
+        K6 => K6;
+        K7 => K7;
     ]
 }
 
@@ -195,4 +307,30 @@ pub fn rip_relative(target: JumpTarget) -> Arg {
 }
 pub fn rip_nonrelative(label: JumpTarget) -> Arg { 
     Arg::JumpTarget(JumpType::Forward(label), None)
+}
+
+// Symbols are either defined by Assembler::global or left to the linker.
+// extern_symbol is the target of a call or jmp, or the absolute address of the symbol as an immediate.
+pub fn extern_symbol(name: &str) -> Arg {
+    Arg::JumpTarget(JumpType::Global(name.to_string(), None), None)
+}
+pub fn extern_rip_relative(name: &str) -> Arg {
+    Arg::IndirectJumpTarget(JumpType::Global(name.to_string(), None), None)
+}
+// The GOT entry holding the address of the symbol, like [rip + name@GOTPCREL]. COFF has no GOT, the
+// import address table entry __imp_name is used instead.
+pub fn extern_got(name: &str) -> Arg {
+    Arg::IndirectJumpTarget(JumpType::Global(name.to_string(), Some(SymbolReferenceKind::Got)), None)
+}
+// A call or jmp through the PLT, like name@PLT
+pub fn extern_plt(name: &str) -> Arg {
+    Arg::JumpTarget(JumpType::Global(name.to_string(), Some(SymbolReferenceKind::Branch)), None)
+}
+// A thread-local variable of the executable at [fs:name@TPOFF] (local-exec), or the GOT entry holding
+// its offset from the thread pointer at [rip + name@GOTTPOFF] (initial-exec).
+pub fn extern_tpoff(name: &str) -> Arg {
+    Arg::IndirectJumpTarget(JumpType::Global(name.to_string(), Some(SymbolReferenceKind::TpOff)), None)
+}
+pub fn extern_gottpoff(name: &str) -> Arg {
+    Arg::IndirectJumpTarget(JumpType::Global(name.to_string(), Some(SymbolReferenceKind::GotTpOff)), None)
 }
\ No newline at end of file
@@ -7,11 +7,11 @@ pub mod flags {
     pub const AUTO_NO32 : u32 = 0x0000_0008; // 16 bit -> OPSIZE , 32-bit -> illegal, 64-bit -> None
     pub const AUTO_REXW : u32 = 0x0000_0010; // 16 bit -> illegal, 32-bit -> None   , 64-bit -> REX.W/VEX.W/XOP.W
     pub const AUTO_VEXL : u32 = 0x0000_0020; // 128bit -> None   , 256bit -> VEX.L
-    pub const WORD_SIZE : u32 = 0x0000_0040; // implies opsize prefix
+    pub const WORD_SIZE : u32 = 0x0000_0040; // 16-bit operands: implies opsize prefix outside of 16-bit mode
     pub const WITH_REXW : u32 = 0x0000_0080; // implies REX.W/VEX.W/XOP.W
     pub const WITH_VEXL : u32 = 0x0000_0100; // implies VEX.L/XOP.L
 
-    pub const PREF_66   : u32 = WORD_SIZE; // mandatory prefix (same as WORD_SIZE)
+    pub const PREF_66   : u32 = 0x0010_0000; // mandatory prefix, regardless of mode
     pub const PREF_67   : u32 = 0x0000_0200; // mandatory prefix (same as SMALL_ADDRESS)
     pub const PREF_F0   : u32 = 0x0000_0400; // mandatory prefix (same as LOCK)
     pub const PREF_F2   : u32 = 0x0000_0800; // mandatory prefix (REPNE)
@@ -24,5 +24,17 @@ pub mod flags {
     pub const SHORT_ARG : u32 = 0x0001_0000; // a register argument is encoded in the last byte of the opcode
     pub const ENC_MR    : u32 = 0x0002_0000; //  select alternate arg encoding
     pub const ENC_VM    : u32 = 0x0004_0000; //  select alternate arg encoding
+    pub const DWORD_SIZE: u32 = 0x0008_0000; // 32-bit operands: implies opsize prefix in 16-bit mode
+
+    pub const WORD_ADDR : u32 = 0x0020_0000; // 16-bit addresses: implies addrsize prefix in 32-bit mode
+    pub const DWORD_ADDR: u32 = 0x0040_0000; // 32-bit addresses: implies addrsize prefix outside of 32-bit mode
+    pub const NO_LONG   : u32 = 0x0080_0000; // not available in 64-bit mode
+    pub const LONG_ONLY : u32 = 0x0100_0000; // only available in 64-bit mode
+
+    pub const EVEX_OP   : u32 = 0x0200_0000; // this instruction requires an EVEX prefix to be encoded
+    pub const WITH_EVEXL: u32 = 0x0400_0000; // implies EVEX.L' (512-bit vectors)
+    pub const BCST      : u32 = 0x0800_0000; // a memory operand can be broadcast from a single element, sized by EVEX.W
+    pub const EVEX_ER   : u32 = 0x1000_0000; // embedded rounding control can be used with register operands
+    pub const EVEX_SAE  : u32 = 0x2000_0000; // exceptions can be suppressed with register operands
 }
 